@@ -0,0 +1,3 @@
+fn main() -> anyhow::Result<()> {
+    cuda_interposer_build::StubDriverBuilder::new().build()
+}
@@ -0,0 +1,302 @@
+//! Contexts and the per-thread context stack.
+//!
+//! A context owns the allocations, streams, events and modules made while it was current, and
+//! destroying it (or resetting a primary context) releases them. Primary contexts keep the same
+//! handle for the life of the process.
+
+use crate::devices;
+use crate::{CUDA_ERROR_INVALID_CONTEXT, api, initialized, new_handle, write};
+use crate::{memory, modules, streams};
+use cuda_interposer_common::ffi::*;
+use once_cell::sync::Lazy;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_int, c_uint};
+use std::sync::Mutex;
+
+/// The API version `cuCtxGetApiVersion` reports for every context.
+const API_VERSION: c_uint = 3020;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Context {
+    pub device: CUdevice,
+    pub flags: u32,
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Primary {
+    ctx: u64,
+    retains: u32,
+    flags: u32,
+}
+
+static CONTEXTS: Lazy<Mutex<HashMap<u64, Context>>> = Lazy::new(Default::default);
+static PRIMARY: Lazy<Mutex<HashMap<CUdevice, Primary>>> = Lazy::new(Default::default);
+
+thread_local! {
+    static STACK: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the context `ctx` refers to, if it is alive.
+pub fn get(ctx: u64) -> Option<Context> {
+    CONTEXTS.lock().unwrap().get(&ctx).copied()
+}
+
+/// Returns the context current on this thread, failing with `CUDA_ERROR_INVALID_CONTEXT` if
+/// there is none.
+pub fn current() -> Result<u64, CUresult> {
+    initialized()?;
+    let ctx = STACK.with(|s| s.borrow().last().copied());
+    match ctx {
+        Some(ctx) if get(ctx).is_some() => Ok(ctx),
+        _ => Err(CUDA_ERROR_INVALID_CONTEXT),
+    }
+}
+
+/// Returns the device of the current context.
+pub fn current_device() -> Result<CUdevice, CUresult> {
+    let ctx = current()?;
+    get(ctx).map(|c| c.device).ok_or(CUDA_ERROR_INVALID_CONTEXT)
+}
+
+/// Releases everything the context owns.
+fn release_resources(ctx: u64) {
+    modules::release_context(ctx);
+    streams::release_context(ctx);
+    memory::release_context(ctx);
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuCtxCreate_v2(
+    pctx: *mut CUcontext,
+    flags: c_uint,
+    dev: CUdevice,
+) -> CUresult {
+    api(|| {
+        devices::check(dev)?;
+        let ctx = new_handle();
+        let context = Context {
+            device: dev,
+            flags,
+            primary: false,
+        };
+        unsafe { write(pctx, ctx as CUcontext)? };
+        CONTEXTS.lock().unwrap().insert(ctx, context);
+        STACK.with(|s| s.borrow_mut().push(ctx));
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuCtxDestroy_v2(ctx: CUcontext) -> CUresult {
+    api(|| {
+        initialized()?;
+        let ctx = ctx as u64;
+        let mut contexts = CONTEXTS.lock().unwrap();
+        match contexts.get(&ctx) {
+            Some(context) if !context.primary => contexts.remove(&ctx),
+            _ => return Err(CUDA_ERROR_INVALID_CONTEXT),
+        };
+        drop(contexts);
+        release_resources(ctx);
+        STACK.with(|s| s.borrow_mut().retain(|c| *c != ctx));
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuCtxPushCurrent_v2(ctx: CUcontext) -> CUresult {
+    api(|| {
+        initialized()?;
+        get(ctx as u64).ok_or(CUDA_ERROR_INVALID_CONTEXT)?;
+        STACK.with(|s| s.borrow_mut().push(ctx as u64));
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuCtxPopCurrent_v2(pctx: *mut CUcontext) -> CUresult {
+    api(|| {
+        initialized()?;
+        let ctx = STACK
+            .with(|s| s.borrow_mut().pop())
+            .ok_or(CUDA_ERROR_INVALID_CONTEXT)?;
+        if !pctx.is_null() {
+            unsafe { *pctx = ctx as CUcontext };
+        }
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuCtxSetCurrent(ctx: CUcontext) -> CUresult {
+    api(|| {
+        initialized()?;
+        let ctx = ctx as u64;
+        if ctx != 0 {
+            get(ctx).ok_or(CUDA_ERROR_INVALID_CONTEXT)?;
+        }
+        STACK.with(|s| {
+            let mut stack = s.borrow_mut();
+            stack.pop();
+            if ctx != 0 {
+                stack.push(ctx);
+            }
+        });
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuCtxGetCurrent(pctx: *mut CUcontext) -> CUresult {
+    api(|| {
+        initialized()?;
+        let ctx = STACK.with(|s| s.borrow().last().copied()).unwrap_or(0);
+        unsafe { write(pctx, ctx as CUcontext) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuCtxGetDevice(device: *mut CUdevice) -> CUresult {
+    api(|| unsafe { write(device, current_device()?) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuCtxGetFlags(flags: *mut c_uint) -> CUresult {
+    api(|| {
+        let context = get(current()?).ok_or(CUDA_ERROR_INVALID_CONTEXT)?;
+        unsafe { write(flags, context.flags) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuCtxGetApiVersion(ctx: CUcontext, version: *mut c_uint) -> CUresult {
+    api(|| {
+        let ctx = if ctx.is_null() {
+            current()?
+        } else {
+            ctx as u64
+        };
+        get(ctx).ok_or(CUDA_ERROR_INVALID_CONTEXT)?;
+        unsafe { write(version, API_VERSION) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuCtxSynchronize() -> CUresult {
+    // Work runs when it is issued, so there is never anything to wait for
+    api(|| current().map(drop))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuCtxGetStreamPriorityRange(
+    least_priority: *mut c_int,
+    greatest_priority: *mut c_int,
+) -> CUresult {
+    api(|| {
+        current()?;
+        if !least_priority.is_null() {
+            unsafe { *least_priority = streams::LEAST_PRIORITY };
+        }
+        if !greatest_priority.is_null() {
+            unsafe { *greatest_priority = streams::GREATEST_PRIORITY };
+        }
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDevicePrimaryCtxRetain(pctx: *mut CUcontext, dev: CUdevice) -> CUresult {
+    api(|| {
+        devices::check(dev)?;
+        if pctx.is_null() {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let mut primary = PRIMARY.lock().unwrap();
+        let entry = primary.entry(dev).or_insert_with(|| Primary {
+            ctx: new_handle(),
+            retains: 0,
+            flags: 0,
+        });
+        if entry.retains == 0 {
+            let context = Context {
+                device: dev,
+                flags: entry.flags,
+                primary: true,
+            };
+            CONTEXTS.lock().unwrap().insert(entry.ctx, context);
+        }
+        entry.retains += 1;
+        unsafe { *pctx = entry.ctx as CUcontext };
+        Ok(())
+    })
+}
+
+/// Drops a primary context and everything it owns, keeping its handle for the next
+/// retain.
+fn deactivate_primary(entry: &mut Primary) {
+    entry.retains = 0;
+    CONTEXTS.lock().unwrap().remove(&entry.ctx);
+    release_resources(entry.ctx);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuDevicePrimaryCtxRelease_v2(dev: CUdevice) -> CUresult {
+    api(|| {
+        devices::check(dev)?;
+        let mut primary = PRIMARY.lock().unwrap();
+        let entry = primary
+            .get_mut(&dev)
+            .filter(|e| e.retains > 0)
+            .ok_or(CUDA_ERROR_INVALID_CONTEXT)?;
+        entry.retains -= 1;
+        if entry.retains == 0 {
+            deactivate_primary(entry);
+        }
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuDevicePrimaryCtxReset_v2(dev: CUdevice) -> CUresult {
+    api(|| {
+        devices::check(dev)?;
+        if let Some(entry) = PRIMARY.lock().unwrap().get_mut(&dev) {
+            deactivate_primary(entry);
+        }
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuDevicePrimaryCtxSetFlags_v2(dev: CUdevice, flags: c_uint) -> CUresult {
+    api(|| {
+        devices::check(dev)?;
+        let mut primary = PRIMARY.lock().unwrap();
+        let entry = primary.entry(dev).or_insert_with(|| Primary {
+            ctx: new_handle(),
+            retains: 0,
+            flags: 0,
+        });
+        entry.flags = flags;
+        if let Some(context) = CONTEXTS.lock().unwrap().get_mut(&entry.ctx) {
+            context.flags = flags;
+        }
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDevicePrimaryCtxGetState(
+    dev: CUdevice,
+    flags: *mut c_uint,
+    active: *mut c_int,
+) -> CUresult {
+    api(|| {
+        devices::check(dev)?;
+        let entry = PRIMARY.lock().unwrap().get(&dev).copied();
+        unsafe { write(flags, entry.map_or(0, |e| e.flags))? };
+        unsafe { write(active, entry.is_some_and(|e| e.retains > 0) as c_int) }
+    })
+}
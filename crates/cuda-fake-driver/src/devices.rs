@@ -0,0 +1,254 @@
+//! The devices the fake driver reports.
+//!
+//! There is one device unless `CUDA_FAKE_DEVICE_COUNT` says otherwise. What they report is
+//! described by a [`Profile`], the same TOML format `CUDA_HOOK_DEVICE_PROFILE` uses to spoof a
+//! real driver, loaded from `CUDA_FAKE_DEVICE_PROFILE` or set with [`configure`]. Anything the
+//! profile leaves out is reported as an A100-like device with 16 GiB of memory.
+
+use crate::{api, initialized, write};
+use cuda_interposer_common::config::ConfigError;
+use cuda_interposer_common::ffi::*;
+use cuda_interposer_common::profile::{
+    CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR, CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR,
+    Profile, attribute_id,
+};
+use once_cell::sync::Lazy;
+use std::ffi::{c_char, c_int};
+use std::sync::RwLock;
+use tracing::warn;
+
+const COUNT_ENV: &str = "CUDA_FAKE_DEVICE_COUNT";
+const PROFILE_ENV: &str = "CUDA_FAKE_DEVICE_PROFILE";
+
+pub const DEFAULT_NAME: &str = "cudaflow Fake GPU";
+pub const DEFAULT_TOTAL_MEM: usize = 16 << 30;
+pub const DEFAULT_COMPUTE_CAPABILITY: (i32, i32) = (8, 0);
+
+/// Attributes reported when the profile does not set them, named as in profiles. Unlisted
+/// attributes are 0.
+const DEFAULT_ATTRIBUTES: &[(&str, i32)] = &[
+    ("max_threads_per_block", 1024),
+    ("max_block_dim_x", 1024),
+    ("max_block_dim_y", 1024),
+    ("max_block_dim_z", 64),
+    ("max_grid_dim_x", i32::MAX),
+    ("max_grid_dim_y", 65535),
+    ("max_grid_dim_z", 65535),
+    ("max_shared_memory_per_block", 49152),
+    ("total_constant_memory", 65536),
+    ("warp_size", 32),
+    ("max_pitch", i32::MAX),
+    ("max_registers_per_block", 65536),
+    ("clock_rate", 1410000),
+    ("texture_alignment", 512),
+    ("gpu_overlap", 1),
+    ("multiprocessor_count", 108),
+    ("can_map_host_memory", 1),
+    ("concurrent_kernels", 1),
+    ("memory_clock_rate", 1215000),
+    ("global_memory_bus_width", 5120),
+    ("l2_cache_size", 41943040),
+    ("max_threads_per_multiprocessor", 2048),
+    ("async_engine_count", 2),
+    ("unified_addressing", 1),
+    ("max_shared_memory_per_multiprocessor", 167936),
+    ("max_registers_per_multiprocessor", 65536),
+    ("managed_memory", 1),
+    ("concurrent_managed_access", 1),
+    ("compute_preemption_supported", 1),
+    ("cooperative_launch", 1),
+    ("max_shared_memory_per_block_optin", 166912),
+    ("virtual_memory_management_supported", 1),
+    ("max_blocks_per_multiprocessor", 32),
+    ("memory_pools_supported", 1),
+];
+
+const CU_DEVICE_ATTRIBUTE_PCI_BUS_ID: i32 = 33;
+
+/// The devices the driver reports.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub count: usize,
+    pub profile: Profile,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            profile: Profile::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `CUDA_FAKE_DEVICE_COUNT` and `CUDA_FAKE_DEVICE_PROFILE`.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(count) = std::env::var(COUNT_ENV) {
+            match count.trim().parse() {
+                Ok(n) => config.count = n,
+                Err(_) => warn!(
+                    "Ignoring {}",
+                    ConfigError::new(COUNT_ENV, &count, "expected a number of devices")
+                ),
+            }
+        }
+        if let Some(path) = std::env::var_os(PROFILE_ENV) {
+            match Profile::load(&path) {
+                Ok(profile) => config.profile = profile,
+                Err(e) => warn!("Ignoring device profile {:?}: {}", path, e),
+            }
+        }
+        config
+    }
+}
+
+static CONFIG: Lazy<RwLock<Config>> = Lazy::new(|| RwLock::new(Config::from_env()));
+
+/// Replaces the device configuration. Contexts and allocations made before keep their devices.
+pub fn configure(config: Config) {
+    *CONFIG.write().unwrap() = config;
+}
+
+pub fn count() -> usize {
+    CONFIG.read().unwrap().count
+}
+
+pub fn name(dev: CUdevice) -> String {
+    let config = CONFIG.read().unwrap();
+    config
+        .profile
+        .name(dev)
+        .unwrap_or_else(|| DEFAULT_NAME.to_string())
+}
+
+pub fn total_mem(dev: CUdevice) -> usize {
+    let config = CONFIG.read().unwrap();
+    config.profile.total_mem(dev).unwrap_or(DEFAULT_TOTAL_MEM)
+}
+
+pub fn compute_capability(dev: CUdevice) -> (i32, i32) {
+    let config = CONFIG.read().unwrap();
+    config
+        .profile
+        .compute_capability(dev)
+        .unwrap_or(DEFAULT_COMPUTE_CAPABILITY)
+}
+
+pub fn attribute(dev: CUdevice, attrib: i32) -> i32 {
+    if let Some(value) = CONFIG.read().unwrap().profile.attribute(dev, attrib) {
+        return value;
+    }
+    let (major, minor) = compute_capability(dev);
+    match attrib {
+        CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR => major,
+        CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR => minor,
+        CU_DEVICE_ATTRIBUTE_PCI_BUS_ID => dev + 1,
+        _ => DEFAULT_ATTRIBUTES
+            .iter()
+            .find(|(name, _)| attribute_id(name) == Some(attrib))
+            .map_or(0, |(_, value)| *value),
+    }
+}
+
+/// Fails unless the driver is initialized and `dev` is one of its devices.
+pub(crate) fn check(dev: CUdevice) -> Result<(), CUresult> {
+    initialized()?;
+    if dev < 0 || dev as usize >= count() {
+        return Err(CUDA_ERROR_INVALID_DEVICE);
+    }
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDeviceGetCount(count: *mut c_int) -> CUresult {
+    api(|| {
+        initialized()?;
+        unsafe { write(count, self::count() as c_int) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDeviceGet(device: *mut CUdevice, ordinal: c_int) -> CUresult {
+    api(|| {
+        check(ordinal)?;
+        unsafe { write(device, ordinal) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDeviceGetName(name: *mut c_char, len: c_int, dev: CUdevice) -> CUresult {
+    api(|| {
+        check(dev)?;
+        if name.is_null() || len <= 0 {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let value = self::name(dev);
+        let n = value.len().min(len as usize - 1);
+        unsafe {
+            std::ptr::copy_nonoverlapping(value.as_ptr() as *const c_char, name, n);
+            *name.add(n) = 0;
+        }
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDeviceTotalMem_v2(bytes: *mut usize, dev: CUdevice) -> CUresult {
+    api(|| {
+        check(dev)?;
+        unsafe { write(bytes, total_mem(dev)) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDeviceGetAttribute(
+    pi: *mut c_int,
+    attrib: c_int,
+    dev: CUdevice,
+) -> CUresult {
+    api(|| {
+        check(dev)?;
+        unsafe { write(pi, attribute(dev, attrib)) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDeviceComputeCapability(
+    major: *mut c_int,
+    minor: *mut c_int,
+    dev: CUdevice,
+) -> CUresult {
+    api(|| {
+        check(dev)?;
+        let (cc_major, cc_minor) = compute_capability(dev);
+        unsafe { write(major, cc_major)? };
+        unsafe { write(minor, cc_minor) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDeviceGetUuid_v2(uuid: *mut CUuuid, dev: CUdevice) -> CUresult {
+    api(|| unsafe { get_uuid(uuid, dev) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDeviceGetUuid(uuid: *mut CUuuid, dev: CUdevice) -> CUresult {
+    api(|| unsafe { get_uuid(uuid, dev) })
+}
+
+unsafe fn get_uuid(uuid: *mut CUuuid, dev: CUdevice) -> Result<(), CUresult> {
+    check(dev)?;
+    // Stable per ordinal, and marked as not coming from real hardware
+    let mut bytes = *b"cudaflow-fake\0\0\0";
+    bytes[14..].copy_from_slice(&(dev as u16).to_be_bytes());
+    unsafe {
+        write(
+            uuid,
+            CUuuid {
+                bytes: bytes.map(|b| b as c_char),
+            },
+        )
+    }
+}
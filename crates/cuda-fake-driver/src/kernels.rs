@@ -0,0 +1,306 @@
+//! Kernel launches.
+//!
+//! A launch checks its function, stream and geometry and then does nothing, unless a callback
+//! is registered for the kernel. Callbacks run on the launching thread, before the launch
+//! returns, and see the kernel parameters as the application passed them; device pointers among
+//! them are host addresses they can read and write. Parameters passed in an `extra` buffer are
+//! found through the kernel's parameter layout. An error a callback returns is the result of the
+//! launch.
+//!
+//! Callbacks are registered by mangled name, by demangled signature (`scale(float*, int)`) or by
+//! bare name (`scale`), with [`register`] from Rust code linked against this crate, or with
+//! `cufakeRegisterKernel` through the library the application loaded:
+//!
+//! ```ignore
+//! cuda::kernels::register("scale", |launch| {
+//!     let (data, n): (u64, i32) = unsafe { (launch.arg(0), launch.arg(1)) };
+//!     let data = unsafe { std::slice::from_raw_parts_mut(data as *mut f32, n as usize) };
+//!     data.iter_mut().for_each(|x| *x *= 2.0);
+//!     Ok(())
+//! });
+//! ```
+
+use crate::modules::{self, Function};
+use crate::streams;
+use crate::{CUDA_ERROR_INVALID_HANDLE, api};
+use cuda_interposer_common::ffi::*;
+use cuda_interposer_common::kernel::KernelName;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_char, c_uint, c_void};
+use std::sync::{Arc, RwLock};
+
+/// The largest number of threads in a block.
+const MAX_THREADS_PER_BLOCK: u64 = 1024;
+
+/// A kernel launch, as a callback sees it.
+#[derive(Debug)]
+pub struct Launch<'a> {
+    pub name: &'a KernelName,
+    pub grid: (u32, u32, u32),
+    pub block: (u32, u32, u32),
+    pub shared_mem_bytes: u32,
+    pub stream: CUstream,
+    /// The `kernelParams` of the launch: one pointer per parameter. For launches that pass
+    /// their parameters through `extra`, pointers into its buffer, or null if the kernel's
+    /// parameter layout is unknown.
+    pub params: *mut *mut c_void,
+}
+
+impl Launch<'_> {
+    /// Reads parameter `i`.
+    ///
+    /// # Safety
+    /// The launch must have at least `i + 1` parameters, and parameter `i` must be a `T`.
+    pub unsafe fn arg<T: Copy>(&self, i: usize) -> T {
+        unsafe { (*self.params.add(i) as *const T).read_unaligned() }
+    }
+}
+
+type Callback = Arc<dyn Fn(&Launch) -> Result<(), CUresult> + Send + Sync>;
+
+static CALLBACKS: Lazy<RwLock<HashMap<String, Callback>>> = Lazy::new(Default::default);
+
+/// Runs `f` for every launch of the kernel called `name`, replacing any earlier callback.
+pub fn register(
+    name: impl Into<String>,
+    f: impl Fn(&Launch) -> Result<(), CUresult> + Send + Sync + 'static,
+) {
+    CALLBACKS.write().unwrap().insert(name.into(), Arc::new(f));
+}
+
+/// Makes launches of the kernel called `name` no-ops again.
+pub fn unregister(name: &str) {
+    CALLBACKS.write().unwrap().remove(name);
+}
+
+fn callback(name: &KernelName) -> Option<Callback> {
+    let callbacks = CALLBACKS.read().unwrap();
+    let bare = name.demangled.split('(').next().unwrap_or(&name.demangled);
+    [name.mangled.as_str(), name.demangled.as_str(), bare]
+        .iter()
+        .find_map(|n| callbacks.get(*n).cloned())
+}
+
+/// Checks a launch and runs its callback, if any.
+fn launch(
+    f: CUfunction,
+    grid: (u32, u32, u32),
+    block: (u32, u32, u32),
+    shared_mem_bytes: u32,
+    stream: CUstream,
+    params: *mut *mut c_void,
+    extra: *mut *mut c_void,
+) -> Result<(), CUresult> {
+    streams::check(stream)?;
+    let function = modules::function(f).ok_or(CUDA_ERROR_INVALID_HANDLE)?;
+    let threads = block.0 as u64 * block.1 as u64 * block.2 as u64;
+    let blocks = grid.0 as u64 * grid.1 as u64 * grid.2 as u64;
+    if threads == 0 || threads > MAX_THREADS_PER_BLOCK || blocks == 0 {
+        return Err(CUDA_ERROR_INVALID_VALUE);
+    }
+    let Some(callback) = callback(&function.name) else {
+        return Ok(());
+    };
+    let mut from_extra = Vec::new();
+    if params.is_null() && !extra.is_null() {
+        from_extra = unsafe { extra_params(&function, extra) }.unwrap_or_default();
+    }
+    let params = if from_extra.is_empty() {
+        params
+    } else {
+        from_extra.as_mut_ptr()
+    };
+    callback(&Launch {
+        name: &function.name,
+        grid,
+        block,
+        shared_mem_bytes,
+        stream,
+        params,
+    })
+}
+
+/// Points one pointer at each argument in the parameter buffer of an `extra` array, where
+/// the kernel's parameter layout puts it.
+///
+/// # Safety
+/// `extra` must be a `CU_LAUNCH_PARAM_END`-terminated array as `cuLaunchKernel` accepts it.
+unsafe fn extra_params(function: &Function, extra: *mut *mut c_void) -> Option<Vec<*mut c_void>> {
+    let mut buffer = std::ptr::null_mut::<u8>();
+    for i in (0..).step_by(2) {
+        let key = unsafe { *extra.add(i) } as usize;
+        if key == CU_LAUNCH_PARAM_END {
+            break;
+        }
+        if key == CU_LAUNCH_PARAM_BUFFER_POINTER {
+            buffer = unsafe { *extra.add(i + 1) } as *mut u8;
+        }
+    }
+    if buffer.is_null() {
+        return None;
+    }
+    let params = modules::params(function)?;
+    Some(
+        params
+            .iter()
+            .map(|&(offset, _)| unsafe { buffer.add(offset) } as *mut c_void)
+            .collect(),
+    )
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn cuLaunchKernel(
+    f: CUfunction,
+    grid_dim_x: c_uint,
+    grid_dim_y: c_uint,
+    grid_dim_z: c_uint,
+    block_dim_x: c_uint,
+    block_dim_y: c_uint,
+    block_dim_z: c_uint,
+    shared_mem_bytes: c_uint,
+    h_stream: CUstream,
+    kernel_params: *mut *mut c_void,
+    extra: *mut *mut c_void,
+) -> CUresult {
+    let grid = (grid_dim_x, grid_dim_y, grid_dim_z);
+    let block = (block_dim_x, block_dim_y, block_dim_z);
+    api(|| {
+        launch(
+            f,
+            grid,
+            block,
+            shared_mem_bytes,
+            h_stream,
+            kernel_params,
+            extra,
+        )
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn cuLaunchCooperativeKernel(
+    f: CUfunction,
+    grid_dim_x: c_uint,
+    grid_dim_y: c_uint,
+    grid_dim_z: c_uint,
+    block_dim_x: c_uint,
+    block_dim_y: c_uint,
+    block_dim_z: c_uint,
+    shared_mem_bytes: c_uint,
+    h_stream: CUstream,
+    kernel_params: *mut *mut c_void,
+) -> CUresult {
+    let grid = (grid_dim_x, grid_dim_y, grid_dim_z);
+    let block = (block_dim_x, block_dim_y, block_dim_z);
+    api(|| {
+        launch(
+            f,
+            grid,
+            block,
+            shared_mem_bytes,
+            h_stream,
+            kernel_params,
+            std::ptr::null_mut(),
+        )
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuLaunchKernelEx(
+    config: *const CUlaunchConfig,
+    f: CUfunction,
+    kernel_params: *mut *mut c_void,
+    extra: *mut *mut c_void,
+) -> CUresult {
+    api(|| {
+        if config.is_null() {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let c = unsafe { &*config };
+        let grid = (c.gridDimX, c.gridDimY, c.gridDimZ);
+        let block = (c.blockDimX, c.blockDimY, c.blockDimZ);
+        launch(
+            f,
+            grid,
+            block,
+            c.sharedMemBytes,
+            c.hStream,
+            kernel_params,
+            extra,
+        )
+    })
+}
+
+/// A kernel launch, as `cufakeRegisterKernel` callbacks see it.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CUfakeLaunch {
+    /// The mangled name of the kernel.
+    pub name: *const c_char,
+    pub gridDimX: c_uint,
+    pub gridDimY: c_uint,
+    pub gridDimZ: c_uint,
+    pub blockDimX: c_uint,
+    pub blockDimY: c_uint,
+    pub blockDimZ: c_uint,
+    pub sharedMemBytes: c_uint,
+    pub hStream: CUstream,
+    pub kernelParams: *mut *mut c_void,
+}
+
+pub type CUfakeKernel =
+    unsafe extern "C" fn(launch: *const CUfakeLaunch, user_data: *mut c_void) -> CUresult;
+
+struct UserData(*mut c_void);
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Registers `kernel` as the callback for the kernel called `name`, or removes the callback if
+/// `kernel` is null. For code that loads the library rather than linking against this crate.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cufakeRegisterKernel(
+    name: *const c_char,
+    kernel: Option<CUfakeKernel>,
+    user_data: *mut c_void,
+) -> CUresult {
+    if name.is_null() {
+        return CUDA_ERROR_INVALID_VALUE;
+    }
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .into_owned();
+    let Some(kernel) = kernel else {
+        unregister(&name);
+        return CUDA_SUCCESS;
+    };
+    let user_data = UserData(user_data);
+    register(name, move |launch| {
+        let mangled = CString::new(launch.name.mangled.as_str()).unwrap_or_default();
+        let c_launch = CUfakeLaunch {
+            name: mangled.as_ptr(),
+            gridDimX: launch.grid.0,
+            gridDimY: launch.grid.1,
+            gridDimZ: launch.grid.2,
+            blockDimX: launch.block.0,
+            blockDimY: launch.block.1,
+            blockDimZ: launch.block.2,
+            sharedMemBytes: launch.shared_mem_bytes,
+            hStream: launch.stream,
+            kernelParams: launch.params,
+        };
+        match unsafe { kernel(&c_launch, user_data.get()) } {
+            CUDA_SUCCESS => Ok(()),
+            rc => Err(rc),
+        }
+    });
+    CUDA_SUCCESS
+}
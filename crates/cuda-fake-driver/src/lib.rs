@@ -0,0 +1,353 @@
+//! A host-memory stand-in for `libcuda.so`, for running driver API applications and
+//! interposers in CI without a GPU.
+//!
+//! The library exports every driver API listed in `cuda_interposer_tables::driver_symbols`. The
+//! core of the API is implemented against host memory:
+//!
+//! - initialization, versions, error strings and `cuGetProcAddress`
+//! - device queries, configured by [`devices`]
+//! - contexts, including primary contexts and the per-thread context stack ([`context`])
+//! - streams and events ([`streams`])
+//! - allocations, copies and memsets, including the VMM APIs ([`memory`])
+//! - module loading and function lookup ([`modules`])
+//! - kernel launches, which do nothing unless a callback is registered for the kernel
+//!   ([`kernels`])
+//!
+//! Device pointers are host addresses, and all work runs synchronously on the calling thread, so
+//! streams and events only need bookkeeping. The other driver APIs are stubs generated by
+//! `cuda_interposer_build::StubDriverBuilder`, which return `CUDA_ERROR_NOT_SUPPORTED`. Neither
+//! the stubs nor the crate need the CUDA SDK.
+//!
+//! The crate builds as `libcuda.so`. Point `CUDA_HOOK_DRIVER` at it to run an interposer
+//! against it, or put its directory first in `LD_LIBRARY_PATH` to run an application directly.
+
+#![allow(non_snake_case, clippy::missing_safety_doc)]
+
+use cuda_interposer_common::ffi::*;
+use cuda_interposer_tables::status::DRIVER_ERRORS;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_char, c_int, c_uint, c_void};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tracing::debug;
+
+pub mod context;
+pub mod devices;
+pub mod kernels;
+pub mod memory;
+pub mod modules;
+pub mod streams;
+
+#[allow(unused_imports, clippy::all)]
+mod unsupported_driver {
+    use cuda_interposer_common::ffi::{CUDA_ERROR_NOT_SUPPORTED, CUresult};
+
+    include!(concat!(env!("OUT_DIR"), "/unsupported_driver.rs"));
+}
+
+pub use unsupported_driver::UNSUPPORTED;
+
+pub const CUDA_ERROR_NOT_INITIALIZED: CUresult = 3;
+pub const CUDA_ERROR_NO_DEVICE: CUresult = 100;
+pub const CUDA_ERROR_INVALID_IMAGE: CUresult = 200;
+pub const CUDA_ERROR_INVALID_CONTEXT: CUresult = 201;
+pub const CUDA_ERROR_ALREADY_MAPPED: CUresult = 208;
+pub const CUDA_ERROR_NOT_MAPPED: CUresult = 211;
+pub const CUDA_ERROR_FILE_NOT_FOUND: CUresult = 301;
+pub const CUDA_ERROR_INVALID_HANDLE: CUresult = 400;
+pub const CUDA_ERROR_ILLEGAL_ADDRESS: CUresult = 700;
+pub const CUDA_ERROR_LAUNCH_FAILED: CUresult = 719;
+
+/// The version `cuDriverGetVersion` reports, that of the bindings the stubs come from.
+pub const DRIVER_VERSION: c_int = 13010;
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Handles are small numbers rather than addresses, shared by every kind of object so that a
+/// handle of the wrong kind is never valid.
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(0x1000);
+
+pub(crate) fn new_handle() -> u64 {
+    NEXT_HANDLE.fetch_add(0x10, Ordering::Relaxed)
+}
+
+/// Runs the body of an API, turning its error into the returned status.
+///
+/// APIs never call each other's exported entry points, as those calls would resolve to an
+/// interposer preloaded ahead of this library and be seen as calls from the application.
+/// Versions of the same API share a private function instead.
+pub(crate) fn api(f: impl FnOnce() -> Result<(), CUresult>) -> CUresult {
+    match f() {
+        Ok(()) => CUDA_SUCCESS,
+        Err(rc) => rc,
+    }
+}
+
+/// Fails with `CUDA_ERROR_NOT_INITIALIZED` before `cuInit`.
+pub(crate) fn initialized() -> Result<(), CUresult> {
+    if INITIALIZED.load(Ordering::Acquire) {
+        Ok(())
+    } else {
+        Err(CUDA_ERROR_NOT_INITIALIZED)
+    }
+}
+
+/// Writes an output parameter, failing with `CUDA_ERROR_INVALID_VALUE` if it is null.
+pub(crate) unsafe fn write<T>(out: *mut T, value: T) -> Result<(), CUresult> {
+    if out.is_null() {
+        return Err(CUDA_ERROR_INVALID_VALUE);
+    }
+    unsafe { out.write(value) };
+    Ok(())
+}
+
+/// Called by the generated stubs.
+#[allow(dead_code)]
+pub(crate) fn unsupported(name: &str) {
+    debug!("{} is not supported by the fake driver", name);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuInit(flags: c_uint) -> CUresult {
+    if flags != 0 {
+        return CUDA_ERROR_INVALID_VALUE;
+    }
+    if devices::count() == 0 {
+        return CUDA_ERROR_NO_DEVICE;
+    }
+    INITIALIZED.store(true, Ordering::Release);
+    CUDA_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuDriverGetVersion(version: *mut c_int) -> CUresult {
+    api(|| unsafe { write(version, DRIVER_VERSION) })
+}
+
+/// The names and descriptions of the error codes, as C strings.
+static ERROR_STRINGS: Lazy<HashMap<CUresult, (CString, CString)>> = Lazy::new(|| {
+    std::iter::once(("CUDA_SUCCESS", CUDA_SUCCESS))
+        .chain(DRIVER_ERRORS.iter().copied())
+        .map(|(name, rc)| {
+            let description = name
+                .trim_start_matches("CUDA_ERROR_")
+                .replace('_', " ")
+                .to_lowercase();
+            let name = CString::new(name).unwrap();
+            (rc, (name, CString::new(description).unwrap()))
+        })
+        .collect()
+});
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuGetErrorName(error: CUresult, p_str: *mut *const c_char) -> CUresult {
+    api(|| {
+        let (name, _) = ERROR_STRINGS.get(&error).ok_or(CUDA_ERROR_INVALID_VALUE)?;
+        unsafe { write(p_str, name.as_ptr()) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuGetErrorString(error: CUresult, p_str: *mut *const c_char) -> CUresult {
+    api(|| {
+        let (_, description) = ERROR_STRINGS.get(&error).ok_or(CUDA_ERROR_INVALID_VALUE)?;
+        unsafe { write(p_str, description.as_ptr()) }
+    })
+}
+
+/// This library, as loaded, for looking up its own symbols.
+struct Handle(*mut c_void);
+unsafe impl Send for Handle {}
+unsafe impl Sync for Handle {}
+
+static SELF: Lazy<Handle> = Lazy::new(|| unsafe {
+    let mut info: libc::Dl_info = std::mem::zeroed();
+    let addr = cuInit as *const c_void;
+    if libc::dladdr(addr, &mut info) == 0 || info.dli_fname.is_null() {
+        return Handle(std::ptr::null_mut());
+    }
+    Handle(libc::dlopen(
+        info.dli_fname,
+        libc::RTLD_NOW | libc::RTLD_NOLOAD,
+    ))
+});
+
+const CU_GET_PROC_ADDRESS_PER_THREAD_DEFAULT_STREAM: u64 = 0x2;
+const CU_GET_PROC_ADDRESS_SUCCESS: c_int = 0;
+const CU_GET_PROC_ADDRESS_SYMBOL_NOT_FOUND: c_int = 1;
+
+/// Finds the entry point for an unversioned API name. The newest implemented version wins,
+/// then the newest stub, so that callers asking for `cuMemAlloc` get `cuMemAlloc_v2`.
+fn proc_address(symbol: &str, flags: u64) -> *mut c_void {
+    if SELF.0.is_null() {
+        return std::ptr::null_mut();
+    }
+    let mut bases: Vec<String> = (2..=9).rev().map(|v| format!("{symbol}_v{v}")).collect();
+    bases.push(symbol.to_string());
+    let mut candidates = Vec::new();
+    for base in bases {
+        if flags & CU_GET_PROC_ADDRESS_PER_THREAD_DEFAULT_STREAM != 0 {
+            candidates.push(format!("{base}_ptsz"));
+            candidates.push(format!("{base}_ptds"));
+        }
+        candidates.push(base);
+    }
+    let lookup = |name: &String| {
+        let c_name = CString::new(name.as_str()).ok()?;
+        let ptr = unsafe { libc::dlsym(SELF.0, c_name.as_ptr()) };
+        (!ptr.is_null()).then_some(ptr)
+    };
+    candidates
+        .iter()
+        .filter(|name| !UNSUPPORTED.contains(&name.as_str()))
+        .find_map(lookup)
+        .or_else(|| candidates.iter().find_map(lookup))
+        .unwrap_or(std::ptr::null_mut())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuGetProcAddress_v2(
+    symbol: *const c_char,
+    pfn: *mut *mut c_void,
+    _cuda_version: c_int,
+    flags: u64,
+    symbol_status: *mut c_int,
+) -> CUresult {
+    api(|| unsafe { get_proc_address(symbol, pfn, flags, symbol_status) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuGetProcAddress(
+    symbol: *const c_char,
+    pfn: *mut *mut c_void,
+    _cuda_version: c_int,
+    flags: u64,
+) -> CUresult {
+    api(|| unsafe { get_proc_address(symbol, pfn, flags, std::ptr::null_mut()) })
+}
+
+unsafe fn get_proc_address(
+    symbol: *const c_char,
+    pfn: *mut *mut c_void,
+    flags: u64,
+    symbol_status: *mut c_int,
+) -> Result<(), CUresult> {
+    if symbol.is_null() || pfn.is_null() {
+        return Err(CUDA_ERROR_INVALID_VALUE);
+    }
+    let name = unsafe { CStr::from_ptr(symbol) }.to_string_lossy();
+    let ptr = proc_address(&name, flags);
+    unsafe { *pfn = ptr };
+    let status = if ptr.is_null() {
+        CU_GET_PROC_ADDRESS_SYMBOL_NOT_FOUND
+    } else {
+        CU_GET_PROC_ADDRESS_SUCCESS
+    };
+    if !symbol_status.is_null() {
+        unsafe { *symbol_status = status };
+    }
+    if ptr.is_null() {
+        Err(CUDA_ERROR_NOT_FOUND)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr::null_mut;
+
+    const PTX: &[u8] = b".version 8.0\n.target sm_80\n.address_size 64\n\n\
+        .visible .entry scale(.param .u64 data, .param .u32 n)\n{\n\tret;\n}\n\0";
+
+    #[test]
+    fn smoke() {
+        assert_eq!(cuInit(0), CUDA_SUCCESS);
+
+        let mut ctx = null_mut();
+        assert_eq!(
+            unsafe { context::cuCtxCreate_v2(&mut ctx, 0, 0) },
+            CUDA_SUCCESS
+        );
+        let mut current = null_mut();
+        assert_eq!(
+            unsafe { context::cuCtxGetCurrent(&mut current) },
+            CUDA_SUCCESS
+        );
+        assert_eq!(current, ctx);
+
+        let input = [1.0f32, 2.0, 3.0, 4.0];
+        let bytes = size_of_val(&input);
+        let mut dptr = 0;
+        assert_eq!(
+            unsafe { memory::cuMemAlloc_v2(&mut dptr, bytes) },
+            CUDA_SUCCESS
+        );
+        assert_ne!(dptr, 0);
+        assert_eq!(
+            unsafe { memory::cuMemcpyHtoD_v2(dptr, input.as_ptr().cast(), bytes) },
+            CUDA_SUCCESS
+        );
+
+        let mut module = null_mut();
+        assert_eq!(
+            unsafe { modules::cuModuleLoadData(&mut module, PTX.as_ptr().cast()) },
+            CUDA_SUCCESS
+        );
+        let mut function = null_mut();
+        assert_eq!(
+            unsafe { modules::cuModuleGetFunction(&mut function, module, c"scale".as_ptr()) },
+            CUDA_SUCCESS
+        );
+        assert_eq!(
+            unsafe { modules::cuModuleGetFunction(&mut function, module, c"missing".as_ptr()) },
+            CUDA_ERROR_NOT_FOUND
+        );
+
+        kernels::register("scale", |launch| {
+            assert_eq!(launch.grid, (1, 1, 1));
+            assert_eq!(launch.block, (4, 1, 1));
+            let (data, n): (u64, u32) = unsafe { (launch.arg(0), launch.arg(1)) };
+            let data = unsafe { std::slice::from_raw_parts_mut(data as *mut f32, n as usize) };
+            data.iter_mut().for_each(|x| *x *= 2.0);
+            Ok(())
+        });
+        let mut n = input.len() as u32;
+        let mut params = [(&mut dptr as *mut u64).cast(), (&mut n as *mut u32).cast()];
+        let rc = unsafe {
+            kernels::cuLaunchKernel(
+                function,
+                1,
+                1,
+                1,
+                4,
+                1,
+                1,
+                0,
+                null_mut(),
+                params.as_mut_ptr(),
+                null_mut(),
+            )
+        };
+        assert_eq!(rc, CUDA_SUCCESS);
+
+        let mut output = [0f32; 4];
+        assert_eq!(
+            unsafe { memory::cuMemcpyDtoH_v2(output.as_mut_ptr().cast(), dptr, bytes) },
+            CUDA_SUCCESS
+        );
+        assert_eq!(output, [2.0, 4.0, 6.0, 8.0]);
+
+        let (mut offset, mut size) = (0, 0);
+        let info = |i, offset: &mut usize, size: &mut usize| unsafe {
+            modules::cuFuncGetParamInfo(function, i, offset, size)
+        };
+        assert_eq!(info(1, &mut offset, &mut size), CUDA_SUCCESS);
+        assert_eq!((offset, size), (8, 4));
+        assert_eq!(info(2, &mut offset, &mut size), CUDA_ERROR_INVALID_VALUE);
+
+        assert_eq!(memory::cuMemFree_v2(dptr), CUDA_SUCCESS);
+        assert_eq!(context::cuCtxDestroy_v2(ctx), CUDA_SUCCESS);
+    }
+}
@@ -0,0 +1,832 @@
+//! Device and host memory.
+//!
+//! Every allocation is an anonymous mapping of the process, so device pointers are host
+//! addresses that copies, memsets and kernel callbacks use directly. Device allocations count
+//! against the total memory of the device and fail with `CUDA_ERROR_OUT_OF_MEMORY` past it;
+//! mappings are made with `MAP_NORESERVE`, so large devices cost nothing until written. Device
+//! allocations take whole granules of address space, aligned to the VMM granularity, so that
+//! the pages of a freed allocation can be reserved again at its address, as checkpoint restores
+//! do.
+//!
+//! The VMM APIs are backed by the same mechanisms: `cuMemAddressReserve` reserves an
+//! inaccessible range (at the requested address when it is free), `cuMemCreate` creates a
+//! `memfd` of the requested size, and `cuMemMap` maps it shared into the reserved range, so that
+//! one physical allocation mapped twice shows the same data at both addresses.
+
+use crate::context;
+use crate::devices;
+use crate::streams;
+use crate::{CUDA_ERROR_ALREADY_MAPPED, CUDA_ERROR_INVALID_CONTEXT, CUDA_ERROR_NOT_MAPPED};
+use crate::{api, initialized, new_handle, write};
+use cuda_interposer_common::ffi::*;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{c_uint, c_void};
+use std::sync::Mutex;
+
+/// The granularity of the VMM APIs, that of real devices.
+pub const GRANULARITY: usize = 2 << 20;
+
+/// Rows of `cuMemAllocPitch_v2` allocations are padded to this many bytes.
+const PITCH_ALIGNMENT: usize = 512;
+
+const CU_POINTER_ATTRIBUTE_CONTEXT: c_uint = 1;
+const CU_POINTER_ATTRIBUTE_MEMORY_TYPE: c_uint = 2;
+const CU_POINTER_ATTRIBUTE_DEVICE_POINTER: c_uint = 3;
+const CU_POINTER_ATTRIBUTE_HOST_POINTER: c_uint = 4;
+const CU_POINTER_ATTRIBUTE_IS_MANAGED: c_uint = 8;
+const CU_POINTER_ATTRIBUTE_DEVICE_ORDINAL: c_uint = 9;
+const CU_POINTER_ATTRIBUTE_RANGE_START_ADDR: c_uint = 11;
+const CU_POINTER_ATTRIBUTE_RANGE_SIZE: c_uint = 12;
+const CU_POINTER_ATTRIBUTE_MAPPED: c_uint = 13;
+
+const CU_MEMORYTYPE_HOST: c_uint = 1;
+const CU_MEMORYTYPE_DEVICE: c_uint = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// `cuMemAlloc*`, counted against the device.
+    Device,
+    /// `cuMemAllocManaged`, counted against the device.
+    Managed,
+    /// `cuMemAllocHost_v2` and `cuMemHostAlloc`.
+    Host,
+    /// Host memory the application owns, registered with `cuMemHostRegister_v2`.
+    Registered,
+    /// A range mapped with `cuMemMap`, to this physical allocation.
+    Mapped(CUmemGenericAllocationHandle),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Allocation {
+    pub size: usize,
+    pub kind: Kind,
+    pub device: CUdevice,
+    /// The context that made it, which releases it when destroyed. None for VMM mappings.
+    pub context: Option<u64>,
+}
+
+/// A physical allocation from `cuMemCreate`.
+#[derive(Debug)]
+struct Physical {
+    fd: i32,
+    size: usize,
+    device: CUdevice,
+}
+
+#[derive(Default)]
+struct State {
+    allocations: BTreeMap<u64, Allocation>,
+    /// Reserved address ranges, by start.
+    reservations: BTreeMap<u64, usize>,
+    physical: HashMap<CUmemGenericAllocationHandle, Physical>,
+    /// Bytes counted against each device.
+    used: HashMap<CUdevice, usize>,
+}
+
+impl State {
+    /// Returns the allocation containing `ptr`, with its start.
+    fn containing(&self, ptr: u64) -> Option<(u64, Allocation)> {
+        let (&base, alloc) = self.allocations.range(..=ptr).next_back()?;
+        (ptr < base + alloc.size.max(1) as u64).then_some((base, *alloc))
+    }
+
+    /// Counts `size` bytes against `device`, failing if that goes past its memory.
+    fn charge(&mut self, device: CUdevice, size: usize) -> Result<(), CUresult> {
+        let used = self.used.entry(device).or_default();
+        if *used + size > devices::total_mem(device) {
+            return Err(CUDA_ERROR_OUT_OF_MEMORY);
+        }
+        *used += size;
+        Ok(())
+    }
+
+    fn refund(&mut self, device: CUdevice, size: usize) {
+        if let Some(used) = self.used.get_mut(&device) {
+            *used = used.saturating_sub(size);
+        }
+    }
+}
+
+static STATE: Lazy<Mutex<State>> = Lazy::new(Default::default);
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Maps `size` bytes of anonymous memory, at exactly `addr` if it is not 0.
+fn map_anonymous(addr: u64, size: usize, prot: i32) -> Option<u64> {
+    let mut flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE;
+    if addr != 0 {
+        flags |= libc::MAP_FIXED_NOREPLACE;
+    }
+    let ptr = unsafe { libc::mmap(addr as *mut c_void, size, prot, flags, -1, 0) };
+    if ptr == libc::MAP_FAILED || (addr != 0 && ptr as u64 != addr) {
+        return None;
+    }
+    Some(ptr as u64)
+}
+
+/// Maps `size` bytes of device memory at the start of an aligned run of granules.
+fn map_device(size: usize) -> Option<u64> {
+    let span = size.next_multiple_of(GRANULARITY);
+    let mapped = map_anonymous(0, span + GRANULARITY, libc::PROT_READ | libc::PROT_WRITE)?;
+    let base = mapped.next_multiple_of(GRANULARITY as u64);
+    let head = (base - mapped) as usize;
+    if head > 0 {
+        unmap(mapped, head);
+    }
+    unmap(base + span as u64, GRANULARITY - head);
+    Some(base)
+}
+
+fn unmap(addr: u64, size: usize) {
+    unsafe { libc::munmap(addr as *mut c_void, size) };
+}
+
+/// Returns the bytes in use on `dev`, by device and managed allocations and physical VMM
+/// allocations.
+pub fn used(dev: CUdevice) -> usize {
+    STATE.lock().unwrap().used.get(&dev).copied().unwrap_or(0)
+}
+
+/// Returns the allocation containing `ptr`, with its start.
+pub fn allocation(ptr: CUdeviceptr) -> Option<(CUdeviceptr, Allocation)> {
+    STATE.lock().unwrap().containing(ptr)
+}
+
+/// Fails with `CUDA_ERROR_INVALID_VALUE` unless `[ptr, ptr + len)` lies within one allocation.
+pub fn check_range(ptr: CUdeviceptr, len: usize) -> Result<(), CUresult> {
+    if len == 0 {
+        return Ok(());
+    }
+    match allocation(ptr) {
+        Some((base, alloc)) if ptr + len as u64 <= base + alloc.size as u64 => Ok(()),
+        _ => Err(CUDA_ERROR_INVALID_VALUE),
+    }
+}
+
+/// Frees the allocations a destroyed context made.
+pub(crate) fn release_context(ctx: u64) {
+    let mut state = STATE.lock().unwrap();
+    let owned: Vec<_> = state
+        .allocations
+        .iter()
+        .filter(|(_, a)| a.context == Some(ctx))
+        .map(|(base, a)| (*base, *a))
+        .collect();
+    for (base, alloc) in owned {
+        state.allocations.remove(&base);
+        release(&mut state, base, alloc);
+    }
+}
+
+fn release(state: &mut State, base: u64, alloc: Allocation) {
+    match alloc.kind {
+        Kind::Device | Kind::Managed => {
+            state.refund(alloc.device, alloc.size);
+            unmap(base, alloc.size.next_multiple_of(GRANULARITY));
+        }
+        Kind::Host => unmap(base, alloc.size),
+        Kind::Registered | Kind::Mapped(_) => {}
+    }
+}
+
+/// Makes a new allocation owned by the current context.
+fn allocate(size: usize, kind: Kind) -> Result<u64, CUresult> {
+    let ctx = context::current()?;
+    let device = context::get(ctx).ok_or(CUDA_ERROR_INVALID_CONTEXT)?.device;
+    if size == 0 {
+        return Err(CUDA_ERROR_INVALID_VALUE);
+    }
+    let mut state = STATE.lock().unwrap();
+    if matches!(kind, Kind::Device | Kind::Managed) {
+        state.charge(device, size)?;
+    }
+    let mapped = match kind {
+        Kind::Device | Kind::Managed => map_device(size),
+        _ => map_anonymous(0, size, libc::PROT_READ | libc::PROT_WRITE),
+    };
+    let Some(ptr) = mapped else {
+        if matches!(kind, Kind::Device | Kind::Managed) {
+            state.refund(device, size);
+        }
+        return Err(CUDA_ERROR_OUT_OF_MEMORY);
+    };
+    let alloc = Allocation {
+        size,
+        kind,
+        device,
+        context: Some(ctx),
+    };
+    state.allocations.insert(ptr, alloc);
+    Ok(ptr)
+}
+
+/// Frees the allocation starting at `ptr`, if it is one of `kinds`.
+fn free(ptr: u64, kinds: &[Kind]) -> Result<(), CUresult> {
+    initialized()?;
+    let mut state = STATE.lock().unwrap();
+    let alloc = match state.allocations.get(&ptr) {
+        Some(alloc) if kinds.contains(&alloc.kind) => *alloc,
+        _ => return Err(CUDA_ERROR_INVALID_VALUE),
+    };
+    state.allocations.remove(&ptr);
+    release(&mut state, ptr, alloc);
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemAlloc_v2(dptr: *mut CUdeviceptr, bytesize: usize) -> CUresult {
+    api(|| {
+        let ptr = allocate(bytesize, Kind::Device)?;
+        unsafe { write(dptr, ptr) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemAllocAsync(
+    dptr: *mut CUdeviceptr,
+    bytesize: usize,
+    h_stream: CUstream,
+) -> CUresult {
+    api(|| {
+        streams::check(h_stream)?;
+        let ptr = allocate(bytesize, Kind::Device)?;
+        unsafe { write(dptr, ptr) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemAllocPitch_v2(
+    dptr: *mut CUdeviceptr,
+    p_pitch: *mut usize,
+    width_in_bytes: usize,
+    height: usize,
+    element_size_bytes: c_uint,
+) -> CUresult {
+    api(|| {
+        if !matches!(element_size_bytes, 4 | 8 | 16) || p_pitch.is_null() {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let pitch = width_in_bytes.next_multiple_of(PITCH_ALIGNMENT);
+        let ptr = allocate(pitch * height, Kind::Device)?;
+        unsafe { *p_pitch = pitch };
+        unsafe { write(dptr, ptr) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemAllocManaged(
+    dptr: *mut CUdeviceptr,
+    bytesize: usize,
+    _flags: c_uint,
+) -> CUresult {
+    api(|| {
+        let ptr = allocate(bytesize, Kind::Managed)?;
+        unsafe { write(dptr, ptr) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemFree_v2(dptr: CUdeviceptr) -> CUresult {
+    api(|| free(dptr, &[Kind::Device, Kind::Managed]))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemFreeAsync(dptr: CUdeviceptr, h_stream: CUstream) -> CUresult {
+    api(|| {
+        streams::check(h_stream)?;
+        free(dptr, &[Kind::Device, Kind::Managed])
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemHostAlloc(
+    pp: *mut *mut c_void,
+    bytesize: usize,
+    _flags: c_uint,
+) -> CUresult {
+    api(|| unsafe { host_alloc(pp, bytesize) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemAllocHost_v2(pp: *mut *mut c_void, bytesize: usize) -> CUresult {
+    api(|| unsafe { host_alloc(pp, bytesize) })
+}
+
+unsafe fn host_alloc(pp: *mut *mut c_void, bytesize: usize) -> Result<(), CUresult> {
+    let ptr = allocate(bytesize, Kind::Host)?;
+    unsafe { write(pp, ptr as *mut c_void) }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemFreeHost(p: *mut c_void) -> CUresult {
+    api(|| free(p as u64, &[Kind::Host]))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemHostGetDevicePointer_v2(
+    pdptr: *mut CUdeviceptr,
+    p: *mut c_void,
+    _flags: c_uint,
+) -> CUresult {
+    api(|| {
+        initialized()?;
+        match allocation(p as u64) {
+            Some((_, alloc)) if matches!(alloc.kind, Kind::Host | Kind::Registered) => unsafe {
+                write(pdptr, p as CUdeviceptr)
+            },
+            _ => Err(CUDA_ERROR_INVALID_VALUE),
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemHostRegister_v2(
+    p: *mut c_void,
+    bytesize: usize,
+    _flags: c_uint,
+) -> CUresult {
+    api(|| {
+        let ctx = context::current()?;
+        let device = context::get(ctx).ok_or(CUDA_ERROR_INVALID_CONTEXT)?.device;
+        if p.is_null() || bytesize == 0 {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let mut state = STATE.lock().unwrap();
+        if state.containing(p as u64).is_some() {
+            return Err(CUDA_ERROR_ALREADY_MAPPED);
+        }
+        let alloc = Allocation {
+            size: bytesize,
+            kind: Kind::Registered,
+            device,
+            context: Some(ctx),
+        };
+        state.allocations.insert(p as u64, alloc);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemHostUnregister(p: *mut c_void) -> CUresult {
+    api(|| match free(p as u64, &[Kind::Registered]) {
+        Err(CUDA_ERROR_INVALID_VALUE) => Err(CUDA_ERROR_NOT_MAPPED),
+        rc => rc,
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemGetInfo_v2(free: *mut usize, total: *mut usize) -> CUresult {
+    api(|| {
+        let device = context::current_device()?;
+        let total_mem = devices::total_mem(device);
+        unsafe { write(free, total_mem.saturating_sub(used(device)))? };
+        unsafe { write(total, total_mem) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemGetAddressRange_v2(
+    pbase: *mut CUdeviceptr,
+    psize: *mut usize,
+    dptr: CUdeviceptr,
+) -> CUresult {
+    api(|| {
+        initialized()?;
+        let (base, alloc) = allocation(dptr).ok_or(CUDA_ERROR_NOT_FOUND)?;
+        if !pbase.is_null() {
+            unsafe { *pbase = base };
+        }
+        if !psize.is_null() {
+            unsafe { *psize = alloc.size };
+        }
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuPointerGetAttribute(
+    data: *mut c_void,
+    attribute: c_uint,
+    ptr: CUdeviceptr,
+) -> CUresult {
+    api(|| {
+        initialized()?;
+        let (base, alloc) = allocation(ptr).ok_or(CUDA_ERROR_INVALID_VALUE)?;
+        let host = matches!(alloc.kind, Kind::Host | Kind::Registered);
+        unsafe {
+            match attribute {
+                CU_POINTER_ATTRIBUTE_CONTEXT => write(
+                    data as *mut CUcontext,
+                    alloc.context.unwrap_or(0) as CUcontext,
+                ),
+                CU_POINTER_ATTRIBUTE_MEMORY_TYPE => {
+                    let memory_type = if host {
+                        CU_MEMORYTYPE_HOST
+                    } else {
+                        CU_MEMORYTYPE_DEVICE
+                    };
+                    write(data as *mut c_uint, memory_type)
+                }
+                CU_POINTER_ATTRIBUTE_DEVICE_POINTER => write(data as *mut CUdeviceptr, ptr),
+                CU_POINTER_ATTRIBUTE_HOST_POINTER if host || alloc.kind == Kind::Managed => {
+                    write(data as *mut *mut c_void, ptr as *mut c_void)
+                }
+                CU_POINTER_ATTRIBUTE_IS_MANAGED => {
+                    write(data as *mut c_uint, (alloc.kind == Kind::Managed) as c_uint)
+                }
+                CU_POINTER_ATTRIBUTE_DEVICE_ORDINAL => write(data as *mut CUdevice, alloc.device),
+                CU_POINTER_ATTRIBUTE_RANGE_START_ADDR => write(data as *mut CUdeviceptr, base),
+                CU_POINTER_ATTRIBUTE_RANGE_SIZE => write(data as *mut usize, alloc.size),
+                CU_POINTER_ATTRIBUTE_MAPPED => write(data as *mut c_uint, 1),
+                _ => Err(CUDA_ERROR_INVALID_VALUE),
+            }
+        }
+    })
+}
+
+// ─── Copies and memsets ──────────────────────────────────────────────────────
+
+/// Copies between two ranges of this process, checking the sides that must be device memory.
+unsafe fn copy(
+    dst: u64,
+    dst_device: bool,
+    src: u64,
+    src_device: bool,
+    n: usize,
+) -> Result<(), CUresult> {
+    context::current()?;
+    if n == 0 {
+        return Ok(());
+    }
+    if dst_device {
+        check_range(dst, n)?;
+    }
+    if src_device {
+        check_range(src, n)?;
+    }
+    if dst == 0 || src == 0 {
+        return Err(CUDA_ERROR_INVALID_VALUE);
+    }
+    unsafe { std::ptr::copy(src as *const u8, dst as *mut u8, n) };
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemcpyHtoD_v2(
+    dst: CUdeviceptr,
+    src: *const c_void,
+    n: usize,
+) -> CUresult {
+    api(|| unsafe { copy(dst, true, src as u64, false, n) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemcpyDtoH_v2(dst: *mut c_void, src: CUdeviceptr, n: usize) -> CUresult {
+    api(|| unsafe { copy(dst as u64, false, src, true, n) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemcpyDtoD_v2(dst: CUdeviceptr, src: CUdeviceptr, n: usize) -> CUresult {
+    api(|| unsafe { copy(dst, true, src, true, n) })
+}
+
+/// Unified addressing: either side may be host or device memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemcpy(dst: CUdeviceptr, src: CUdeviceptr, n: usize) -> CUresult {
+    api(|| unsafe { copy(dst, false, src, false, n) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemcpyHtoDAsync_v2(
+    dst: CUdeviceptr,
+    src: *const c_void,
+    n: usize,
+    h_stream: CUstream,
+) -> CUresult {
+    api(|| {
+        streams::check(h_stream)?;
+        unsafe { copy(dst, true, src as u64, false, n) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemcpyDtoHAsync_v2(
+    dst: *mut c_void,
+    src: CUdeviceptr,
+    n: usize,
+    h_stream: CUstream,
+) -> CUresult {
+    api(|| {
+        streams::check(h_stream)?;
+        unsafe { copy(dst as u64, false, src, true, n) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemcpyDtoDAsync_v2(
+    dst: CUdeviceptr,
+    src: CUdeviceptr,
+    n: usize,
+    h_stream: CUstream,
+) -> CUresult {
+    api(|| {
+        streams::check(h_stream)?;
+        unsafe { copy(dst, true, src, true, n) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemcpyAsync(
+    dst: CUdeviceptr,
+    src: CUdeviceptr,
+    n: usize,
+    h_stream: CUstream,
+) -> CUresult {
+    api(|| {
+        streams::check(h_stream)?;
+        unsafe { copy(dst, false, src, false, n) }
+    })
+}
+
+/// Fills `n` elements of `T` at `dst`.
+fn fill<T: Copy>(dst: CUdeviceptr, value: T, n: usize) -> Result<(), CUresult> {
+    context::current()?;
+    let size = std::mem::size_of::<T>();
+    check_range(dst, n * size)?;
+    if !dst.is_multiple_of(size as u64) {
+        return Err(CUDA_ERROR_INVALID_VALUE);
+    }
+    let elements = unsafe { std::slice::from_raw_parts_mut(dst as *mut T, n) };
+    elements.fill(value);
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemsetD8_v2(dst: CUdeviceptr, uc: u8, n: usize) -> CUresult {
+    api(|| fill(dst, uc, n))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemsetD16_v2(dst: CUdeviceptr, us: u16, n: usize) -> CUresult {
+    api(|| fill(dst, us, n))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemsetD32_v2(dst: CUdeviceptr, ui: c_uint, n: usize) -> CUresult {
+    api(|| fill(dst, ui, n))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemsetD8Async(
+    dst: CUdeviceptr,
+    uc: u8,
+    n: usize,
+    h_stream: CUstream,
+) -> CUresult {
+    api(|| {
+        streams::check(h_stream)?;
+        fill(dst, uc, n)
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemsetD16Async(
+    dst: CUdeviceptr,
+    us: u16,
+    n: usize,
+    h_stream: CUstream,
+) -> CUresult {
+    api(|| {
+        streams::check(h_stream)?;
+        fill(dst, us, n)
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemsetD32Async(
+    dst: CUdeviceptr,
+    ui: c_uint,
+    n: usize,
+    h_stream: CUstream,
+) -> CUresult {
+    api(|| {
+        streams::check(h_stream)?;
+        fill(dst, ui, n)
+    })
+}
+
+// ─── Virtual memory management ───────────────────────────────────────────────
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemGetAllocationGranularity(
+    granularity: *mut usize,
+    prop: *const CUmemAllocationProp,
+    _option: c_uint,
+) -> CUresult {
+    api(|| {
+        initialized()?;
+        if prop.is_null() {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        unsafe { write(granularity, GRANULARITY) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemAddressReserve(
+    ptr: *mut CUdeviceptr,
+    size: usize,
+    _alignment: usize,
+    addr: CUdeviceptr,
+    _flags: u64,
+) -> CUresult {
+    api(|| {
+        initialized()?;
+        if size == 0 || !size.is_multiple_of(page_size()) || ptr.is_null() {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        // The address is a hint: fall back to anywhere when it is taken
+        let base = (addr != 0)
+            .then(|| map_anonymous(addr, size, libc::PROT_NONE))
+            .flatten()
+            .or_else(|| map_anonymous(0, size, libc::PROT_NONE))
+            .ok_or(CUDA_ERROR_OUT_OF_MEMORY)?;
+        STATE.lock().unwrap().reservations.insert(base, size);
+        unsafe { *ptr = base };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemAddressFree(ptr: CUdeviceptr, size: usize) -> CUresult {
+    api(|| {
+        initialized()?;
+        let mut state = STATE.lock().unwrap();
+        if state.reservations.get(&ptr) != Some(&size) {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let end = ptr + size as u64;
+        if state.allocations.range(ptr..end).next().is_some() {
+            // Still mapped
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        state.reservations.remove(&ptr);
+        unmap(ptr, size);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemCreate(
+    handle: *mut CUmemGenericAllocationHandle,
+    size: usize,
+    prop: *const CUmemAllocationProp,
+    _flags: u64,
+) -> CUresult {
+    api(|| {
+        initialized()?;
+        if prop.is_null() || handle.is_null() || size == 0 || !size.is_multiple_of(GRANULARITY) {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let prop = unsafe { &*prop };
+        if prop.location.type_ != CU_MEM_LOCATION_TYPE_DEVICE {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let device = prop.location.id;
+        devices::check(device)?;
+
+        let mut state = STATE.lock().unwrap();
+        state.charge(device, size)?;
+        let fd = unsafe { libc::memfd_create(c"cuda-fake".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 || unsafe { libc::ftruncate(fd, size as libc::off_t) } != 0 {
+            if fd >= 0 {
+                unsafe { libc::close(fd) };
+            }
+            state.refund(device, size);
+            return Err(CUDA_ERROR_OUT_OF_MEMORY);
+        }
+        let h = new_handle();
+        state.physical.insert(h, Physical { fd, size, device });
+        unsafe { *handle = h };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemRelease(handle: CUmemGenericAllocationHandle) -> CUresult {
+    api(|| {
+        initialized()?;
+        let mut state = STATE.lock().unwrap();
+        let physical = state
+            .physical
+            .remove(&handle)
+            .ok_or(CUDA_ERROR_INVALID_VALUE)?;
+        // Mappings keep the memory alive until they are unmapped, as on a real device
+        unsafe { libc::close(physical.fd) };
+        state.refund(physical.device, physical.size);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemMap(
+    ptr: CUdeviceptr,
+    size: usize,
+    offset: usize,
+    handle: CUmemGenericAllocationHandle,
+    _flags: u64,
+) -> CUresult {
+    api(|| {
+        initialized()?;
+        let mut state = STATE.lock().unwrap();
+        let end = ptr + size as u64;
+        let reserved = state
+            .reservations
+            .range(..=ptr)
+            .next_back()
+            .is_some_and(|(base, len)| end <= base + *len as u64);
+        if !reserved || size == 0 || !size.is_multiple_of(GRANULARITY) {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let physical = state
+            .physical
+            .get(&handle)
+            .ok_or(CUDA_ERROR_INVALID_VALUE)?;
+        if offset + size > physical.size {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let overlaps =
+            state.containing(ptr).is_some() || state.allocations.range(ptr..end).next().is_some();
+        if overlaps {
+            return Err(CUDA_ERROR_ALREADY_MAPPED);
+        }
+        let mapped = unsafe {
+            libc::mmap(
+                ptr as *mut c_void,
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_FIXED,
+                physical.fd,
+                offset as libc::off_t,
+            )
+        };
+        if mapped == libc::MAP_FAILED {
+            return Err(CUDA_ERROR_OUT_OF_MEMORY);
+        }
+        let alloc = Allocation {
+            size,
+            kind: Kind::Mapped(handle),
+            device: physical.device,
+            context: None,
+        };
+        state.allocations.insert(ptr, alloc);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuMemUnmap(ptr: CUdeviceptr, size: usize) -> CUresult {
+    api(|| {
+        initialized()?;
+        let mut state = STATE.lock().unwrap();
+        match state.allocations.get(&ptr) {
+            Some(alloc) if matches!(alloc.kind, Kind::Mapped(_)) && alloc.size == size => {}
+            _ => return Err(CUDA_ERROR_INVALID_VALUE),
+        }
+        // Put the reservation back in place of the mapping
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | libc::MAP_FIXED;
+        unsafe { libc::mmap(ptr as *mut c_void, size, libc::PROT_NONE, flags, -1, 0) };
+        state.allocations.remove(&ptr);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuMemSetAccess(
+    ptr: CUdeviceptr,
+    size: usize,
+    desc: *const CUmemAccessDesc,
+    count: usize,
+) -> CUresult {
+    api(|| {
+        initialized()?;
+        if desc.is_null() || count == 0 {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        // Mappings are always readable and writable; only check that the range is mapped
+        let mut cursor = ptr;
+        let end = ptr + size as u64;
+        while cursor < end {
+            match allocation(cursor) {
+                Some((base, alloc)) if matches!(alloc.kind, Kind::Mapped(_)) => {
+                    cursor = base + alloc.size as u64;
+                }
+                _ => return Err(CUDA_ERROR_INVALID_VALUE),
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,332 @@
+//! Module loading and function lookup.
+//!
+//! Loaded images are kept, and the kernels they define are read from them: from the `.nv.info`
+//! sections of cubins, the `.entry` directives of PTX, and both in fatbins. Looking up a kernel
+//! the image does not define fails with `CUDA_ERROR_NOT_FOUND`. Images that cannot be parsed are
+//! accepted, and then any name can be looked up in them. `cuFuncGetParamInfo` reads parameter
+//! layouts from the same places.
+
+use crate::context;
+use crate::{CUDA_ERROR_FILE_NOT_FOUND, CUDA_ERROR_INVALID_HANDLE, CUDA_ERROR_INVALID_IMAGE};
+use crate::{api, new_handle, write};
+use cuda_interposer_common::cubin::Cubin;
+use cuda_interposer_common::fatbin::{EntryKind, Fatbin};
+use cuda_interposer_common::ffi::*;
+use cuda_interposer_common::image::image_bytes;
+use cuda_interposer_common::kernel::KernelName;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{CStr, CString, c_char, c_uint, c_void};
+use std::sync::{Arc, Mutex};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+#[derive(Debug)]
+pub struct Module {
+    pub context: u64,
+    pub image: Vec<u8>,
+    /// The kernels the image defines, if it could be parsed.
+    pub kernels: Option<BTreeSet<String>>,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub module: u64,
+    pub name: KernelName,
+    c_name: CString,
+}
+
+static MODULES: Lazy<Mutex<HashMap<u64, Module>>> = Lazy::new(Default::default);
+static FUNCTIONS: Lazy<Mutex<HashMap<u64, Arc<Function>>>> = Lazy::new(Default::default);
+
+/// Returns the function `func` refers to, if its module is loaded.
+pub fn function(func: CUfunction) -> Option<Arc<Function>> {
+    FUNCTIONS.lock().unwrap().get(&(func as u64)).cloned()
+}
+
+/// Returns the names of the kernels a PTX, cubin or fatbin image defines, or `None` if it is
+/// none of those.
+pub fn kernel_names(image: &[u8]) -> Option<BTreeSet<String>> {
+    if image.starts_with(ELF_MAGIC) {
+        return cubin_kernels(image);
+    }
+    if let Ok(fatbin) = Fatbin::parse(image) {
+        let mut names = BTreeSet::new();
+        for entry in fatbin.entries().flatten() {
+            let Ok(data) = entry.data() else {
+                continue;
+            };
+            match entry.kind {
+                EntryKind::Cubin => names.extend(cubin_kernels(&data).unwrap_or_default()),
+                EntryKind::Ptx => names.extend(ptx_kernels(&String::from_utf8_lossy(&data))),
+                _ => {}
+            }
+        }
+        return Some(names);
+    }
+    let text = std::str::from_utf8(image).ok()?;
+    text.contains(".version").then(|| ptx_kernels(text))
+}
+
+fn cubin_kernels(image: &[u8]) -> Option<BTreeSet<String>> {
+    let kernels = Cubin::parse(image).and_then(|c| c.kernels()).ok()?;
+    Some(kernels.into_keys().collect())
+}
+
+/// The names after the `.entry` directives of a PTX module.
+fn ptx_kernels(ptx: &str) -> BTreeSet<String> {
+    ptx.split(".entry")
+        .skip(1)
+        .filter_map(|rest| {
+            let name = rest.trim_start();
+            let end = name.find(|c: char| c == '(' || c.is_whitespace())?;
+            Some(name[..end].to_string())
+        })
+        .collect()
+}
+
+/// Returns the offset and size of each parameter of `function`, if its image describes them.
+pub fn params(function: &Function) -> Option<Vec<(usize, usize)>> {
+    let modules = MODULES.lock().unwrap();
+    param_layout(
+        &modules.get(&function.module)?.image,
+        &function.name.mangled,
+    )
+}
+
+/// Returns the offset and size of each parameter of the kernel `name` in `image`, from the
+/// `.nv.info` sections of cubins and the `.param` declarations of PTX.
+fn param_layout(image: &[u8], name: &str) -> Option<Vec<(usize, usize)>> {
+    if image.starts_with(ELF_MAGIC) {
+        return cubin_params(image, name);
+    }
+    if let Ok(fatbin) = Fatbin::parse(image) {
+        return fatbin.entries().flatten().find_map(|entry| {
+            let data = entry.data().ok()?;
+            match entry.kind {
+                EntryKind::Cubin => cubin_params(&data, name),
+                EntryKind::Ptx => ptx_params(&String::from_utf8_lossy(&data), name),
+                _ => None,
+            }
+        });
+    }
+    ptx_params(std::str::from_utf8(image).ok()?, name)
+}
+
+fn cubin_params(image: &[u8], name: &str) -> Option<Vec<(usize, usize)>> {
+    let kernels = Cubin::parse(image).and_then(|c| c.kernels()).ok()?;
+    let params = &kernels.get(name)?.params.params;
+    Some(
+        params
+            .iter()
+            .map(|p| (p.offset as usize, p.size as usize))
+            .collect(),
+    )
+}
+
+/// Lays out the `.param` declarations of the PTX entry `name`, such as `.param .u64 p` or
+/// `.param .align 8 .b8 p[16]`, each aligned to its `.align` or else its element size.
+fn ptx_params(ptx: &str, name: &str) -> Option<Vec<(usize, usize)>> {
+    let rest = ptx.split(".entry").skip(1).find_map(|rest| {
+        let rest = rest.trim_start().strip_prefix(name)?;
+        rest.trim_start().strip_prefix('(')
+    })?;
+    let list = &rest[..rest.find(')')?];
+    let mut params = Vec::new();
+    let mut offset = 0usize;
+    for decl in list.split(',').filter(|d| !d.trim().is_empty()) {
+        let words: Vec<&str> = decl.split_whitespace().collect();
+        let element = words.iter().find_map(|w| {
+            let bits = w.strip_prefix('.')?.strip_prefix(['u', 's', 'b', 'f'])?;
+            bits.parse::<usize>().ok()
+        })? / 8;
+        let align = words
+            .iter()
+            .position(|w| *w == ".align")
+            .and_then(|i| words.get(i + 1)?.parse().ok())
+            .unwrap_or(element);
+        let count = words
+            .last()
+            .and_then(|w| w.split_once('['))
+            .map_or(Some(1), |(_, n)| n.trim_end_matches(']').parse().ok())?;
+        offset = offset.next_multiple_of(align.max(1));
+        params.push((offset, element * count));
+        offset += element * count;
+    }
+    Some(params)
+}
+
+/// Loads `image` into the current context.
+fn load(module: *mut CUmodule, image: &[u8]) -> Result<(), CUresult> {
+    let context = context::current()?;
+    if module.is_null() {
+        return Err(CUDA_ERROR_INVALID_VALUE);
+    }
+    if image.starts_with(ELF_MAGIC) && cubin_kernels(image).is_none() {
+        return Err(CUDA_ERROR_INVALID_IMAGE);
+    }
+    let handle = new_handle();
+    let loaded = Module {
+        context,
+        image: image.to_vec(),
+        kernels: kernel_names(image),
+    };
+    MODULES.lock().unwrap().insert(handle, loaded);
+    unsafe { *module = handle as CUmodule };
+    Ok(())
+}
+
+/// Loads an image passed by address, sized from its headers.
+unsafe fn load_data(module: *mut CUmodule, image: *const c_void) -> Result<(), CUresult> {
+    let image = unsafe { image_bytes(image) }.ok_or(CUDA_ERROR_INVALID_VALUE)?;
+    load(module, image)
+}
+
+/// Unloads the modules of a destroyed context.
+pub(crate) fn release_context(ctx: u64) {
+    let mut modules = MODULES.lock().unwrap();
+    let unloaded: Vec<u64> = modules
+        .iter()
+        .filter(|(_, m)| m.context == ctx)
+        .map(|(h, _)| *h)
+        .collect();
+    for module in &unloaded {
+        modules.remove(module);
+    }
+    FUNCTIONS
+        .lock()
+        .unwrap()
+        .retain(|_, f| !unloaded.contains(&f.module));
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuModuleLoadData(module: *mut CUmodule, image: *const c_void) -> CUresult {
+    api(|| unsafe { load_data(module, image) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuModuleLoadDataEx(
+    module: *mut CUmodule,
+    image: *const c_void,
+    _num_options: c_uint,
+    _options: *mut c_uint,
+    _option_values: *mut *mut c_void,
+) -> CUresult {
+    api(|| unsafe { load_data(module, image) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuModuleLoadFatBinary(
+    module: *mut CUmodule,
+    fat_cubin: *const c_void,
+) -> CUresult {
+    api(|| unsafe { load_data(module, fat_cubin) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuModuleLoad(module: *mut CUmodule, fname: *const c_char) -> CUresult {
+    api(|| {
+        if fname.is_null() {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let path = unsafe { CStr::from_ptr(fname) }
+            .to_string_lossy()
+            .into_owned();
+        let image = std::fs::read(path).map_err(|_| CUDA_ERROR_FILE_NOT_FOUND)?;
+        load(module, &image)
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuModuleUnload(hmod: CUmodule) -> CUresult {
+    api(|| {
+        context::current()?;
+        let module = hmod as u64;
+        MODULES
+            .lock()
+            .unwrap()
+            .remove(&module)
+            .ok_or(CUDA_ERROR_INVALID_HANDLE)?;
+        FUNCTIONS.lock().unwrap().retain(|_, f| f.module != module);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuModuleGetFunction(
+    hfunc: *mut CUfunction,
+    hmod: CUmodule,
+    name: *const c_char,
+) -> CUresult {
+    api(|| {
+        context::current()?;
+        if hfunc.is_null() || name.is_null() {
+            return Err(CUDA_ERROR_INVALID_VALUE);
+        }
+        let name = unsafe { CStr::from_ptr(name) };
+        let module = hmod as u64;
+        let modules = MODULES.lock().unwrap();
+        let loaded = modules.get(&module).ok_or(CUDA_ERROR_INVALID_HANDLE)?;
+        let mangled = name.to_string_lossy();
+        if loaded
+            .kernels
+            .as_ref()
+            .is_some_and(|k| !k.contains(mangled.as_ref()))
+        {
+            return Err(CUDA_ERROR_NOT_FOUND);
+        }
+        drop(modules);
+
+        // The same kernel always has the same handle
+        let mut functions = FUNCTIONS.lock().unwrap();
+        let existing = functions
+            .iter()
+            .find(|(_, f)| f.module == module && f.c_name.as_c_str() == name)
+            .map(|(h, _)| *h);
+        let handle = existing.unwrap_or_else(|| {
+            let handle = new_handle();
+            let function = Function {
+                module,
+                name: KernelName::new(mangled.as_ref()),
+                c_name: name.to_owned(),
+            };
+            functions.insert(handle, Arc::new(function));
+            handle
+        });
+        unsafe { *hfunc = handle as CUfunction };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuFuncGetName(name: *mut *const c_char, hfunc: CUfunction) -> CUresult {
+    api(|| {
+        let function = function(hfunc).ok_or(CUDA_ERROR_INVALID_HANDLE)?;
+        // Functions live as long as their module; the name is interned with them
+        unsafe { write(name, function.c_name.as_ptr()) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuFuncGetModule(hmod: *mut CUmodule, hfunc: CUfunction) -> CUresult {
+    api(|| {
+        let function = function(hfunc).ok_or(CUDA_ERROR_INVALID_HANDLE)?;
+        unsafe { write(hmod, function.module as CUmodule) }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuFuncGetParamInfo(
+    func: CUfunction,
+    param_index: usize,
+    param_offset: *mut usize,
+    param_size: *mut usize,
+) -> CUresult {
+    api(|| {
+        let function = function(func).ok_or(CUDA_ERROR_INVALID_HANDLE)?;
+        let params = params(&function).ok_or(CUDA_ERROR_NOT_SUPPORTED)?;
+        let &(offset, size) = params.get(param_index).ok_or(CUDA_ERROR_INVALID_VALUE)?;
+        unsafe { write(param_offset, offset)? };
+        unsafe { write(param_size, size) }
+    })
+}
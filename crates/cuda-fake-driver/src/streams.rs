@@ -0,0 +1,263 @@
+//! Streams and events.
+//!
+//! Work runs as it is issued, so streams are only validated and every event is complete once
+//! recorded. Events record the host time, which is what `cuEventElapsedTime` reports.
+
+use crate::context;
+use crate::{CUDA_ERROR_INVALID_HANDLE, api, new_handle, write};
+use cuda_interposer_common::ffi::*;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ffi::{c_int, c_uint, c_void};
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub const LEAST_PRIORITY: c_int = 0;
+pub const GREATEST_PRIORITY: c_int = -5;
+
+const CU_EVENT_DISABLE_TIMING: u32 = 0x2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stream {
+    pub context: u64,
+    pub flags: u32,
+    pub priority: c_int,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub context: u64,
+    pub flags: u32,
+    pub recorded: Option<Instant>,
+}
+
+static STREAMS: Lazy<Mutex<HashMap<u64, Stream>>> = Lazy::new(Default::default);
+static EVENTS: Lazy<Mutex<HashMap<u64, Event>>> = Lazy::new(Default::default);
+
+/// Fails unless `stream` is a live stream or a default stream with a current context.
+pub fn check(stream: CUstream) -> Result<(), CUresult> {
+    if stream.is_null() || stream == CU_STREAM_LEGACY || stream == CU_STREAM_PER_THREAD {
+        return context::current().map(drop);
+    }
+    context::current()?;
+    match STREAMS.lock().unwrap().contains_key(&(stream as u64)) {
+        true => Ok(()),
+        false => Err(CUDA_ERROR_INVALID_HANDLE),
+    }
+}
+
+/// Returns the event `event` refers to, if it is alive.
+pub fn event(event: CUevent) -> Option<Event> {
+    EVENTS.lock().unwrap().get(&(event as u64)).copied()
+}
+
+/// Forgets the streams and events of a destroyed context.
+pub(crate) fn release_context(ctx: u64) {
+    STREAMS.lock().unwrap().retain(|_, s| s.context != ctx);
+    EVENTS.lock().unwrap().retain(|_, e| e.context != ctx);
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuStreamCreateWithPriority(
+    ph_stream: *mut CUstream,
+    flags: c_uint,
+    priority: c_int,
+) -> CUresult {
+    api(|| unsafe { create_stream(ph_stream, flags, priority) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuStreamCreate(ph_stream: *mut CUstream, flags: c_uint) -> CUresult {
+    api(|| unsafe { create_stream(ph_stream, flags, LEAST_PRIORITY) })
+}
+
+unsafe fn create_stream(
+    ph_stream: *mut CUstream,
+    flags: c_uint,
+    priority: c_int,
+) -> Result<(), CUresult> {
+    let stream = Stream {
+        context: context::current()?,
+        flags,
+        priority: priority.clamp(GREATEST_PRIORITY, LEAST_PRIORITY),
+    };
+    let handle = new_handle();
+    unsafe { write(ph_stream, handle as CUstream)? };
+    STREAMS.lock().unwrap().insert(handle, stream);
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuStreamDestroy_v2(stream: CUstream) -> CUresult {
+    api(|| {
+        context::current()?;
+        match STREAMS.lock().unwrap().remove(&(stream as u64)) {
+            Some(_) => Ok(()),
+            None => Err(CUDA_ERROR_INVALID_HANDLE),
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuStreamSynchronize(stream: CUstream) -> CUresult {
+    api(|| check(stream))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuStreamQuery(stream: CUstream) -> CUresult {
+    api(|| check(stream))
+}
+
+/// Returns the stream `stream` refers to, with the defaults for the default streams.
+fn lookup(stream: CUstream) -> Result<Stream, CUresult> {
+    check(stream)?;
+    Ok(match STREAMS.lock().unwrap().get(&(stream as u64)) {
+        Some(s) => *s,
+        None => Stream {
+            context: context::current()?,
+            flags: 0,
+            priority: LEAST_PRIORITY,
+        },
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuStreamGetPriority(stream: CUstream, priority: *mut c_int) -> CUresult {
+    api(|| unsafe { write(priority, lookup(stream)?.priority) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuStreamGetFlags(stream: CUstream, flags: *mut c_uint) -> CUresult {
+    api(|| unsafe { write(flags, lookup(stream)?.flags) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuStreamGetCtx(stream: CUstream, pctx: *mut CUcontext) -> CUresult {
+    api(|| unsafe { write(pctx, lookup(stream)?.context as CUcontext) })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuStreamWaitEvent(stream: CUstream, event: CUevent, _flags: c_uint) -> CUresult {
+    api(|| {
+        check(stream)?;
+        self::event(event).ok_or(CUDA_ERROR_INVALID_HANDLE)?;
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuLaunchHostFunc(
+    stream: CUstream,
+    func: Option<unsafe extern "C" fn(user_data: *mut c_void)>,
+    user_data: *mut c_void,
+) -> CUresult {
+    api(|| {
+        check(stream)?;
+        let func = func.ok_or(CUDA_ERROR_INVALID_VALUE)?;
+        unsafe { func(user_data) };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuEventCreate(ph_event: *mut CUevent, flags: c_uint) -> CUresult {
+    api(|| {
+        let event = Event {
+            context: context::current()?,
+            flags,
+            recorded: None,
+        };
+        let handle = new_handle();
+        unsafe { write(ph_event, handle as CUevent)? };
+        EVENTS.lock().unwrap().insert(handle, event);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuEventDestroy_v2(event: CUevent) -> CUresult {
+    api(|| match EVENTS.lock().unwrap().remove(&(event as u64)) {
+        Some(_) => Ok(()),
+        None => Err(CUDA_ERROR_INVALID_HANDLE),
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuEventRecordWithFlags(
+    event: CUevent,
+    stream: CUstream,
+    _flags: c_uint,
+) -> CUresult {
+    api(|| record(event, stream))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuEventRecord(event: CUevent, stream: CUstream) -> CUresult {
+    api(|| record(event, stream))
+}
+
+fn record(event: CUevent, stream: CUstream) -> Result<(), CUresult> {
+    check(stream)?;
+    let mut events = EVENTS.lock().unwrap();
+    let event = events
+        .get_mut(&(event as u64))
+        .ok_or(CUDA_ERROR_INVALID_HANDLE)?;
+    event.recorded = Some(Instant::now());
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuEventQuery(event: CUevent) -> CUresult {
+    api(|| {
+        self::event(event)
+            .map(drop)
+            .ok_or(CUDA_ERROR_INVALID_HANDLE)
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn cuEventSynchronize(event: CUevent) -> CUresult {
+    api(|| {
+        self::event(event)
+            .map(drop)
+            .ok_or(CUDA_ERROR_INVALID_HANDLE)
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuEventElapsedTime_v2(
+    ms: *mut f32,
+    start: CUevent,
+    end: CUevent,
+) -> CUresult {
+    api(|| unsafe { write(ms, elapsed_ms(start, end)?) })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cuEventElapsedTime(
+    ms: *mut f32,
+    start: CUevent,
+    end: CUevent,
+) -> CUresult {
+    api(|| unsafe { write(ms, elapsed_ms(start, end)?) })
+}
+
+/// The time between two recorded events, which fails for events created without timing.
+fn elapsed_ms(start: CUevent, end: CUevent) -> Result<f32, CUresult> {
+    let (start, end) = match (event(start), event(end)) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Err(CUDA_ERROR_INVALID_HANDLE),
+    };
+    if (start.flags | end.flags) & CU_EVENT_DISABLE_TIMING != 0 {
+        return Err(CUDA_ERROR_INVALID_HANDLE);
+    }
+    let (Some(start), Some(end)) = (start.recorded, end.recorded) else {
+        return Err(CUDA_ERROR_INVALID_HANDLE);
+    };
+    let elapsed = if end >= start {
+        end.duration_since(start).as_secs_f32()
+    } else {
+        -start.duration_since(end).as_secs_f32()
+    };
+    Ok(elapsed * 1e3)
+}
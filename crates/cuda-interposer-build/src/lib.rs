@@ -1,5 +1,8 @@
 use anyhow::{Context, Result};
 use cfg_expr::{Expression, Predicate};
+use cuda_interposer_tables::calls::{followed, followed_array};
+use cuda_interposer_tables::driver_symbols::DRIVER_SYMBOLS;
+use cuda_interposer_tables::handles::{HandleKind, released_by};
 use std::collections::HashMap;
 use std::env;
 use std::fs;
@@ -56,8 +59,10 @@ impl InterposerBuilder {
         let target_dir = find_target_dir(&self.out_dir);
 
         // Scan both driver and runtime prototypes
-        let all_protos =
-            scan_bindgen_prototypes(&target_dir, &["driver_internal_sys.rs", "runtime_sys.rs"])?;
+        let bindgen_files = ["driver_internal_sys.rs", "runtime_sys.rs"];
+        let mut all_protos = scan_bindgen_prototypes(&target_dir, &bindgen_files)?;
+        all_protos.sort_by(|a, b| a.name.cmp(&b.name));
+        let types = scan_bindgen_types(&target_dir, &bindgen_files)?;
 
         if all_protos.is_empty() {
             println!(
@@ -65,24 +70,42 @@ impl InterposerBuilder {
             );
         }
 
+        let mut driver_calls = Vec::new();
+        let mut runtime_calls = Vec::new();
         let mut driver_passthroughs = Vec::new();
         let mut runtime_passthroughs = Vec::new();
 
         for proto in all_protos {
+            // Distinguish between Runtime (cuda*) and Driver (cu*)
+            // Note: 'cuda' technically starts with 'cu', so we check cuda first.
+            let runtime = proto.name.starts_with("cuda") || proto.name.starts_with("__cuda");
+            if runtime {
+                runtime_calls.push(proto.clone());
+            } else if proto.name.starts_with("cu") {
+                driver_calls.push(proto.clone());
+            }
+
             // Skip functions explicitly hooked by the user
             if manual_hooks.contains_key(&proto.name) {
                 continue;
             }
 
-            // Distinguish between Runtime (cuda*) and Driver (cu*)
-            // Note: 'cuda' technically starts with 'cu', so we check cuda first.
-            if proto.name.starts_with("cuda") || proto.name.starts_with("__cuda") {
+            if runtime {
                 runtime_passthroughs.push(proto);
             } else if proto.name.starts_with("cu") {
                 driver_passthroughs.push(proto);
             }
         }
 
+        // Every call, hooked or not, as data
+        emit_calls(
+            &self.out_dir.join("calls_driver.rs"),
+            &driver_calls,
+            &types,
+            Some(DRIVER_PARAMS),
+        )?;
+        emit_calls(&self.out_dir.join("calls_runtime.rs"), &runtime_calls, &types, None)?;
+
         emit_passthroughs(
             &self.out_dir.join("passthroughs_driver.rs"),
             &driver_passthroughs,
@@ -97,6 +120,54 @@ impl InterposerBuilder {
     }
 }
 
+/// Builds a stand-in driver library. Every driver API the crate does not define itself with
+/// `#[unsafe(no_mangle)]` gets a stub returning `CUDA_ERROR_NOT_SUPPORTED`, so the library
+/// exports the same symbols as `libcuda.so`.
+pub struct StubDriverBuilder {
+    src_dir: PathBuf,
+    out_dir: PathBuf,
+}
+
+impl Default for StubDriverBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StubDriverBuilder {
+    pub fn new() -> Self {
+        let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+        Self {
+            src_dir: manifest_dir.join("src"),
+            out_dir: PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR not set")),
+        }
+    }
+
+    /// Sets the source directory to scan for implemented functions (default: ./src)
+    pub fn with_src(mut self, path: impl Into<PathBuf>) -> Self {
+        self.src_dir = path.into();
+        self
+    }
+
+    /// Writes the stubs to `unsupported_driver.rs` in `OUT_DIR`, to be included where
+    /// `CUresult` and `CUDA_ERROR_NOT_SUPPORTED` are in scope.
+    ///
+    /// The driver APIs are taken from `cuda_interposer_tables::driver_symbols`, so the stubs are
+    /// the same whether or not the CUDA SDK is installed.
+    pub fn build(self) -> Result<()> {
+        println!("cargo:rerun-if-changed={}", self.src_dir.display());
+
+        let implemented = scan_exported_functions(&self.src_dir)?;
+        let names: Vec<&str> = DRIVER_SYMBOLS
+            .iter()
+            .copied()
+            .filter(|name| !implemented.iter().any(|i| i == name))
+            .collect();
+
+        emit_unsupported(&self.out_dir.join("unsupported_driver.rs"), &names)
+    }
+}
+
 #[derive(Clone, Debug)]
 struct Prototype {
     name: String,
@@ -165,6 +236,15 @@ fn scan_local_hooks(root: &Path) -> Result<HashMap<String, String>> {
         )"#,
     )?;
 
+    let hook_set_query = tree_sitter::Query::new(
+        &tree_sitter_rust::LANGUAGE.into(),
+        r#"(macro_invocation
+        macro: [
+            (identifier) @macro_name
+            (scoped_identifier name: (identifier) @macro_name)
+        ])"#,
+    )?;
+
     let func_query = tree_sitter::Query::new(
         &tree_sitter_rust::LANGUAGE.into(),
         r#"(function_item
@@ -181,6 +261,26 @@ fn scan_local_hooks(root: &Path) -> Result<HashMap<String, String>> {
                 .context("Failed to parse rust file")?;
             let mut cursor = tree_sitter::QueryCursor::new();
 
+            // Built-in hook sets, e.g. `install_memory_hooks!()`
+            let mut set_matches =
+                cursor.matches(&hook_set_query, tree.root_node(), src.as_bytes());
+            while let Some(m) = set_matches.next() {
+                let name_node = m.captures[0].node;
+                let Some(set) = cuda_interposer_tables::hook_sets::find(get_text(&src, name_node)) else {
+                    continue;
+                };
+                let invocation = name_node
+                    .parent()
+                    .filter(|p| p.kind() == "macro_invocation")
+                    .or_else(|| name_node.parent().and_then(|p| p.parent()));
+                if invocation.is_some_and(|n| !is_node_cfg_enabled(n, &src)) {
+                    continue;
+                }
+                for sym in set.symbols {
+                    hooks.insert(sym.to_string(), sym.to_string());
+                }
+            }
+
             let mut matches = cursor.matches(&macro_query, tree.root_node(), src.as_bytes());
 
             while let Some(m) = matches.next() {
@@ -221,12 +321,55 @@ fn scan_local_hooks(root: &Path) -> Result<HashMap<String, String>> {
     Ok(hooks)
 }
 
-fn scan_bindgen_prototypes(root: &Path, filenames: &[&str]) -> Result<Vec<Prototype>> {
-    let mut prototypes = HashMap::new();
+/// Returns the functions defined with `#[unsafe(no_mangle)]` (or `#[no_mangle]`) under `root`.
+fn scan_exported_functions(root: &Path) -> Result<Vec<String>> {
+    let mut functions = Vec::new();
     let mut parser = create_rust_parser();
 
-    // Find the bindgen output files in target dir
-    let valid_files: Vec<PathBuf> = WalkDir::new(root)
+    let func_query = tree_sitter::Query::new(
+        &tree_sitter_rust::LANGUAGE.into(),
+        r#"(function_item
+        name: (identifier) @func_name
+        )"#,
+    )?;
+
+    for entry in WalkDir::new(root) {
+        let entry = entry?;
+        if entry.path().extension().is_some_and(|e| e == "rs") {
+            let src = fs::read_to_string(entry.path())?;
+            let tree = parser
+                .parse(&src, None)
+                .context("Failed to parse rust file")?;
+            let mut cursor = tree_sitter::QueryCursor::new();
+            let mut matches = cursor.matches(&func_query, tree.root_node(), src.as_bytes());
+
+            while let Some(m) = matches.next() {
+                let name_node = m.captures[0].node;
+                let Some(item) = name_node.parent() else {
+                    continue;
+                };
+                if !is_node_cfg_enabled(item, &src) {
+                    continue;
+                }
+                // The attributes are the siblings right before the item
+                let mut exported = false;
+                let mut sib = item.prev_sibling();
+                while let Some(node) = sib.filter(|n| n.kind() == "attribute_item" || n.kind() == "line_comment") {
+                    exported |= get_text(&src, node).contains("no_mangle");
+                    sib = node.prev_sibling();
+                }
+                if exported {
+                    functions.push(get_text(&src, name_node).to_string());
+                }
+            }
+        }
+    }
+    Ok(functions)
+}
+
+/// Finds the bindgen output files in target dir
+fn find_bindgen_files(root: &Path, filenames: &[&str]) -> Vec<PathBuf> {
+    WalkDir::new(root)
         .into_iter()
         .filter_map(|e| e.ok())
         .filter(|e| {
@@ -235,9 +378,14 @@ fn scan_bindgen_prototypes(root: &Path, filenames: &[&str]) -> Result<Vec<Protot
                 .any(|f| e.file_name().to_string_lossy() == *f)
         })
         .map(|e| e.path().to_path_buf())
-        .collect();
+        .collect()
+}
+
+fn scan_bindgen_prototypes(root: &Path, filenames: &[&str]) -> Result<Vec<Prototype>> {
+    let mut prototypes = HashMap::new();
+    let mut parser = create_rust_parser();
 
-    for file in valid_files {
+    for file in find_bindgen_files(root, filenames) {
         let src = fs::read_to_string(&file)?;
         let tree = parser.parse(&src, None).context("Parse bindgen file")?;
 
@@ -284,6 +432,251 @@ fn scan_bindgen_prototypes(root: &Path, filenames: &[&str]) -> Result<Vec<Protot
     Ok(prototypes.into_values().collect())
 }
 
+/// The type aliases and enums bindgen declared, for deciding how each argument serializes.
+#[derive(Default, Debug)]
+struct TypeTable {
+    aliases: HashMap<String, String>,
+    /// Enum names and their `#[repr]`.
+    enums: HashMap<String, String>,
+}
+
+fn scan_bindgen_types(root: &Path, filenames: &[&str]) -> Result<TypeTable> {
+    let mut types = TypeTable::default();
+    let mut parser = create_rust_parser();
+
+    let query = tree_sitter::Query::new(
+        &tree_sitter_rust::LANGUAGE.into(),
+        r#"[
+            (type_item name: (type_identifier) @alias type: (_) @target)
+            (use_declaration argument: (use_as_clause path: (_) @target alias: (identifier) @alias))
+            (enum_item name: (type_identifier) @enum)
+        ]"#,
+    )?;
+    let capture = |name| query.capture_index_for_name(name).unwrap();
+    let (alias_idx, target_idx) = (capture("alias"), capture("target"));
+
+    for file in find_bindgen_files(root, filenames) {
+        let src = fs::read_to_string(&file)?;
+        let tree = parser.parse(&src, None).context("Parse bindgen file")?;
+        let mut cursor = tree_sitter::QueryCursor::new();
+        let mut matches = cursor.matches(&query, tree.root_node(), src.as_bytes());
+
+        while let Some(m) = matches.next() {
+            // Typedefs of enums are `pub use self::X as Y;`, the others `pub type Y = X;`
+            let alias = m.captures.iter().find(|c| c.index == alias_idx);
+            let target = m.captures.iter().find(|c| c.index == target_idx);
+            if let (Some(alias), Some(target)) = (alias, target) {
+                types.aliases.insert(
+                    get_text(&src, alias.node).to_string(),
+                    get_text(&src, target.node).to_string(),
+                );
+                continue;
+            }
+
+            // Bindgen puts `#[repr(u32)]` among the attributes before the enum
+            let name = get_text(&src, m.captures[0].node).to_string();
+            let mut repr = "u32".to_string();
+            let mut sib = m.captures[0].node.parent().and_then(|n| n.prev_sibling());
+            while let Some(node) = sib.filter(|n| n.kind() == "attribute_item") {
+                let attr = get_text(&src, node);
+                if let Some(r) = attr.strip_prefix("#[repr(").and_then(|a| a.strip_suffix(")]")) {
+                    repr = r.to_string();
+                }
+                sib = node.prev_sibling();
+            }
+            types.enums.insert(name, repr);
+        }
+    }
+    Ok(types)
+}
+
+/// How an argument is held in a generated `CudaCall`.
+#[derive(Debug, PartialEq)]
+enum ArgRepr {
+    /// A number or `bool`, cast to this Rust type.
+    Scalar(&'static str),
+    /// An enum, as its integer value.
+    Enum(String),
+    /// A pointer or handle, as its address.
+    Address,
+    /// A pointer to a structure read into this `cuda_interposer::calls` type.
+    Followed(&'static str),
+    /// A pointer to an array of such structures, with the parameter holding its length.
+    FollowedArray(&'static str, &'static str),
+    /// A function pointer, as its address.
+    Function,
+    /// Anything else passed by value, as its bytes.
+    Bytes,
+}
+
+fn scalar_type(ty: &str) -> Option<&'static str> {
+    Some(match ty {
+        "c_char" | "c_schar" | "i8" => "i8",
+        "c_uchar" | "u8" => "u8",
+        "c_short" | "i16" => "i16",
+        "c_ushort" | "u16" => "u16",
+        "c_int" | "i32" => "i32",
+        "c_uint" | "u32" => "u32",
+        "c_long" | "c_longlong" | "i64" => "i64",
+        "c_ulong" | "c_ulonglong" | "u64" => "u64",
+        "isize" => "isize",
+        "usize" => "usize",
+        "c_float" | "f32" => "f32",
+        "c_double" | "f64" => "f64",
+        "bool" => "bool",
+        _ => return None,
+    })
+}
+
+fn classify_arg(symbol: &str, param: &str, ty: &str, types: &TypeTable) -> ArgRepr {
+    let mut ty = ty.trim();
+    // Typedef chains are short; the bound only guards against cycles.
+    for _ in 0..16 {
+        if let Some(pointee) = ty.strip_prefix("*mut ").or_else(|| ty.strip_prefix("*const ")) {
+            let pointee = pointee.trim().rsplit("::").next().unwrap_or(pointee);
+            return match (followed(pointee), followed_array(symbol, param)) {
+                (Some(repr), Some(count)) => ArgRepr::FollowedArray(repr, count),
+                (Some(repr), None) => ArgRepr::Followed(repr),
+                (None, _) => ArgRepr::Address,
+            };
+        }
+        // Function pointers, as `Option<unsafe extern "C" fn(..)>` when nullable
+        if ty.contains("fn(") {
+            return ArgRepr::Function;
+        }
+        let name = ty.rsplit("::").next().unwrap_or(ty);
+        if let Some(scalar) = scalar_type(name) {
+            return ArgRepr::Scalar(scalar);
+        }
+        if let Some(repr) = types.enums.get(name) {
+            return ArgRepr::Enum(repr.clone());
+        }
+        match types.aliases.get(name) {
+            Some(target) => ty = target.trim(),
+            None => break,
+        }
+    }
+    ArgRepr::Bytes
+}
+
+/// The parameter metadata of the driver API in `cuda-interposer-sys`.
+const DRIVER_PARAMS: &str = "cuda_interposer_sys::driver_params";
+
+/// Writes the `CudaCall` enum of `protos`. With `params`, the module of a parameter metadata
+/// table, calls also look up the direction and buffer size of their parameters in it.
+fn emit_calls(
+    path: &Path,
+    protos: &[Prototype],
+    types: &TypeTable,
+    params: Option<&str>,
+) -> Result<()> {
+    let mut f = fs::File::create(path)?;
+    let derive = "#[derive(Debug, Clone, PartialEq, cuda_interposer::serde::Serialize, cuda_interposer::serde::Deserialize)]";
+
+    writeln!(f, "/// A call to one of the APIs, with its arguments. See `cuda_interposer::calls`.")?;
+    writeln!(f, "{}", derive)?;
+    writeln!(f, "#[serde(crate = \"cuda_interposer::serde\", tag = \"api\", content = \"args\")]")?;
+    writeln!(f, "pub enum CudaCall {{")?;
+    for p in protos {
+        writeln!(f, "    {}(args::{}),", p.name, p.name)?;
+    }
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    writeln!(f, "impl CudaCall {{")?;
+    writeln!(f, "    /// Returns the API the call was made through.")?;
+    writeln!(f, "    pub fn name(&self) -> &'static str {{")?;
+    writeln!(f, "        match self {{")?;
+    for p in protos {
+        writeln!(f, "            CudaCall::{}(_) => \"{}\",", p.name, p.name)?;
+    }
+    if protos.is_empty() {
+        writeln!(f, "            _ => unreachable!(),")?;
+    }
+    writeln!(f, "        }}")?;
+    writeln!(f, "    }}")?;
+    if let Some(module) = params {
+        writeln!(f)?;
+        writeln!(f, "    /// Returns the direction and buffer size of the parameters of the API, in order.")?;
+        writeln!(f, "    pub fn params(&self) -> &'static [{}::ParamInfo] {{", module)?;
+        writeln!(f, "        {}::params(self.name()).unwrap_or_default()", module)?;
+        writeln!(f, "    }}")?;
+    }
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    writeln!(f, "/// The arguments of each API.")?;
+    writeln!(f, "pub mod args {{")?;
+    writeln!(f, "    use super::*;")?;
+    writeln!(f, "    use cuda_interposer::calls::Follow as _;")?;
+    for p in protos {
+        let reprs: Vec<_> = p
+            .args
+            .iter()
+            .map(|(n, t)| (n, t, classify_arg(&p.name, n, t, types)))
+            .collect();
+
+        writeln!(f)?;
+        writeln!(f, "    {}", derive)?;
+        writeln!(f, "    #[serde(crate = \"cuda_interposer::serde\")]")?;
+        writeln!(f, "    pub struct {} {{", p.name)?;
+        for (n, _, repr) in &reprs {
+            let field_ty = match repr {
+                ArgRepr::Scalar(t) => t.to_string(),
+                ArgRepr::Enum(t) => t.clone(),
+                ArgRepr::Address | ArgRepr::Function => "u64".to_string(),
+                ArgRepr::Followed(r) => format!("Option<cuda_interposer::calls::{}>", r),
+                ArgRepr::FollowedArray(r, _) => format!("Vec<cuda_interposer::calls::{}>", r),
+                ArgRepr::Bytes => "Vec<u8>".to_string(),
+            };
+            writeln!(f, "        pub {}: {},", n, field_ty)?;
+        }
+        writeln!(f, "    }}")?;
+        writeln!(f)?;
+
+        let params = p
+            .args
+            .iter()
+            .map(|(n, t)| format!("{}: {}", n, t))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(f, "    impl {} {{", p.name)?;
+        writeln!(f, "        /// Captures the arguments of a call, reading the structures they point to.")?;
+        writeln!(f, "        #[allow(clippy::too_many_arguments, clippy::unnecessary_cast, unused_unsafe)]")?;
+        writeln!(f, "        pub unsafe fn new({}) -> Self {{", params)?;
+        writeln!(f, "            Self {{")?;
+        for (n, _, repr) in &reprs {
+            let value = match repr {
+                ArgRepr::Scalar(t) => format!("{} as {}", n, t),
+                ArgRepr::Enum(t) => format!("{} as {}", n, t),
+                ArgRepr::Address => format!("{} as u64", n),
+                ArgRepr::Function => format!("unsafe {{ cuda_interposer::calls::address_of(&{}) }}", n),
+                ArgRepr::Followed(r) => format!(
+                    "unsafe {{ cuda_interposer::calls::{}::read({} as *const std::ffi::c_void) }}",
+                    r, n
+                ),
+                ArgRepr::FollowedArray(r, count) => format!(
+                    "unsafe {{ cuda_interposer::calls::{}::read_array({} as *const std::ffi::c_void, {} as usize) }}",
+                    r, n, count
+                ),
+                ArgRepr::Bytes => format!("unsafe {{ cuda_interposer::calls::bytes_of(&{}) }}", n),
+            };
+            writeln!(f, "                {}: {},", n, value)?;
+        }
+        writeln!(f, "            }}")?;
+        writeln!(f, "        }}")?;
+        writeln!(f, "    }}")?;
+        writeln!(f)?;
+        writeln!(f, "    impl From<{}> for CudaCall {{", p.name)?;
+        writeln!(f, "        fn from(args: {}) -> Self {{", p.name)?;
+        writeln!(f, "            CudaCall::{}(args)", p.name)?;
+        writeln!(f, "        }}")?;
+        writeln!(f, "    }}")?;
+    }
+    writeln!(f, "}}")?;
+    Ok(())
+}
+
 fn generate_hook_map(out_dir: &Path, hooks: &HashMap<String, String>) -> Result<()> {
     let mut f = fs::File::create(out_dir.join("hook_map.rs"))?;
 
@@ -305,6 +698,16 @@ fn generate_hook_map(out_dir: &Path, hooks: &HashMap<String, String>) -> Result<
     writeln!(f, "    }}")?;
     writeln!(f, "}}")?;
 
+    // The hooked names on their own, for matching `cuGetProcAddress` results by address.
+    let mut f = fs::File::create(out_dir.join("hook_names.rs"))?;
+    let mut names: Vec<_> = hooks.keys().collect();
+    names.sort();
+    writeln!(f, "&[")?;
+    for name in names {
+        writeln!(f, "    \"{}\",", name)?;
+    }
+    writeln!(f, "]")?;
+
     Ok(())
 }
 
@@ -318,6 +721,16 @@ fn emit_passthroughs(path: &Path, protos: &[Prototype]) -> Result<()> {
             .collect::<Vec<_>>()
             .join(", ");
 
+        // Out-parameters that return handles, and the handle a destroy call releases, for
+        // `cuda_interposer::handles`.
+        let outputs = p.args.iter().filter_map(|(n, t)| {
+            HandleKind::of_output(t).map(|kind| format!("(out {} {:?})", n, kind))
+        });
+        let release = released_by(&p.name)
+            .zip(p.args.first())
+            .map(|(kind, (n, _))| format!("(release {} {:?})", n, kind));
+        let handles_str = outputs.chain(release).collect::<Vec<_>>().join(", ");
+
         let aliases_str = if p.aliases.is_empty() {
             String::new()
         } else {
@@ -326,13 +739,35 @@ fn emit_passthroughs(path: &Path, protos: &[Prototype]) -> Result<()> {
 
         writeln!(
             f,
-            "cuda_interposer::generate_proxy! {{ fn {}([{}]) -> {}; name: {}{} }}",
-            p.name, args_str, p.ret, p.name, aliases_str
+            "cuda_interposer::generate_proxy! {{ fn {}([{}]) -> {}; name: {}, handles: [{}]{} }}",
+            p.name, args_str, p.ret, p.name, handles_str, aliases_str
         )?;
     }
     Ok(())
 }
 
+fn emit_unsupported(path: &Path, names: &[&str]) -> Result<()> {
+    let mut f = fs::File::create(path)?;
+    for name in names {
+        // The stubs ignore their arguments, so they are declared without any, which leaves them
+        // free of the binding types. C callers pass and clean up the arguments either way.
+        writeln!(f, "#[unsafe(no_mangle)]")?;
+        writeln!(f, "pub unsafe extern \"C\" fn {}() -> CUresult {{", name)?;
+        writeln!(f, "    crate::unsupported(\"{}\");", name)?;
+        writeln!(f, "    CUDA_ERROR_NOT_SUPPORTED")?;
+        writeln!(f, "}}")?;
+        writeln!(f)?;
+    }
+
+    // The stubbed names, so that `cuGetProcAddress` can prefer implemented versions.
+    writeln!(f, "pub static UNSUPPORTED: &[&str] = &[")?;
+    for name in names {
+        writeln!(f, "    \"{}\",", name)?;
+    }
+    writeln!(f, "];")?;
+    Ok(())
+}
+
 fn create_rust_parser() -> tree_sitter::Parser {
     let mut parser = tree_sitter::Parser::new();
     parser
@@ -372,3 +807,41 @@ fn find_target_dir(out_dir: &Path) -> PathBuf {
     }
     p
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks the `CudaCall` generated for `testdata/driver_internal_sys.rs` against
+    /// `testdata/calls_driver.rs`, which `cuda-interposer` compiles in its `calls` test. Set
+    /// `UPDATE_SNAPSHOTS=1` to rewrite the snapshot.
+    #[test]
+    fn emits_calls_for_the_bindgen_fixture() -> Result<()> {
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let files = ["driver_internal_sys.rs"];
+        let mut protos = scan_bindgen_prototypes(&testdata, &files)?;
+        protos.sort_by(|a, b| a.name.cmp(&b.name));
+        let types = scan_bindgen_types(&testdata, &files)?;
+
+        let out = env::temp_dir().join(format!("calls_driver-{}.rs", std::process::id()));
+        emit_calls(&out, &protos, &types, Some(DRIVER_PARAMS))?;
+        let emitted = fs::read_to_string(&out)?;
+        fs::remove_file(&out)?;
+
+        let snapshot = testdata.join("calls_driver.rs");
+        if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::write(&snapshot, &emitted)?;
+        }
+        assert_eq!(emitted, fs::read_to_string(&snapshot)?, "{}", snapshot.display());
+
+        // Pointers to followed structures are read, one or an array of them
+        assert!(emitted.contains("pub pCopy: Option<cuda_interposer::calls::Memcpy3D>,"));
+        assert!(emitted.contains(
+            "pub launchParamsList: Vec<cuda_interposer::calls::LaunchParams>,"
+        ));
+        assert!(emitted.contains(
+            "read_array(launchParamsList as *const std::ffi::c_void, numDevices as usize)"
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+/// A call to one of the APIs, with its arguments. See `cuda_interposer::calls`.
+#[derive(Debug, Clone, PartialEq, cuda_interposer::serde::Serialize, cuda_interposer::serde::Deserialize)]
+#[serde(crate = "cuda_interposer::serde", tag = "api", content = "args")]
+pub enum CudaCall {
+    cuInit(args::cuInit),
+    cuLaunchCooperativeKernelMultiDevice(args::cuLaunchCooperativeKernelMultiDevice),
+    cuLaunchHostFunc(args::cuLaunchHostFunc),
+    cuMemPrefetchAsync_v2(args::cuMemPrefetchAsync_v2),
+    cuMemcpy3D_v2(args::cuMemcpy3D_v2),
+    cuMemcpyHtoD_v2(args::cuMemcpyHtoD_v2),
+    cuSigned(args::cuSigned),
+}
+
+impl CudaCall {
+    /// Returns the API the call was made through.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CudaCall::cuInit(_) => "cuInit",
+            CudaCall::cuLaunchCooperativeKernelMultiDevice(_) => "cuLaunchCooperativeKernelMultiDevice",
+            CudaCall::cuLaunchHostFunc(_) => "cuLaunchHostFunc",
+            CudaCall::cuMemPrefetchAsync_v2(_) => "cuMemPrefetchAsync_v2",
+            CudaCall::cuMemcpy3D_v2(_) => "cuMemcpy3D_v2",
+            CudaCall::cuMemcpyHtoD_v2(_) => "cuMemcpyHtoD_v2",
+            CudaCall::cuSigned(_) => "cuSigned",
+        }
+    }
+
+    /// Returns the direction and buffer size of the parameters of the API, in order.
+    pub fn params(&self) -> &'static [cuda_interposer_sys::driver_params::ParamInfo] {
+        cuda_interposer_sys::driver_params::params(self.name()).unwrap_or_default()
+    }
+}
+
+/// The arguments of each API.
+pub mod args {
+    use super::*;
+    use cuda_interposer::calls::Follow as _;
+
+    #[derive(Debug, Clone, PartialEq, cuda_interposer::serde::Serialize, cuda_interposer::serde::Deserialize)]
+    #[serde(crate = "cuda_interposer::serde")]
+    pub struct cuInit {
+        pub Flags: u32,
+    }
+
+    impl cuInit {
+        /// Captures the arguments of a call, reading the structures they point to.
+        #[allow(clippy::too_many_arguments, clippy::unnecessary_cast, unused_unsafe)]
+        pub unsafe fn new(Flags: ::std::os::raw::c_uint) -> Self {
+            Self {
+                Flags: Flags as u32,
+            }
+        }
+    }
+
+    impl From<cuInit> for CudaCall {
+        fn from(args: cuInit) -> Self {
+            CudaCall::cuInit(args)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, cuda_interposer::serde::Serialize, cuda_interposer::serde::Deserialize)]
+    #[serde(crate = "cuda_interposer::serde")]
+    pub struct cuLaunchCooperativeKernelMultiDevice {
+        pub launchParamsList: Vec<cuda_interposer::calls::LaunchParams>,
+        pub numDevices: u32,
+        pub flags: u32,
+    }
+
+    impl cuLaunchCooperativeKernelMultiDevice {
+        /// Captures the arguments of a call, reading the structures they point to.
+        #[allow(clippy::too_many_arguments, clippy::unnecessary_cast, unused_unsafe)]
+        pub unsafe fn new(launchParamsList: *mut CUDA_LAUNCH_PARAMS, numDevices: ::std::os::raw::c_uint, flags: ::std::os::raw::c_uint) -> Self {
+            Self {
+                launchParamsList: unsafe { cuda_interposer::calls::LaunchParams::read_array(launchParamsList as *const std::ffi::c_void, numDevices as usize) },
+                numDevices: numDevices as u32,
+                flags: flags as u32,
+            }
+        }
+    }
+
+    impl From<cuLaunchCooperativeKernelMultiDevice> for CudaCall {
+        fn from(args: cuLaunchCooperativeKernelMultiDevice) -> Self {
+            CudaCall::cuLaunchCooperativeKernelMultiDevice(args)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, cuda_interposer::serde::Serialize, cuda_interposer::serde::Deserialize)]
+    #[serde(crate = "cuda_interposer::serde")]
+    pub struct cuLaunchHostFunc {
+        pub hStream: u64,
+        pub fn_: u64,
+        pub userData: u64,
+    }
+
+    impl cuLaunchHostFunc {
+        /// Captures the arguments of a call, reading the structures they point to.
+        #[allow(clippy::too_many_arguments, clippy::unnecessary_cast, unused_unsafe)]
+        pub unsafe fn new(hStream: CUstream, fn_: CUhostFn, userData: *mut ::std::os::raw::c_void) -> Self {
+            Self {
+                hStream: hStream as u64,
+                fn_: unsafe { cuda_interposer::calls::address_of(&fn_) },
+                userData: userData as u64,
+            }
+        }
+    }
+
+    impl From<cuLaunchHostFunc> for CudaCall {
+        fn from(args: cuLaunchHostFunc) -> Self {
+            CudaCall::cuLaunchHostFunc(args)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, cuda_interposer::serde::Serialize, cuda_interposer::serde::Deserialize)]
+    #[serde(crate = "cuda_interposer::serde")]
+    pub struct cuMemPrefetchAsync_v2 {
+        pub devPtr: u64,
+        pub count: usize,
+        pub location: Vec<u8>,
+        pub flags: u32,
+        pub hStream: u64,
+    }
+
+    impl cuMemPrefetchAsync_v2 {
+        /// Captures the arguments of a call, reading the structures they point to.
+        #[allow(clippy::too_many_arguments, clippy::unnecessary_cast, unused_unsafe)]
+        pub unsafe fn new(devPtr: CUdeviceptr, count: usize, location: CUmemLocation, flags: ::std::os::raw::c_uint, hStream: CUstream) -> Self {
+            Self {
+                devPtr: devPtr as u64,
+                count: count as usize,
+                location: unsafe { cuda_interposer::calls::bytes_of(&location) },
+                flags: flags as u32,
+                hStream: hStream as u64,
+            }
+        }
+    }
+
+    impl From<cuMemPrefetchAsync_v2> for CudaCall {
+        fn from(args: cuMemPrefetchAsync_v2) -> Self {
+            CudaCall::cuMemPrefetchAsync_v2(args)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, cuda_interposer::serde::Serialize, cuda_interposer::serde::Deserialize)]
+    #[serde(crate = "cuda_interposer::serde")]
+    pub struct cuMemcpy3D_v2 {
+        pub pCopy: Option<cuda_interposer::calls::Memcpy3D>,
+    }
+
+    impl cuMemcpy3D_v2 {
+        /// Captures the arguments of a call, reading the structures they point to.
+        #[allow(clippy::too_many_arguments, clippy::unnecessary_cast, unused_unsafe)]
+        pub unsafe fn new(pCopy: *const CUDA_MEMCPY3D) -> Self {
+            Self {
+                pCopy: unsafe { cuda_interposer::calls::Memcpy3D::read(pCopy as *const std::ffi::c_void) },
+            }
+        }
+    }
+
+    impl From<cuMemcpy3D_v2> for CudaCall {
+        fn from(args: cuMemcpy3D_v2) -> Self {
+            CudaCall::cuMemcpy3D_v2(args)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, cuda_interposer::serde::Serialize, cuda_interposer::serde::Deserialize)]
+    #[serde(crate = "cuda_interposer::serde")]
+    pub struct cuMemcpyHtoD_v2 {
+        pub dstDevice: u64,
+        pub srcHost: u64,
+        pub ByteCount: usize,
+    }
+
+    impl cuMemcpyHtoD_v2 {
+        /// Captures the arguments of a call, reading the structures they point to.
+        #[allow(clippy::too_many_arguments, clippy::unnecessary_cast, unused_unsafe)]
+        pub unsafe fn new(dstDevice: CUdeviceptr, srcHost: *const ::std::os::raw::c_void, ByteCount: usize) -> Self {
+            Self {
+                dstDevice: dstDevice as u64,
+                srcHost: srcHost as u64,
+                ByteCount: ByteCount as usize,
+            }
+        }
+    }
+
+    impl From<cuMemcpyHtoD_v2> for CudaCall {
+        fn from(args: cuMemcpyHtoD_v2) -> Self {
+            CudaCall::cuMemcpyHtoD_v2(args)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, cuda_interposer::serde::Serialize, cuda_interposer::serde::Deserialize)]
+    #[serde(crate = "cuda_interposer::serde")]
+    pub struct cuSigned {
+        pub v: i32,
+        pub f: f32,
+        pub b: bool,
+    }
+
+    impl cuSigned {
+        /// Captures the arguments of a call, reading the structures they point to.
+        #[allow(clippy::too_many_arguments, clippy::unnecessary_cast, unused_unsafe)]
+        pub unsafe fn new(v: CUsigned, f: f32, b: bool) -> Self {
+            Self {
+                v: v as i32,
+                f: f as f32,
+                b: b as bool,
+            }
+        }
+    }
+
+    impl From<cuSigned> for CudaCall {
+        fn from(args: cuSigned) -> Self {
+            CudaCall::cuSigned(args)
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Parsing helpers shared by the environment-driven hook sets.
+
+use std::fmt;
+use std::time::Duration;
+
+/// A malformed configuration value.
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    pub key: String,
+    pub value: String,
+    pub reason: &'static str,
+}
+
+impl ConfigError {
+    pub fn new(key: &str, value: &str, reason: &'static str) -> Self {
+        Self {
+            key: key.to_string(),
+            value: value.to_string(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid {} value '{}': {}",
+            self.key, self.value, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Parses a byte size such as `512`, `64K`, `8G` or `1.5GiB`. Suffixes are binary.
+pub fn parse_size(s: &str) -> Option<usize> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: f64 = num.parse().ok()?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let shift = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return None,
+    };
+    Some((num * (1u64 << shift) as f64) as usize)
+}
+
+/// Parses a duration such as `10s`, `250ms`, `50us`, `2m` or `1.5h`. A bare number is seconds.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: f64 = num.parse().ok()?;
+    let secs = match unit.trim() {
+        "" | "s" => num,
+        "ms" => num / 1e3,
+        "us" => num / 1e6,
+        "ns" => num / 1e9,
+        "m" | "min" => num * 60.0,
+        "h" => num * 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
@@ -0,0 +1,313 @@
+//! Kernel metadata from cubin images.
+//!
+//! `ptxas` describes each kernel in a `.nv.info.<kernel>` section of the cubin, a list of
+//! attribute records (`EIATTR_*`), and records per-function resource usage in the shared
+//! `.nv.info` section. [`Cubin::kernels`] collects both into a [`KernelInfo`] per kernel, whose
+//! [`ParamLayout`] gives the size and offset of every parameter. This is what decoding
+//! `kernelParams` needs on drivers without `cuFuncGetParamInfo`.
+//!
+//! Records are 4-byte aligned: a format byte, an attribute byte, then a 16-bit value or, for
+//! sized records, a 16-bit length followed by that many bytes.
+
+use crate::elf::{self, Elf, ElfError};
+use std::collections::BTreeMap;
+use std::fmt;
+
+const EIFMT_NVAL: u8 = 0x01;
+const EIFMT_BVAL: u8 = 0x02;
+const EIFMT_HVAL: u8 = 0x03;
+const EIFMT_SVAL: u8 = 0x04;
+
+pub const EIATTR_MAX_THREADS: u8 = 0x05;
+pub const EIATTR_PARAM_CBANK: u8 = 0x0a;
+pub const EIATTR_REQNTID: u8 = 0x10;
+pub const EIATTR_FRAME_SIZE: u8 = 0x11;
+pub const EIATTR_MIN_STACK_SIZE: u8 = 0x12;
+pub const EIATTR_KPARAM_INFO: u8 = 0x17;
+pub const EIATTR_CBANK_PARAM_SIZE: u8 = 0x19;
+pub const EIATTR_MAXREG_COUNT: u8 = 0x1b;
+pub const EIATTR_MAX_STACK_SIZE: u8 = 0x23;
+pub const EIATTR_REGCOUNT: u8 = 0x2f;
+
+#[derive(Debug)]
+pub enum CubinError {
+    Elf(ElfError),
+    BadRecord { section: String, offset: usize },
+}
+
+impl fmt::Display for CubinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CubinError::Elf(e) => write!(f, "{e}"),
+            CubinError::BadRecord { section, offset } => {
+                write!(f, "bad attribute record in {section} at offset {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CubinError {}
+
+impl From<ElfError> for CubinError {
+    fn from(e: ElfError) -> Self {
+        CubinError::Elf(e)
+    }
+}
+
+/// The value of an `.nv.info` record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordValue<'a> {
+    None,
+    Value(u16),
+    Bytes(&'a [u8]),
+}
+
+/// One `.nv.info` attribute record.
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub attribute: u8,
+    pub value: RecordValue<'a>,
+}
+
+impl<'a> Record<'a> {
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        match self.value {
+            RecordValue::Bytes(b) => b
+                .get(offset..offset + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap())),
+            _ => None,
+        }
+    }
+
+    fn dims(&self) -> Option<[u32; 3]> {
+        Some([self.u32_at(0)?, self.u32_at(4)?, self.u32_at(8)?])
+    }
+}
+
+/// Parses the attribute records of an `.nv.info` section.
+pub fn records<'a>(section: &str, data: &'a [u8]) -> Result<Vec<Record<'a>>, CubinError> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= data.len() {
+        let (format, attribute) = (data[offset], data[offset + 1]);
+        let half = u16::from_le_bytes([data[offset + 2], data[offset + 3]]);
+        offset += 4;
+        let value = match format {
+            EIFMT_NVAL => RecordValue::None,
+            EIFMT_BVAL | EIFMT_HVAL => RecordValue::Value(half),
+            EIFMT_SVAL => {
+                let bytes = data.get(offset..offset + half as usize).ok_or_else(|| {
+                    CubinError::BadRecord {
+                        section: section.to_string(),
+                        offset,
+                    }
+                })?;
+                offset += half as usize;
+                RecordValue::Bytes(bytes)
+            }
+            _ => {
+                return Err(CubinError::BadRecord {
+                    section: section.to_string(),
+                    offset: offset - 4,
+                });
+            }
+        };
+        records.push(Record { attribute, value });
+    }
+    Ok(records)
+}
+
+/// A kernel parameter, from an `EIATTR_KPARAM_INFO` record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Param {
+    pub ordinal: u16,
+    /// Offset in the parameter buffer.
+    pub offset: u16,
+    pub size: u16,
+    /// The raw flags word, which also holds the alignment and constant bank.
+    pub flags: u32,
+}
+
+/// The parameters of a kernel, in declaration order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParamLayout {
+    pub params: Vec<Param>,
+    /// Total size of the parameter buffer.
+    pub size: u32,
+}
+
+impl ParamLayout {
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+}
+
+/// What a cubin records about one kernel.
+#[derive(Debug, Clone, Default)]
+pub struct KernelInfo {
+    pub name: String,
+    pub params: ParamLayout,
+    /// `.maxntid`, from `__launch_bounds__`.
+    pub max_threads: Option<[u32; 3]>,
+    /// `.reqntid`.
+    pub required_threads: Option<[u32; 3]>,
+    pub max_registers: Option<u32>,
+    pub registers: Option<u32>,
+    pub frame_size: Option<u32>,
+    pub min_stack_size: Option<u32>,
+    pub max_stack_size: Option<u32>,
+}
+
+impl KernelInfo {
+    fn apply(&mut self, record: &Record) {
+        match (record.attribute, record.value) {
+            (EIATTR_KPARAM_INFO, RecordValue::Bytes(b)) if b.len() >= 12 => {
+                let flags = u32::from_le_bytes(b[8..12].try_into().unwrap());
+                self.params.params.push(Param {
+                    ordinal: u16::from_le_bytes([b[4], b[5]]),
+                    offset: u16::from_le_bytes([b[6], b[7]]),
+                    size: ((flags >> 18) & 0x3fff) as u16,
+                    flags,
+                });
+            }
+            (EIATTR_CBANK_PARAM_SIZE, RecordValue::Value(size)) => self.params.size = size as u32,
+            (EIATTR_MAX_THREADS, _) => self.max_threads = record.dims(),
+            (EIATTR_REQNTID, _) => self.required_threads = record.dims(),
+            (EIATTR_MAXREG_COUNT, RecordValue::Value(n)) => self.max_registers = Some(n as u32),
+            _ => {}
+        }
+    }
+
+    /// Applies a record of the shared `.nv.info` section, which names its function by symbol
+    /// index in the first word.
+    fn apply_function_record(&mut self, record: &Record) {
+        let value = record.u32_at(4);
+        match record.attribute {
+            EIATTR_REGCOUNT => self.registers = value,
+            EIATTR_FRAME_SIZE => self.frame_size = value,
+            EIATTR_MIN_STACK_SIZE => self.min_stack_size = value,
+            EIATTR_MAX_STACK_SIZE => self.max_stack_size = value,
+            _ => {}
+        }
+    }
+}
+
+/// A parsed cubin, borrowing its bytes.
+#[derive(Debug, Clone)]
+pub struct Cubin<'a> {
+    pub elf: Elf<'a>,
+}
+
+impl<'a> Cubin<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, CubinError> {
+        Ok(Self {
+            elf: Elf::parse(bytes)?,
+        })
+    }
+
+    /// The target architecture, e.g. `80` for `sm_80`, from `EF_CUDA_SM` in the ELF header.
+    pub fn sm_arch(&self) -> u32 {
+        self.elf.flags & 0xff
+    }
+
+    /// Returns the metadata of every kernel in the cubin, by name.
+    pub fn kernels(&self) -> Result<BTreeMap<String, KernelInfo>, CubinError> {
+        let mut kernels = BTreeMap::new();
+        for section in self.elf.sections() {
+            let Some(name) = section.name.strip_prefix(".nv.info.") else {
+                continue;
+            };
+            let mut info = KernelInfo {
+                name: name.to_string(),
+                ..Default::default()
+            };
+            for record in records(section.name, section.data)? {
+                info.apply(&record);
+            }
+            info.params.params.sort_by_key(|p| p.ordinal);
+            if info.params.size == 0 {
+                info.params.size = info
+                    .params
+                    .params
+                    .iter()
+                    .map(|p| p.offset as u32 + p.size as u32)
+                    .max()
+                    .unwrap_or(0);
+            }
+            kernels.insert(name.to_string(), info);
+        }
+
+        if let Some(shared) = self.elf.section(".nv.info") {
+            let symbols = self.elf.symbols(elf::SHT_SYMTAB)?;
+            for record in records(shared.name, shared.data)? {
+                let symbol = record
+                    .u32_at(0)
+                    .and_then(|i| symbols.get(i as usize))
+                    .map(|s| s.name);
+                if let Some(info) = symbol.and_then(|name| kernels.get_mut(name)) {
+                    info.apply_function_record(&record);
+                }
+            }
+        }
+        Ok(kernels)
+    }
+
+    pub fn kernel(&self, name: &str) -> Result<Option<KernelInfo>, CubinError> {
+        Ok(self.kernels()?.remove(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Assembled by testdata/make_cubin.py
+    const KERNELS: &[u8] = include_bytes!("../testdata/kernels.cubin");
+
+    #[test]
+    fn reads_kernel_metadata() {
+        let cubin = Cubin::parse(KERNELS).unwrap();
+        assert_eq!(cubin.sm_arch(), 80);
+        let kernels = cubin.kernels().unwrap();
+        assert_eq!(kernels.keys().collect::<Vec<_>>(), ["fill", "scale"]);
+
+        let scale = &kernels["scale"];
+        let params: Vec<_> = scale
+            .params
+            .params
+            .iter()
+            .map(|p| (p.ordinal, p.offset, p.size))
+            .collect();
+        assert_eq!(params, [(0, 0, 8), (1, 8, 4)]);
+        assert_eq!(scale.params.size, 12);
+        assert_eq!(scale.max_threads, Some([256, 1, 1]));
+        assert_eq!(scale.registers, Some(16));
+        assert_eq!(scale.required_threads, None);
+    }
+
+    #[test]
+    fn sizes_the_parameter_buffer_without_cbank_param_size() {
+        let fill = Cubin::parse(KERNELS)
+            .unwrap()
+            .kernel("fill")
+            .unwrap()
+            .unwrap();
+        assert_eq!(fill.params.len(), 1);
+        assert_eq!(fill.params.size, 8);
+        assert_eq!(fill.max_threads, None);
+        assert_eq!(fill.registers, None);
+    }
+
+    #[test]
+    fn rejects_records_running_past_their_section() {
+        let record = [EIFMT_SVAL, EIATTR_MAX_THREADS, 12, 0, 1, 0, 0, 0];
+        assert!(matches!(
+            records(".nv.info.k", &record),
+            Err(CubinError::BadRecord { offset: 4, .. })
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+//! A minimal reader for little-endian ELF64 images: sections and symbol tables.
+//!
+//! This is all [`crate::cubin`] needs from device code, and enough to look up symbols in host
+//! libraries.
+
+use std::fmt;
+
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_DYNSYM: u32 = 11;
+
+const EHDR_SIZE: usize = 64;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+
+#[derive(Debug)]
+pub enum ElfError {
+    BadMagic,
+    Unsupported(&'static str),
+    Truncated { offset: usize, needed: usize },
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::BadMagic => write!(f, "not an ELF image"),
+            ElfError::Unsupported(what) => write!(f, "unsupported ELF image: {what}"),
+            ElfError::Truncated { offset, needed } => {
+                write!(
+                    f,
+                    "ELF image truncated: {needed} bytes needed at offset {offset}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ElfError {}
+
+fn slice(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8], ElfError> {
+    bytes
+        .get(offset..offset.saturating_add(len))
+        .ok_or(ElfError::Truncated {
+            offset,
+            needed: len,
+        })
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, ElfError> {
+    Ok(u16::from_le_bytes(
+        slice(bytes, offset, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, ElfError> {
+    Ok(u32::from_le_bytes(
+        slice(bytes, offset, 4)?.try_into().unwrap(),
+    ))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> Result<u64, ElfError> {
+    Ok(u64::from_le_bytes(
+        slice(bytes, offset, 8)?.try_into().unwrap(),
+    ))
+}
+
+/// Reads the NUL-terminated string at `offset` of a string table.
+fn str_at(strtab: &[u8], offset: usize) -> &str {
+    let bytes = strtab.get(offset..).unwrap_or_default();
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Section<'a> {
+    pub name: &'a str,
+    pub kind: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub link: u32,
+    pub info: u32,
+    /// The contents, empty for `SHT_NOBITS` sections.
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub info: u8,
+    pub other: u8,
+    pub shndx: u16,
+    pub value: u64,
+    pub size: u64,
+}
+
+impl Symbol<'_> {
+    pub const STT_FUNC: u8 = 2;
+
+    pub fn kind(&self) -> u8 {
+        self.info & 0xf
+    }
+}
+
+/// A parsed ELF image, borrowing its bytes.
+#[derive(Debug, Clone)]
+pub struct Elf<'a> {
+    pub machine: u16,
+    pub flags: u32,
+    sections: Vec<Section<'a>>,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ElfError> {
+        let ident = slice(bytes, 0, EHDR_SIZE)?;
+        if &ident[..4] != b"\x7fELF" {
+            return Err(ElfError::BadMagic);
+        }
+        if ident[4] != 2 {
+            return Err(ElfError::Unsupported("not 64-bit"));
+        }
+        if ident[5] != 1 {
+            return Err(ElfError::Unsupported("not little-endian"));
+        }
+        let machine = u16_at(bytes, 0x12)?;
+        let flags = u32_at(bytes, 0x30)?;
+        let shoff = u64_at(bytes, 0x28)? as usize;
+        let shnum = u16_at(bytes, 0x3c)? as usize;
+        let shstrndx = u16_at(bytes, 0x3e)? as usize;
+
+        let mut sections = Vec::with_capacity(shnum);
+        let mut name_offsets = Vec::with_capacity(shnum);
+        for i in 0..shnum {
+            let sh_offset = i
+                .checked_mul(SHDR_SIZE)
+                .and_then(|o| o.checked_add(shoff))
+                .ok_or(ElfError::Truncated {
+                    offset: shoff,
+                    needed: shnum * SHDR_SIZE,
+                })?;
+            let sh = slice(bytes, sh_offset, SHDR_SIZE)?;
+            let kind = u32_at(sh, 4)?;
+            let offset = u64_at(sh, 0x18)? as usize;
+            let size = u64_at(sh, 0x20)? as usize;
+            name_offsets.push(u32_at(sh, 0)? as usize);
+            sections.push(Section {
+                name: "",
+                kind,
+                flags: u64_at(sh, 8)?,
+                addr: u64_at(sh, 0x10)?,
+                link: u32_at(sh, 0x28)?,
+                info: u32_at(sh, 0x2c)?,
+                data: if kind == SHT_NOBITS {
+                    &[]
+                } else {
+                    slice(bytes, offset, size)?
+                },
+            });
+        }
+        let shstrtab = sections.get(shstrndx).map_or(&[][..], |s| s.data);
+        for (section, offset) in sections.iter_mut().zip(name_offsets) {
+            section.name = str_at(shstrtab, offset);
+        }
+        Ok(Self {
+            machine,
+            flags,
+            sections,
+        })
+    }
+
+    pub fn sections(&self) -> &[Section<'a>] {
+        &self.sections
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section<'a>> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// Returns the symbols of the first section of type `kind`, `SHT_SYMTAB` or `SHT_DYNSYM`.
+    /// The null symbol at index 0 is included so that indices match the table.
+    pub fn symbols(&self, kind: u32) -> Result<Vec<Symbol<'a>>, ElfError> {
+        let Some(table) = self.sections.iter().find(|s| s.kind == kind) else {
+            return Ok(Vec::new());
+        };
+        let strtab = self
+            .sections
+            .get(table.link as usize)
+            .map_or(&[][..], |s| s.data);
+        table
+            .data
+            .chunks_exact(SYM_SIZE)
+            .map(|sym| {
+                Ok(Symbol {
+                    name: str_at(strtab, u32_at(sym, 0)? as usize),
+                    info: sym[4],
+                    other: sym[5],
+                    shndx: u16_at(sym, 6)?,
+                    value: u64_at(sym, 8)?,
+                    size: u64_at(sym, 16)?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_section_headers_past_the_address_space() {
+        let mut image = include_bytes!("../testdata/kernels.cubin").to_vec();
+        image[0x28..0x30].copy_from_slice(&(u64::MAX - 64).to_le_bytes());
+        assert!(matches!(
+            Elf::parse(&image),
+            Err(ElfError::Truncated { .. })
+        ));
+    }
+}
@@ -0,0 +1,452 @@
+//! Fatbinary container parsing.
+//!
+//! A fatbin bundles the PTX and cubin images `nvcc` builds for each target architecture. Code
+//! compiled into an application reaches the runtime through `__cudaRegisterFatBinary` as a
+//! [`FatbinWrapper`] pointing at the fatbin, and can also be passed to `cuModuleLoadData` and
+//! `cuModuleLoadFatBinary` directly.
+//!
+//! The layout is undocumented; this follows what `cuobjdump` accepts. A fatbin is a 16-byte
+//! header followed by entries, each a header describing the payload after it:
+//!
+//! ```text
+//! 0  u32 magic (0xba55ed50) | 4  u16 version | 6  u16 header_size | 8  u64 entries_size
+//!
+//! 0  u16 kind               | 2  u16 (unknown)           | 4  u32 header_size
+//! 8  u64 payload_size       | 16 u32 compressed_size     | 20 u32 (unknown)
+//! 24 u16 minor              | 26 u16 major               | 28 u32 arch
+//! 32 u32 name_offset        | 36 u32 name_size           | 40 u64 flags
+//! 48 u64 (zero)             | 56 u64 uncompressed_size
+//! ```
+//!
+//! `payload_size` covers the payload and its padding, up to the next entry. Payloads may be LZ4
+//! or Zstandard compressed, as the flags say, in which case only the first `compressed_size`
+//! bytes are the compressed payload and [`Entry::data`] decompresses them to
+//! `uncompressed_size` bytes, which may be at most [`MAX_IMAGE_SIZE`].
+
+use std::borrow::Cow;
+use std::ffi::c_void;
+use std::fmt;
+use std::io::Read;
+
+pub const FATBIN_WRAPPER_MAGIC: u32 = 0x466243b1;
+pub const FATBIN_MAGIC: u32 = 0xba55ed50;
+
+const HEADER_SIZE: usize = 16;
+const ENTRY_HEADER_SIZE: usize = 64;
+
+const FLAG_64BIT: u64 = 0x1;
+const FLAG_DEBUG: u64 = 0x2;
+const FLAG_COMPRESSED_LZ4: u64 = 0x2000;
+const FLAG_COMPRESSED_ZSTD: u64 = 0x8000;
+
+/// The largest image a compressed entry may decompress to.
+pub const MAX_IMAGE_SIZE: usize = 1 << 30;
+/// LZ4 block data expands by at most this factor.
+const LZ4_MAX_RATIO: usize = 255;
+
+#[derive(Debug)]
+pub enum FatbinError {
+    BadMagic(u32),
+    BadEntry {
+        offset: usize,
+    },
+    Truncated {
+        offset: usize,
+        needed: usize,
+    },
+    Decompress(String),
+    /// A compressed entry claims to decompress to more than its payload or [`MAX_IMAGE_SIZE`]
+    /// allow.
+    TooLarge {
+        size: usize,
+    },
+}
+
+impl fmt::Display for FatbinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FatbinError::BadMagic(magic) => write!(f, "bad fatbin magic {magic:#x}"),
+            FatbinError::BadEntry { offset } => {
+                write!(f, "bad fatbin entry header at offset {offset}")
+            }
+            FatbinError::Truncated { offset, needed } => {
+                write!(
+                    f,
+                    "fatbin truncated: {needed} bytes needed at offset {offset}"
+                )
+            }
+            FatbinError::Decompress(e) => write!(f, "failed to decompress fatbin entry: {e}"),
+            FatbinError::TooLarge { size } => {
+                write!(f, "fatbin entry too large: {size} bytes once decompressed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FatbinError {}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N], FatbinError> {
+    bytes
+        .get(offset..offset + N)
+        .and_then(|b| b.try_into().ok())
+        .ok_or(FatbinError::Truncated { offset, needed: N })
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, FatbinError> {
+    read(bytes, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, FatbinError> {
+    read(bytes, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, FatbinError> {
+    read(bytes, offset).map(u64::from_le_bytes)
+}
+
+/// The structure `__cudaRegisterFatBinary` is passed.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FatbinWrapper {
+    pub magic: u32,
+    pub version: u32,
+    pub data: *const c_void,
+    pub filename_or_fatbins: *const c_void,
+}
+
+/// A parsed fatbin, borrowing its bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct Fatbin<'a> {
+    pub version: u16,
+    bytes: &'a [u8],
+    header_size: usize,
+}
+
+impl<'a> Fatbin<'a> {
+    /// Parses the fatbin at the start of `bytes`. Trailing bytes are ignored.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, FatbinError> {
+        let magic = read_u32(bytes, 0)?;
+        if magic != FATBIN_MAGIC {
+            return Err(FatbinError::BadMagic(magic));
+        }
+        let version = read_u16(bytes, 4)?;
+        let header_size = read_u16(bytes, 6)? as usize;
+        let size = header_size + read_u64(bytes, 8)? as usize;
+        let bytes = bytes.get(..size).ok_or(FatbinError::Truncated {
+            offset: 0,
+            needed: size,
+        })?;
+        Ok(Self {
+            version,
+            bytes,
+            header_size,
+        })
+    }
+
+    /// Parses the fatbin at `ptr`, whose size is taken from its header.
+    ///
+    /// # Safety
+    /// `ptr` must point to a complete fatbin.
+    pub unsafe fn from_ptr(ptr: *const c_void) -> Result<Self, FatbinError> {
+        let header = unsafe { std::slice::from_raw_parts(ptr as *const u8, HEADER_SIZE) };
+        let magic = read_u32(header, 0)?;
+        if magic != FATBIN_MAGIC {
+            return Err(FatbinError::BadMagic(magic));
+        }
+        let size = read_u16(header, 6)? as usize + read_u64(header, 8)? as usize;
+        Self::parse(unsafe { std::slice::from_raw_parts(ptr as *const u8, size) })
+    }
+
+    /// Parses the fatbin a [`FatbinWrapper`] at `ptr` points to.
+    ///
+    /// # Safety
+    /// `ptr` must point to a fatbin wrapper whose data is a complete fatbin.
+    pub unsafe fn from_wrapper(ptr: *const c_void) -> Result<Self, FatbinError> {
+        let wrapper = unsafe { (ptr as *const FatbinWrapper).read_unaligned() };
+        if wrapper.magic != FATBIN_WRAPPER_MAGIC {
+            return Err(FatbinError::BadMagic(wrapper.magic));
+        }
+        unsafe { Self::from_ptr(wrapper.data) }
+    }
+
+    /// The bytes of the whole fatbin, header included.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            bytes: self.bytes,
+            offset: self.header_size,
+        }
+    }
+}
+
+/// Iterates over the entries of a [`Fatbin`].
+#[derive(Debug, Clone)]
+pub struct Entries<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, FatbinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.bytes.len() {
+            return None;
+        }
+        let entry = Entry::parse(self.bytes, self.offset);
+        match &entry {
+            Ok(e) => self.offset += e.header_size + e.padded_size,
+            Err(_) => self.offset = self.bytes.len(),
+        }
+        Some(entry)
+    }
+}
+
+/// What a fatbin entry contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Ptx,
+    Cubin,
+    /// A relocatable device code archive.
+    Archive,
+    Unknown(u16),
+}
+
+impl From<u16> for EntryKind {
+    fn from(kind: u16) -> Self {
+        match kind {
+            1 => EntryKind::Ptx,
+            2 => EntryKind::Cubin,
+            4 => EntryKind::Archive,
+            other => EntryKind::Unknown(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+/// One PTX or cubin image in a fatbin.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    pub kind: EntryKind,
+    /// The PTX ISA or cubin format version, as `(major, minor)`.
+    pub version: (u16, u16),
+    /// The target architecture, e.g. `80` for `sm_80`.
+    pub sm_arch: u32,
+    pub flags: u64,
+    /// The size of the payload once decompressed.
+    pub uncompressed_size: usize,
+    /// The payload as stored, compressed or not.
+    pub payload: &'a [u8],
+    header_size: usize,
+    padded_size: usize,
+}
+
+impl<'a> Entry<'a> {
+    fn parse(bytes: &'a [u8], offset: usize) -> Result<Self, FatbinError> {
+        read::<ENTRY_HEADER_SIZE>(bytes, offset)?;
+        let header_size = read_u32(bytes, offset + 4)? as usize;
+        if header_size < ENTRY_HEADER_SIZE {
+            return Err(FatbinError::BadEntry { offset });
+        }
+        let padded_size = usize::try_from(read_u64(bytes, offset + 8)?)
+            .map_err(|_| FatbinError::BadEntry { offset })?;
+        let flags = read_u64(bytes, offset + 40)?;
+        let compressed = flags & (FLAG_COMPRESSED_LZ4 | FLAG_COMPRESSED_ZSTD) != 0;
+        let payload_size = if compressed {
+            let size = read_u32(bytes, offset + 16)? as usize;
+            if size > padded_size {
+                return Err(FatbinError::BadEntry { offset });
+            }
+            size
+        } else {
+            padded_size
+        };
+        let start = offset + header_size;
+        let payload = start
+            .checked_add(payload_size)
+            .and_then(|end| bytes.get(start..end))
+            .ok_or(FatbinError::Truncated {
+                offset: start,
+                needed: payload_size,
+            })?;
+        let uncompressed_size = if compressed {
+            usize::try_from(read_u64(bytes, offset + 56)?).unwrap_or(usize::MAX)
+        } else {
+            payload_size
+        };
+        Ok(Self {
+            kind: read_u16(bytes, offset)?.into(),
+            version: (read_u16(bytes, offset + 26)?, read_u16(bytes, offset + 24)?),
+            sm_arch: read_u32(bytes, offset + 28)?,
+            flags,
+            uncompressed_size,
+            payload,
+            header_size,
+            padded_size,
+        })
+    }
+
+    /// The architecture name, e.g. `sm_80`, or `compute_80` for PTX.
+    pub fn arch(&self) -> String {
+        match self.kind {
+            EntryKind::Ptx => format!("compute_{}", self.sm_arch),
+            _ => format!("sm_{}", self.sm_arch),
+        }
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        self.flags & FLAG_64BIT != 0
+    }
+
+    pub fn is_debug(&self) -> bool {
+        self.flags & FLAG_DEBUG != 0
+    }
+
+    pub fn compression(&self) -> Compression {
+        if self.flags & FLAG_COMPRESSED_LZ4 != 0 {
+            Compression::Lz4
+        } else if self.flags & FLAG_COMPRESSED_ZSTD != 0 {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// The decompressed image. PTX is returned without the NUL padding it is stored with.
+    pub fn data(&self) -> Result<Cow<'a, [u8]>, FatbinError> {
+        let decompress_err = |e: &dyn fmt::Display| FatbinError::Decompress(e.to_string());
+        let too_large = FatbinError::TooLarge {
+            size: self.uncompressed_size,
+        };
+        let mut data = match self.compression() {
+            Compression::None => Cow::Borrowed(self.payload),
+            Compression::Lz4 => {
+                let bound = self.payload.len().saturating_mul(LZ4_MAX_RATIO);
+                if self.uncompressed_size > bound.min(MAX_IMAGE_SIZE) {
+                    return Err(too_large);
+                }
+                let mut out = vec![0; self.uncompressed_size];
+                let n = lz4_flex::block::decompress_into(self.payload, &mut out)
+                    .map_err(|e| decompress_err(&e))?;
+                out.truncate(n);
+                Cow::Owned(out)
+            }
+            Compression::Zstd => {
+                if self.uncompressed_size > MAX_IMAGE_SIZE {
+                    return Err(too_large);
+                }
+                // The header is not trusted to bound the output, so read at most one byte more
+                // than it promises.
+                let mut out = Vec::with_capacity(self.uncompressed_size);
+                ruzstd::decoding::StreamingDecoder::new(self.payload)
+                    .map_err(|e| decompress_err(&e))?
+                    .take(self.uncompressed_size as u64 + 1)
+                    .read_to_end(&mut out)
+                    .map_err(|e| decompress_err(&e))?;
+                if out.len() > self.uncompressed_size {
+                    return Err(too_large);
+                }
+                Cow::Owned(out)
+            }
+        };
+        if self.kind == EntryKind::Ptx {
+            let len = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+            match &mut data {
+                Cow::Borrowed(b) => *b = &b[..len],
+                Cow::Owned(v) => v.truncate(len),
+            }
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Assembled by testdata/make_fatbins.py
+    const PLAIN: &[u8] = include_bytes!("../testdata/plain.fatbin");
+    const COMPRESSED: &[u8] = include_bytes!("../testdata/compressed.fatbin");
+    const CUBIN: &[u8] = include_bytes!("../testdata/cubin.bin");
+    const PTX: &[u8] = include_bytes!("../testdata/kernel.ptx");
+
+    fn entries(bytes: &[u8]) -> Vec<Entry<'_>> {
+        Fatbin::parse(bytes)
+            .unwrap()
+            .entries()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_uncompressed_entries() {
+        let fatbin = Fatbin::parse(PLAIN).unwrap();
+        assert_eq!(fatbin.version, 1);
+        assert_eq!(fatbin.bytes().len(), PLAIN.len());
+
+        let entries = entries(PLAIN);
+        assert_eq!(entries.len(), 2);
+        let (cubin, ptx) = (&entries[0], &entries[1]);
+        assert_eq!(cubin.kind, EntryKind::Cubin);
+        assert_eq!(cubin.arch(), "sm_80");
+        assert_eq!(cubin.version, (1, 7));
+        assert!(cubin.is_64bit());
+        assert_eq!(cubin.compression(), Compression::None);
+        assert_eq!(cubin.uncompressed_size, CUBIN.len());
+        assert_eq!(&*cubin.data().unwrap(), CUBIN);
+
+        assert_eq!(ptx.kind, EntryKind::Ptx);
+        assert_eq!(ptx.arch(), "compute_80");
+        assert_eq!(ptx.version, (8, 0));
+        assert_eq!(&*ptx.data().unwrap(), PTX);
+    }
+
+    #[test]
+    fn decompresses_lz4_and_zstd_entries() {
+        let entries = entries(COMPRESSED);
+        assert_eq!(entries.len(), 2);
+        let (cubin, ptx) = (&entries[0], &entries[1]);
+        assert_eq!(cubin.compression(), Compression::Lz4);
+        assert!(cubin.payload.len() < CUBIN.len());
+        assert_eq!(cubin.uncompressed_size, CUBIN.len());
+        assert_eq!(&*cubin.data().unwrap(), CUBIN);
+
+        assert_eq!(ptx.compression(), Compression::Zstd);
+        assert_eq!(ptx.arch(), "compute_80");
+        assert_eq!(&*ptx.data().unwrap(), PTX);
+    }
+
+    #[test]
+    fn rejects_oversized_and_truncated_entries() {
+        // Claim an LZ4 output far beyond what the payload can expand to
+        let mut bytes = COMPRESSED.to_vec();
+        bytes[HEADER_SIZE + 56..HEADER_SIZE + 64].copy_from_slice(&u64::MAX.to_le_bytes());
+        let cubin = entries(&bytes).remove(0);
+        assert!(matches!(cubin.data(), Err(FatbinError::TooLarge { .. })));
+
+        // And a Zstandard output smaller than the frame decodes to
+        let mut bytes = COMPRESSED.to_vec();
+        let ptx = HEADER_SIZE + ENTRY_HEADER_SIZE + entries(COMPRESSED)[0].padded_size;
+        bytes[ptx + 56..ptx + 64].copy_from_slice(&16u64.to_le_bytes());
+        let ptx = entries(&bytes).remove(1);
+        assert!(matches!(ptx.data(), Err(FatbinError::TooLarge { .. })));
+
+        assert!(matches!(
+            Fatbin::parse(&PLAIN[..PLAIN.len() - 1]),
+            Err(FatbinError::Truncated { .. })
+        ));
+        assert!(matches!(
+            Fatbin::parse(CUBIN),
+            Err(FatbinError::BadMagic(0x464c457f))
+        ));
+    }
+}
@@ -0,0 +1,259 @@
+//! Minimal CUDA driver ABI definitions used by the built-in hook sets and the fake driver.
+//!
+//! Neither depends on `cuda-interposer-sys`, so the few types they need are spelled out here
+//! with their C layouts, the same way `install_hooks!` treats `CUresult` as a plain `u32`.
+#![allow(non_camel_case_types, non_snake_case)]
+
+use std::os::raw::c_void;
+
+pub type CUresult = u32;
+pub type CUdevice = i32;
+pub type CUdeviceptr = u64;
+pub type CUcontext = *mut c_void;
+pub type CUstream = *mut c_void;
+pub type CUevent = *mut c_void;
+pub type CUmemoryPool = *mut c_void;
+pub type CUmemGenericAllocationHandle = u64;
+
+pub const CUDA_SUCCESS: CUresult = 0;
+pub const CUDA_ERROR_INVALID_VALUE: CUresult = 1;
+pub const CUDA_ERROR_OUT_OF_MEMORY: CUresult = 2;
+pub const CUDA_ERROR_INVALID_DEVICE: CUresult = 101;
+pub const CUDA_ERROR_NOT_SUPPORTED: CUresult = 801;
+pub const CUDA_ERROR_UNKNOWN: CUresult = 999;
+
+pub const CU_MEM_LOCATION_TYPE_DEVICE: u32 = 1;
+pub const CU_MEM_ALLOCATION_TYPE_PINNED: u32 = 1;
+pub const CU_MEM_ACCESS_FLAGS_PROT_READWRITE: u32 = 3;
+pub const CU_MEM_ALLOC_GRANULARITY_MINIMUM: u32 = 0;
+/// `CUpointer_attribute` value whose data is the ordinal of the device owning the memory.
+pub const CU_POINTER_ATTRIBUTE_DEVICE_ORDINAL: u32 = 9;
+
+/// The legacy default stream, which synchronizes with every blocking stream.
+pub const CU_STREAM_LEGACY: CUstream = 0x1 as CUstream;
+/// The calling thread's default stream.
+pub const CU_STREAM_PER_THREAD: CUstream = 0x2 as CUstream;
+/// `cuStreamCreate` flag for streams that do not synchronize with the legacy default stream.
+pub const CU_STREAM_NON_BLOCKING: u32 = 0x1;
+/// `CUstreamCaptureStatus` of a stream that is not being captured.
+pub const CU_STREAM_CAPTURE_STATUS_NONE: u32 = 0;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUmemLocation {
+    pub type_: u32,
+    pub id: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUmemAllocationProp_allocFlags {
+    pub compressionType: u8,
+    pub gpuDirectRDMACapable: u8,
+    pub usage: u16,
+    pub reserved: [u8; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUmemAllocationProp {
+    pub type_: u32,
+    pub requestedHandleTypes: u32,
+    pub location: CUmemLocation,
+    pub win32HandleMetaData: *mut c_void,
+    pub allocFlags: CUmemAllocationProp_allocFlags,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUmemAccessDesc {
+    pub location: CUmemLocation,
+    pub flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUmemPoolProps {
+    pub allocType: u32,
+    pub handleTypes: u32,
+    pub location: CUmemLocation,
+    pub win32SecurityAttributes: *mut c_void,
+    pub maxSize: usize,
+    pub usage: u16,
+    pub reserved: [u8; 54],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CUuuid {
+    pub bytes: [std::os::raw::c_char; 16],
+}
+
+pub type CUfunction = *mut c_void;
+pub type CUmodule = *mut c_void;
+pub type CUlibrary = *mut c_void;
+pub type CUkernel = *mut c_void;
+
+pub const CUDA_ERROR_NOT_FOUND: CUresult = 500;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUlaunchAttribute {
+    pub id: u32,
+    pub pad: [u8; 4],
+    /// `CUlaunchAttributeValue`, a union of at most 64 bytes.
+    pub value: [u32; 16],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUlaunchConfig {
+    pub gridDimX: u32,
+    pub gridDimY: u32,
+    pub gridDimZ: u32,
+    pub blockDimX: u32,
+    pub blockDimY: u32,
+    pub blockDimZ: u32,
+    pub sharedMemBytes: u32,
+    pub hStream: CUstream,
+    pub attrs: *mut CUlaunchAttribute,
+    pub numAttrs: u32,
+}
+
+/// The keys of the `extra` array of `cuLaunchKernel`.
+pub const CU_LAUNCH_PARAM_END: usize = 0x00;
+pub const CU_LAUNCH_PARAM_BUFFER_POINTER: usize = 0x01;
+pub const CU_LAUNCH_PARAM_BUFFER_SIZE: usize = 0x02;
+
+/// The runtime API's `dim3`, passed by value to `cudaLaunchKernel`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct dim3 {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+pub type CUgraph = *mut c_void;
+pub type CUgraphNode = *mut c_void;
+pub type CUgraphExec = *mut c_void;
+pub type CUarray = *mut c_void;
+
+/// `CUgraphNodeType` values.
+pub const CU_GRAPH_NODE_TYPE_KERNEL: u32 = 0;
+pub const CU_GRAPH_NODE_TYPE_MEMCPY: u32 = 1;
+pub const CU_GRAPH_NODE_TYPE_MEMSET: u32 = 2;
+pub const CU_GRAPH_NODE_TYPE_HOST: u32 = 3;
+pub const CU_GRAPH_NODE_TYPE_GRAPH: u32 = 4;
+pub const CU_GRAPH_NODE_TYPE_EMPTY: u32 = 5;
+pub const CU_GRAPH_NODE_TYPE_WAIT_EVENT: u32 = 6;
+pub const CU_GRAPH_NODE_TYPE_EVENT_RECORD: u32 = 7;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUDA_KERNEL_NODE_PARAMS_v2 {
+    pub func: CUfunction,
+    pub gridDimX: u32,
+    pub gridDimY: u32,
+    pub gridDimZ: u32,
+    pub blockDimX: u32,
+    pub blockDimY: u32,
+    pub blockDimZ: u32,
+    pub sharedMemBytes: u32,
+    pub kernelParams: *mut *mut c_void,
+    pub extra: *mut *mut c_void,
+    pub kern: CUkernel,
+    pub ctx: CUcontext,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUDA_MEMSET_NODE_PARAMS {
+    pub dst: CUdeviceptr,
+    pub pitch: usize,
+    pub value: u32,
+    pub elementSize: u32,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUDA_MEMCPY3D {
+    pub srcXInBytes: usize,
+    pub srcY: usize,
+    pub srcZ: usize,
+    pub srcLOD: usize,
+    /// `CUmemorytype`: 1 host, 2 device, 3 array, 4 unified.
+    pub srcMemoryType: u32,
+    pub srcHost: *const c_void,
+    pub srcDevice: CUdeviceptr,
+    pub srcArray: CUarray,
+    pub reserved0: *mut c_void,
+    pub srcPitch: usize,
+    pub srcHeight: usize,
+    pub dstXInBytes: usize,
+    pub dstY: usize,
+    pub dstZ: usize,
+    pub dstLOD: usize,
+    pub dstMemoryType: u32,
+    pub dstHost: *mut c_void,
+    pub dstDevice: CUdeviceptr,
+    pub dstArray: CUarray,
+    pub reserved1: *mut c_void,
+    pub dstPitch: usize,
+    pub dstHeight: usize,
+    pub WidthInBytes: usize,
+    pub Height: usize,
+    pub Depth: usize,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUDA_MEMCPY2D {
+    pub srcXInBytes: usize,
+    pub srcY: usize,
+    pub srcMemoryType: u32,
+    pub srcHost: *const c_void,
+    pub srcDevice: CUdeviceptr,
+    pub srcArray: CUarray,
+    pub srcPitch: usize,
+    pub dstXInBytes: usize,
+    pub dstY: usize,
+    pub dstMemoryType: u32,
+    pub dstHost: *mut c_void,
+    pub dstDevice: CUdeviceptr,
+    pub dstArray: CUarray,
+    pub dstPitch: usize,
+    pub WidthInBytes: usize,
+    pub Height: usize,
+}
+
+/// `CUgraphNodeParams`, taken by `cuGraphAddNode` and `cuGraphNodeSetParams`. Kernel nodes
+/// hold a `CUDA_KERNEL_NODE_PARAMS_v2` at the start of `params`, memcpy nodes their context at
+/// byte 8 and a `CUDA_MEMCPY3D` at byte 16, and memset nodes a `CUDA_MEMSET_NODE_PARAMS`
+/// followed by their context at byte 40.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUgraphNodeParams {
+    pub type_: u32,
+    pub reserved0: [i32; 3],
+    /// The per-type parameters, a union of at most 232 bytes.
+    pub params: [u64; 29],
+    pub reserved2: i64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CUDA_LAUNCH_PARAMS {
+    pub function: CUfunction,
+    pub gridDimX: u32,
+    pub gridDimY: u32,
+    pub gridDimZ: u32,
+    pub blockDimX: u32,
+    pub blockDimY: u32,
+    pub blockDimZ: u32,
+    pub sharedMemBytes: u32,
+    pub hStream: CUstream,
+    pub kernelParams: *mut *mut c_void,
+}
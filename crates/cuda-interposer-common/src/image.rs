@@ -0,0 +1,71 @@
+//! Module images passed to the driver without a size.
+//!
+//! `cuModuleLoadData` and its relatives take a pointer to a PTX, cubin or fatbin image and no
+//! length. [`image_bytes`] measures them the way the driver does: ELF and fatbin images by their
+//! header fields, anything else as NUL-terminated PTX.
+
+use crate::fatbin::{FATBIN_MAGIC, FATBIN_WRAPPER_MAGIC, Fatbin};
+use std::ffi::{CStr, c_char, c_void};
+
+pub const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+
+/// Returns the bytes of an image passed to the driver without a size: an ELF cubin, a fatbin, a
+/// fatbin wrapper (in which case the fatbin it points to), or NUL-terminated PTX.
+///
+/// # Safety
+/// `image` must be null or point to an image the driver would accept.
+pub unsafe fn image_bytes<'a>(image: *const c_void) -> Option<&'a [u8]> {
+    if image.is_null() {
+        return None;
+    }
+    // PTX may be shorter than a magic number, so stop at its terminator
+    let mut head = [0u8; 4];
+    for (i, b) in head.iter_mut().enumerate() {
+        *b = unsafe { *(image as *const u8).add(i) };
+        if *b == 0 {
+            break;
+        }
+    }
+    let magic = u32::from_le_bytes(head);
+    let len = if &head == ELF_MAGIC {
+        unsafe { elf_size(image as *const u8) }
+    } else if magic == FATBIN_WRAPPER_MAGIC {
+        return unsafe { Fatbin::from_wrapper(image) }
+            .ok()
+            .map(|f| f.bytes());
+    } else if magic == FATBIN_MAGIC {
+        return unsafe { Fatbin::from_ptr(image) }.ok().map(|f| f.bytes());
+    } else {
+        unsafe { CStr::from_ptr(image as *const c_char) }
+            .to_bytes()
+            .len()
+    };
+    Some(unsafe { std::slice::from_raw_parts(image as *const u8, len) })
+}
+
+/// The size of a 64-bit ELF image, which ends with its section or program header table.
+unsafe fn elf_size(elf: *const u8) -> usize {
+    let read = |offset: usize, len: usize| -> u64 {
+        let mut buf = [0u8; 8];
+        unsafe { std::ptr::copy_nonoverlapping(elf.add(offset), buf.as_mut_ptr(), len) };
+        u64::from_le_bytes(buf)
+    };
+    let (phoff, shoff) = (read(0x20, 8), read(0x28, 8));
+    let (phentsize, phnum) = (read(0x36, 2), read(0x38, 2));
+    let (shentsize, shnum) = (read(0x3a, 2), read(0x3c, 2));
+    let shend = shoff.saturating_add(shentsize * shnum);
+    shend.max(phoff.saturating_add(phentsize * phnum)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_ptx_shorter_than_a_magic_number() {
+        for ptx in [&b"\0"[..], b"a\0", b"ab\0", b"abc\0"] {
+            let bytes = unsafe { image_bytes(ptx.as_ptr() as *const c_void) }.unwrap();
+            assert_eq!(bytes, &ptx[..ptx.len() - 1]);
+        }
+    }
+}
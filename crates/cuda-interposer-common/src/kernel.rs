@@ -0,0 +1,26 @@
+//! Kernel names.
+
+use serde::Serialize;
+
+/// The name of a kernel, as compiled and as written in the source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KernelName {
+    pub mangled: String,
+    pub demangled: String,
+}
+
+impl KernelName {
+    pub fn new(mangled: impl Into<String>) -> Self {
+        let mangled = mangled.into();
+        let demangled = demangle(&mangled);
+        Self { mangled, demangled }
+    }
+}
+
+/// Demangles an Itanium C++ symbol, returning names that are not mangled unchanged.
+pub fn demangle(name: &str) -> String {
+    cpp_demangle::Symbol::new(name)
+        .ok()
+        .and_then(|sym| sym.demangle(&Default::default()).ok())
+        .unwrap_or_else(|| name.to_string())
+}
@@ -0,0 +1,16 @@
+//! The parts of `cuda-interposer` that hook nothing and hold no state: driver types,
+//! configuration parsing, device profiles, kernel names and the ELF, cubin and fatbin parsers.
+//!
+//! `cuda-fake-driver` needs these too, and `cuda-interposer` runs its tests against the fake
+//! driver. Keeping them here lets the fake driver use them without linking the interposer, so
+//! the interposer's tests do not carry a second copy of it and its global state.
+//! `cuda-interposer` re-exports every module under the same name.
+
+pub mod config;
+pub mod cubin;
+pub mod elf;
+pub mod fatbin;
+pub mod ffi;
+pub mod image;
+pub mod kernel;
+pub mod profile;
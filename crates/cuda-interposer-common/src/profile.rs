@@ -0,0 +1,320 @@
+//! Device profiles: what a driver reports about its devices.
+//!
+//! A [`Profile`] sets the name, total memory, compute capability and any
+//! `cuDeviceGetAttribute` value of each device. `cuda-interposer` applies one to a real driver
+//! (`CUDA_HOOK_DEVICE_PROFILE`) and `cuda-fake-driver` reports one (`CUDA_FAKE_DEVICE_PROFILE`).
+//! Profiles are TOML files:
+//!
+//! ```toml
+//! name = "NVIDIA H100 80GB HBM3"
+//! total_mem = "80G"
+//! compute_capability = [9, 0]
+//!
+//! [attributes]
+//! multiprocessor_count = 132
+//! l2_cache_size = 52428800
+//!
+//! # Overrides for device 1 only
+//! [devices.1]
+//! name = "NVIDIA A100-SXM4-40GB"
+//! compute_capability = [8, 0]
+//! ```
+//!
+//! Attributes are named after `CUdevice_attribute` without the `CU_DEVICE_ATTRIBUTE_` prefix,
+//! in lower case, or given by number.
+
+use crate::config::parse_size;
+use crate::ffi::CUdevice;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+pub const CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR: i32 = 75;
+pub const CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR: i32 = 76;
+
+/// `CUdevice_attribute` values that can be named in a profile.
+pub const ATTRIBUTE_NAMES: &[(&str, i32)] = &[
+    ("max_threads_per_block", 1),
+    ("max_block_dim_x", 2),
+    ("max_block_dim_y", 3),
+    ("max_block_dim_z", 4),
+    ("max_grid_dim_x", 5),
+    ("max_grid_dim_y", 6),
+    ("max_grid_dim_z", 7),
+    ("max_shared_memory_per_block", 8),
+    ("total_constant_memory", 9),
+    ("warp_size", 10),
+    ("max_pitch", 11),
+    ("max_registers_per_block", 12),
+    ("clock_rate", 13),
+    ("texture_alignment", 14),
+    ("gpu_overlap", 15),
+    ("multiprocessor_count", 16),
+    ("kernel_exec_timeout", 17),
+    ("integrated", 18),
+    ("can_map_host_memory", 19),
+    ("compute_mode", 20),
+    ("concurrent_kernels", 31),
+    ("ecc_enabled", 32),
+    ("pci_bus_id", 33),
+    ("pci_device_id", 34),
+    ("tcc_driver", 35),
+    ("memory_clock_rate", 36),
+    ("global_memory_bus_width", 37),
+    ("l2_cache_size", 38),
+    ("max_threads_per_multiprocessor", 39),
+    ("async_engine_count", 40),
+    ("unified_addressing", 41),
+    ("pci_domain_id", 50),
+    ("compute_capability_major", 75),
+    ("compute_capability_minor", 76),
+    ("max_shared_memory_per_multiprocessor", 81),
+    ("max_registers_per_multiprocessor", 82),
+    ("managed_memory", 83),
+    ("multi_gpu_board", 84),
+    ("multi_gpu_board_group_id", 85),
+    ("host_native_atomic_supported", 86),
+    ("single_to_double_precision_perf_ratio", 87),
+    ("pageable_memory_access", 88),
+    ("concurrent_managed_access", 89),
+    ("compute_preemption_supported", 90),
+    ("can_use_host_pointer_for_registered_mem", 91),
+    ("cooperative_launch", 95),
+    ("cooperative_multi_device_launch", 96),
+    ("max_shared_memory_per_block_optin", 97),
+    ("virtual_memory_management_supported", 102),
+    ("max_blocks_per_multiprocessor", 106),
+    ("reserved_shared_memory_per_block", 111),
+    ("memory_pools_supported", 115),
+];
+
+/// Returns the `CUdevice_attribute` value for a profile key.
+pub fn attribute_id(key: &str) -> Option<i32> {
+    key.parse().ok().or_else(|| {
+        let key = key.to_ascii_lowercase();
+        let key = key.trim_start_matches("cu_device_attribute_");
+        ATTRIBUTE_NAMES
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, id)| *id)
+    })
+}
+
+/// A memory size, written either as a number of bytes or as a string such as `"80G"`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "SizeRepr")]
+pub struct Size(pub usize);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeRepr {
+    Bytes(u64),
+    Text(String),
+}
+
+impl TryFrom<SizeRepr> for Size {
+    type Error = String;
+
+    fn try_from(repr: SizeRepr) -> Result<Self, Self::Error> {
+        match repr {
+            SizeRepr::Bytes(b) => Ok(Size(b as usize)),
+            SizeRepr::Text(s) => parse_size(&s)
+                .map(Size)
+                .ok_or_else(|| format!("invalid size '{s}'")),
+        }
+    }
+}
+
+/// Overrides for one device. Unset fields are reported as the driver has them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceProfile {
+    pub name: Option<String>,
+    pub total_mem: Option<Size>,
+    pub compute_capability: Option<(i32, i32)>,
+    #[serde(default)]
+    pub attributes: BTreeMap<String, i32>,
+}
+
+/// A device profile with per-device overrides.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(from = "ProfileRepr")]
+pub struct Profile {
+    pub default: DeviceProfile,
+    pub devices: BTreeMap<CUdevice, DeviceProfile>,
+}
+
+/// The TOML layout of a [`Profile`]. The defaults are spelled out rather than flattened, since
+/// `serde(flatten)` would let misspelled keys through.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileRepr {
+    name: Option<String>,
+    total_mem: Option<Size>,
+    compute_capability: Option<(i32, i32)>,
+    #[serde(default)]
+    attributes: BTreeMap<String, i32>,
+    #[serde(default)]
+    devices: BTreeMap<CUdevice, DeviceProfile>,
+}
+
+impl From<ProfileRepr> for Profile {
+    fn from(repr: ProfileRepr) -> Self {
+        Profile {
+            default: DeviceProfile {
+                name: repr.name,
+                total_mem: repr.total_mem,
+                compute_capability: repr.compute_capability,
+                attributes: repr.attributes,
+            },
+            devices: repr.devices,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    UnknownAttribute(String),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io(e) => write!(f, "{e}"),
+            ProfileError::Toml(e) => write!(f, "{e}"),
+            ProfileError::UnknownAttribute(name) => write!(f, "unknown device attribute '{name}'"),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl Profile {
+    pub fn from_toml(src: &str) -> Result<Self, ProfileError> {
+        let profile: Profile = toml::from_str(src).map_err(ProfileError::Toml)?;
+        let keys = profile
+            .devices
+            .values()
+            .chain(std::iter::once(&profile.default))
+            .flat_map(|d| d.attributes.keys());
+        for key in keys {
+            if attribute_id(key).is_none() {
+                return Err(ProfileError::UnknownAttribute(key.clone()));
+            }
+        }
+        Ok(profile)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ProfileError> {
+        let src = std::fs::read_to_string(path).map_err(ProfileError::Io)?;
+        Self::from_toml(&src)
+    }
+
+    fn lookup<T>(&self, dev: CUdevice, f: impl Fn(&DeviceProfile) -> Option<T>) -> Option<T> {
+        self.devices
+            .get(&dev)
+            .and_then(&f)
+            .or_else(|| f(&self.default))
+    }
+
+    pub fn name(&self, dev: CUdevice) -> Option<String> {
+        self.lookup(dev, |d| d.name.clone())
+    }
+
+    pub fn total_mem(&self, dev: CUdevice) -> Option<usize> {
+        self.lookup(dev, |d| d.total_mem.map(|s| s.0))
+    }
+
+    pub fn compute_capability(&self, dev: CUdevice) -> Option<(i32, i32)> {
+        self.lookup(dev, |d| d.compute_capability)
+    }
+
+    pub fn attribute(&self, dev: CUdevice, attrib: i32) -> Option<i32> {
+        let from_table = |d: &DeviceProfile| {
+            d.attributes
+                .iter()
+                .find(|(key, _)| attribute_id(key) == Some(attrib))
+                .map(|(_, v)| *v)
+        };
+        self.lookup(dev, from_table).or_else(|| {
+            let (major, minor) = self.compute_capability(dev)?;
+            match attrib {
+                CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR => Some(major),
+                CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR => Some(minor),
+                _ => None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = r#"
+        name = "NVIDIA H100 80GB HBM3"
+        total_mem = "80G"
+        compute_capability = [9, 0]
+
+        [attributes]
+        multiprocessor_count = 132
+        CU_DEVICE_ATTRIBUTE_L2_CACHE_SIZE = 52428800
+        39 = 2048
+
+        [devices.1]
+        name = "NVIDIA A100-SXM4-40GB"
+        total_mem = 42949672960
+        compute_capability = [8, 0]
+
+        [devices.1.attributes]
+        multiprocessor_count = 108
+    "#;
+
+    #[test]
+    fn resolves_attribute_names_and_numbers() {
+        assert_eq!(attribute_id("multiprocessor_count"), Some(16));
+        assert_eq!(attribute_id("CU_DEVICE_ATTRIBUTE_WARP_SIZE"), Some(10));
+        assert_eq!(attribute_id("120"), Some(120));
+        assert_eq!(attribute_id("not_an_attribute"), None);
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let profile = Profile::from_toml(PROFILE).unwrap();
+        assert_eq!(profile.name(0).as_deref(), Some("NVIDIA H100 80GB HBM3"));
+        assert_eq!(profile.name(1).as_deref(), Some("NVIDIA A100-SXM4-40GB"));
+        assert_eq!(profile.total_mem(0), Some(80 << 30));
+        assert_eq!(profile.total_mem(1), Some(40 << 30));
+        assert_eq!(profile.compute_capability(1), Some((8, 0)));
+
+        let err = Profile::from_toml("[attributes]\nwarp_width = 64").unwrap_err();
+        assert!(matches!(err, ProfileError::UnknownAttribute(name) if name == "warp_width"));
+        assert!(matches!(
+            Profile::from_toml("nmae = \"x\""),
+            Err(ProfileError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn overrides_attributes_per_device() {
+        let profile = Profile::from_toml(PROFILE).unwrap();
+        // Per-device attributes win, then the defaults, then the compute capability
+        assert_eq!(profile.attribute(0, 16), Some(132));
+        assert_eq!(profile.attribute(1, 16), Some(108));
+        assert_eq!(profile.attribute(1, 38), Some(52428800));
+        assert_eq!(profile.attribute(0, 39), Some(2048));
+        assert_eq!(
+            profile.attribute(0, CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR),
+            Some(9)
+        );
+        assert_eq!(
+            profile.attribute(1, CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR),
+            Some(8)
+        );
+        assert_eq!(profile.attribute(0, 10), None);
+        assert_eq!(Profile::default().attribute(0, 16), None);
+    }
+}
@@ -0,0 +1,127 @@
+#!/usr/bin/env python3
+"""Writes `kernels.cubin`, the cubin fixture the `cubin` unit tests parse.
+
+There is no ptxas in the test environment, so the cubin is assembled here following the layout
+documented in `src/cubin.rs`. It has two kernels:
+
+- `scale(float* data, int n)`, with `__launch_bounds__(256)`: its `.nv.info.scale` section lists
+  the parameters last first, as ptxas does, followed by `EIATTR_CBANK_PARAM_SIZE` and
+  `EIATTR_MAX_THREADS`. The shared `.nv.info` section gives it 16 registers.
+- `fill(char* data)`: one parameter and no `EIATTR_CBANK_PARAM_SIZE`, so the buffer size is
+  worked out from the parameters.
+
+Run from this directory to regenerate: `python3 make_cubin.py`.
+"""
+
+import struct
+
+EIFMT_HVAL = 0x03
+EIFMT_SVAL = 0x04
+EIATTR_MAX_THREADS = 0x05
+EIATTR_KPARAM_INFO = 0x17
+EIATTR_CBANK_PARAM_SIZE = 0x19
+EIATTR_REGCOUNT = 0x2F
+
+SHT_PROGBITS = 1
+SHT_SYMTAB = 2
+SHT_STRTAB = 3
+SHT_CUDA_INFO = 0x70000000
+STT_FUNC = 2
+STB_GLOBAL = 1
+
+
+def hval(attribute, value):
+    return struct.pack("<BBH", EIFMT_HVAL, attribute, value)
+
+
+def sval(attribute, payload):
+    return struct.pack("<BBH", EIFMT_SVAL, attribute, len(payload)) + payload
+
+
+def kparam(ordinal, offset, size):
+    # Constant bank 0x1f and 8-byte alignment below the size
+    flags = (size << 18) | 0x1F000
+    return sval(EIATTR_KPARAM_INFO, struct.pack("<IHHI", 0, ordinal, offset, flags))
+
+
+def strtab(names):
+    table = b"\0"
+    offsets = {}
+    for name in names:
+        offsets[name] = len(table)
+        table += name.encode() + b"\0"
+    return table, offsets
+
+
+def main():
+    section_names = [
+        ".shstrtab",
+        ".strtab",
+        ".symtab",
+        ".nv.info",
+        ".nv.info.scale",
+        ".nv.info.fill",
+        ".text.scale",
+        ".text.fill",
+    ]
+    shstrtab, shnames = strtab(section_names)
+    symstrtab, symnames = strtab(["scale", "fill"])
+
+    # Section indices: 7 and 8 are the code of the kernels
+    symtab = bytes(24)
+    for name, shndx in (("scale", 7), ("fill", 8)):
+        symtab += struct.pack(
+            "<IBBHQQ", symnames[name], (STB_GLOBAL << 4) | STT_FUNC, 0, shndx, 0, 16
+        )
+
+    shared_info = sval(EIATTR_REGCOUNT, struct.pack("<II", 1, 16))
+    scale_info = (
+        kparam(1, 8, 4)
+        + kparam(0, 0, 8)
+        + hval(EIATTR_CBANK_PARAM_SIZE, 12)
+        + sval(EIATTR_MAX_THREADS, struct.pack("<III", 256, 1, 1))
+    )
+    fill_info = kparam(0, 0, 8)
+    code = bytes(16)
+
+    # (name, type, link, info, contents)
+    sections = [
+        (".shstrtab", SHT_STRTAB, 0, 0, shstrtab),
+        (".strtab", SHT_STRTAB, 0, 0, symstrtab),
+        (".symtab", SHT_SYMTAB, 2, 1, symtab),
+        (".nv.info", SHT_CUDA_INFO, 3, 0, shared_info),
+        (".nv.info.scale", SHT_CUDA_INFO, 3, 7, scale_info),
+        (".nv.info.fill", SHT_CUDA_INFO, 3, 8, fill_info),
+        (".text.scale", SHT_PROGBITS, 3, 0, code),
+        (".text.fill", SHT_PROGBITS, 3, 0, code),
+    ]
+
+    body = b""
+    offsets = []
+    for _, _, _, _, contents in sections:
+        body += bytes(-len(body) % 8)
+        offsets.append(64 + len(body))
+        body += contents
+    body += bytes(-len(body) % 8)
+    shoff = 64 + len(body)
+
+    headers = bytes(64)
+    for (name, kind, link, info, contents), offset in zip(sections, offsets):
+        entsize = 24 if kind == SHT_SYMTAB else 0
+        headers += struct.pack(
+            "<IIQQQQIIQQ", shnames[name], kind, 0, 0, offset, len(contents), link, info, 8,
+            entsize,
+        )
+
+    # EM_CUDA, sm_80, the section header string table at index 1
+    header = b"\x7fELF\x02\x01\x01\x33\x07" + bytes(7) + struct.pack(
+        "<HHIQQQIHHHHHH", 2, 190, 1, 0, 0, shoff, 0x500550, 64, 0, 0, 64, len(sections) + 1, 1
+    )
+    assert len(header) == 64
+
+    with open("kernels.cubin", "wb") as f:
+        f.write(header + body + headers)
+
+
+if __name__ == "__main__":
+    main()
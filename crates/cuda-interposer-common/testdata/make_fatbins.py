@@ -0,0 +1,156 @@
+#!/usr/bin/env python3
+"""Writes the fatbin fixtures the `fatbin` unit tests parse.
+
+There is no nvcc in the test environment, so the fixtures are assembled here following the
+layout documented in `src/fatbin.rs`:
+
+- `plain.fatbin`: an uncompressed sm_80 cubin entry and an uncompressed compute_80 PTX entry.
+- `compressed.fatbin`: the same cubin, LZ4 compressed, and the same PTX, Zstandard compressed.
+
+The compressors are written out by hand to keep the script dependency-free. The LZ4 block
+encodes the zero run after the ELF header as a match; the Zstandard frame stores the PTX text as
+a raw block and its NUL padding as an RLE block.
+
+Run from this directory to regenerate: `python3 make_fatbins.py`.
+"""
+
+import struct
+
+FATBIN_MAGIC = 0xBA55ED50
+KIND_PTX = 1
+KIND_CUBIN = 2
+FLAG_64BIT = 0x1
+FLAG_COMPRESSED_LZ4 = 0x2000
+FLAG_COMPRESSED_ZSTD = 0x8000
+
+# An ELF64 header for a CUDA cubin (EM_CUDA = 190), followed by zeros standing in for sections.
+CUBIN = (
+    b"\x7fELF\x02\x01\x01\x33\x07" + bytes(7)
+    + struct.pack("<HHIQQQIHHHHHH", 2, 190, 1, 0, 0, 0, 0x500550, 64, 0, 0, 0, 0, 0)
+    + bytes(192)
+)
+PTX = b"""//
+// Generated for cuda-interposer's fatbin tests
+//
+
+.version 8.0
+.target sm_80
+.address_size 64
+
+.visible .entry add_one(
+\t.param .u64 add_one_param_0
+)
+{
+\tret;
+}
+"""
+PTX_PADDED = PTX + bytes(64 - len(PTX) % 64)
+
+
+def pad8(data):
+    return data + bytes(-len(data) % 8)
+
+
+def entry(kind, arch, version, payload, flags=FLAG_64BIT, uncompressed_size=0):
+    compressed = flags & (FLAG_COMPRESSED_LZ4 | FLAG_COMPRESSED_ZSTD)
+    padded = pad8(payload)
+    header = struct.pack(
+        "<HHIQIIHHIIIQQQ",
+        kind,
+        0x0101,
+        64,
+        len(padded),
+        len(payload) if compressed else 0,
+        0,
+        version[1],
+        version[0],
+        arch,
+        0,
+        0,
+        flags,
+        0,
+        uncompressed_size,
+    )
+    assert len(header) == 64
+    return header + padded
+
+
+def fatbin(*entries):
+    body = b"".join(entries)
+    return struct.pack("<IHHQ", FATBIN_MAGIC, 1, 16, len(body)) + body
+
+
+def lz4_length(n):
+    out = b""
+    while n >= 255:
+        out += b"\xff"
+        n -= 255
+    return out + bytes([n])
+
+
+def lz4_block(data):
+    """Encodes `data`, a prefix followed by a run of zeros, as one literal run ending in the
+    first zero, a match copying that zero over the rest of the run, and the five literal bytes
+    an LZ4 block must end with."""
+    prefix = data.rstrip(b"\0") + b"\0"
+    run = len(data) - len(prefix) - 5
+    assert run >= 4
+    match = run - 4
+    token = (min(len(prefix), 15) << 4) | min(match, 15)
+    block = bytes([token])
+    if len(prefix) >= 15:
+        block += lz4_length(len(prefix) - 15)
+    block += prefix + struct.pack("<H", 1)
+    if match >= 15:
+        block += lz4_length(match - 15)
+    return block + bytes([5 << 4]) + bytes(5)
+
+
+def zstd_frame(data):
+    """Encodes `data`, text followed by a run of zeros, as a raw block and an RLE block."""
+    text = data.rstrip(b"\0")
+    run = len(data) - len(text)
+    # Single segment frame with a 4-byte content size
+    frame = struct.pack("<I", 0xFD2FB528) + bytes([0xA0]) + struct.pack("<I", len(data))
+    frame += struct.pack("<I", len(text) << 3)[:3] + text
+    frame += struct.pack("<I", (run << 3) | (1 << 1) | 1)[:3] + b"\0"
+    return frame
+
+
+def main():
+    with open("plain.fatbin", "wb") as f:
+        f.write(
+            fatbin(
+                entry(KIND_CUBIN, 80, (1, 7), CUBIN),
+                entry(KIND_PTX, 80, (8, 0), PTX_PADDED),
+            )
+        )
+    with open("compressed.fatbin", "wb") as f:
+        f.write(
+            fatbin(
+                entry(
+                    KIND_CUBIN,
+                    80,
+                    (1, 7),
+                    lz4_block(CUBIN),
+                    FLAG_64BIT | FLAG_COMPRESSED_LZ4,
+                    len(CUBIN),
+                ),
+                entry(
+                    KIND_PTX,
+                    80,
+                    (8, 0),
+                    zstd_frame(PTX_PADDED),
+                    FLAG_64BIT | FLAG_COMPRESSED_ZSTD,
+                    len(PTX_PADDED),
+                ),
+            )
+        )
+    with open("cubin.bin", "wb") as f:
+        f.write(CUBIN)
+    with open("kernel.ptx", "wb") as f:
+        f.write(PTX)
+
+
+if __name__ == "__main__":
+    main()
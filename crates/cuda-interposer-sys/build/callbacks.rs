@@ -5,6 +5,8 @@ use std::sync;
 
 use bindgen::callbacks::{DeriveInfo, ItemInfo, ItemKind, MacroParsingBehavior, ParseCallbacks};
 
+use crate::params;
+
 /// Enum to handle different callback combinations
 #[derive(Debug)]
 pub(crate) enum BindgenCallbacks {
@@ -12,6 +14,8 @@ pub(crate) enum BindgenCallbacks {
     WithFunctionRenames {
         function_renames: Box<FunctionRenames>,
         cargo_callbacks: bindgen::CargoCallbacks,
+        /// Collects the parameter directions of the doxygen comments, when set.
+        param_docs: Option<sync::Arc<params::ParamDocs>>,
     },
     /// For bindings that only need comment processing (nvptx, nvvm)
     Simple {
@@ -24,9 +28,18 @@ impl BindgenCallbacks {
         Self::WithFunctionRenames {
             function_renames: Box::new(function_renames),
             cargo_callbacks: bindgen::CargoCallbacks::new(),
+            param_docs: None,
         }
     }
 
+    /// Records the `\param` entries of the comments into `docs` as well.
+    pub fn with_param_docs(mut self, docs: sync::Arc<params::ParamDocs>) -> Self {
+        if let Self::WithFunctionRenames { param_docs, .. } = &mut self {
+            *param_docs = Some(docs);
+        }
+        self
+    }
+
     pub fn simple() -> Self {
         Self::Simple {
             cargo_callbacks: bindgen::CargoCallbacks::new(),
@@ -36,6 +49,13 @@ impl BindgenCallbacks {
 
 impl ParseCallbacks for BindgenCallbacks {
     fn process_comment(&self, comment: &str) -> Option<String> {
+        if let Self::WithFunctionRenames {
+            param_docs: Some(docs),
+            ..
+        } = self
+        {
+            docs.record(comment);
+        }
         // First replace backslashes with @ to avoid doctest parsing issues
         let cleaned = comment.replace('\\', "@");
         // Then transform doxygen syntax to rustdoc
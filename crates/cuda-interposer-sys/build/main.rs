@@ -23,13 +23,23 @@
 //! - `DEP_CUDA_INCLUDES`: The include directories for the CUDA SDK, separated by platform-specific path separator.
 //! - `DEP_CUDA_NVVM_INCLUDES`: The include directories for NVVM headers, separated by platform-specific path separator.
 //!
+//! ## Parameter metadata
+//! Along with the internal driver bindings, the build script writes `driver_params.rs`, the
+//! direction and buffer size of every parameter of the driver API, exposed as
+//! `driver_params::FUNCTION_PARAMS`. Directions come from the `\param[in]`/`\param[out]`
+//! doxygen comments of `cuda.h`, or from descriptions starting with `Returned`, and sizes from
+//! the pointee types and `build/param_sizes.txt`. The generated file starts with a comment
+//! counting the pointer parameters whose direction is known.
+//!
 
 use std::env;
 use std::fs;
 use std::path;
+use std::sync;
 
 pub mod callbacks;
 pub mod cuda_sdk;
+pub mod params;
 
 fn main() {
     let outdir = path::PathBuf::from(
@@ -179,6 +189,7 @@ fn create_cuda_driver_internal_bindings(
     let bindgen_path = path::PathBuf::from(format!("{}/driver_internal_sys.rs", outdir.display()));
     let header = manifest_dir.join("build/driver_internal_wrapper.h");
     println!("cargo::rerun-if-changed={}", header.display());
+    let param_docs = sync::Arc::new(params::ParamDocs::default());
     let bindings = bindgen::Builder::default()
         .header(header.to_str().expect("header should be valid UTF-8"))
         .parse_callbacks(Box::new(
@@ -187,7 +198,8 @@ fn create_cuda_driver_internal_bindings(
                 outdir,
                 header,
                 sdk.cuda_include_paths().to_owned(),
-            )),
+            ))
+            .with_param_docs(param_docs.clone()),
         ))
         .clang_args(
             sdk.cuda_include_paths()
@@ -222,6 +234,13 @@ fn create_cuda_driver_internal_bindings(
     bindings
         .write_to_file(bindgen_path.as_path())
         .expect("Cannot write CUDA driver bindgen output to file.");
+    params::write_table(
+        outdir.join("driver_params.rs").as_path(),
+        &bindings.to_string(),
+        "cu",
+        &param_docs,
+        manifest_dir.join("build/param_sizes.txt").as_path(),
+    );
 }
 
 fn create_cuda_runtime_bindings(
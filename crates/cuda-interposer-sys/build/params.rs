@@ -0,0 +1,121 @@
+//! Parameter direction and buffer size metadata.
+//!
+//! The doxygen comments of the headers document the parameters of each function with
+//! `\param[in]`, `\param[out]` or `\param[in,out]`, or, as most of `cuda.h` does, with a
+//! description starting with `Returned` for outputs. Bindgen hands comments to
+//! [`crate::callbacks::BindgenCallbacks`] without the item they belong to, so [`ParamDocs`]
+//! keys the directions of a comment by its parameter names, in order, and [`write_table`] pairs
+//! them with the functions of the generated bindings afterwards. Two comments with the same
+//! parameter names but different directions make the directions of those parameters unknown.
+//!
+//! The headers do not say how large the pointee of a `void *` is, so sizes come from the pointee
+//! type when it has one, and from `build/param_sizes.txt` otherwise.
+//!
+//! The parsing is done by [`cuda_interposer_tables::params`], where it is tested.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path;
+
+pub(crate) use cuda_interposer_tables::params::ParamDocs;
+use cuda_interposer_tables::params::{Direction, Overrides, Size, functions, parse_overrides};
+
+/// How a size is written in the table.
+fn size_expr(size: &Size) -> String {
+    match size {
+        Size::Unknown => "Size::Unknown".into(),
+        Size::Pointee(ty) => format!("Size::pointee::<{ty}>()"),
+        Size::Bytes(n) => format!("Size::Bytes({n})"),
+        Size::Param(p) => format!("Size::Param({p:?})"),
+        Size::Elements(count, ty) => format!(
+            "Size::Elements {{ count: {count:?}, elem_size: ::core::mem::size_of::<{ty}>() }}"
+        ),
+        Size::CStr => "Size::CStr".into(),
+        Size::Image => "Size::Image".into(),
+    }
+}
+
+/// Reads the manual sizes and directions, as `function param size [direction]` lines.
+fn read_overrides(path: &path::Path) -> Overrides {
+    println!("cargo::rerun-if-changed={}", path.display());
+    let content = fs::read_to_string(path).expect("Cannot read parameter size overrides.");
+    parse_overrides(&content).unwrap_or_else(|e| panic!("{}:{e}", path.display()))
+}
+
+/// Returns the default direction and size of a parameter of type `ty`.
+fn defaults(ty: &str) -> (Direction, Size) {
+    let pointee = |prefix: &str| ty.strip_prefix(prefix).map(str::trim);
+    if let Some(pointee) = pointee("*const") {
+        let size = match pointee {
+            "::std::os::raw::c_char" | "::core::ffi::c_char" => Size::CStr,
+            "::std::os::raw::c_void" | "::core::ffi::c_void" => Size::Unknown,
+            ty => Size::Pointee(ty.into()),
+        };
+        (Direction::In, size)
+    } else if let Some(pointee) = pointee("*mut") {
+        let size = match pointee {
+            "::std::os::raw::c_void" | "::core::ffi::c_void" => Size::Unknown,
+            "::std::os::raw::c_char" | "::core::ffi::c_char" => Size::Unknown,
+            ty => Size::Pointee(ty.into()),
+        };
+        (Direction::Unknown, size)
+    } else {
+        (Direction::In, Size::Unknown)
+    }
+}
+
+/// Writes the parameter table of the `prefix` functions in `bindings` to `path`, as a
+/// `FUNCTION_PARAMS` slice of `FunctionParams` included where the binding types are in scope.
+/// The table starts with a comment counting the pointer parameters whose direction is known.
+pub(crate) fn write_table(
+    path: &path::Path,
+    bindings: &str,
+    prefix: &str,
+    docs: &ParamDocs,
+    overrides_path: &path::Path,
+) {
+    let mut overrides = read_overrides(overrides_path);
+    let mut out = String::from("pub static FUNCTION_PARAMS: &[FunctionParams] = &[\n");
+    // Pointer parameters, and how many of them have a known direction
+    let (mut pointers, mut resolved) = (0, 0);
+    for (function, params) in functions(bindings, prefix) {
+        let directions = docs.directions(&params);
+        writeln!(
+            out,
+            "    FunctionParams {{\n        name: {function:?},\n        params: &["
+        )
+        .unwrap();
+        for (i, param) in params.iter().enumerate() {
+            let (mut direction, mut size) = defaults(&param.ty);
+            match directions.as_ref().map(|d| d[i]) {
+                Some(Direction::Unknown) | None => {}
+                Some(documented) => direction = documented,
+            }
+            if let Some((s, d)) = overrides.remove(&(function.clone(), param.name.clone())) {
+                size = s.unwrap_or(size);
+                direction = d.unwrap_or(direction);
+            }
+            if param.ty.starts_with('*') {
+                pointers += 1;
+                resolved += usize::from(direction != Direction::Unknown);
+            }
+            writeln!(
+                out,
+                "            ParamInfo {{ name: {:?}, direction: Direction::{}, size: {} }},",
+                param.name,
+                direction.variant(),
+                size_expr(&size)
+            )
+            .unwrap();
+        }
+        writeln!(out, "        ],\n    }},").unwrap();
+    }
+    out.push_str("];\n");
+    let out = format!(
+        "// The direction of {resolved} of the {pointers} pointer parameters is known.\n\n{out}"
+    );
+    for (function, param) in overrides.keys() {
+        println!("cargo:warning=Parameter size override for unknown parameter {function}({param})");
+    }
+    fs::write(path, out).expect("Cannot write parameter metadata table.");
+}
@@ -0,0 +1,71 @@
+//! Direction and buffer size of the parameters of the driver API.
+//!
+//! The table is generated by the build script from the doxygen comments of `cuda.h`, the
+//! pointee types of the parameters, and `build/param_sizes.txt` for the pointers whose size the
+//! types do not tell, as `cuMemcpyHtoD_v2` reading `ByteCount` bytes from `srcHost`.
+
+use crate::driver_internal_sys::*;
+
+/// Whether the function reads or writes through a parameter. Parameters passed by value and
+/// `const` pointers are inputs unless documented otherwise.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    In,
+    Out,
+    InOut,
+    Unknown,
+}
+
+/// The size of the buffer a pointer parameter points to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Size {
+    /// Not a pointer, or nothing is known about the pointee.
+    Unknown,
+    /// A fixed number of bytes, as the pointee type of a typed pointer.
+    Bytes(usize),
+    /// As many bytes as the value of another parameter.
+    Param(&'static str),
+    /// As many elements of `elem_size` bytes as the value of another parameter, or the value it
+    /// points to.
+    Elements {
+        count: &'static str,
+        elem_size: usize,
+    },
+    /// A NUL-terminated string.
+    CStr,
+    /// A module image, sized by its own headers.
+    Image,
+}
+
+impl Size {
+    /// The size of one `T`, or [`Size::Unknown`] for opaque types.
+    pub const fn pointee<T>() -> Self {
+        match size_of::<T>() {
+            0 => Self::Unknown,
+            n => Self::Bytes(n),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub direction: Direction,
+    pub size: Size,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FunctionParams {
+    pub name: &'static str,
+    pub params: &'static [ParamInfo],
+}
+
+include!(concat!(env!("OUT_DIR"), "/driver_params.rs"));
+
+/// Returns the parameters of driver function `name`, in order.
+pub fn params(name: &str) -> Option<&'static [ParamInfo]> {
+    FUNCTION_PARAMS
+        .iter()
+        .find(|f| f.name == name)
+        .map(|f| f.params)
+}
@@ -10,10 +10,15 @@ pub mod driver_internal_sys;
 #[allow(clippy::missing_safety_doc)]
 pub mod driver_internal_helpers;
 
+#[cfg(feature = "driver")]
+pub mod driver_params;
+
 #[cfg(feature = "runtime")]
 #[allow(clippy::missing_safety_doc)]
 pub mod runtime_sys;
 
+mod status;
+
 #[cfg(feature = "cublas")]
 #[allow(clippy::missing_safety_doc)]
 pub mod cublas_sys;
@@ -0,0 +1,27 @@
+//! [`Status`] for the status enums, so `cuda-interposer` can inject faults into calls returning
+//! them.
+
+#[allow(unused_imports)]
+use cuda_interposer_tables::status::{DRIVER_ERRORS, RUNTIME_ERRORS, Status};
+
+#[allow(unused_macros)]
+macro_rules! impl_status {
+    ($ty:ty, $codes:expr) => {
+        impl Status for $ty {
+            fn from_code(code: u32) -> Option<Self> {
+                // SAFETY: the enum is `repr(u32)` and defines every code of the table.
+                $codes
+                    .iter()
+                    .any(|(_, c)| *c == code)
+                    .then(|| unsafe { std::mem::transmute::<u32, Self>(code) })
+            }
+        }
+    };
+}
+
+#[cfg(feature = "driver")]
+impl_status!(crate::driver_sys::CUresult, DRIVER_ERRORS);
+#[cfg(feature = "driver")]
+impl_status!(crate::driver_internal_sys::CUresult, DRIVER_ERRORS);
+#[cfg(feature = "runtime")]
+impl_status!(crate::runtime_sys::cudaError_t, RUNTIME_ERRORS);
@@ -0,0 +1,22 @@
+//! The structures the generated `CudaCall` reads through pointers. See `cuda_interposer::calls`.
+
+/// Returns the type of `cuda_interposer::calls` a pointer to C type `ty` is read into, if it is
+/// followed.
+pub fn followed(ty: &str) -> Option<&'static str> {
+    Some(match ty {
+        "CUDA_MEMCPY3D" | "CUDA_MEMCPY3D_v2" | "CUDA_MEMCPY3D_st" => "Memcpy3D",
+        "CUDA_MEMCPY2D" | "CUDA_MEMCPY2D_v2" | "CUDA_MEMCPY2D_st" => "Memcpy2D",
+        "CUDA_LAUNCH_PARAMS" | "CUDA_LAUNCH_PARAMS_v1" | "CUDA_LAUNCH_PARAMS_st" => "LaunchParams",
+        "CUlaunchConfig" | "CUlaunchConfig_v1" | "CUlaunchConfig_st" => "LaunchConfig",
+        _ => return None,
+    })
+}
+
+/// Returns the parameter holding the length of the array that parameter `param` of `symbol`
+/// points to, for followed pointers to more than one structure.
+pub fn followed_array(symbol: &str, param: &str) -> Option<&'static str> {
+    match (symbol, param) {
+        ("cuLaunchCooperativeKernelMultiDevice", "launchParamsList") => Some("numDevices"),
+        _ => None,
+    }
+}
@@ -0,0 +1,64 @@
+//! The kinds of handles that can be virtualized, and the calls that create and release them.
+//! See `cuda_interposer::handles`.
+
+/// A kind of driver handle that can be virtualized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HandleKind {
+    Context,
+    Stream,
+    Event,
+    Module,
+    Function,
+    DevicePtr,
+}
+
+impl HandleKind {
+    pub const ALL: [HandleKind; 6] = [
+        HandleKind::Context,
+        HandleKind::Stream,
+        HandleKind::Event,
+        HandleKind::Module,
+        HandleKind::Function,
+        HandleKind::DevicePtr,
+    ];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "context" => HandleKind::Context,
+            "stream" => HandleKind::Stream,
+            "event" => HandleKind::Event,
+            "module" => HandleKind::Module,
+            "function" => HandleKind::Function,
+            "deviceptr" => HandleKind::DevicePtr,
+            _ => return None,
+        })
+    }
+
+    /// Returns the kind of handle a pointer of type `ty`, as bindgen writes it, points to, i.e.
+    /// what an out-parameter of that type returns. Used by `cuda-interposer-build`.
+    pub fn of_output(ty: &str) -> Option<Self> {
+        let ty = ty.trim().strip_prefix("*mut")?.trim();
+        Some(match ty {
+            "CUcontext" => HandleKind::Context,
+            "CUstream" | "cudaStream_t" => HandleKind::Stream,
+            "CUevent" | "cudaEvent_t" => HandleKind::Event,
+            "CUmodule" => HandleKind::Module,
+            "CUfunction" => HandleKind::Function,
+            "CUdeviceptr" | "CUdeviceptr_v2" => HandleKind::DevicePtr,
+            _ => return None,
+        })
+    }
+}
+
+/// Returns the kind of handle `symbol` destroys, passed as its first argument. Used by
+/// `cuda-interposer-build`.
+pub fn released_by(symbol: &str) -> Option<HandleKind> {
+    Some(match symbol {
+        "cuCtxDestroy" | "cuCtxDestroy_v2" => HandleKind::Context,
+        "cuStreamDestroy" | "cuStreamDestroy_v2" | "cudaStreamDestroy" => HandleKind::Stream,
+        "cuEventDestroy" | "cuEventDestroy_v2" | "cudaEventDestroy" => HandleKind::Event,
+        "cuModuleUnload" => HandleKind::Module,
+        "cuMemFree" | "cuMemFree_v2" | "cuMemFreeAsync" => HandleKind::DevicePtr,
+        _ => return None,
+    })
+}
@@ -0,0 +1,225 @@
+//! The symbols defined by each of the built-in `install_*_hooks!` macros.
+//!
+//! `cuda-interposer-build` only sees the source of the crate it builds, so it cannot tell which
+//! hooks a macro like `install_memory_hooks!()` expands to. It looks the macro up here instead,
+//! so the hooked symbols land in the hook map and are left out of the generated passthroughs.
+
+/// A group of hooks installed by one macro.
+#[derive(Debug, Clone, Copy)]
+pub struct HookSet {
+    pub macro_name: &'static str,
+    pub symbols: &'static [&'static str],
+}
+
+pub const HOOK_SETS: &[HookSet] = &[
+    HookSet {
+        macro_name: "install_memory_hooks",
+        symbols: &[
+            "cuMemAlloc_v2",
+            "cuMemAllocPitch_v2",
+            "cuMemAllocAsync",
+            "cuMemAllocFromPoolAsync",
+            "cuMemAllocManaged",
+            "cuMemFree_v2",
+            "cuMemFreeAsync",
+            "cuMemCreate",
+            "cuMemRelease",
+            "cuMemGetInfo_v2",
+        ],
+    },
+    HookSet {
+        macro_name: "install_device_hooks",
+        symbols: &[
+            "cuDeviceGetCount",
+            "cuDeviceGet",
+            "cuDeviceGetByPCIBusId",
+            "cuDeviceGetUuid",
+            "cuDeviceGetUuid_v2",
+            "cuDevicePrimaryCtxRetain",
+            "cuCtxGetDevice",
+            "cuDevicePrimaryCtxRelease_v2",
+            "cuDevicePrimaryCtxReset_v2",
+            "cuDevicePrimaryCtxSetFlags_v2",
+            "cuDevicePrimaryCtxGetState",
+            "cuDeviceGetAttribute",
+            "cuDeviceGetName",
+            "cuDeviceTotalMem_v2",
+            "cuDeviceComputeCapability",
+            "cuDeviceGetPCIBusId",
+            "cuCtxCreate_v2",
+            "cuCtxCreate_v3",
+            "cuCtxCreate_v4",
+            "cuMemPrefetchAsync",
+            "cuMemAdvise",
+            "cuMemPrefetchAsync_v2",
+            "cuMemAdvise_v2",
+            "cuMemSetAccess",
+            "cuMemPoolSetAccess",
+            "cuMemGetAccess",
+            "cuMemPoolGetAccess",
+            "cuMemGetAllocationGranularity",
+            "cuMemGetAllocationPropertiesFromHandle",
+            "cuMemPoolCreate",
+            "cuPointerGetAttribute",
+            "cuPointerGetAttributes",
+            "cuDeviceGetLuid",
+            "cuDeviceGetProperties",
+            "cuDeviceGetTexture1DLinearMaxWidth",
+            "cuDeviceGetNvSciSyncAttributes",
+            "cuDeviceGetExecAffinitySupport",
+            "cuDeviceCanAccessPeer",
+            "cuDeviceGetP2PAttribute",
+            "cuDeviceGetDefaultMemPool",
+            "cuDeviceGetMemPool",
+            "cuDeviceSetMemPool",
+            "cuDeviceGetGraphMemAttribute",
+            "cuDeviceSetGraphMemAttribute",
+            "cuDeviceGraphMemTrim",
+            "cuDeviceGetDevResource",
+            "cuDeviceRegisterAsyncNotification",
+            "cuDeviceUnregisterAsyncNotification",
+            "cuGreenCtxCreate",
+            "cuMulticastAddDevice",
+        ],
+    },
+    HookSet {
+        macro_name: "install_runtime_device_hooks",
+        symbols: &["cudaGetDeviceProperties", "cudaGetDeviceProperties_v2"],
+    },
+    HookSet {
+        macro_name: "install_module_hooks",
+        symbols: &["cuModuleGetFunction", "cuLibraryGetKernel"],
+    },
+    HookSet {
+        macro_name: "install_launch_hooks",
+        symbols: &[
+            "cuLaunchKernel",
+            "cuLaunchKernel_ptsz",
+            "cuLaunchCooperativeKernel",
+            "cuLaunchCooperativeKernel_ptsz",
+            "cuLaunchKernelEx",
+            "cuLaunchKernelEx_ptsz",
+            "cudaLaunchKernel",
+            "cudaLaunchKernel_ptsz",
+        ],
+    },
+    HookSet {
+        macro_name: "install_capture_hooks",
+        symbols: &[
+            "cuModuleLoad",
+            "cuModuleLoadData",
+            "cuModuleLoadDataEx",
+            "cuModuleLoadFatBinary",
+            "cuLibraryLoadData",
+            "cuLinkAddData_v2",
+        ],
+    },
+    HookSet {
+        macro_name: "install_registration_hooks",
+        symbols: &[
+            "__cudaRegisterFatBinary",
+            "__cudaUnregisterFatBinary",
+            "__cudaRegisterFunction",
+            "__cudaRegisterVar",
+        ],
+    },
+    HookSet {
+        macro_name: "install_stream_hooks",
+        symbols: &[
+            "cuStreamCreate",
+            "cuStreamCreateWithPriority",
+            "cuStreamDestroy_v2",
+            "cuEventRecord",
+            "cuEventRecord_ptsz",
+            "cuEventRecordWithFlags",
+            "cuEventRecordWithFlags_ptsz",
+            "cuStreamWaitEvent",
+            "cuStreamWaitEvent_ptsz",
+            "cuStreamSynchronize",
+            "cuStreamSynchronize_ptsz",
+            "cuEventSynchronize",
+            "cuCtxSynchronize",
+            "cuMemcpy",
+            "cuMemcpy_ptds",
+            "cuMemcpyAsync",
+            "cuMemcpyAsync_ptsz",
+            "cuMemcpyHtoD_v2",
+            "cuMemcpyHtoD_v2_ptds",
+            "cuMemcpyDtoH_v2",
+            "cuMemcpyDtoH_v2_ptds",
+            "cuMemcpyDtoD_v2",
+            "cuMemcpyDtoD_v2_ptds",
+            "cuMemcpyHtoDAsync_v2",
+            "cuMemcpyHtoDAsync_v2_ptsz",
+            "cuMemcpyDtoHAsync_v2",
+            "cuMemcpyDtoHAsync_v2_ptsz",
+            "cuMemcpyDtoDAsync_v2",
+            "cuMemcpyDtoDAsync_v2_ptsz",
+            "cuMemsetD8_v2",
+            "cuMemsetD8_v2_ptds",
+            "cuMemsetD32_v2",
+            "cuMemsetD32_v2_ptds",
+            "cuMemsetD8Async",
+            "cuMemsetD8Async_ptsz",
+            "cuMemsetD32Async",
+            "cuMemsetD32Async_ptsz",
+        ],
+    },
+    HookSet {
+        macro_name: "install_graph_hooks",
+        symbols: &[
+            "cuGraphInstantiate",
+            "cuGraphInstantiate_v2",
+            "cuGraphInstantiateWithFlags",
+            "cuGraphInstantiateWithParams",
+            "cuGraphLaunch",
+            "cuGraphLaunch_ptsz",
+            "cuGraphExecDestroy",
+        ],
+    },
+    HookSet {
+        macro_name: "install_handle_hooks",
+        symbols: &[
+            "cuMemcpy2D_v2",
+            "cuMemcpy2D_v2_ptds",
+            "cuMemcpy2DUnaligned_v2",
+            "cuMemcpy2DUnaligned_v2_ptds",
+            "cuMemcpy2DAsync_v2",
+            "cuMemcpy2DAsync_v2_ptsz",
+            "cuMemcpy3D_v2",
+            "cuMemcpy3D_v2_ptds",
+            "cuMemcpy3DAsync_v2",
+            "cuMemcpy3DAsync_v2_ptsz",
+            "cuLaunchCooperativeKernelMultiDevice",
+            "cuGraphAddKernelNode_v2",
+            "cuGraphKernelNodeSetParams_v2",
+            "cuGraphExecKernelNodeSetParams_v2",
+            "cuGraphAddMemcpyNode",
+            "cuGraphMemcpyNodeSetParams",
+            "cuGraphExecMemcpyNodeSetParams",
+            "cuGraphAddMemsetNode",
+            "cuGraphMemsetNodeSetParams",
+            "cuGraphExecMemsetNodeSetParams",
+            "cuGraphAddNode",
+            "cuGraphAddNode_v2",
+            "cuGraphNodeSetParams",
+            "cuGraphExecNodeSetParams",
+        ],
+    },
+    HookSet {
+        macro_name: "install_record_hooks",
+        symbols: &[
+            "cuInit",
+            "cuCtxDestroy_v2",
+            "cuCtxSetCurrent",
+            "cuModuleUnload",
+            "cuEventCreate",
+            "cuEventDestroy_v2",
+        ],
+    },
+];
+
+/// Returns the hook set installed by the macro called `macro_name`, if it is a built-in one.
+pub fn find(macro_name: &str) -> Option<&'static HookSet> {
+    HOOK_SETS.iter().find(|s| s.macro_name == macro_name)
+}
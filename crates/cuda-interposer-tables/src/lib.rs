@@ -0,0 +1,16 @@
+//! Tables shared by `cuda-interposer`, `cuda-interposer-build` and `cuda-interposer-sys`.
+//!
+//! The build script crate needs to know which symbols the built-in hook sets define, which calls
+//! release handles, and which structures the generated `CudaCall` follows. The sys crate
+//! implements [`status::Status`] for its status enums so faults can be injected into them.
+//! Keeping these here lets both use them without depending on the runtime crate and everything
+//! it pulls in. [`driver_symbols`] lists the driver API the fake driver stubs out, so it does not
+//! depend on whether the sys bindings were built. [`params`] holds the parsers the sys crate's
+//! build script reads parameter directions and sizes with.
+
+pub mod calls;
+pub mod driver_symbols;
+pub mod handles;
+pub mod hook_sets;
+pub mod params;
+pub mod status;
@@ -0,0 +1,433 @@
+//! Parsers behind the parameter metadata table `cuda-interposer-sys` generates as
+//! `driver_params.rs`. The build script drives them; they live here so they can be tested.
+//!
+//! [`ParamDocs`] collects parameter directions from the doxygen comments of the headers,
+//! [`functions`] reads the parameters of every function out of the generated bindings, and
+//! [`parse_overrides`] reads the manual sizes and directions of `build/param_sizes.txt`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Whether a function reads or writes through a parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+    InOut,
+    Unknown,
+}
+
+impl Direction {
+    /// Parses the `in`, `out` or `in,out` of a `\param[...]` or an override.
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s.replace(' ', "").as_str() {
+            "in" => Self::In,
+            "out" => Self::Out,
+            "in,out" | "inout" | "out,in" => Self::InOut,
+            _ => return None,
+        })
+    }
+
+    /// The name of the variant, as written in the generated table.
+    pub fn variant(self) -> &'static str {
+        match self {
+            Self::In => "In",
+            Self::Out => "Out",
+            Self::InOut => "InOut",
+            Self::Unknown => "Unknown",
+        }
+    }
+
+    /// The direction a `\param` without one implies from its description. `cuda.h` mostly
+    /// leaves directions out, and describes outputs as `Returned ...`.
+    fn from_description(description: &str) -> Self {
+        let description = description.trim_start_matches(|c: char| c.is_whitespace() || c == '-');
+        let lower = description.to_ascii_lowercase();
+        if ["returned ", "returns ", "pointer to return"]
+            .iter()
+            .any(|prefix| lower.starts_with(prefix))
+        {
+            Self::Out
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+/// Parameter directions seen in doxygen comments, keyed by the parameter names of the comment.
+///
+/// Bindgen hands comments over without the item they belong to, so the directions are paired
+/// with functions by their parameter names. Two comments with the same parameter names but
+/// different directions make the directions of those parameters unknown.
+#[derive(Debug, Default)]
+pub struct ParamDocs {
+    by_names: Mutex<HashMap<Vec<String>, Vec<Direction>>>,
+}
+
+impl ParamDocs {
+    /// Records the `\param` (or `@param`) entries of a raw doxygen comment. Entries without a
+    /// `[in]`/`[out]` are outputs if their description starts with `Returned`, and unknown
+    /// otherwise.
+    pub fn record(&self, comment: &str) {
+        let mut names = Vec::new();
+        let mut directions = Vec::new();
+        for line in comment.lines() {
+            let line = line.trim_start_matches(|c: char| c.is_whitespace() || c == '*');
+            let Some(rest) = line
+                .strip_prefix("\\param")
+                .or_else(|| line.strip_prefix("@param"))
+            else {
+                continue;
+            };
+            let documented = match rest.strip_prefix('[') {
+                Some(rest) => {
+                    let Some((dir, rest)) = rest.split_once(']') else {
+                        continue;
+                    };
+                    Some((Direction::parse(dir).unwrap_or(Direction::Unknown), rest))
+                }
+                None => None,
+            };
+            let rest = documented.map_or(rest, |(_, rest)| rest).trim_start();
+            let Some(name) = rest.split_whitespace().next() else {
+                continue;
+            };
+            let direction = match documented {
+                Some((direction, _)) => direction,
+                None => Direction::from_description(&rest[name.len()..]),
+            };
+            names.push(name.to_string());
+            directions.push(direction);
+        }
+        if names.is_empty() {
+            return;
+        }
+        let mut by_names = self.by_names.lock().unwrap();
+        match by_names.get_mut(&names) {
+            Some(seen) => {
+                for (seen, new) in seen.iter_mut().zip(directions) {
+                    if *seen != new {
+                        *seen = Direction::Unknown;
+                    }
+                }
+            }
+            None => {
+                by_names.insert(names, directions);
+            }
+        }
+    }
+
+    /// Returns the documented directions of a function with these parameters, in order.
+    pub fn directions(&self, params: &[Param]) -> Option<Vec<Direction>> {
+        let names: Vec<String> = params.iter().map(|p| p.name.clone()).collect();
+        self.by_names.lock().unwrap().get(&names).cloned()
+    }
+}
+
+/// A parameter of a function in the generated bindings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    /// The Rust type, with whitespace collapsed.
+    pub ty: String,
+}
+
+/// Returns the functions of the generated bindings whose name starts with `prefix`, with their
+/// parameters.
+pub fn functions(bindings: &str, prefix: &str) -> Vec<(String, Vec<Param>)> {
+    let mut functions = Vec::new();
+    let mut rest = bindings;
+    while let Some(start) = rest.find("pub fn ") {
+        rest = &rest[start + "pub fn ".len()..];
+        let Some(open) = rest.find('(') else {
+            break;
+        };
+        let name = rest[..open].trim().to_string();
+        // Split the parameter list on top-level commas.
+        let mut depth = 0;
+        let mut params = Vec::new();
+        let mut current = String::new();
+        let mut end = open + 1;
+        for (i, c) in rest[open + 1..].char_indices() {
+            match c {
+                '(' | '<' | '[' => depth += 1,
+                ')' | ']' if depth > 0 => depth -= 1,
+                // The `>` of a `->` in a function pointer type closes nothing
+                '>' if depth > 0 && !current.ends_with('-') => depth -= 1,
+                ')' => {
+                    end = open + 1 + i;
+                    break;
+                }
+                ',' if depth == 0 => {
+                    params.push(std::mem::take(&mut current));
+                    continue;
+                }
+                _ => {}
+            }
+            current.push(c);
+        }
+        params.push(current);
+        let params = params
+            .iter()
+            .filter_map(|p| {
+                let (name, ty) = p.split_once(':')?;
+                Some(Param {
+                    name: name.trim().to_string(),
+                    ty: ty.split_whitespace().collect::<Vec<_>>().join(" "),
+                })
+            })
+            .collect();
+        rest = &rest[end..];
+        // Skip the methods bindgen generates for bitfields and the like.
+        if name.starts_with(prefix) {
+            functions.push((name, params));
+        }
+    }
+    functions
+}
+
+/// The size of the buffer a parameter points to, as given in the override file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Size {
+    Unknown,
+    /// The size of the pointee type.
+    Pointee(String),
+    Bytes(usize),
+    /// As many bytes as the value of this parameter.
+    Param(String),
+    /// As many elements of this type as the value of (or pointed to by) this parameter.
+    Elements(String, String),
+    CStr,
+    Image,
+}
+
+impl Size {
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "cstr" => Self::CStr,
+            "image" => Self::Image,
+            s if s.parse::<usize>().is_ok() => Self::Bytes(s.parse().ok()?),
+            s => match s.split_once('*') {
+                Some((count, ty)) if !count.trim().is_empty() && !ty.trim().is_empty() => {
+                    Self::Elements(count.trim().into(), ty.trim().into())
+                }
+                Some(_) => return None,
+                None => Self::Param(s.into()),
+            },
+        })
+    }
+}
+
+/// Manual sizes and directions by function and parameter. A `None` keeps the size or direction
+/// the headers give.
+pub type Overrides = HashMap<(String, String), (Option<Size>, Option<Direction>)>;
+
+/// Parses `function param size [direction]` lines, where a size of `-` keeps the pointee size.
+/// Errors name the 1-based line.
+pub fn parse_overrides(content: &str) -> Result<Overrides, String> {
+    let mut overrides = Overrides::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (function, param, rest) = match fields.as_slice() {
+            [function, param, rest @ ..] if !rest.is_empty() => (function, param, rest),
+            _ => {
+                return Err(format!(
+                    "{}: expected `function param size [direction]`",
+                    i + 1
+                ));
+            }
+        };
+        // Sizes like `numNodes * CUgraphNode` contain spaces; the direction, if any, is last.
+        let (size, direction) = match rest.split_last() {
+            Some((last, size)) if !size.is_empty() && Direction::parse(last).is_some() => {
+                (size.join(" "), Direction::parse(last))
+            }
+            _ => (rest.join(" "), None),
+        };
+        let size = match size.as_str() {
+            "-" => None,
+            s => Some(Size::parse(s).ok_or_else(|| format!("{}: invalid size `{s}`", i + 1))?),
+        };
+        overrides.insert((function.to_string(), param.to_string()), (size, direction));
+    }
+    Ok(overrides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(name: &str, ty: &str) -> Param {
+        Param {
+            name: name.into(),
+            ty: ty.into(),
+        }
+    }
+
+    #[test]
+    fn records_documented_directions() {
+        let docs = ParamDocs::default();
+        docs.record(
+            "\\brief Copies memory from Host to Device
+             *
+             * \\param[out] dstDevice - Destination device pointer
+             * \\param[in]  srcHost   - Source host pointer
+             * @param ByteCount - Size of memory copy in bytes
+             * \\return CUDA_SUCCESS",
+        );
+        // cuda.h style, with the direction left to the description
+        docs.record(
+            " * \\param dptr     - Returned device pointer
+              * \\param bytesize - Requested allocation size in bytes",
+        );
+        docs.record("\\param[in,out] x - Both\n\\param[bogus] y - Neither");
+
+        let params = [
+            param("dstDevice", "CUdeviceptr"),
+            param("srcHost", "*const ::core::ffi::c_void"),
+            param("ByteCount", "usize"),
+        ];
+        assert_eq!(
+            docs.directions(&params),
+            Some(vec![Direction::Out, Direction::In, Direction::Unknown])
+        );
+        let params = [
+            param("dptr", "*mut CUdeviceptr"),
+            param("bytesize", "usize"),
+        ];
+        assert_eq!(
+            docs.directions(&params),
+            Some(vec![Direction::Out, Direction::Unknown])
+        );
+        let params = [param("x", "*mut u32"), param("y", "*mut u32")];
+        assert_eq!(
+            docs.directions(&params),
+            Some(vec![Direction::InOut, Direction::Unknown])
+        );
+        assert_eq!(docs.directions(&params[..1]), None);
+    }
+
+    #[test]
+    fn forgets_directions_comments_disagree_on() {
+        let docs = ParamDocs::default();
+        docs.record("\\param[out] handle - A\n\\param[in] flags - B");
+        docs.record("\\param[in] handle - A\n\\param[in] flags - B");
+        docs.record("no parameters here");
+        let params = [param("handle", "*mut u64"), param("flags", "u32")];
+        assert_eq!(
+            docs.directions(&params),
+            Some(vec![Direction::Unknown, Direction::In])
+        );
+    }
+
+    #[test]
+    fn splits_parameters_of_the_bindings() {
+        let bindings = r#"
+            unsafe extern "C" {
+                pub fn cuLaunchHostFunc(
+                    hStream: CUstream,
+                    fn_: CUhostFn,
+                    userData: *mut ::core::ffi::c_void,
+                ) -> CUresult;
+            }
+            unsafe extern "C" {
+                pub fn cuCallback(
+                    callback: ::core::option::Option<
+                        unsafe extern "C" fn(a: *mut u8, b: u32) -> CUresult,
+                    >,
+                    dims: [u32; 3usize],
+                    count: *mut *const u8,
+                ) -> CUresult;
+            }
+            impl Bitfield {
+                pub fn set_bit(&mut self, index: usize, val: bool) {}
+            }
+            unsafe extern "C" {
+                pub fn cuInit(Flags: ::core::ffi::c_uint) -> CUresult;
+                pub fn cuCtxSynchronize() -> CUresult;
+            }
+        "#;
+        let functions = functions(bindings, "cu");
+        let names: Vec<_> = functions.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "cuLaunchHostFunc",
+                "cuCallback",
+                "cuInit",
+                "cuCtxSynchronize"
+            ]
+        );
+        assert_eq!(
+            functions[0].1,
+            [
+                param("hStream", "CUstream"),
+                param("fn_", "CUhostFn"),
+                param("userData", "*mut ::core::ffi::c_void"),
+            ]
+        );
+        assert_eq!(
+            functions[1].1,
+            [
+                param(
+                    "callback",
+                    "::core::option::Option< unsafe extern \"C\" fn(a: *mut u8, b: u32) -> CUresult, >"
+                ),
+                param("dims", "[u32; 3usize]"),
+                param("count", "*mut *const u8"),
+            ]
+        );
+        assert_eq!(functions[2].1, [param("Flags", "::core::ffi::c_uint")]);
+        assert_eq!(functions[3].1, []);
+    }
+
+    #[test]
+    fn parses_overrides() {
+        let overrides = parse_overrides(
+            "# Copies
+             cuMemcpyHtoD_v2   srcHost    ByteCount  in
+             cuGraphGetNodes   nodes      numNodes * CUgraphNode   out
+             cuModuleLoadData  image      image
+             cuDeviceGetName   name       len
+             cuMemGetInfo_v2   free       -          out  # a trailing comment
+             cuIpcGetMemHandle pHandle    64",
+        )
+        .unwrap();
+        let get = |function: &str, param: &str| overrides[&(function.into(), param.into())].clone();
+        assert_eq!(overrides.len(), 6);
+        assert_eq!(
+            get("cuMemcpyHtoD_v2", "srcHost"),
+            (Some(Size::Param("ByteCount".into())), Some(Direction::In))
+        );
+        assert_eq!(
+            get("cuGraphGetNodes", "nodes"),
+            (
+                Some(Size::Elements("numNodes".into(), "CUgraphNode".into())),
+                Some(Direction::Out)
+            )
+        );
+        assert_eq!(get("cuModuleLoadData", "image"), (Some(Size::Image), None));
+        assert_eq!(
+            get("cuDeviceGetName", "name"),
+            (Some(Size::Param("len".into())), None)
+        );
+        assert_eq!(get("cuMemGetInfo_v2", "free"), (None, Some(Direction::Out)));
+        assert_eq!(
+            get("cuIpcGetMemHandle", "pHandle"),
+            (Some(Size::Bytes(64)), None)
+        );
+
+        assert_eq!(
+            parse_overrides("\n\ncuInit Flags").unwrap_err(),
+            "3: expected `function param size [direction]`"
+        );
+        assert_eq!(
+            parse_overrides("cuInit Flags count *").unwrap_err(),
+            "1: invalid size `count *`"
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! The status codes fault injection can return, and the return types it can return them as.
+//! See `cuda_interposer::faults`.
+
+/// `CUresult` values that can be named in a rule.
+pub const DRIVER_ERRORS: &[(&str, u32)] = &[
+    ("CUDA_ERROR_INVALID_VALUE", 1),
+    ("CUDA_ERROR_OUT_OF_MEMORY", 2),
+    ("CUDA_ERROR_NOT_INITIALIZED", 3),
+    ("CUDA_ERROR_DEINITIALIZED", 4),
+    ("CUDA_ERROR_DEVICE_UNAVAILABLE", 46),
+    ("CUDA_ERROR_NO_DEVICE", 100),
+    ("CUDA_ERROR_INVALID_DEVICE", 101),
+    ("CUDA_ERROR_INVALID_IMAGE", 200),
+    ("CUDA_ERROR_INVALID_CONTEXT", 201),
+    ("CUDA_ERROR_NO_BINARY_FOR_GPU", 209),
+    ("CUDA_ERROR_ECC_UNCORRECTABLE", 214),
+    ("CUDA_ERROR_INVALID_PTX", 218),
+    ("CUDA_ERROR_NVLINK_UNCORRECTABLE", 220),
+    ("CUDA_ERROR_JIT_COMPILER_NOT_FOUND", 221),
+    ("CUDA_ERROR_UNSUPPORTED_PTX_VERSION", 222),
+    ("CUDA_ERROR_FILE_NOT_FOUND", 301),
+    ("CUDA_ERROR_INVALID_HANDLE", 400),
+    ("CUDA_ERROR_NOT_FOUND", 500),
+    ("CUDA_ERROR_NOT_READY", 600),
+    ("CUDA_ERROR_ILLEGAL_ADDRESS", 700),
+    ("CUDA_ERROR_LAUNCH_OUT_OF_RESOURCES", 701),
+    ("CUDA_ERROR_LAUNCH_TIMEOUT", 702),
+    ("CUDA_ERROR_CONTEXT_IS_DESTROYED", 708),
+    ("CUDA_ERROR_ASSERT", 710),
+    ("CUDA_ERROR_HARDWARE_STACK_ERROR", 714),
+    ("CUDA_ERROR_ILLEGAL_INSTRUCTION", 715),
+    ("CUDA_ERROR_MISALIGNED_ADDRESS", 716),
+    ("CUDA_ERROR_LAUNCH_FAILED", 719),
+    ("CUDA_ERROR_NOT_PERMITTED", 800),
+    ("CUDA_ERROR_NOT_SUPPORTED", 801),
+    ("CUDA_ERROR_SYSTEM_NOT_READY", 802),
+    ("CUDA_ERROR_STREAM_CAPTURE_INVALIDATED", 901),
+    ("CUDA_ERROR_TIMEOUT", 909),
+    ("CUDA_ERROR_UNKNOWN", 999),
+];
+
+/// `cudaError_t` values that can be named in a rule.
+pub const RUNTIME_ERRORS: &[(&str, u32)] = &[
+    ("cudaErrorInvalidValue", 1),
+    ("cudaErrorMemoryAllocation", 2),
+    ("cudaErrorInitializationError", 3),
+    ("cudaErrorCudartUnloading", 4),
+    ("cudaErrorInvalidConfiguration", 9),
+    ("cudaErrorInvalidSymbol", 13),
+    ("cudaErrorInvalidDevicePointer", 17),
+    ("cudaErrorInvalidMemcpyDirection", 21),
+    ("cudaErrorInsufficientDriver", 35),
+    ("cudaErrorDevicesUnavailable", 46),
+    ("cudaErrorNoDevice", 100),
+    ("cudaErrorInvalidDevice", 101),
+    ("cudaErrorInvalidKernelImage", 200),
+    ("cudaErrorNoKernelImageForDevice", 209),
+    ("cudaErrorECCUncorrectable", 214),
+    ("cudaErrorInvalidResourceHandle", 400),
+    ("cudaErrorNotReady", 600),
+    ("cudaErrorIllegalAddress", 700),
+    ("cudaErrorLaunchOutOfResources", 701),
+    ("cudaErrorLaunchTimeout", 702),
+    ("cudaErrorAssert", 710),
+    ("cudaErrorIllegalInstruction", 715),
+    ("cudaErrorMisalignedAddress", 716),
+    ("cudaErrorLaunchFailure", 719),
+    ("cudaErrorNotSupported", 801),
+    ("cudaErrorTimeout", 909),
+    ("cudaErrorUnknown", 999),
+];
+
+/// A status type an intercepted call can be failed with.
+///
+/// Faults are only injected into calls returning a `Status`, so calls returning handles,
+/// pointers, counts or nothing are never failed. `cuda-interposer` implements it for `u32`,
+/// which its own `CUresult` is an alias of, and `cuda-interposer-sys` for its `CUresult` and
+/// `cudaError_t` enums.
+pub trait Status: Copy {
+    /// Returns `code` as this type, or `None` if the type has no such value.
+    fn from_code(code: u32) -> Option<Self>;
+}
+
+impl Status for u32 {
+    fn from_code(code: u32) -> Option<Self> {
+        Some(code)
+    }
+}
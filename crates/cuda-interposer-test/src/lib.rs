@@ -0,0 +1,439 @@
+//! A scriptable mock driver for unit testing hooks.
+//!
+//! Hooks written with `cuda_hook!` forward to `__real_*` entry points, which are normally looked
+//! up in libcuda. [`mock_driver!`] declares stand-ins for the real entry points a test needs and
+//! a `mock_driver()` function that installs them with [`cuda_interposer::set_resolver`]. Tests
+//! then say what the driver does with [`MockDriver::expect`], call their hooks directly, and
+//! check what the hooks forwarded with the call log:
+//!
+//! ```ignore
+//! use cuda_interposer::ffi::*;
+//! use cuda_interposer_test::mock_driver;
+//!
+//! mock_driver! {
+//!     fn cuMemAlloc_v2(dptr: *mut CUdeviceptr, bytesize: usize) -> CUresult;
+//! }
+//!
+//! #[test]
+//! fn rounds_up_allocations() {
+//!     let driver = mock_driver();
+//!     driver.expect("cuMemAlloc_v2").writes(0, 0x7f00_0000_0000u64);
+//!     let mut ptr = 0;
+//!     assert_eq!(unsafe { my_hooks::cuMemAlloc_v2(&mut ptr, 1000) }, CUDA_SUCCESS);
+//!     assert_eq!(ptr, 0x7f00_0000_0000);
+//!     assert_eq!(driver.calls_to("cuMemAlloc_v2")[0].args[1], 1024);
+//! }
+//! ```
+//!
+//! Arguments are logged as 64-bit words: integers and enums by value, pointers and handles by
+//! address. A call nothing expects is logged, returns `CUDA_ERROR_NOT_SUPPORTED`, and fails
+//! [`MockDriver::verify`].
+//!
+//! Each `__real_*` entry point is resolved once per process, so a test binary should declare
+//! every entry point its tests mock in one `mock_driver!`. Tests holding a [`MockDriver`] run
+//! one at a time, since the hooks and the mock share process-wide state.
+
+use cuda_interposer::ffi::{CUDA_ERROR_NOT_SUPPORTED, CUDA_SUCCESS, CUresult};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+
+/// A call a hook forwarded to the driver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub name: String,
+    pub args: Vec<u64>,
+}
+
+type Action = Arc<dyn Fn(&[u64]) -> CUresult + Send + Sync>;
+
+struct Expectation {
+    name: String,
+    returns: CUresult,
+    writes: Vec<(usize, Vec<u8>)>,
+    action: Option<Action>,
+    times: Option<usize>,
+    calls: usize,
+}
+
+impl Expectation {
+    fn exhausted(&self) -> bool {
+        self.times.is_some_and(|times| self.calls >= times)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    expectations: Vec<Expectation>,
+    calls: Vec<Call>,
+    unexpected: Vec<Call>,
+}
+
+/// The declared entry points by name. Kept across tests, since the `__real_*` entry points that
+/// resolved to them are.
+static SYMBOLS: Lazy<RwLock<HashMap<&'static str, usize>>> = Lazy::new(Default::default);
+static STATE: Lazy<Mutex<State>> = Lazy::new(Default::default);
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn resolve(name: &str) -> *mut c_void {
+    match SYMBOLS.read().unwrap().get(name) {
+        Some(&addr) => addr as *mut c_void,
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Declares mock entry points for driver or runtime APIs, with the signatures the hooks under
+/// test give them, and a `mock_driver()` function returning a [`MockDriver`] that installs them.
+#[macro_export]
+macro_rules! mock_driver {
+    ( $( fn $name:ident ( $($arg:ident : $arg_ty:ty),* $(,)? ) -> $ret:ty; )* ) => {
+        mod __mock_driver {
+            #![allow(non_snake_case, unused_imports)]
+            use super::*;
+
+            $(
+                pub unsafe extern "C" fn $name( $($arg : $arg_ty),* ) -> $ret {
+                    let rc = $crate::call(stringify!($name), &[ $( $crate::word($arg) ),* ]);
+                    $crate::from_word(rc as u64)
+                }
+            )*
+        }
+
+        /// Installs the mock driver declared by `mock_driver!`.
+        #[allow(dead_code)]
+        fn mock_driver() -> $crate::MockDriver {
+            $crate::MockDriver::install(&[
+                $( (stringify!($name), __mock_driver::$name as *const ::std::ffi::c_void) ),*
+            ])
+        }
+    };
+}
+
+/// Logs a call to a mock entry point and answers it from the expectations.
+#[doc(hidden)]
+pub fn call(name: &'static str, args: &[u64]) -> CUresult {
+    let call = Call {
+        name: name.to_string(),
+        args: args.to_vec(),
+    };
+    let mut state = state();
+    state.calls.push(call.clone());
+    let Some(expectation) = state
+        .expectations
+        .iter_mut()
+        .find(|e| e.name == name && !e.exhausted())
+    else {
+        state.unexpected.push(call);
+        return CUDA_ERROR_NOT_SUPPORTED;
+    };
+    expectation.calls += 1;
+    let returns = expectation.returns;
+    let writes = expectation.writes.clone();
+    let action = expectation.action.clone();
+    // Actions may call back into hooks
+    drop(state);
+
+    for (index, bytes) in writes {
+        let out = args.get(index).copied().unwrap_or(0) as *mut u8;
+        if !out.is_null() {
+            unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len()) };
+        }
+    }
+    match action {
+        Some(action) => action(args),
+        None => returns,
+    }
+}
+
+/// Widens an argument to the word it is logged as.
+#[doc(hidden)]
+pub fn word<T: Copy>(value: T) -> u64 {
+    let ptr = &value as *const T;
+    unsafe {
+        match size_of::<T>() {
+            1 => ptr.cast::<u8>().read_unaligned() as u64,
+            2 => ptr.cast::<u16>().read_unaligned() as u64,
+            4 => ptr.cast::<u32>().read_unaligned() as u64,
+            8 => ptr.cast::<u64>().read_unaligned(),
+            n => panic!("{n}-byte arguments cannot be mocked"),
+        }
+    }
+}
+
+/// Narrows a word to the return type of a mock entry point.
+#[doc(hidden)]
+pub fn from_word<T: Copy>(word: u64) -> T {
+    unsafe {
+        match size_of::<T>() {
+            1 => std::mem::transmute_copy(&(word as u8)),
+            2 => std::mem::transmute_copy(&(word as u16)),
+            4 => std::mem::transmute_copy(&(word as u32)),
+            8 => std::mem::transmute_copy(&word),
+            n => panic!("{n}-byte return values cannot be mocked"),
+        }
+    }
+}
+
+/// The installed mock driver. Expectations and the call log are reset when it is installed, and
+/// checked with [`MockDriver::verify`] when it is dropped.
+pub struct MockDriver {
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl MockDriver {
+    /// Resolves the real entry points of hooks to `symbols`, as declared by [`mock_driver!`].
+    pub fn install(symbols: &[(&'static str, *const c_void)]) -> Self {
+        let serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+        SYMBOLS
+            .write()
+            .unwrap()
+            .extend(symbols.iter().map(|&(name, ptr)| (name, ptr as usize)));
+        *state() = State::default();
+        cuda_interposer::set_resolver(Some(resolve));
+        Self { _serial: serial }
+    }
+
+    /// Expects calls to `name`, which return `CUDA_SUCCESS` unless told otherwise. Calls are
+    /// answered by the first expectation for the API that is not exhausted.
+    pub fn expect(&self, name: &str) -> Expect<'_> {
+        let mut state = state();
+        state.expectations.push(Expectation {
+            name: name.to_string(),
+            returns: CUDA_SUCCESS,
+            writes: Vec::new(),
+            action: None,
+            times: None,
+            calls: 0,
+        });
+        Expect {
+            index: state.expectations.len() - 1,
+            _driver: self,
+        }
+    }
+
+    /// Every call the hooks forwarded, in order.
+    pub fn calls(&self) -> Vec<Call> {
+        state().calls.clone()
+    }
+
+    /// The calls forwarded to `name`, in order.
+    pub fn calls_to(&self, name: &str) -> Vec<Call> {
+        state()
+            .calls
+            .iter()
+            .filter(|c| c.name == name)
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets the calls logged so far.
+    pub fn clear_calls(&self) {
+        let mut state = state();
+        state.calls.clear();
+        state.unexpected.clear();
+    }
+
+    /// Panics unless `name` was called `times` times.
+    #[track_caller]
+    pub fn assert_called(&self, name: &str, times: usize) {
+        let calls = self.calls_to(name);
+        assert_eq!(
+            calls.len(),
+            times,
+            "expected {} calls to {}, got {:#?}",
+            times,
+            name,
+            self.calls()
+        );
+    }
+
+    /// Panics unless the APIs called are `names`, in that order.
+    #[track_caller]
+    pub fn assert_sequence(&self, names: &[&str]) {
+        let calls = self.calls();
+        let called: Vec<&str> = calls.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(called, names, "unexpected call sequence");
+    }
+
+    /// Panics if a call was not expected, or an expectation given a number of calls did not get
+    /// them all.
+    #[track_caller]
+    pub fn verify(&self) {
+        let state = state();
+        assert!(
+            state.unexpected.is_empty(),
+            "unexpected calls: {:#?}",
+            state.unexpected
+        );
+        for e in &state.expectations {
+            if let Some(times) = e.times {
+                assert_eq!(
+                    e.calls, times,
+                    "expected {} calls to {}, got {}",
+                    times, e.name, e.calls
+                );
+            }
+        }
+    }
+}
+
+impl Drop for MockDriver {
+    fn drop(&mut self) {
+        cuda_interposer::set_resolver(None);
+        if !std::thread::panicking() {
+            self.verify();
+        }
+    }
+}
+
+/// An expectation being set up by [`MockDriver::expect`].
+pub struct Expect<'a> {
+    index: usize,
+    _driver: &'a MockDriver,
+}
+
+impl Expect<'_> {
+    fn update(self, f: impl FnOnce(&mut Expectation)) -> Self {
+        f(&mut state().expectations[self.index]);
+        self
+    }
+
+    /// Returns `rc` from the calls.
+    pub fn returns(self, rc: CUresult) -> Self {
+        self.update(|e| e.returns = rc)
+    }
+
+    /// Writes `value` through the pointer passed as argument `index` (from 0), as the driver
+    /// writes its outputs. Null pointers are skipped.
+    pub fn writes<T: Copy>(self, index: usize, value: T) -> Self {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()).to_vec()
+        };
+        self.update(|e| e.writes.push((index, bytes)))
+    }
+
+    /// Answers the calls with `action`, given the arguments as words, after the writes. Its
+    /// result is returned in place of [`Expect::returns`].
+    pub fn with(self, action: impl Fn(&[u64]) -> CUresult + Send + Sync + 'static) -> Self {
+        self.update(|e| e.action = Some(Arc::new(action)))
+    }
+
+    /// Answers only the next `times` calls, which [`MockDriver::verify`] then expects.
+    pub fn times(self, times: usize) -> Self {
+        self.update(|e| e.times = Some(times))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cuda_interposer::ffi::*;
+
+    mock_driver! {
+        fn cuMemAlloc_v2(dptr: *mut CUdeviceptr, bytesize: usize) -> CUresult;
+        fn cuMemFree_v2(dptr: CUdeviceptr) -> CUresult;
+    }
+
+    mod hooks {
+        use cuda_interposer::ffi::*;
+
+        cuda_interposer::cuda_hook! {
+            pub unsafe extern "C" fn cuMemAlloc_v2(
+                dptr: *mut CUdeviceptr,
+                bytesize: usize
+            ) -> CUresult {
+                unsafe { (*__real_cuMemAlloc_v2)(dptr, bytesize.next_multiple_of(1024)) }
+            }
+        }
+
+        cuda_interposer::cuda_hook! {
+            pub unsafe extern "C" fn cuMemFree_v2(dptr: CUdeviceptr) -> CUresult {
+                unsafe { (*__real_cuMemFree_v2)(dptr) }
+            }
+        }
+    }
+
+    #[test]
+    fn routes_real_entry_points_to_the_mocks() {
+        let driver = mock_driver();
+        driver
+            .expect("cuMemAlloc_v2")
+            .writes(0, 0x7f00_0000_0000u64);
+        let mut ptr = 0;
+        assert_eq!(
+            unsafe { hooks::cuMemAlloc_v2(&mut ptr, 1000) },
+            CUDA_SUCCESS
+        );
+        assert_eq!(ptr, 0x7f00_0000_0000);
+        let calls = driver.calls_to("cuMemAlloc_v2");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].args, [&mut ptr as *mut u64 as u64, 1024]);
+    }
+
+    #[test]
+    fn logs_calls_in_order() {
+        let driver = mock_driver();
+        driver.expect("cuMemAlloc_v2").writes(0, 0x1000u64);
+        driver.expect("cuMemFree_v2");
+        let mut ptr = 0;
+        unsafe { hooks::cuMemAlloc_v2(&mut ptr, 1) };
+        unsafe { hooks::cuMemFree_v2(ptr) };
+        unsafe { hooks::cuMemFree_v2(ptr) };
+        driver.assert_sequence(&["cuMemAlloc_v2", "cuMemFree_v2", "cuMemFree_v2"]);
+        driver.assert_called("cuMemFree_v2", 2);
+        assert_eq!(driver.calls()[1].args, [0x1000]);
+
+        driver.clear_calls();
+        assert!(driver.calls().is_empty());
+    }
+
+    #[test]
+    fn answers_from_the_first_expectation_left() {
+        let driver = mock_driver();
+        driver.expect("cuMemFree_v2").times(1);
+        driver
+            .expect("cuMemFree_v2")
+            .returns(CUDA_ERROR_INVALID_VALUE)
+            .times(1);
+        driver.expect("cuMemFree_v2").with(|args| {
+            if args[0] == 0 {
+                CUDA_ERROR_INVALID_VALUE
+            } else {
+                CUDA_SUCCESS
+            }
+        });
+        assert_eq!(unsafe { hooks::cuMemFree_v2(0x1000) }, CUDA_SUCCESS);
+        assert_eq!(
+            unsafe { hooks::cuMemFree_v2(0x1000) },
+            CUDA_ERROR_INVALID_VALUE
+        );
+        assert_eq!(unsafe { hooks::cuMemFree_v2(0x2000) }, CUDA_SUCCESS);
+        assert_eq!(unsafe { hooks::cuMemFree_v2(0) }, CUDA_ERROR_INVALID_VALUE);
+        driver.verify();
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected calls")]
+    fn verify_fails_on_unexpected_calls() {
+        let driver = mock_driver();
+        assert_eq!(
+            unsafe { hooks::cuMemFree_v2(0x1000) },
+            CUDA_ERROR_NOT_SUPPORTED
+        );
+        driver.verify();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected 2 calls to cuMemFree_v2, got 1")]
+    fn verify_fails_on_missing_calls() {
+        let driver = mock_driver();
+        driver.expect("cuMemFree_v2").times(2);
+        unsafe { hooks::cuMemFree_v2(0x1000) };
+        driver.verify();
+    }
+}
@@ -0,0 +1,380 @@
+//! Kernel argument decoding and device pointer validation.
+//!
+//! When enabled, [`install_launch_hooks!`] decodes the arguments of every launch, passed either
+//! as `kernelParams` or packed in an `extra` buffer, into [`KernelArg`]s on the
+//! [`crate::launch::LaunchEvent`]. Parameter layouts come from `cuFuncGetParamInfo` where the
+//! driver has it (CUDA 12.4), and otherwise from the cubins registered with
+//! [`crate::modules::register_image`], which [`install_capture_hooks!`] does as modules are
+//! loaded.
+//!
+//! Any 8-byte argument that falls within the address range of the allocations tracked by
+//! [`install_memory_hooks!`] is taken for a device pointer and checked against the live
+//! allocations. Pointers into freed memory, or into no allocation at all, are logged as
+//! warnings, catching use-after-free and wrong-buffer bugs at the launch that has them rather
+//! than as a later `CUDA_ERROR_ILLEGAL_ADDRESS`.
+//!
+//! Decoding is enabled with `CUDA_HOOK_LAUNCH_ARGS=1` or [`set_enabled`].
+
+use crate::cubin::{Param, ParamLayout};
+use crate::driver;
+use crate::ffi::*;
+use crate::memory::ALLOCATIONS;
+use crate::modules::{self, KernelName};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+const ARGS_ENV: &str = "CUDA_HOOK_LAUNCH_ARGS";
+
+pub use crate::ffi::{
+    CU_LAUNCH_PARAM_BUFFER_POINTER, CU_LAUNCH_PARAM_BUFFER_SIZE, CU_LAUNCH_PARAM_END,
+};
+
+static ENABLED: Lazy<AtomicBool> =
+    Lazy::new(|| AtomicBool::new(std::env::var(ARGS_ENV).is_ok_and(|v| v == "1")));
+
+static DRIVER_LAYOUTS: Lazy<RwLock<HashMap<usize, Option<Arc<ParamLayout>>>>> =
+    Lazy::new(Default::default);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// What an argument that looks like a device pointer points into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PointerStatus {
+    /// A live allocation, at `offset` bytes from its start.
+    Live { base: CUdeviceptr, offset: u64 },
+    /// An allocation that has been freed.
+    Freed { base: CUdeviceptr, size: usize },
+    /// No allocation the interposer knows of.
+    Unknown,
+}
+
+impl PointerStatus {
+    pub fn is_valid(&self) -> bool {
+        matches!(self, PointerStatus::Live { .. })
+    }
+}
+
+/// The value of a kernel argument.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum ArgValue {
+    /// Arguments of 1, 2, 4 or 8 bytes, as an unsigned integer.
+    Scalar(u64),
+    /// Larger arguments, such as structs passed by value.
+    Bytes(Vec<u8>),
+}
+
+/// A decoded kernel argument.
+#[derive(Debug, Clone, Serialize)]
+pub struct KernelArg {
+    pub index: usize,
+    pub offset: usize,
+    pub size: usize,
+    pub value: ArgValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pointer: Option<PointerStatus>,
+}
+
+/// Returns the parameter layout of `func`, asking the driver first.
+pub fn layout(func: CUfunction, kernel: Option<&KernelName>) -> Option<Arc<ParamLayout>> {
+    let cached = DRIVER_LAYOUTS
+        .read()
+        .unwrap()
+        .get(&(func as usize))
+        .cloned();
+    let from_driver = cached.unwrap_or_else(|| {
+        let layout = driver::function_params(func).map(|params| Arc::new(from_driver(&params)));
+        DRIVER_LAYOUTS
+            .write()
+            .unwrap()
+            .insert(func as usize, layout.clone());
+        layout
+    });
+    from_driver.or_else(|| modules::param_layout(&kernel?.mangled))
+}
+
+fn from_driver(params: &[(usize, usize)]) -> ParamLayout {
+    ParamLayout {
+        params: params
+            .iter()
+            .enumerate()
+            .map(|(i, &(offset, size))| Param {
+                ordinal: i as u16,
+                offset: offset as u16,
+                size: size as u16,
+                flags: 0,
+            })
+            .collect(),
+        size: params
+            .iter()
+            .map(|(o, s)| (o + s) as u32)
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+/// Returns what `value` points into, if it looks like a device pointer.
+pub fn check_pointer(value: u64) -> Option<PointerStatus> {
+    ALLOCATIONS.pointer_status(value)
+}
+
+fn decode(index: usize, offset: usize, bytes: &[u8]) -> KernelArg {
+    let value = match bytes.len() {
+        1 | 2 | 4 | 8 => {
+            let mut buf = [0u8; 8];
+            buf[..bytes.len()].copy_from_slice(bytes);
+            ArgValue::Scalar(u64::from_le_bytes(buf))
+        }
+        _ => ArgValue::Bytes(bytes.to_vec()),
+    };
+    let pointer = match value {
+        ArgValue::Scalar(v) if bytes.len() == 8 => check_pointer(v),
+        _ => None,
+    };
+    KernelArg {
+        index,
+        offset,
+        size: bytes.len(),
+        value,
+        pointer,
+    }
+}
+
+/// Finds the argument buffer in a `cuLaunchKernel` `extra` array.
+///
+/// # Safety
+/// `extra` must be null or a `CU_LAUNCH_PARAM_END`-terminated array as the driver accepts it.
+pub(crate) unsafe fn extra_buffer<'a>(extra: *mut *mut c_void) -> Option<&'a [u8]> {
+    if extra.is_null() {
+        return None;
+    }
+    let (mut buffer, mut size) = (std::ptr::null::<u8>(), None);
+    for i in (0..).step_by(2) {
+        let key = unsafe { *extra.add(i) } as usize;
+        if key == CU_LAUNCH_PARAM_END {
+            break;
+        }
+        let value = unsafe { *extra.add(i + 1) };
+        match key {
+            CU_LAUNCH_PARAM_BUFFER_POINTER => buffer = value as *const u8,
+            CU_LAUNCH_PARAM_BUFFER_SIZE if !value.is_null() => {
+                size = Some(unsafe { *(value as *const usize) })
+            }
+            _ => break,
+        }
+    }
+    if buffer.is_null() {
+        return None;
+    }
+    Some(unsafe { std::slice::from_raw_parts(buffer, size?) })
+}
+
+/// Returns the index, offset and bytes of each argument of a launch with parameter `layout`,
+/// passed either as `kernel_params` or in an `extra` buffer. Arguments that cannot be read are
+/// left out.
+///
+/// # Safety
+/// `kernel_params` and `extra` must be as passed to `cuLaunchKernel` for a kernel with
+/// parameter `layout`, and the arguments must outlive the returned slices.
+pub unsafe fn param_bytes<'a>(
+    layout: &ParamLayout,
+    kernel_params: *mut *mut c_void,
+    extra: *mut *mut c_void,
+) -> Vec<(usize, usize, &'a [u8])> {
+    let buffer = unsafe { extra_buffer(extra) };
+    let mut params = Vec::with_capacity(layout.len());
+    for (index, param) in layout.params.iter().enumerate() {
+        let (offset, size) = (param.offset as usize, param.size as usize);
+        let bytes = if !kernel_params.is_null() {
+            let ptr = unsafe { *kernel_params.add(index) } as *const u8;
+            if ptr.is_null() {
+                continue;
+            }
+            unsafe { std::slice::from_raw_parts(ptr, size) }
+        } else if let Some(bytes) = buffer.and_then(|b| b.get(offset..offset + size)) {
+            bytes
+        } else {
+            continue;
+        };
+        params.push((index, offset, bytes));
+    }
+    params
+}
+
+/// Decodes the arguments of a launch with parameter `layout`, and warns about device pointers
+/// that are not in a live allocation. Returns nothing when the layout is unknown.
+///
+/// # Safety
+/// `kernel_params` and `extra` must be as passed to `cuLaunchKernel` for a kernel with
+/// parameter `layout`.
+pub unsafe fn decode_launch(
+    layout: Option<&ParamLayout>,
+    kernel: Option<&KernelName>,
+    kernel_params: *mut *mut c_void,
+    extra: *mut *mut c_void,
+) -> Vec<KernelArg> {
+    let Some(layout) = layout else {
+        return Vec::new();
+    };
+    let args: Vec<_> = unsafe { param_bytes(layout, kernel_params, extra) }
+        .into_iter()
+        .map(|(index, offset, bytes)| decode(index, offset, bytes))
+        .collect();
+    for arg in &args {
+        if let (Some(status), ArgValue::Scalar(value)) = (arg.pointer, &arg.value)
+            && !status.is_valid()
+        {
+            let name = kernel.map_or("<unknown kernel>", |k| k.demangled.as_str());
+            match status {
+                PointerStatus::Freed { base, size } => warn!(
+                    "{} argument {} is {:#x}, inside freed allocation {:#x} ({} bytes)",
+                    name, arg.index, value, base, size
+                ),
+                _ => warn!(
+                    "{} argument {} is {:#x}, outside every live allocation",
+                    name, arg.index, value
+                ),
+            }
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `kernel(float* data, int n, float3 scale)`, as `cuFuncGetParamInfo` describes it.
+    fn layout() -> ParamLayout {
+        from_driver(&[(0, 8), (8, 4), (16, 12)])
+    }
+
+    fn values(args: &[KernelArg]) -> Vec<(usize, usize, usize, ArgValue)> {
+        args.iter()
+            .map(|a| (a.index, a.offset, a.size, a.value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn lays_out_driver_params() {
+        let layout = layout();
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout.size, 28);
+        assert_eq!(
+            layout.params.iter().map(|p| p.ordinal).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        assert_eq!(from_driver(&[]), ParamLayout::default());
+    }
+
+    #[test]
+    fn decodes_kernel_params() {
+        let data: u64 = 0xdead_0000;
+        let n: i32 = -3;
+        let scale: [f32; 3] = [1.0, 2.0, 0.5];
+        let mut kernel_params = [
+            &data as *const _ as *mut c_void,
+            &n as *const _ as *mut c_void,
+            &scale as *const _ as *mut c_void,
+        ];
+        let scale_bytes: Vec<u8> = scale.iter().flat_map(|f| f.to_le_bytes()).collect();
+
+        let args = unsafe {
+            decode_launch(
+                Some(&layout()),
+                None,
+                kernel_params.as_mut_ptr(),
+                std::ptr::null_mut(),
+            )
+        };
+        assert_eq!(
+            values(&args),
+            [
+                (0, 0, 8, ArgValue::Scalar(0xdead_0000)),
+                (1, 8, 4, ArgValue::Scalar(n as u32 as u64)),
+                (2, 16, 12, ArgValue::Bytes(scale_bytes.clone())),
+            ]
+        );
+        // Only 8-byte arguments can be device pointers
+        assert!(args[1..].iter().all(|a| a.pointer.is_none()));
+
+        // A null entry is left out
+        kernel_params[1] = std::ptr::null_mut();
+        let args = unsafe {
+            decode_launch(
+                Some(&layout()),
+                None,
+                kernel_params.as_mut_ptr(),
+                std::ptr::null_mut(),
+            )
+        };
+        assert_eq!(
+            values(&args).iter().map(|v| v.0).collect::<Vec<_>>(),
+            [0, 2]
+        );
+    }
+
+    #[test]
+    fn decodes_an_extra_buffer() {
+        let mut buffer = [0u8; 28];
+        buffer[..8].copy_from_slice(&0x1234_u64.to_le_bytes());
+        buffer[8..12].copy_from_slice(&7_i32.to_le_bytes());
+        let size = std::cell::Cell::new(buffer.len());
+        let mut extra = [
+            CU_LAUNCH_PARAM_BUFFER_POINTER as *mut c_void,
+            buffer.as_mut_ptr() as *mut c_void,
+            CU_LAUNCH_PARAM_BUFFER_SIZE as *mut c_void,
+            size.as_ptr() as *mut c_void,
+            CU_LAUNCH_PARAM_END as *mut c_void,
+        ];
+        let args = unsafe {
+            decode_launch(
+                Some(&layout()),
+                None,
+                std::ptr::null_mut(),
+                extra.as_mut_ptr(),
+            )
+        };
+        assert_eq!(
+            values(&args),
+            [
+                (0, 0, 8, ArgValue::Scalar(0x1234)),
+                (1, 8, 4, ArgValue::Scalar(7)),
+                (2, 16, 12, ArgValue::Bytes(vec![0; 12])),
+            ]
+        );
+
+        // Arguments past the end of a short buffer are left out
+        size.set(12);
+        let args = unsafe {
+            decode_launch(
+                Some(&layout()),
+                None,
+                std::ptr::null_mut(),
+                extra.as_mut_ptr(),
+            )
+        };
+        assert_eq!(args.len(), 2);
+    }
+
+    #[test]
+    fn decodes_nothing_without_a_layout() {
+        let data: u64 = 1;
+        let mut kernel_params = [&data as *const _ as *mut c_void];
+        let args =
+            unsafe { decode_launch(None, None, kernel_params.as_mut_ptr(), std::ptr::null_mut()) };
+        assert!(args.is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+//! Launch-blocking debug mode with error attribution.
+//!
+//! `CUDA_LAUNCH_BLOCKING=1` serializes the application but leaves it to the user to find which
+//! call the error that surfaces belongs to. In this mode the interposer synchronizes the stream
+//! after every kernel launch and memcpy it sees, and attributes the first failure, of the call
+//! itself or of the work it queued, to a [`FaultReport`] naming the API, the kernel, its launch
+//! geometry and arguments (decoded as by [`crate::args`]), and the host backtrace of the call.
+//! The report is written to stderr, and the process optionally aborted.
+//!
+//! The mode is enabled with `CUDA_HOOK_BLOCKING`, set to `1` for every launch and memcpy or to a
+//! [`PatternSet`] of kernel names (mangled or demangled) to only check those kernels, keeping
+//! the overhead down; `0` or an empty value leaves it off. Set `CUDA_HOOK_BLOCKING_ABORT=1` to
+//! abort after reporting. Launches are seen through [`install_launch_hooks!`] and memcpys
+//! through [`install_stream_hooks!`].
+//!
+//! Work queued on a stream that is being captured into a graph only runs when the graph is
+//! launched, and synchronizing the stream would invalidate the capture, so it is not checked.
+
+use crate::args::KernelArg;
+use crate::driver;
+use crate::ffi::*;
+use crate::launch::{LaunchApi, LaunchEvent};
+use crate::modules::KernelName;
+use crate::pattern::PatternSet;
+use once_cell::sync::Lazy;
+use std::backtrace::Backtrace;
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
+
+const BLOCKING_ENV: &str = "CUDA_HOOK_BLOCKING";
+const ABORT_ENV: &str = "CUDA_HOOK_BLOCKING_ABORT";
+
+static CONFIG: Lazy<RwLock<Option<Config>>> = Lazy::new(|| {
+    let config = std::env::var(BLOCKING_ENV)
+        .ok()
+        .and_then(|v| parse_kernels(&v))
+        .map(|kernels| Config {
+            kernels,
+            abort: std::env::var(ABORT_ENV).is_ok_and(|v| v == "1"),
+        });
+    RwLock::new(config)
+});
+
+/// Parses `CUDA_HOOK_BLOCKING`: `None` when the mode is off, and the kernels to check otherwise.
+fn parse_kernels(value: &str) -> Option<PatternSet> {
+    match value.trim() {
+        "" | "0" => None,
+        "1" => Some(PatternSet::default()),
+        patterns => Some(PatternSet::parse(patterns)),
+    }
+}
+
+static FIRST_FAULT: OnceLock<FaultReport> = OnceLock::new();
+
+/// What the debug mode checks.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// The kernels to check; every launch and memcpy when empty.
+    pub kernels: PatternSet,
+    /// Whether to abort the process after reporting the first failure.
+    pub abort: bool,
+}
+
+/// Enables the debug mode with `config`, or disables it with `None`.
+pub fn set_config(config: Option<Config>) {
+    *CONFIG.write().unwrap() = config;
+}
+
+pub fn enabled() -> bool {
+    CONFIG.read().unwrap().is_some()
+}
+
+/// The first failing call seen in the debug mode.
+#[derive(Debug, Clone)]
+pub struct FaultReport {
+    /// Microseconds since the interposer was loaded.
+    pub at_us: u64,
+    pub api: &'static str,
+    pub kernel: Option<Arc<KernelName>>,
+    pub grid: Option<[u32; 3]>,
+    pub block: Option<[u32; 3]>,
+    pub stream: usize,
+    pub args: Vec<KernelArg>,
+    /// Bytes copied, for memcpys.
+    pub bytes: Option<usize>,
+    /// Whether the call itself failed, rather than the work it queued.
+    pub at_call: bool,
+    pub result: u32,
+    pub error: String,
+    pub backtrace: String,
+}
+
+impl fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let when = if self.at_call {
+            "failed"
+        } else {
+            "queued work that failed"
+        };
+        writeln!(
+            f,
+            "{} {} with {} ({}) at {} us",
+            self.api, when, self.error, self.result, self.at_us
+        )?;
+        if let Some(kernel) = &self.kernel {
+            writeln!(f, "  kernel: {} ({})", kernel.demangled, kernel.mangled)?;
+        }
+        if let (Some(grid), Some(block)) = (self.grid, self.block) {
+            writeln!(f, "  grid: {grid:?} block: {block:?}")?;
+        }
+        if let Some(bytes) = self.bytes {
+            writeln!(f, "  bytes: {bytes}")?;
+        }
+        writeln!(f, "  stream: {:#x}", self.stream)?;
+        for arg in &self.args {
+            write!(
+                f,
+                "  arg {} (+{}, {} bytes): {:?}",
+                arg.index, arg.offset, arg.size, arg.value
+            )?;
+            match arg.pointer {
+                Some(status) => writeln!(f, " {status:?}")?,
+                None => writeln!(f)?,
+            }
+        }
+        writeln!(f, "  host backtrace:")?;
+        for line in self.backtrace.lines() {
+            writeln!(f, "    {line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the first failure seen, if any.
+pub fn first_fault() -> Option<&'static FaultReport> {
+    FIRST_FAULT.get()
+}
+
+/// Returns whether launches of `kernel` are checked.
+pub fn selects(kernel: Option<&KernelName>) -> bool {
+    let config = CONFIG.read().unwrap();
+    let Some(config) = config.as_ref() else {
+        return false;
+    };
+    config.kernels.is_empty()
+        || kernel.is_some_and(|k| {
+            config.kernels.matches(&k.mangled) || config.kernels.matches(&k.demangled)
+        })
+}
+
+/// Waits for `stream` unless the call already failed, returning the first error and whether it
+/// came from the call itself. Streams being captured are not waited for.
+fn check(rc: u32, stream: usize) -> Option<(u32, bool)> {
+    if rc != CUDA_SUCCESS {
+        return Some((rc, true));
+    }
+    if driver::stream_is_capturing(stream as CUstream) {
+        return None;
+    }
+    let rc = driver::stream_synchronize(stream as CUstream);
+    (rc != CUDA_SUCCESS).then_some((rc, false))
+}
+
+fn report(fault: FaultReport) {
+    if FIRST_FAULT.set(fault).is_err() {
+        // Errors are sticky; only the first is worth attributing.
+        return;
+    }
+    let fault = FIRST_FAULT.get().unwrap();
+    eprintln!("cuda-interposer: first failing CUDA call\n{fault}");
+    if CONFIG.read().unwrap().as_ref().is_some_and(|c| c.abort) {
+        std::process::abort();
+    }
+}
+
+/// Synchronizes after a launch and reports it if it failed. Called by [`crate::launch`].
+pub fn after_launch(event: &LaunchEvent) {
+    if FIRST_FAULT.get().is_some() || !selects(event.kernel.as_deref()) {
+        return;
+    }
+    let Some((result, at_call)) = check(event.result, event.stream) else {
+        return;
+    };
+    let runtime = event.api == LaunchApi::RuntimeLaunchKernel;
+    let error = if runtime && at_call {
+        // `cudaLaunchKernel` returns a `cudaError_t`, which the driver cannot name.
+        format!("cudaError {result}")
+    } else {
+        driver::error_name(result).unwrap_or_else(|| "unknown error".into())
+    };
+    let api = match event.api {
+        LaunchApi::LaunchKernel => "cuLaunchKernel",
+        LaunchApi::LaunchKernelEx => "cuLaunchKernelEx",
+        LaunchApi::LaunchCooperativeKernel => "cuLaunchCooperativeKernel",
+        LaunchApi::RuntimeLaunchKernel => "cudaLaunchKernel",
+    };
+    report(FaultReport {
+        at_us: event.at_us,
+        api,
+        kernel: event.kernel.clone(),
+        grid: Some(event.grid),
+        block: Some(event.block),
+        stream: event.stream,
+        args: event.args.clone(),
+        bytes: None,
+        at_call,
+        result,
+        error,
+        backtrace: Backtrace::force_capture().to_string(),
+    });
+}
+
+/// Synchronizes after a memcpy and reports it if it failed. Called by [`crate::streams`].
+pub fn after_memcpy(rc: CUresult, api: &'static str, stream: CUstream, bytes: usize) {
+    if FIRST_FAULT.get().is_some() || !selects(None) {
+        return;
+    }
+    let Some((result, at_call)) = check(rc, stream as usize) else {
+        return;
+    };
+    report(FaultReport {
+        at_us: crate::elapsed().as_micros() as u64,
+        api,
+        kernel: None,
+        grid: None,
+        block: None,
+        stream: stream as usize,
+        args: Vec::new(),
+        bytes: Some(bytes),
+        at_call,
+        result,
+        error: driver::error_name(result).unwrap_or_else(|| "unknown error".into()),
+        backtrace: Backtrace::force_capture().to_string(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_mode_switch() {
+        assert!(parse_kernels("").is_none());
+        assert!(parse_kernels("0").is_none());
+        assert!(parse_kernels("1").is_some_and(|k| k.is_empty()));
+        let kernels = parse_kernels("gemm*").unwrap();
+        assert!(kernels.matches("gemm_kernel"));
+        assert!(!kernels.matches("reduce"));
+    }
+}
@@ -0,0 +1,275 @@
+//! Serializable API calls.
+//!
+//! `cuda-interposer-build` generates a `CudaCall` enum from the bindgen prototypes, with one
+//! variant per API, into `calls_driver.rs` and `calls_runtime.rs`. Each variant holds a struct of
+//! the same name from the generated `args` module, with a field per parameter:
+//!
+//! - integers, floats and `bool` as themselves, and enums as their integer value;
+//! - pointers and handles as their address;
+//! - pointers to the structures [`followed`] names as the structure they point to, read into
+//!   the types of this module, or as every element of the array for the parameters
+//!   [`followed_array`] names;
+//! - function pointers as their address, and other structures passed by value as their bytes.
+//!
+//! The structs are built from the parameters of a hook, as `args::cuMemcpy3D_v2::new(pCopy)`,
+//! and turn into a `CudaCall` with `From`. The driver's `CudaCall::params` returns the direction
+//! and buffer size of each parameter, from `cuda_interposer_sys::driver_params`.
+//!
+//! The generated files are included next to the passthroughs, where the bindgen types are in
+//! scope:
+//!
+//! ```ignore
+//! pub mod calls {
+//!     #![allow(non_snake_case, non_camel_case_types, clippy::missing_safety_doc)]
+//!     use cuda_interposer_sys::driver_internal_sys::*;
+//!     include!(concat!(env!("OUT_DIR"), "/calls_driver.rs"));
+//! }
+//! ```
+
+use crate::args;
+use crate::ffi::*;
+use crate::modules;
+use serde::{Deserialize, Serialize};
+use std::ffi::c_void;
+
+pub use cuda_interposer_tables::calls::{followed, followed_array};
+
+/// A structure read through a followed pointer.
+pub trait Follow: Sized {
+    /// The C layout the pointer points to.
+    type Raw;
+
+    fn from_raw(raw: &Self::Raw) -> Self;
+
+    /// Reads the structure at `ptr`, or returns `None` if it is null.
+    ///
+    /// # Safety
+    /// `ptr` must be null or point to a valid `Self::Raw`.
+    unsafe fn read(ptr: *const c_void) -> Option<Self> {
+        let raw = unsafe { (ptr as *const Self::Raw).as_ref() }?;
+        Some(Self::from_raw(raw))
+    }
+
+    /// Reads an array of `count` structures.
+    ///
+    /// # Safety
+    /// `ptr` must be null or point to `count` valid `Self::Raw`s.
+    unsafe fn read_array(ptr: *const c_void, count: usize) -> Vec<Self> {
+        if ptr.is_null() {
+            return Vec::new();
+        }
+        let raw = unsafe { std::slice::from_raw_parts(ptr as *const Self::Raw, count) };
+        raw.iter().map(Self::from_raw).collect()
+    }
+}
+
+/// Returns the bytes of a parameter passed by value.
+///
+/// # Safety
+/// `T` must be a C type without padding, as the structures and unions bindgen generates for the
+/// API are.
+pub unsafe fn bytes_of<T>(value: &T) -> Vec<u8> {
+    let ptr = value as *const T as *const u8;
+    unsafe { std::slice::from_raw_parts(ptr, size_of::<T>()) }.to_vec()
+}
+
+/// Returns a function pointer parameter, nullable or not, as an address.
+///
+/// # Safety
+/// `T` must be a function pointer or an `Option` of one.
+pub unsafe fn address_of<T>(value: &T) -> u64 {
+    assert_eq!(size_of::<T>(), size_of::<u64>());
+    unsafe { std::mem::transmute_copy::<T, u64>(value) }
+}
+
+/// One side of a copy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemcpySide {
+    /// `CUmemorytype`: 1 host, 2 device, 3 array, 4 unified.
+    pub memory_type: u32,
+    pub host: u64,
+    pub device: CUdeviceptr,
+    pub array: u64,
+    pub x_in_bytes: usize,
+    pub y: usize,
+    pub z: usize,
+    pub lod: usize,
+    pub pitch: usize,
+    /// Height of the allocation, in rows; 0 in 2D copies.
+    pub height: usize,
+}
+
+/// A `CUDA_MEMCPY3D`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Memcpy3D {
+    pub src: MemcpySide,
+    pub dst: MemcpySide,
+    pub width_in_bytes: usize,
+    pub height: usize,
+    pub depth: usize,
+}
+
+impl Follow for Memcpy3D {
+    type Raw = CUDA_MEMCPY3D;
+
+    fn from_raw(c: &CUDA_MEMCPY3D) -> Self {
+        Self {
+            src: MemcpySide {
+                memory_type: c.srcMemoryType,
+                host: c.srcHost as u64,
+                device: c.srcDevice,
+                array: c.srcArray as u64,
+                x_in_bytes: c.srcXInBytes,
+                y: c.srcY,
+                z: c.srcZ,
+                lod: c.srcLOD,
+                pitch: c.srcPitch,
+                height: c.srcHeight,
+            },
+            dst: MemcpySide {
+                memory_type: c.dstMemoryType,
+                host: c.dstHost as u64,
+                device: c.dstDevice,
+                array: c.dstArray as u64,
+                x_in_bytes: c.dstXInBytes,
+                y: c.dstY,
+                z: c.dstZ,
+                lod: c.dstLOD,
+                pitch: c.dstPitch,
+                height: c.dstHeight,
+            },
+            width_in_bytes: c.WidthInBytes,
+            height: c.Height,
+            depth: c.Depth,
+        }
+    }
+}
+
+/// A `CUDA_MEMCPY2D`, whose sides have no `z`, `lod` or `height`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Memcpy2D {
+    pub src: MemcpySide,
+    pub dst: MemcpySide,
+    pub width_in_bytes: usize,
+    pub height: usize,
+}
+
+impl Follow for Memcpy2D {
+    type Raw = CUDA_MEMCPY2D;
+
+    fn from_raw(c: &CUDA_MEMCPY2D) -> Self {
+        let side = |memory_type, host, device, array, x_in_bytes, y, pitch| MemcpySide {
+            memory_type,
+            host,
+            device,
+            array,
+            x_in_bytes,
+            y,
+            z: 0,
+            lod: 0,
+            pitch,
+            height: 0,
+        };
+        Self {
+            src: side(
+                c.srcMemoryType,
+                c.srcHost as u64,
+                c.srcDevice,
+                c.srcArray as u64,
+                c.srcXInBytes,
+                c.srcY,
+                c.srcPitch,
+            ),
+            dst: side(
+                c.dstMemoryType,
+                c.dstHost as u64,
+                c.dstDevice,
+                c.dstArray as u64,
+                c.dstXInBytes,
+                c.dstY,
+                c.dstPitch,
+            ),
+            width_in_bytes: c.WidthInBytes,
+            height: c.Height,
+        }
+    }
+}
+
+/// A `CUDA_LAUNCH_PARAMS`, with the bytes of each kernel argument. `args` is `None` when the
+/// kernel's parameter layout is unknown, as in [`crate::record`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LaunchParams {
+    pub function: u64,
+    pub grid: [u32; 3],
+    pub block: [u32; 3],
+    pub shared_mem: u32,
+    pub stream: u64,
+    pub args: Option<Vec<Vec<u8>>>,
+}
+
+impl Follow for LaunchParams {
+    type Raw = CUDA_LAUNCH_PARAMS;
+
+    fn from_raw(c: &CUDA_LAUNCH_PARAMS) -> Self {
+        let kernel = modules::kernel_name(c.function);
+        let layout = (!c.function.is_null())
+            .then(|| args::layout(c.function, kernel.as_deref()))
+            .flatten();
+        let args = layout.and_then(|layout| {
+            let params =
+                unsafe { args::param_bytes(&layout, c.kernelParams, std::ptr::null_mut()) };
+            (params.len() == layout.len())
+                .then(|| params.into_iter().map(|(_, _, b)| b.to_vec()).collect())
+        });
+        Self {
+            function: c.function as u64,
+            grid: [c.gridDimX, c.gridDimY, c.gridDimZ],
+            block: [c.blockDimX, c.blockDimY, c.blockDimZ],
+            shared_mem: c.sharedMemBytes,
+            stream: c.hStream as u64,
+            args,
+        }
+    }
+}
+
+/// A launch attribute: its `CUlaunchAttributeID` and the words of its value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LaunchAttribute {
+    pub id: u32,
+    pub value: [u32; 16],
+}
+
+/// A `CUlaunchConfig`, with its attributes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LaunchConfig {
+    pub grid: [u32; 3],
+    pub block: [u32; 3],
+    pub shared_mem: u32,
+    pub stream: u64,
+    pub attrs: Vec<LaunchAttribute>,
+}
+
+impl Follow for LaunchConfig {
+    type Raw = CUlaunchConfig;
+
+    fn from_raw(c: &CUlaunchConfig) -> Self {
+        let attrs = if c.attrs.is_null() {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(c.attrs, c.numAttrs as usize) }
+        };
+        Self {
+            grid: [c.gridDimX, c.gridDimY, c.gridDimZ],
+            block: [c.blockDimX, c.blockDimY, c.blockDimZ],
+            shared_mem: c.sharedMemBytes,
+            stream: c.hStream as u64,
+            attrs: attrs
+                .iter()
+                .map(|a| LaunchAttribute {
+                    id: a.id,
+                    value: a.value,
+                })
+                .collect(),
+        }
+    }
+}
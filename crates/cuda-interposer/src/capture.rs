@@ -0,0 +1,507 @@
+//! Module image capture.
+//!
+//! [`install_capture_hooks!`] interposes the driver APIs that load device code and writes every
+//! PTX, cubin and fatbin image they are given to a content-addressed directory, named by the
+//! SHA-256 of the image. Each load is appended to `manifest.jsonl` in the same directory as a
+//! [`ManifestEntry`], recording the image, the loading process and thread, the JIT options and
+//! the driver's result. Images are written before the driver sees them, so that images the JIT
+//! compiler fails on are kept too.
+//!
+//! Capture is enabled by setting `CUDA_HOOK_CAPTURE_DIR` to the directory, or with [`set_dir`].
+//! When [`crate::args`] decoding or [`crate::record`]ing is enabled, the same hooks register
+//! cubin and fatbin images with [`crate::modules::register_image`] for their kernels' parameter
+//! layouts, and recording takes the loads into its trace.
+//! Images passed without a size are measured from their headers: ELF and fatbin images by their
+//! header fields, anything else as NUL-terminated PTX.
+
+use crate::args;
+use crate::fatbin::FATBIN_MAGIC;
+use crate::ffi::*;
+use crate::modules;
+use crate::record;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::ffi::{CStr, c_char, c_void};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use tracing::warn;
+
+pub use cuda_interposer_common::image::{ELF_MAGIC, image_bytes};
+
+const CAPTURE_ENV: &str = "CUDA_HOOK_CAPTURE_DIR";
+
+static DIR: Lazy<RwLock<Option<PathBuf>>> =
+    Lazy::new(|| RwLock::new(std::env::var_os(CAPTURE_ENV).map(PathBuf::from)));
+
+static MANIFEST: Mutex<()> = Mutex::new(());
+
+/// Sets the capture directory, or disables capture when `None`.
+pub fn set_dir(dir: Option<PathBuf>) {
+    *DIR.write().unwrap() = dir;
+}
+
+/// Returns the capture directory, if capture is enabled.
+pub fn dir() -> Option<PathBuf> {
+    DIR.read().unwrap().clone()
+}
+
+/// The format of a captured image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageKind {
+    Ptx,
+    Cubin,
+    Fatbin,
+    Object,
+    Library,
+    Nvvm,
+    Unknown,
+}
+
+impl ImageKind {
+    /// The extension captured images of this kind are written with.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageKind::Ptx => "ptx",
+            ImageKind::Cubin => "cubin",
+            ImageKind::Fatbin => "fatbin",
+            ImageKind::Object => "o",
+            ImageKind::Library => "a",
+            ImageKind::Nvvm => "nvvm",
+            ImageKind::Unknown => "bin",
+        }
+    }
+
+    /// The kind of a `cuLinkAddData` input of `CUjitInputType` `ty`.
+    pub fn from_jit_input_type(ty: u32) -> Self {
+        match ty {
+            0 => ImageKind::Cubin,
+            1 => ImageKind::Ptx,
+            2 => ImageKind::Fatbin,
+            3 => ImageKind::Object,
+            4 => ImageKind::Library,
+            5 => ImageKind::Nvvm,
+            _ => ImageKind::Unknown,
+        }
+    }
+
+    /// Guesses the kind of an image from its leading bytes.
+    pub fn sniff(bytes: &[u8]) -> Self {
+        match bytes {
+            [0x7f, b'E', b'L', b'F', ..] => ImageKind::Cubin,
+            [b0, b1, b2, b3, ..] if u32::from_le_bytes([*b0, *b1, *b2, *b3]) == FATBIN_MAGIC => {
+                ImageKind::Fatbin
+            }
+            _ => ImageKind::Ptx,
+        }
+    }
+}
+
+/// A JIT option passed alongside an image.
+#[derive(Debug, Clone, Serialize)]
+pub struct JitOption {
+    pub id: u32,
+    pub name: Option<&'static str>,
+    /// The option value slot. Scalar options are stored in it directly; for the others it is
+    /// an address.
+    pub value: usize,
+}
+
+/// Returns the `CUjit_option` name of `id`, without the `CU_JIT_` prefix.
+pub fn jit_option_name(id: u32) -> Option<&'static str> {
+    Some(match id {
+        0 => "MAX_REGISTERS",
+        1 => "THREADS_PER_BLOCK",
+        2 => "WALL_TIME",
+        3 => "INFO_LOG_BUFFER",
+        4 => "INFO_LOG_BUFFER_SIZE_BYTES",
+        5 => "ERROR_LOG_BUFFER",
+        6 => "ERROR_LOG_BUFFER_SIZE_BYTES",
+        7 => "OPTIMIZATION_LEVEL",
+        8 => "TARGET_FROM_CUCONTEXT",
+        9 => "TARGET",
+        10 => "FALLBACK_STRATEGY",
+        11 => "GENERATE_DEBUG_INFO",
+        12 => "LOG_VERBOSE",
+        13 => "GENERATE_LINE_INFO",
+        14 => "CACHE_MODE",
+        15 => "NEW_SM3X_OPT",
+        16 => "FAST_COMPILE",
+        17 => "GLOBAL_SYMBOL_NAMES",
+        18 => "GLOBAL_SYMBOL_ADDRESSES",
+        19 => "GLOBAL_SYMBOL_COUNT",
+        20 => "LTO",
+        21 => "FTZ",
+        22 => "PREC_DIV",
+        23 => "PREC_SQRT",
+        24 => "FMA",
+        25 => "REFERENCED_KERNEL_NAMES",
+        26 => "REFERENCED_KERNEL_COUNT",
+        27 => "REFERENCED_VARIABLE_NAMES",
+        28 => "REFERENCED_VARIABLE_COUNT",
+        29 => "OPTIMIZE_UNUSED_DEVICE_VARIABLES",
+        30 => "POSITION_INDEPENDENT_CODE",
+        31 => "MIN_CTA_PER_SM",
+        32 => "MAX_THREADS_PER_BLOCK",
+        33 => "OVERRIDE_DIRECTIVE_VALUES",
+        _ => return None,
+    })
+}
+
+/// Collects the JIT options of a load call.
+///
+/// # Safety
+/// `options` and `values` must be null or point to `count` elements.
+pub unsafe fn jit_options(
+    count: u32,
+    options: *const u32,
+    values: *const *mut c_void,
+) -> Vec<JitOption> {
+    if count == 0 || options.is_null() {
+        return Vec::new();
+    }
+    let options = unsafe { std::slice::from_raw_parts(options, count as usize) };
+    options
+        .iter()
+        .enumerate()
+        .map(|(i, &id)| JitOption {
+            id,
+            name: jit_option_name(id),
+            value: if values.is_null() {
+                0
+            } else {
+                unsafe { *values.add(i) as usize }
+            },
+        })
+        .collect()
+}
+
+/// One line of `manifest.jsonl`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestEntry {
+    /// Microseconds since the interposer was loaded.
+    pub at_us: u64,
+    pub pid: u32,
+    pub tid: i32,
+    pub api: &'static str,
+    pub sha256: String,
+    pub kind: ImageKind,
+    pub size: usize,
+    /// The file passed to `cuModuleLoad`, or the name passed to `cuLinkAddData`.
+    pub source: Option<String>,
+    pub jit_options: Vec<JitOption>,
+    pub result: CUresult,
+}
+
+/// An image that has been written to the capture directory, awaiting the result of its load.
+#[derive(Debug)]
+pub struct Captured {
+    entry: ManifestEntry,
+    dir: PathBuf,
+}
+
+impl Captured {
+    /// Appends the load to the manifest with the driver's result.
+    pub fn finish(mut self, result: CUresult) {
+        self.entry.result = result;
+        let mut line = match serde_json::to_vec(&self.entry) {
+            Ok(line) => line,
+            Err(e) => return warn!("Failed to serialize capture manifest entry: {}", e),
+        };
+        line.push(b'\n');
+        let _guard = MANIFEST.lock().unwrap();
+        let path = self.dir.join("manifest.jsonl");
+        let written = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut f| f.write_all(&line));
+        if let Err(e) = written {
+            warn!("Failed to append to {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Writes `image` to the capture directory, if capture is enabled.
+pub fn capture(
+    api: &'static str,
+    image: &[u8],
+    kind: ImageKind,
+    source: Option<String>,
+    jit_options: Vec<JitOption>,
+) -> Option<Captured> {
+    let dir = dir()?;
+    let sha256: String = Sha256::digest(image)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let path = dir.join(format!("{sha256}.{}", kind.extension()));
+    if let Err(e) = write_image(&dir, &path, image) {
+        warn!("Failed to capture module image to {:?}: {}", path, e);
+        return None;
+    }
+    let entry = ManifestEntry {
+        at_us: crate::elapsed().as_micros() as u64,
+        pid: std::process::id(),
+        tid: unsafe { libc::gettid() },
+        api,
+        sha256,
+        kind,
+        size: image.len(),
+        source,
+        jit_options,
+        result: CUDA_SUCCESS,
+    };
+    Some(Captured { entry, dir })
+}
+
+pub(crate) fn write_image(dir: &Path, path: &Path, image: &[u8]) -> std::io::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir)?;
+    // Write under a temporary name, unique to this write, so a concurrent capture of the same
+    // image from another process or thread never sees a partial image.
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    let n = WRITES.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_extension(format!("tmp{}-{}", std::process::id(), n));
+    std::fs::write(&tmp, image)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Whether the hooks need to look at loaded images, to capture them or for their kernels'
+/// parameter layouts.
+fn wanted() -> bool {
+    dir().is_some() || layouts_wanted()
+}
+
+/// Whether kernel parameter layouts are needed, to decode arguments or record launches.
+fn layouts_wanted() -> bool {
+    args::enabled() || record::enabled()
+}
+
+fn register_layouts(image: &[u8], kind: ImageKind) {
+    if layouts_wanted() && matches!(kind, ImageKind::Cubin | ImageKind::Fatbin) {
+        modules::register_image(image);
+    }
+}
+
+/// Captures an image passed to the driver without a size. Used by [`install_capture_hooks!`].
+///
+/// # Safety
+/// `image` must be null or point to an image the driver would accept.
+pub unsafe fn capture_image(
+    api: &'static str,
+    image: *const c_void,
+    jit_options: Vec<JitOption>,
+) -> Option<Captured> {
+    if !wanted() {
+        return None;
+    }
+    let bytes = unsafe { image_bytes(image) }?;
+    let kind = ImageKind::sniff(bytes);
+    register_layouts(bytes, kind);
+    capture(api, bytes, kind, None, jit_options)
+}
+
+/// Captures the file passed to `cuModuleLoad`. Used by [`install_capture_hooks!`].
+///
+/// # Safety
+/// `fname` must be null or a valid C string.
+pub unsafe fn capture_file(fname: *const c_char) -> Option<Captured> {
+    if !wanted() || fname.is_null() {
+        return None;
+    }
+    let fname = unsafe { CStr::from_ptr(fname) }
+        .to_string_lossy()
+        .into_owned();
+    let bytes = std::fs::read(&fname)
+        .map_err(|e| warn!("Failed to read module {:?} for capture: {}", fname, e))
+        .ok()?;
+    let kind = ImageKind::sniff(&bytes);
+    register_layouts(&bytes, kind);
+    capture("cuModuleLoad", &bytes, kind, Some(fname), Vec::new())
+}
+
+/// Captures an input added to a link. Used by [`install_capture_hooks!`].
+///
+/// # Safety
+/// `data` must point to `size` bytes, and `name` must be null or a valid C string.
+pub unsafe fn capture_link_input(
+    ty: u32,
+    data: *const c_void,
+    size: usize,
+    name: *const c_char,
+    jit_options: Vec<JitOption>,
+) -> Option<Captured> {
+    if !wanted() || data.is_null() {
+        return None;
+    }
+    let bytes = unsafe { std::slice::from_raw_parts(data as *const u8, size) };
+    let name = (!name.is_null()).then(|| {
+        unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned()
+    });
+    let kind = ImageKind::from_jit_input_type(ty);
+    register_layouts(bytes, kind);
+    capture("cuLinkAddData_v2", bytes, kind, name, jit_options)
+}
+
+/// Installs hooks that capture every module image the application loads.
+///
+/// The hooked symbols are listed in [`crate::hook_sets::HOOK_SETS`], so
+/// `cuda-interposer-build` leaves them out of the generated passthroughs.
+#[macro_export]
+macro_rules! install_capture_hooks {
+    () => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuModuleLoad(
+                module: *mut $crate::ffi::CUmodule,
+                fname: *const $crate::libc::c_char
+            ) -> $crate::ffi::CUresult {
+                let captured = unsafe { $crate::capture::capture_file(fname) };
+                let rc = unsafe { (*__real_cuModuleLoad)(module, fname) };
+                unsafe { $crate::record::on_module_load_file(rc, module, fname) };
+                unsafe { $crate::checkpoint::on_module_load_file(rc, module, fname) };
+                if let Some(c) = captured {
+                    c.finish(rc);
+                }
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Module, module) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuModuleLoadData(
+                module: *mut $crate::ffi::CUmodule,
+                image: *const $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let captured = unsafe { $crate::capture::capture_image("cuModuleLoadData", image, Vec::new()) };
+                let rc = unsafe { (*__real_cuModuleLoadData)(module, image) };
+                unsafe { $crate::record::on_module_load(rc, "cuModuleLoadData", module, image) };
+                unsafe { $crate::checkpoint::on_module_load(rc, module, image) };
+                if let Some(c) = captured {
+                    c.finish(rc);
+                }
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Module, module) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuModuleLoadDataEx(
+                module: *mut $crate::ffi::CUmodule,
+                image: *const $crate::libc::c_void,
+                num_options: $crate::libc::c_uint,
+                options: *mut $crate::libc::c_uint,
+                option_values: *mut *mut $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let captured = unsafe {
+                    let options = $crate::capture::jit_options(num_options, options, option_values);
+                    $crate::capture::capture_image("cuModuleLoadDataEx", image, options)
+                };
+                let rc = unsafe { (*__real_cuModuleLoadDataEx)(module, image, num_options, options, option_values) };
+                unsafe { $crate::record::on_module_load(rc, "cuModuleLoadDataEx", module, image) };
+                unsafe { $crate::checkpoint::on_module_load(rc, module, image) };
+                if let Some(c) = captured {
+                    c.finish(rc);
+                }
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Module, module) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuModuleLoadFatBinary(
+                module: *mut $crate::ffi::CUmodule,
+                fat_cubin: *const $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let captured = unsafe { $crate::capture::capture_image("cuModuleLoadFatBinary", fat_cubin, Vec::new()) };
+                let rc = unsafe { (*__real_cuModuleLoadFatBinary)(module, fat_cubin) };
+                unsafe { $crate::record::on_module_load(rc, "cuModuleLoadFatBinary", module, fat_cubin) };
+                unsafe { $crate::checkpoint::on_module_load(rc, module, fat_cubin) };
+                if let Some(c) = captured {
+                    c.finish(rc);
+                }
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Module, module) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuLibraryLoadData(
+                library: *mut $crate::ffi::CUlibrary,
+                code: *const $crate::libc::c_void,
+                jit_options: *mut $crate::libc::c_uint,
+                jit_options_values: *mut *mut $crate::libc::c_void,
+                num_jit_options: $crate::libc::c_uint,
+                library_options: *mut $crate::libc::c_uint,
+                library_option_values: *mut *mut $crate::libc::c_void,
+                num_library_options: $crate::libc::c_uint
+            ) -> $crate::ffi::CUresult {
+                let captured = unsafe {
+                    let options = $crate::capture::jit_options(num_jit_options, jit_options, jit_options_values);
+                    $crate::capture::capture_image("cuLibraryLoadData", code, options)
+                };
+                let rc = unsafe {
+                    (*__real_cuLibraryLoadData)(
+                        library, code, jit_options, jit_options_values, num_jit_options,
+                        library_options, library_option_values, num_library_options,
+                    )
+                };
+                if let Some(c) = captured {
+                    c.finish(rc);
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuLinkAddData_v2(
+                state: *mut $crate::libc::c_void,
+                ty: $crate::libc::c_uint,
+                data: *mut $crate::libc::c_void,
+                size: usize,
+                name: *const $crate::libc::c_char,
+                num_options: $crate::libc::c_uint,
+                options: *mut $crate::libc::c_uint,
+                option_values: *mut *mut $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let captured = unsafe {
+                    let jit_options = $crate::capture::jit_options(num_options, options, option_values);
+                    $crate::capture::capture_link_input(ty, data, size, name, jit_options)
+                };
+                let rc = unsafe {
+                    (*__real_cuLinkAddData_v2)(state, ty, data, size, name, num_options, options, option_values)
+                };
+                if let Some(c) = captured {
+                    c.finish(rc);
+                }
+                rc
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_writes_of_an_image_do_not_collide() {
+        let dir = std::env::temp_dir().join(format!("capture-test-{}", std::process::id()));
+        let path = dir.join("image.ptx");
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| write_image(&dir, &path, b"image").unwrap());
+            }
+        });
+        assert_eq!(std::fs::read(&path).unwrap(), b"image");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
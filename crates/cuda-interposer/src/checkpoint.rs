@@ -0,0 +1,1017 @@
+//! Device memory checkpoint and restore.
+//!
+//! [`snapshot`] copies every live allocation tracked by [`install_memory_hooks!`] to host memory
+//! with the real `cuMemcpyDtoH_v2`, once its context is synchronized. It also gathers what is
+//! needed to recreate the driver objects the application holds: its contexts, the images of the
+//! modules it loaded, the functions it looked up in them, and its streams.
+//! [`Checkpoint::save`] writes a checkpoint to a directory, and [`Checkpoint::load`] reads it
+//! back. [`save`] does both at once, streaming each allocation to its file in pieces so that
+//! device memory is never held on the host as a whole.
+//!
+//! [`restore`] rebuilds a checkpoint in a fresh process whose host state came from the same
+//! point, for example one restored by CRIU or by the application from its own checkpoint.
+//! Allocations come back at their original addresses: the pages they covered are reserved with
+//! `cuMemAddressReserve` and backed with `cuMemCreate` and `cuMemMap`. Device pointers held on
+//! the host or stored in device memory therefore stay valid. Freeing a restored allocation
+//! releases its pages once no other restored allocation shares them.
+//!
+//! Contexts, modules, functions and streams are recreated. When handles are virtualized (see
+//! [`crate::handles`]), the virtual handles the application holds are rebound to the new
+//! objects; otherwise the new handles are only reported in [`Restored`]. Managed allocations,
+//! physical memory from `cuMemCreate` and libraries from `cuLibraryLoadData` are not
+//! checkpointed.
+//!
+//! Objects are tracked through [`install_memory_hooks!`] (allocations), [`install_capture_hooks!`]
+//! (module loads), [`install_module_hooks!`] (function lookups), [`install_stream_hooks!`]
+//! (streams), [`install_device_hooks!`] (context creation) and [`install_record_hooks!`]
+//! (`cuInit`, context destruction and module unloads).
+//!
+//! Tracking starts when `CUDA_HOOK_CHECKPOINT_DIR` or `CUDA_HOOK_RESTORE_DIR` is set, or when
+//! [`set_enabled`] is called before the objects are created. With `CUDA_HOOK_CHECKPOINT_DIR`
+//! set, the signal named by `CUDA_HOOK_CHECKPOINT_SIGNAL` (e.g. `USR1`) writes a checkpoint
+//! there; the handler replaces the signal's default action. With `CUDA_HOOK_RESTORE_DIR` set,
+//! the checkpoint in that directory is restored as soon as `cuInit` succeeds.
+
+use crate::config::ConfigError;
+use crate::driver;
+use crate::ffi::*;
+use crate::handles::{self, HandleKind};
+use crate::memory::{self, AllocKind, Allocation};
+use crate::modules;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Mutex, Once};
+use tracing::{debug, warn};
+
+const DIR_ENV: &str = "CUDA_HOOK_CHECKPOINT_DIR";
+const SIGNAL_ENV: &str = "CUDA_HOOK_CHECKPOINT_SIGNAL";
+const RESTORE_ENV: &str = "CUDA_HOOK_RESTORE_DIR";
+
+const MANIFEST: &str = "checkpoint.json";
+
+/// The most device memory [`save`] copies to the host at a time.
+const SAVE_CHUNK: usize = 64 << 20;
+
+static ENABLED: Lazy<AtomicBool> = Lazy::new(|| {
+    let dir = std::env::var_os(DIR_ENV).map(PathBuf::from);
+    if let Some(dir) = &dir {
+        install_signal(dir.clone());
+    }
+    AtomicBool::new(dir.is_some() || std::env::var_os(RESTORE_ENV).is_some())
+});
+
+static TRACKER: Lazy<Mutex<Tracker>> = Lazy::new(Default::default);
+
+static RESTORED: Lazy<Mutex<Restorations>> = Lazy::new(Default::default);
+
+/// Whether any allocation was restored; until then frees need no lookup.
+static ANY_RESTORED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A context. Handles are as the driver returned them in the checkpointed process; `handle` is
+/// what the application was given, which differs when handles are virtualized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedContext {
+    pub ctx: u64,
+    pub handle: u64,
+    pub device: CUdevice,
+    /// The flags of a context from `cuCtxCreate`, or `None` for a primary context.
+    pub flags: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedAllocation {
+    pub ptr: CUdeviceptr,
+    pub handle: CUdeviceptr,
+    pub size: usize,
+    pub kind: AllocKind,
+    pub context: u64,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedModule {
+    pub module: u64,
+    pub handle: u64,
+    pub context: u64,
+    #[serde(skip)]
+    pub image: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedFunction {
+    pub function: u64,
+    pub handle: u64,
+    pub module: u64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedStream {
+    pub stream: u64,
+    pub handle: u64,
+    pub context: u64,
+    pub flags: u32,
+    pub priority: Option<i32>,
+}
+
+/// The device memory and driver objects of a process at one point.
+///
+/// On disk, a checkpoint is a directory with `checkpoint.json`, the contents of each allocation
+/// under `memory/` and each module image under `modules/`, named by their handle in hex.
+/// `checkpoint.json` is written last, so a directory without it holds no complete checkpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub contexts: Vec<SavedContext>,
+    pub allocations: Vec<SavedAllocation>,
+    pub modules: Vec<SavedModule>,
+    pub functions: Vec<SavedFunction>,
+    pub streams: Vec<SavedStream>,
+}
+
+fn memory_path(dir: &Path, ptr: CUdeviceptr) -> PathBuf {
+    dir.join("memory").join(format!("{ptr:016x}.bin"))
+}
+
+fn module_path(dir: &Path, module: u64) -> PathBuf {
+    dir.join("modules").join(format!("{module:016x}.bin"))
+}
+
+impl Checkpoint {
+    /// Returns the bytes of device memory the checkpoint holds.
+    pub fn bytes(&self) -> usize {
+        self.allocations.iter().map(|a| a.size).sum()
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), CheckpointError> {
+        begin_save(dir)?;
+        for a in &self.allocations {
+            std::fs::write(memory_path(dir, a.ptr), &a.data)?;
+        }
+        self.finish_save(dir)
+    }
+
+    /// Writes the module images and then the manifest, once the allocations are saved.
+    fn finish_save(&self, dir: &Path) -> Result<(), CheckpointError> {
+        for m in &self.modules {
+            std::fs::write(module_path(dir, m.module), &m.image)?;
+        }
+        let manifest = serde_json::to_vec_pretty(self).map_err(std::io::Error::from)?;
+        std::fs::write(dir.join(MANIFEST), manifest)?;
+        Ok(())
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, CheckpointError> {
+        let manifest = std::fs::read(dir.join(MANIFEST))?;
+        let mut checkpoint: Checkpoint =
+            serde_json::from_slice(&manifest).map_err(std::io::Error::from)?;
+        for a in &mut checkpoint.allocations {
+            a.data = std::fs::read(memory_path(dir, a.ptr))?;
+            if a.data.len() != a.size {
+                return Err(CheckpointError::Truncated(a.ptr));
+            }
+        }
+        for m in &mut checkpoint.modules {
+            m.image = std::fs::read(module_path(dir, m.module))?;
+        }
+        Ok(checkpoint)
+    }
+}
+
+/// Prepares `dir` for a checkpoint. Until the new manifest is written, it holds none.
+fn begin_save(dir: &Path) -> Result<(), CheckpointError> {
+    match std::fs::remove_file(dir.join(MANIFEST)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    std::fs::create_dir_all(dir.join("memory"))?;
+    std::fs::create_dir_all(dir.join("modules"))?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(std::io::Error),
+    /// The driver lacks an entry point that checkpointing needs.
+    Missing(&'static str),
+    Driver {
+        api: &'static str,
+        result: CUresult,
+    },
+    /// The pages of a restored allocation could not be reserved at their original address.
+    Address(CUdeviceptr),
+    /// The saved contents of the allocation at this address are not as large as it.
+    Truncated(CUdeviceptr),
+    /// An object refers to a context or module the checkpoint does not hold.
+    Dangling(&'static str, u64),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "{e}"),
+            CheckpointError::Missing(api) => write!(f, "the driver does not export {api}"),
+            CheckpointError::Driver { api, result } => match driver::error_name(*result) {
+                Some(name) => write!(f, "{api} failed with {name}"),
+                None => write!(f, "{api} failed with {result}"),
+            },
+            CheckpointError::Address(ptr) => {
+                write!(f, "cannot reserve device address {ptr:#x} again")
+            }
+            CheckpointError::Truncated(ptr) => {
+                write!(f, "the saved allocation at {ptr:#x} is truncated")
+            }
+            CheckpointError::Dangling(kind, handle) => {
+                write!(f, "unknown {kind} {handle:#x} in the checkpoint")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<std::io::Error> for CheckpointError {
+    fn from(e: std::io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+/// Calls a real driver entry point from [`crate::driver`], returning from the enclosing
+/// function with a [`CheckpointError`] if it is missing or fails.
+macro_rules! real {
+    ($name:ident($($arg:expr),* $(,)?)) => {{
+        let f = (*driver::$name).ok_or(CheckpointError::Missing(stringify!($name)))?;
+        match unsafe { f($($arg),*) } {
+            CUDA_SUCCESS => {}
+            result => {
+                return Err(CheckpointError::Driver {
+                    api: stringify!($name),
+                    result,
+                })
+            }
+        }
+    }};
+}
+
+#[derive(Default)]
+struct Tracker {
+    contexts: BTreeMap<u64, SavedContext>,
+    modules: BTreeMap<u64, SavedModule>,
+    functions: BTreeMap<u64, SavedFunction>,
+    streams: BTreeMap<u64, SavedStream>,
+}
+
+/// The pages [`restore`] mapped, and the restored allocations in them.
+#[derive(Default)]
+struct Restorations {
+    /// Base address to mapped region.
+    regions: BTreeMap<CUdeviceptr, Region>,
+    /// Restored allocation to the base of its region.
+    allocations: HashMap<CUdeviceptr, CUdeviceptr>,
+}
+
+struct Region {
+    size: usize,
+    handle: CUmemGenericAllocationHandle,
+    /// Restored allocations in the region not freed yet.
+    live: usize,
+}
+
+fn current_context() -> u64 {
+    driver::current_context() as u64
+}
+
+/// Runs `f`, then makes the context that was current before it current again.
+fn keeping_context<T>(f: impl FnOnce() -> T) -> T {
+    let previous = driver::current_context();
+    let result = f();
+    if let Some(set) = *driver::cuCtxSetCurrent {
+        unsafe { set(previous) };
+    }
+    result
+}
+
+/// Copies the tracked allocations to host memory and gathers the tracked objects.
+pub fn snapshot() -> Result<Checkpoint, CheckpointError> {
+    let tracker = TRACKER.lock().unwrap();
+    keeping_context(|| {
+        snapshot_locked(&tracker, &mut |alloc| {
+            let mut data = vec![0u8; alloc.size];
+            real!(cuMemcpyDtoH_v2(
+                data.as_mut_ptr() as *mut c_void,
+                alloc.ptr,
+                alloc.size
+            ));
+            Ok(data)
+        })
+    })
+}
+
+/// Gathers the tracked objects, and the contents of each allocation as `copy` returns them
+/// once its context is synchronized.
+fn snapshot_locked(
+    tracker: &Tracker,
+    copy: &mut dyn FnMut(&Allocation) -> Result<Vec<u8>, CheckpointError>,
+) -> Result<Checkpoint, CheckpointError> {
+    let mut contexts = tracker.contexts.clone();
+    let mut allocations = Vec::new();
+    let mut synchronized = HashSet::new();
+    let mut live = memory::ALLOCATIONS.live();
+    live.sort_by_key(|a| a.ptr);
+    for alloc in live {
+        if matches!(alloc.kind, AllocKind::Managed | AllocKind::Physical) {
+            warn!(
+                "Not checkpointing the {} allocation at {:#x}",
+                alloc.kind.api(),
+                alloc.ptr
+            );
+            continue;
+        }
+        let ctx = alloc.context as u64;
+        if synchronized.insert(ctx) {
+            real!(cuCtxSetCurrent(ctx as CUcontext));
+            real!(cuCtxSynchronize());
+            contexts.entry(ctx).or_insert_with(|| SavedContext {
+                ctx,
+                handle: ctx,
+                device: alloc.device.unwrap_or(0),
+                flags: None,
+            });
+        }
+        let data = copy(&alloc)?;
+        allocations.push(SavedAllocation {
+            ptr: alloc.ptr,
+            handle: handles::virtual_of(HandleKind::DevicePtr, alloc.ptr),
+            size: alloc.size,
+            kind: alloc.kind,
+            context: ctx,
+            data,
+        });
+    }
+    // The handles are exported to the application after the hooks track them, so look up
+    // what it was given now.
+    Ok(Checkpoint {
+        contexts: contexts
+            .into_values()
+            .map(|c| SavedContext {
+                handle: handles::virtual_of(HandleKind::Context, c.ctx),
+                ..c
+            })
+            .collect(),
+        allocations,
+        modules: tracker
+            .modules
+            .values()
+            .map(|m| SavedModule {
+                handle: handles::virtual_of(HandleKind::Module, m.module),
+                ..m.clone()
+            })
+            .collect(),
+        functions: tracker
+            .functions
+            .values()
+            .map(|f| SavedFunction {
+                handle: handles::virtual_of(HandleKind::Function, f.function),
+                ..f.clone()
+            })
+            .collect(),
+        streams: tracker
+            .streams
+            .values()
+            .map(|s| SavedStream {
+                handle: handles::virtual_of(HandleKind::Stream, s.stream),
+                ..s.clone()
+            })
+            .collect(),
+    })
+}
+
+/// Takes a snapshot and saves it to `dir`. Allocations are copied to their files a piece at a
+/// time rather than held on the host together, so the returned checkpoint does not hold their
+/// contents.
+pub fn save(dir: &Path) -> Result<Checkpoint, CheckpointError> {
+    begin_save(dir)?;
+    let mut buffer = Vec::new();
+    let checkpoint = {
+        let tracker = TRACKER.lock().unwrap();
+        keeping_context(|| {
+            snapshot_locked(&tracker, &mut |alloc| {
+                let mut file = File::create(memory_path(dir, alloc.ptr))?;
+                for offset in (0..alloc.size).step_by(SAVE_CHUNK) {
+                    buffer.resize((alloc.size - offset).min(SAVE_CHUNK), 0);
+                    real!(cuMemcpyDtoH_v2(
+                        buffer.as_mut_ptr() as *mut c_void,
+                        alloc.ptr + offset as u64,
+                        buffer.len()
+                    ));
+                    file.write_all(&buffer)?;
+                }
+                Ok(Vec::new())
+            })
+        })?
+    };
+    checkpoint.finish_save(dir)?;
+    Ok(checkpoint)
+}
+
+/// What [`restore`] recreated.
+#[derive(Debug, Clone, Default)]
+pub struct Restored {
+    pub allocations: usize,
+    pub bytes: usize,
+    /// Each recreated object: its kind, the handle the application holds, and the real handle
+    /// now behind it. Handles that are not virtual are stale unless both are the same.
+    pub handles: Vec<(HandleKind, u64, u64)>,
+}
+
+impl Restored {
+    fn rebind(&mut self, kind: HandleKind, handle: u64, real: u64) {
+        handles::rebind(kind, handle, real, 0);
+        self.handles.push((kind, handle, real));
+    }
+}
+
+/// Recreates the objects of `checkpoint` and restores its allocations at their addresses.
+pub fn restore(checkpoint: &Checkpoint) -> Result<Restored, CheckpointError> {
+    let mut tracker = TRACKER.lock().unwrap();
+    keeping_context(|| restore_locked(checkpoint, &mut tracker))
+}
+
+/// Loads the checkpoint in `dir` and restores it.
+pub fn restore_from(dir: &Path) -> Result<Restored, CheckpointError> {
+    restore(&Checkpoint::load(dir)?)
+}
+
+fn restore_locked(c: &Checkpoint, tracker: &mut Tracker) -> Result<Restored, CheckpointError> {
+    let mut restored = Restored::default();
+
+    let mut contexts = HashMap::new();
+    for saved in &c.contexts {
+        let mut ctx: CUcontext = std::ptr::null_mut();
+        match saved.flags {
+            None => real!(cuDevicePrimaryCtxRetain(&mut ctx, saved.device)),
+            Some(flags) => real!(cuCtxCreate_v2(&mut ctx, flags, saved.device)),
+        }
+        contexts.insert(saved.ctx, (ctx, saved.device));
+        restored.rebind(HandleKind::Context, saved.handle, ctx as u64);
+        let ctx = ctx as u64;
+        tracker.contexts.insert(
+            ctx,
+            SavedContext {
+                ctx,
+                ..saved.clone()
+            },
+        );
+    }
+    let context = |old: u64| {
+        contexts
+            .get(&old)
+            .copied()
+            .ok_or(CheckpointError::Dangling("context", old))
+    };
+
+    let mut by_context: BTreeMap<u64, Vec<&SavedAllocation>> = BTreeMap::new();
+    for a in &c.allocations {
+        by_context.entry(a.context).or_default().push(a);
+    }
+    for (old, allocations) in by_context {
+        let (ctx, device) = context(old)?;
+        real!(cuCtxSetCurrent(ctx));
+        map_pages(device, &allocations)?;
+        for a in allocations {
+            if a.data.len() != a.size {
+                return Err(CheckpointError::Truncated(a.ptr));
+            }
+            real!(cuMemcpyHtoD_v2(
+                a.ptr,
+                a.data.as_ptr() as *const c_void,
+                a.size
+            ));
+            memory::ALLOCATIONS.record_alloc(a.ptr, a.size, a.kind);
+            handles::rebind(HandleKind::DevicePtr, a.handle, a.ptr, a.size);
+            restored.allocations += 1;
+            restored.bytes += a.size;
+        }
+    }
+
+    let mut modules = HashMap::new();
+    for saved in &c.modules {
+        let (ctx, _) = context(saved.context)?;
+        real!(cuCtxSetCurrent(ctx));
+        // PTX images are saved without their terminating NUL.
+        let mut image = saved.image.clone();
+        image.push(0);
+        let mut module: CUmodule = std::ptr::null_mut();
+        real!(cuModuleLoadData(
+            &mut module,
+            image.as_ptr() as *const c_void
+        ));
+        modules.insert(saved.module, module);
+        restored.rebind(HandleKind::Module, saved.handle, module as u64);
+        let module = module as u64;
+        let context = ctx as u64;
+        tracker.modules.insert(
+            module,
+            SavedModule {
+                module,
+                context,
+                ..saved.clone()
+            },
+        );
+    }
+
+    for saved in &c.functions {
+        let module = *modules
+            .get(&saved.module)
+            .ok_or(CheckpointError::Dangling("module", saved.module))?;
+        let name = CString::new(saved.name.as_str()).unwrap_or_default();
+        let mut function: CUfunction = std::ptr::null_mut();
+        real!(cuModuleGetFunction(&mut function, module, name.as_ptr()));
+        unsafe { modules::remember_function(function, name.as_ptr()) };
+        restored.rebind(HandleKind::Function, saved.handle, function as u64);
+        let function = function as u64;
+        tracker.functions.insert(
+            function,
+            SavedFunction {
+                function,
+                module: module as u64,
+                ..saved.clone()
+            },
+        );
+    }
+
+    for saved in &c.streams {
+        let (ctx, _) = context(saved.context)?;
+        real!(cuCtxSetCurrent(ctx));
+        let mut stream: CUstream = std::ptr::null_mut();
+        match saved.priority {
+            Some(priority) => real!(cuStreamCreateWithPriority(
+                &mut stream,
+                saved.flags,
+                priority
+            )),
+            None => real!(cuStreamCreate(&mut stream, saved.flags)),
+        }
+        restored.rebind(HandleKind::Stream, saved.handle, stream as u64);
+        let stream = stream as u64;
+        let context = ctx as u64;
+        tracker.streams.insert(
+            stream,
+            SavedStream {
+                stream,
+                context,
+                ..saved.clone()
+            },
+        );
+    }
+
+    Ok(restored)
+}
+
+/// Reserves the pages covering `allocations` at their original addresses and backs them with
+/// memory on `device`. Allocations that share pages share one mapping.
+fn map_pages(device: CUdevice, allocations: &[&SavedAllocation]) -> Result<(), CheckpointError> {
+    let location = CUmemLocation {
+        type_: CU_MEM_LOCATION_TYPE_DEVICE,
+        id: device,
+    };
+    let prop = CUmemAllocationProp {
+        type_: CU_MEM_ALLOCATION_TYPE_PINNED,
+        requestedHandleTypes: 0,
+        location,
+        win32HandleMetaData: std::ptr::null_mut(),
+        allocFlags: unsafe { std::mem::zeroed() },
+    };
+    let access = CUmemAccessDesc {
+        location,
+        flags: CU_MEM_ACCESS_FLAGS_PROT_READWRITE,
+    };
+    let mut granularity = 0usize;
+    real!(cuMemGetAllocationGranularity(
+        &mut granularity,
+        &prop,
+        CU_MEM_ALLOC_GRANULARITY_MINIMUM
+    ));
+    let granularity = granularity.max(1) as u64;
+
+    // Page ranges as (start, end, allocations in them).
+    let mut ranges: Vec<(u64, u64, Vec<CUdeviceptr>)> = Vec::new();
+    let mut sorted = allocations.to_vec();
+    sorted.sort_by_key(|a| a.ptr);
+    for a in sorted {
+        let start = a.ptr / granularity * granularity;
+        let end = (a.ptr + a.size.max(1) as u64).next_multiple_of(granularity);
+        match ranges.last_mut() {
+            Some(last) if start < last.1 => {
+                last.1 = last.1.max(end);
+                last.2.push(a.ptr);
+            }
+            _ => ranges.push((start, end, vec![a.ptr])),
+        }
+    }
+
+    for (start, end, ptrs) in ranges {
+        let size = (end - start) as usize;
+        let mut base = 0;
+        real!(cuMemAddressReserve(&mut base, size, 0, start, 0));
+        if base != start {
+            if let Some(free) = *driver::cuMemAddressFree {
+                unsafe { free(base, size) };
+            }
+            return Err(CheckpointError::Address(start));
+        }
+        let mut handle = 0;
+        let mapped = (|| {
+            real!(cuMemCreate(&mut handle, size, &prop, 0));
+            real!(cuMemMap(base, size, 0, handle, 0));
+            real!(cuMemSetAccess(base, size, &access, 1));
+            Ok(())
+        })();
+        let region = Region {
+            size,
+            handle,
+            live: ptrs.len(),
+        };
+        if let Err(e) = mapped {
+            unmap(base, &region);
+            return Err(e);
+        }
+        let mut restorations = RESTORED.lock().unwrap();
+        restorations.regions.insert(base, region);
+        for ptr in ptrs {
+            restorations.allocations.insert(ptr, base);
+        }
+        ANY_RESTORED.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Unmaps a region and releases its memory and addresses, returning the first failure.
+fn unmap(base: CUdeviceptr, region: &Region) -> CUresult {
+    let results = [
+        (*driver::cuMemUnmap).map(|f| unsafe { f(base, region.size) }),
+        (*driver::cuMemRelease).map(|f| unsafe { f(region.handle) }),
+        (*driver::cuMemAddressFree).map(|f| unsafe { f(base, region.size) }),
+    ];
+    results
+        .into_iter()
+        .map(|rc| rc.unwrap_or(CUDA_ERROR_NOT_FOUND))
+        .find(|&rc| rc != CUDA_SUCCESS)
+        .unwrap_or(CUDA_SUCCESS)
+}
+
+/// Frees an allocation [`restore`] mapped, once the work on `stream` is done if one is given,
+/// and returns the result; returns `None` if `ptr` is not one. Used by [`install_memory_hooks!`].
+pub fn free_restored(ptr: CUdeviceptr, stream: Option<CUstream>) -> Option<CUresult> {
+    if !ANY_RESTORED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut restorations = RESTORED.lock().unwrap();
+    let base = restorations.allocations.remove(&ptr)?;
+    if let Some(stream) = stream {
+        driver::stream_synchronize(stream);
+    }
+    let region = restorations.regions.get_mut(&base)?;
+    region.live -= 1;
+    if region.live > 0 {
+        return Some(CUDA_SUCCESS);
+    }
+    let region = restorations.regions.remove(&base)?;
+    Some(unmap(base, &region))
+}
+
+// ─── Tracking ────────────────────────────────────────────────────────────────
+
+/// Restores the checkpoint in `CUDA_HOOK_RESTORE_DIR` once the driver is initialized.
+pub fn on_init(rc: CUresult) {
+    static RESTORE: Once = Once::new();
+    if rc != CUDA_SUCCESS {
+        return;
+    }
+    RESTORE.call_once(|| {
+        let Some(dir) = std::env::var_os(RESTORE_ENV) else {
+            return;
+        };
+        match restore_from(Path::new(&dir)) {
+            Ok(r) => debug!(
+                "Restored {} allocations ({} bytes) from {:?}",
+                r.allocations, r.bytes, dir
+            ),
+            Err(e) => warn!("Failed to restore the checkpoint in {:?}: {}", dir, e),
+        }
+    });
+}
+
+/// # Safety
+/// `pctx` must be the output pointer passed to `cuCtxCreate`.
+pub unsafe fn on_ctx_create(rc: CUresult, pctx: *const CUcontext, flags: u32, device: CUdevice) {
+    if enabled() && rc == CUDA_SUCCESS && !pctx.is_null() {
+        let ctx = unsafe { *pctx } as u64;
+        track_context(ctx, device, Some(flags));
+    }
+}
+
+/// # Safety
+/// `pctx` must be the output pointer passed to `cuDevicePrimaryCtxRetain`.
+pub unsafe fn on_primary_ctx_retain(rc: CUresult, pctx: *const CUcontext, device: CUdevice) {
+    if enabled() && rc == CUDA_SUCCESS && !pctx.is_null() {
+        let ctx = unsafe { *pctx } as u64;
+        track_context(ctx, device, None);
+    }
+}
+
+fn track_context(ctx: u64, device: CUdevice, flags: Option<u32>) {
+    TRACKER.lock().unwrap().contexts.insert(
+        ctx,
+        SavedContext {
+            ctx,
+            handle: ctx,
+            device,
+            flags,
+        },
+    );
+}
+
+pub fn on_ctx_destroy(rc: CUresult, ctx: CUcontext) {
+    if enabled() && rc == CUDA_SUCCESS {
+        let ctx = ctx as u64;
+        let mut tracker = TRACKER.lock().unwrap();
+        tracker.contexts.remove(&ctx);
+        tracker.streams.retain(|_, s| s.context != ctx);
+        let modules: HashSet<u64> = tracker
+            .modules
+            .values()
+            .filter(|m| m.context == ctx)
+            .map(|m| m.module)
+            .collect();
+        tracker.modules.retain(|m, _| !modules.contains(m));
+        tracker
+            .functions
+            .retain(|_, f| !modules.contains(&f.module));
+    }
+}
+
+/// # Safety
+/// `module` must be the output pointer passed to the load, and `image` the image.
+pub unsafe fn on_module_load(rc: CUresult, module: *const CUmodule, image: *const c_void) {
+    if enabled()
+        && rc == CUDA_SUCCESS
+        && !module.is_null()
+        && let Some(image) = unsafe { crate::capture::image_bytes(image) }
+    {
+        track_module(unsafe { *module } as u64, image.to_vec());
+    }
+}
+
+/// # Safety
+/// `module` must be the output pointer passed to `cuModuleLoad`, and `fname` null or a C string.
+pub unsafe fn on_module_load_file(rc: CUresult, module: *const CUmodule, fname: *const c_char) {
+    if !enabled() || rc != CUDA_SUCCESS || module.is_null() || fname.is_null() {
+        return;
+    }
+    let fname = unsafe { CStr::from_ptr(fname) }.to_string_lossy();
+    match std::fs::read(&*fname) {
+        Ok(image) => track_module(unsafe { *module } as u64, image),
+        Err(e) => warn!("Failed to read module {:?} for checkpointing: {}", fname, e),
+    }
+}
+
+fn track_module(module: u64, image: Vec<u8>) {
+    TRACKER.lock().unwrap().modules.insert(
+        module,
+        SavedModule {
+            module,
+            handle: module,
+            context: current_context(),
+            image,
+        },
+    );
+}
+
+pub fn on_module_unload(rc: CUresult, module: CUmodule) {
+    if enabled() && rc == CUDA_SUCCESS {
+        let module = module as u64;
+        let mut tracker = TRACKER.lock().unwrap();
+        tracker.modules.remove(&module);
+        tracker.functions.retain(|_, f| f.module != module);
+    }
+}
+
+/// # Safety
+/// `hfunc` must be the output pointer passed to `cuModuleGetFunction`, and `name` a C string.
+pub unsafe fn on_get_function(
+    rc: CUresult,
+    hfunc: *const CUfunction,
+    module: CUmodule,
+    name: *const c_char,
+) {
+    if !enabled() || rc != CUDA_SUCCESS || hfunc.is_null() || name.is_null() {
+        return;
+    }
+    let function = unsafe { *hfunc } as u64;
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .into_owned();
+    TRACKER.lock().unwrap().functions.insert(
+        function,
+        SavedFunction {
+            function,
+            handle: function,
+            module: module as u64,
+            name,
+        },
+    );
+}
+
+/// # Safety
+/// `ph_stream` must be the output pointer passed to the stream creation.
+pub unsafe fn on_stream_create(
+    rc: CUresult,
+    ph_stream: *const CUstream,
+    flags: u32,
+    priority: Option<i32>,
+) {
+    if enabled() && rc == CUDA_SUCCESS && !ph_stream.is_null() {
+        let stream = unsafe { *ph_stream } as u64;
+        TRACKER.lock().unwrap().streams.insert(
+            stream,
+            SavedStream {
+                stream,
+                handle: stream,
+                context: current_context(),
+                flags,
+                priority,
+            },
+        );
+    }
+}
+
+pub fn on_stream_destroy(rc: CUresult, stream: CUstream) {
+    if enabled() && rc == CUDA_SUCCESS {
+        TRACKER.lock().unwrap().streams.remove(&(stream as u64));
+    }
+}
+
+// ─── Signal ──────────────────────────────────────────────────────────────────
+
+/// The write end of the pipe the signal handler wakes the checkpoint thread through.
+static SIGNAL_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    let fd = SIGNAL_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        unsafe { libc::write(fd, [1u8].as_ptr() as *const c_void, 1) };
+    }
+}
+
+fn parse_signal(s: &str) -> Option<libc::c_int> {
+    let s = s.trim().to_ascii_uppercase();
+    Some(match s.strip_prefix("SIG").unwrap_or(&s) {
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "TERM" => libc::SIGTERM,
+        n => n.parse().ok().filter(|&n| n > 0)?,
+    })
+}
+
+/// Checkpoints to `dir` on the signal in `CUDA_HOOK_CHECKPOINT_SIGNAL`. The handler only wakes a
+/// thread, which takes the checkpoint outside of signal context.
+fn install_signal(dir: PathBuf) {
+    let Ok(spec) = std::env::var(SIGNAL_ENV) else {
+        return;
+    };
+    let Some(signal) = parse_signal(&spec) else {
+        let e = ConfigError::new(SIGNAL_ENV, &spec, "expected a signal such as USR1 or 10");
+        return warn!("Not checkpointing on a signal: {}", e);
+    };
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return warn!("Not checkpointing on a signal: {}", e);
+    }
+    let [read_fd, write_fd] = fds;
+    let spawned = std::thread::Builder::new()
+        .name("cuda-checkpoint".into())
+        .spawn(move || {
+            let mut byte = 0u8;
+            loop {
+                let n = unsafe { libc::read(read_fd, &mut byte as *mut u8 as *mut c_void, 1) };
+                if n < 0
+                    && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+                {
+                    continue;
+                }
+                if n != 1 {
+                    break;
+                }
+                match save(&dir) {
+                    Ok(c) => debug!(
+                        "Checkpointed {} allocations ({} bytes) to {:?}",
+                        c.allocations.len(),
+                        c.bytes(),
+                        dir
+                    ),
+                    Err(e) => warn!("Failed to checkpoint to {:?}: {}", dir, e),
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        return warn!("Not checkpointing on a signal: {}", e);
+    }
+    SIGNAL_FD.store(write_fd, Ordering::Relaxed);
+    unsafe { libc::signal(signal, on_signal as *const () as libc::sighandler_t) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{self, PTX};
+    use std::ptr::null_mut;
+
+    #[test]
+    fn saves_and_restores_against_the_fake_driver() {
+        let (d, _guard) = fake::driver();
+        set_enabled(true);
+        let dir = std::env::temp_dir().join(format!("checkpoint-test-{}", std::process::id()));
+
+        assert_eq!(unsafe { d.cuInit.unwrap()(0) }, CUDA_SUCCESS);
+        let mut ctx = null_mut();
+        let rc = unsafe { d.cuCtxCreate_v2.unwrap()(&mut ctx, 0, 0) };
+        unsafe { on_ctx_create(rc, &ctx, 0, 0) };
+        let mut module = null_mut();
+        let image = PTX.as_ptr() as *const c_void;
+        let rc = unsafe { d.cuModuleLoadData.unwrap()(&mut module, image) };
+        unsafe { on_module_load(rc, &module, image) };
+        let mut function = null_mut();
+        let name = c"scale".as_ptr();
+        let rc = unsafe { d.cuModuleGetFunction.unwrap()(&mut function, module, name) };
+        unsafe { on_get_function(rc, &function, module, name) };
+        let mut stream = null_mut();
+        let rc = unsafe { d.cuStreamCreate.unwrap()(&mut stream, 0) };
+        unsafe { on_stream_create(rc, &stream, 0, None) };
+
+        let data: Vec<u8> = (0..3 * 4096).map(|i| (i % 251) as u8).collect();
+        let mut dptr = 0;
+        let rc = unsafe { d.cuMemAlloc_v2.unwrap()(&mut dptr, data.len()) };
+        unsafe { memory::after_alloc(rc, &mut dptr, data.len(), AllocKind::Device) };
+        let src = data.as_ptr() as *const c_void;
+        assert_eq!(
+            unsafe { d.cuMemcpyHtoD_v2.unwrap()(dptr, src, data.len()) },
+            CUDA_SUCCESS
+        );
+
+        let saved = save(&dir).unwrap();
+        assert_eq!(saved.bytes(), data.len());
+        assert!(saved.allocations[0].data.is_empty());
+        assert_eq!(std::fs::read(memory_path(&dir, dptr)).unwrap(), data);
+
+        // Start over as a fresh process would, with the allocation's address free again
+        let rc = unsafe { d.cuMemFree_v2.unwrap()(dptr) };
+        memory::after_free(rc, dptr);
+        let rc = unsafe { d.cuCtxDestroy_v2.unwrap()(ctx) };
+        on_ctx_destroy(rc, ctx);
+
+        let restored = restore_from(&dir).unwrap();
+        assert_eq!((restored.allocations, restored.bytes), (1, data.len()));
+        let kinds: Vec<HandleKind> = restored.handles.iter().map(|h| h.0).collect();
+        let expected = [
+            HandleKind::Context,
+            HandleKind::Module,
+            HandleKind::Function,
+            HandleKind::Stream,
+        ];
+        assert_eq!(kinds, expected);
+        let ctx = restored.handles[0].2 as CUcontext;
+        assert_eq!(unsafe { d.cuCtxSetCurrent.unwrap()(ctx) }, CUDA_SUCCESS);
+        let mut back = vec![0u8; data.len()];
+        let dst = back.as_mut_ptr() as *mut c_void;
+        assert_eq!(
+            unsafe { d.cuMemcpyDtoH_v2.unwrap()(dst, dptr, back.len()) },
+            CUDA_SUCCESS
+        );
+        assert_eq!(back, data);
+
+        assert_eq!(free_restored(dptr, None), Some(CUDA_SUCCESS));
+        memory::after_free(CUDA_SUCCESS, dptr);
+        let rc = unsafe { d.cuCtxDestroy_v2.unwrap()(ctx) };
+        on_ctx_destroy(rc, ctx);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+//! Detecting how the CUDA runtime is linked into the process.
+//!
+//! Hooks on runtime API symbols (`cuda*`, including the passthroughs in
+//! `passthroughs_runtime.rs`) only take effect when the application calls a dynamically linked
+//! `libcudart.so`. `nvcc` links `cudart_static` by default, and then the runtime calls into
+//! itself and the preloaded symbols are never reached. The driver API is still reached either
+//! way, as the runtime resolves it through `cuGetProcAddress`.
+//!
+//! [`detect`] inspects the objects mapped into the process (from `/proc/self/maps`) and their
+//! ELF symbol tables to tell the two apart. An object is taken to contain a static runtime if
+//! it defines a runtime entry point, or if it embeds device code (a `.nv_fatbin` section) without
+//! importing `__cudaRegisterFatBinary` from a shared `libcudart`.
+//!
+//! `install_hooks!()` runs [`check_on_load`] when the interposer is loaded, and [`check`] again
+//! the first time the driver is asked for an entry point, by which time libraries the
+//! application opens itself have been loaded too. Both warn when runtime hooks are defined that
+//! cannot fire, and name the driver-level hooks that can stand in for them (see
+//! [`driver_equivalents`]). Hooks are not moved over automatically, since the driver APIs take
+//! different arguments; the warning says which ones to write.
+
+use crate::elf::{self, Elf};
+use std::collections::HashSet;
+use std::ffi::{CStr, c_void};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use tracing::{debug, warn};
+
+/// Runtime entry points whose definition marks an object as containing a static runtime.
+const RUNTIME_SYMBOLS: &[&str] = &["__cudaRegisterFatBinary", "cudaLaunchKernel", "cudaMalloc"];
+
+/// Runtime APIs and the driver APIs that do their work, for hooking statically linked runtimes.
+const DRIVER_EQUIVALENTS: &[(&str, &[&str])] = &[
+    ("cudaMalloc", &["cuMemAlloc_v2"]),
+    ("cudaMallocPitch", &["cuMemAllocPitch_v2"]),
+    ("cudaMallocAsync", &["cuMemAllocAsync"]),
+    ("cudaMallocFromPoolAsync", &["cuMemAllocFromPoolAsync"]),
+    ("cudaMallocManaged", &["cuMemAllocManaged"]),
+    ("cudaMallocHost", &["cuMemAllocHost_v2"]),
+    ("cudaHostAlloc", &["cuMemHostAlloc"]),
+    ("cudaFree", &["cuMemFree_v2"]),
+    ("cudaFreeAsync", &["cuMemFreeAsync"]),
+    ("cudaFreeHost", &["cuMemFreeHost"]),
+    ("cudaMemGetInfo", &["cuMemGetInfo_v2"]),
+    (
+        "cudaMemcpy",
+        &[
+            "cuMemcpy",
+            "cuMemcpyHtoD_v2",
+            "cuMemcpyDtoH_v2",
+            "cuMemcpyDtoD_v2",
+        ],
+    ),
+    (
+        "cudaMemcpyAsync",
+        &[
+            "cuMemcpyAsync",
+            "cuMemcpyHtoDAsync_v2",
+            "cuMemcpyDtoHAsync_v2",
+            "cuMemcpyDtoDAsync_v2",
+        ],
+    ),
+    ("cudaMemset", &["cuMemsetD8_v2", "cuMemsetD32_v2"]),
+    ("cudaMemsetAsync", &["cuMemsetD8Async", "cuMemsetD32Async"]),
+    ("cudaLaunchKernel", &["cuLaunchKernel"]),
+    ("cudaLaunchKernelExC", &["cuLaunchKernelEx"]),
+    (
+        "cudaLaunchCooperativeKernel",
+        &["cuLaunchCooperativeKernel"],
+    ),
+    ("cudaGetDeviceCount", &["cuDeviceGetCount"]),
+    (
+        "cudaGetDeviceProperties",
+        &[
+            "cuDeviceGetAttribute",
+            "cuDeviceGetName",
+            "cuDeviceTotalMem_v2",
+        ],
+    ),
+    (
+        "cudaGetDeviceProperties_v2",
+        &[
+            "cuDeviceGetAttribute",
+            "cuDeviceGetName",
+            "cuDeviceTotalMem_v2",
+        ],
+    ),
+    ("cudaDeviceGetAttribute", &["cuDeviceGetAttribute"]),
+    (
+        "cudaSetDevice",
+        &["cuDevicePrimaryCtxRetain", "cuCtxSetCurrent"],
+    ),
+    ("cudaDeviceSynchronize", &["cuCtxSynchronize"]),
+    ("cudaDeviceReset", &["cuDevicePrimaryCtxReset_v2"]),
+    ("cudaStreamCreate", &["cuStreamCreate"]),
+    ("cudaStreamCreateWithFlags", &["cuStreamCreate"]),
+    (
+        "cudaStreamCreateWithPriority",
+        &["cuStreamCreateWithPriority"],
+    ),
+    ("cudaStreamDestroy", &["cuStreamDestroy_v2"]),
+    ("cudaStreamSynchronize", &["cuStreamSynchronize"]),
+    ("cudaStreamWaitEvent", &["cuStreamWaitEvent"]),
+    ("cudaEventCreate", &["cuEventCreate"]),
+    ("cudaEventCreateWithFlags", &["cuEventCreate"]),
+    ("cudaEventDestroy", &["cuEventDestroy_v2"]),
+    ("cudaEventRecord", &["cuEventRecord"]),
+    ("cudaEventSynchronize", &["cuEventSynchronize"]),
+    ("cudaGraphInstantiate", &["cuGraphInstantiateWithFlags"]),
+    ("cudaGraphLaunch", &["cuGraphLaunch"]),
+    ("__cudaRegisterFatBinary", &["cuLibraryLoadData"]),
+    ("__cudaRegisterFunction", &["cuLibraryGetKernel"]),
+];
+
+/// How the runtime is linked into the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Linkage {
+    /// Only through a shared `libcudart`; runtime hooks apply.
+    Dynamic,
+    /// Only statically; runtime hooks never fire.
+    Static,
+    /// A shared `libcudart` and objects with their own static copy.
+    Mixed,
+    /// No runtime is loaded (yet).
+    Absent,
+}
+
+impl fmt::Display for Linkage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Linkage::Dynamic => "dynamic",
+            Linkage::Static => "static",
+            Linkage::Mixed => "mixed",
+            Linkage::Absent => "absent",
+        })
+    }
+}
+
+/// Where the runtime was found in the process.
+#[derive(Debug, Clone, Default)]
+pub struct Detection {
+    /// The shared `libcudart` that is loaded, if any.
+    pub dynamic: Option<PathBuf>,
+    /// Loaded objects that carry their own copy of the runtime.
+    pub statically_linked: Vec<PathBuf>,
+}
+
+impl Detection {
+    pub fn linkage(&self) -> Linkage {
+        match (self.dynamic.is_some(), !self.statically_linked.is_empty()) {
+            (true, false) => Linkage::Dynamic,
+            (false, true) => Linkage::Static,
+            (true, true) => Linkage::Mixed,
+            (false, false) => Linkage::Absent,
+        }
+    }
+}
+
+/// A read-only mapping of a whole file.
+struct Mapped {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Mapped {
+    fn open(path: &Path) -> Option<Self> {
+        let file = std::fs::File::open(path).ok()?;
+        let len = file.metadata().ok()?.len() as usize;
+        if len == 0 {
+            return None;
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                std::os::fd::AsRawFd::as_raw_fd(&file),
+                0,
+            )
+        };
+        (ptr != libc::MAP_FAILED).then_some(Self { ptr, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// Returns the file-backed objects mapped into the process, in load order. Device nodes such as
+/// `/dev/nvidia0`, which the driver maps too, are left out.
+fn mapped_objects() -> Vec<PathBuf> {
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap_or_default();
+    let mut seen = HashSet::new();
+    maps.lines()
+        .filter_map(|line| line.split_whitespace().nth(5))
+        .filter(|path| path.starts_with('/'))
+        .map(PathBuf::from)
+        .filter(|path| seen.insert(path.clone()))
+        .filter(|path| std::fs::symlink_metadata(path).is_ok_and(|m| m.is_file()))
+        .collect()
+}
+
+/// The path of the object this crate is linked into, so it is not mistaken for a runtime.
+fn own_object() -> Option<PathBuf> {
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    let addr = own_object as *const c_void;
+    if unsafe { libc::dladdr(addr, &mut info) } == 0 || info.dli_fname.is_null() {
+        return None;
+    }
+    let path = unsafe { CStr::from_ptr(info.dli_fname) }.to_string_lossy();
+    std::fs::canonicalize(&*path).ok()
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+}
+
+/// Returns whether the ELF image at `path` carries its own copy of the runtime.
+fn links_runtime_statically(path: &Path) -> bool {
+    let Some(mapped) = Mapped::open(path) else {
+        return false;
+    };
+    let Ok(elf) = Elf::parse(mapped.bytes()) else {
+        return false;
+    };
+    let dynsym = elf.symbols(elf::SHT_DYNSYM).unwrap_or_default();
+    let symtab = elf.symbols(elf::SHT_SYMTAB).unwrap_or_default();
+    let defines = |name: &str| {
+        dynsym
+            .iter()
+            .chain(&symtab)
+            .any(|s| s.name == name && s.shndx != 0)
+    };
+    if RUNTIME_SYMBOLS.iter().any(|name| defines(name)) {
+        return true;
+    }
+    let imports_registration = dynsym
+        .iter()
+        .any(|s| s.name == "__cudaRegisterFatBinary" && s.shndx == 0);
+    elf.section(".nv_fatbin").is_some() && !imports_registration
+}
+
+/// Inspects the objects loaded in the process for shared and static copies of the runtime.
+pub fn detect() -> Detection {
+    let own = own_object();
+    let mut detection = Detection::default();
+    for path in mapped_objects() {
+        let name = file_name(&path);
+        if name.starts_with("libcudart.so") {
+            detection.dynamic.get_or_insert(path);
+            continue;
+        }
+        if name.starts_with("libcuda.so") || own.as_ref() == Some(&path) {
+            continue;
+        }
+        if links_runtime_statically(&path) {
+            detection.statically_linked.push(path);
+        }
+    }
+    detection
+}
+
+/// Returns the driver APIs that carry out the runtime API `name`, which are the ones to hook
+/// when the runtime is linked statically.
+pub fn driver_equivalents(name: &str) -> Option<&'static [&'static str]> {
+    DRIVER_EQUIVALENTS
+        .iter()
+        .find(|(runtime, _)| *runtime == name)
+        .map(|(_, driver)| *driver)
+}
+
+/// Detects how the runtime is linked and warns when some of `hooks`, the symbols hooked by the
+/// interposer, are runtime APIs that a statically linked runtime will bypass. Only the first
+/// call does anything.
+pub fn check(hooks: &[&str]) {
+    static CHECKED: Once = Once::new();
+    CHECKED.call_once(|| warn_static(hooks));
+}
+
+/// [`check`], for when the interposer is loaded. Runs before the application can have set up
+/// logging, so the warnings go to stderr unless a `tracing` subscriber is already installed.
+pub fn check_on_load(hooks: &[&str]) {
+    static CHECKED: Once = Once::new();
+    CHECKED.call_once(|| warn_static(hooks));
+}
+
+/// Objects already warned about, so the load time and first use checks name each once.
+static REPORTED: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+fn warn_static(hooks: &[&str]) {
+    let detection = detect();
+    debug!(
+        "CUDA runtime linkage: {} (shared: {:?}, static in: {:?})",
+        detection.linkage(),
+        detection.dynamic,
+        detection.statically_linked
+    );
+    let runtime_hooks: Vec<_> = hooks
+        .iter()
+        .filter(|name| name.starts_with("cuda") || name.starts_with("__cuda"))
+        .collect();
+    if runtime_hooks.is_empty() {
+        return;
+    }
+    let statically_linked: Vec<_> = {
+        let mut reported = REPORTED.lock().unwrap();
+        let reported = reported.get_or_insert_with(HashSet::new);
+        detection
+            .statically_linked
+            .into_iter()
+            .filter(|path| reported.insert(path.clone()))
+            .collect()
+    };
+    if statically_linked.is_empty() {
+        return;
+    }
+    let report = |line: String| {
+        if tracing::dispatcher::has_been_set() {
+            warn!("{}", line);
+        } else {
+            eprintln!("cuda-interposer: {line}");
+        }
+    };
+    report(format!(
+        "The CUDA runtime is linked statically into {statically_linked:?}; runtime API hooks \
+         will not be called from there. Hook the driver API instead:"
+    ));
+    for name in runtime_hooks {
+        match driver_equivalents(name) {
+            Some(driver) => report(format!("  {} -> {}", name, driver.join(", "))),
+            None => report(format!("  {name} (no driver equivalent)")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_device_node_mappings() {
+        let file = std::fs::File::open("/dev/zero").unwrap();
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                4096,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                std::os::fd::AsRawFd::as_raw_fd(&file),
+                0,
+            )
+        };
+        assert_ne!(ptr, libc::MAP_FAILED);
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        assert!(maps.contains("/dev/zero"));
+
+        let objects = mapped_objects();
+        unsafe { libc::munmap(ptr, 4096) };
+        assert!(!objects.iter().any(|p| p.starts_with("/dev")));
+        let exe = std::fs::canonicalize("/proc/self/exe").unwrap();
+        assert!(objects.contains(&exe));
+    }
+}
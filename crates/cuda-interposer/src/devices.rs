@@ -0,0 +1,819 @@
+//! Device visibility remapping.
+//!
+//! A [`DeviceMap`] lists, for each logical device the application sees, the physical device
+//! backing it. Devices left out of the map are hidden, the order of the map is the enumeration
+//! order, and listing a physical device several times presents it as several logical devices.
+//! Logical devices beyond the first copy of a physical device report a UUID with its last byte
+//! perturbed, so that frameworks deduplicating by UUID still see distinct devices.
+//!
+//! The map is read from `CUDA_HOOK_DEVICE_MAP` (e.g. `1,0` or `0*4`) and can be replaced at any
+//! time, including after `cuInit`, with [`set_map`]. [`install_device_hooks!`] translates
+//! between logical and physical devices in every driver API that takes a `CUdevice` or a device
+//! `CUmemLocation`: device queries, context creation, peer access, memory pools, managed memory
+//! and virtual memory management. Device ordinals the driver hands back, from `cuCtxGetDevice`,
+//! `cuDeviceGetByPCIBusId`, pointer attributes and allocation properties, are translated back.
+//! Devices outside the map are rejected with `CUDA_ERROR_INVALID_DEVICE`. Device handles are
+//! treated as ordinals, which is what the driver hands out.
+
+use crate::config::ConfigError;
+use crate::ffi::*;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tracing::warn;
+
+const MAP_ENV: &str = "CUDA_HOOK_DEVICE_MAP";
+
+static MAP: Lazy<RwLock<Option<DeviceMap>>> = Lazy::new(|| {
+    let map = std::env::var(MAP_ENV).ok().and_then(|spec| {
+        DeviceMap::parse(&spec)
+            .map_err(|e| warn!("Ignoring device map: {}", e))
+            .ok()
+    });
+    RwLock::new(map)
+});
+
+/// Logical device that retained or created each context, so `cuCtxGetDevice` can answer with
+/// the device the application asked for when a physical device is shared.
+static CONTEXT_DEVICES: Lazy<RwLock<HashMap<usize, CUdevice>>> = Lazy::new(Default::default);
+
+/// Logical to physical device ordinals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceMap {
+    physical: Vec<CUdevice>,
+}
+
+impl DeviceMap {
+    pub fn new(physical: Vec<CUdevice>) -> Self {
+        Self { physical }
+    }
+
+    /// Parses a comma-separated list of physical ordinals, where `N*K` repeats `N` `K` times.
+    pub fn parse(spec: &str) -> Result<Self, ConfigError> {
+        let mut physical = Vec::new();
+        for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let err = |reason| ConfigError::new(MAP_ENV, item, reason);
+            let (dev, count) = match item.split_once('*') {
+                Some((dev, count)) => (dev, count.trim().parse().map_err(|_| err("bad count"))?),
+                None => (item, 1),
+            };
+            let dev: CUdevice = dev.trim().parse().map_err(|_| err("bad device ordinal"))?;
+            if dev < 0 {
+                return Err(err("bad device ordinal"));
+            }
+            physical.extend(std::iter::repeat_n(dev, count));
+        }
+        Ok(Self { physical })
+    }
+
+    pub fn len(&self) -> usize {
+        self.physical.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.physical.is_empty()
+    }
+
+    pub fn physical(&self, logical: CUdevice) -> Option<CUdevice> {
+        usize::try_from(logical)
+            .ok()
+            .and_then(|i| self.physical.get(i))
+            .copied()
+    }
+
+    /// Returns the first logical device backed by `physical`.
+    pub fn logical(&self, physical: CUdevice) -> Option<CUdevice> {
+        self.physical
+            .iter()
+            .position(|&p| p == physical)
+            .map(|i| i as CUdevice)
+    }
+
+    /// Returns how many logical devices before `logical` share its physical device.
+    pub fn replica(&self, logical: CUdevice) -> usize {
+        let Some(physical) = self.physical(logical) else {
+            return 0;
+        };
+        self.physical[..logical as usize]
+            .iter()
+            .filter(|&&p| p == physical)
+            .count()
+    }
+}
+
+/// Replaces the device map. `None` restores the physical view.
+pub fn set_map(map: Option<DeviceMap>) {
+    *MAP.write().unwrap() = map;
+}
+
+pub fn map() -> Option<DeviceMap> {
+    MAP.read().unwrap().clone()
+}
+
+/// Translates a logical device to the physical one backing it. Without a map this is the
+/// identity; with one, devices outside the map are rejected.
+pub fn to_physical(logical: CUdevice) -> Result<CUdevice, CUresult> {
+    match &*MAP.read().unwrap() {
+        Some(map) => map.physical(logical).ok_or(CUDA_ERROR_INVALID_DEVICE),
+        None => Ok(logical),
+    }
+}
+
+/// Translates a physical device to the first logical device it backs.
+pub fn to_logical(physical: CUdevice) -> Result<CUdevice, CUresult> {
+    match &*MAP.read().unwrap() {
+        Some(map) => map.logical(physical).ok_or(CUDA_ERROR_INVALID_DEVICE),
+        None => Ok(physical),
+    }
+}
+
+/// Translates a device argument of the managed memory APIs, where `CU_DEVICE_CPU` and
+/// `CU_DEVICE_INVALID` (negative ordinals) are passed through.
+pub fn to_physical_or_host(logical: CUdevice) -> Result<CUdevice, CUresult> {
+    if logical < 0 {
+        Ok(logical)
+    } else {
+        to_physical(logical)
+    }
+}
+
+/// Translates the device of a memory location. Host and NUMA locations are passed through.
+pub fn physical_location(location: CUmemLocation) -> Result<CUmemLocation, CUresult> {
+    if location.type_ != CU_MEM_LOCATION_TYPE_DEVICE {
+        return Ok(location);
+    }
+    Ok(CUmemLocation {
+        id: to_physical(location.id)?,
+        ..location
+    })
+}
+
+/// Translates the access descriptors passed to `cuMemSetAccess` and `cuMemPoolSetAccess`.
+///
+/// # Safety
+/// `desc` must be null or point to `count` valid descriptors.
+pub unsafe fn physical_access(
+    desc: *const CUmemAccessDesc,
+    count: usize,
+) -> Result<Vec<CUmemAccessDesc>, CUresult> {
+    if desc.is_null() {
+        return Ok(Vec::new());
+    }
+    unsafe { std::slice::from_raw_parts(desc, count) }
+        .iter()
+        .map(|d| {
+            Ok(CUmemAccessDesc {
+                location: physical_location(d.location)?,
+                ..*d
+            })
+        })
+        .collect()
+}
+
+/// Rewrites the device ordinal a `CU_POINTER_ATTRIBUTE_DEVICE_ORDINAL` query wrote to `data` as
+/// the first logical device backed by it.
+///
+/// # Safety
+/// `data` must be null or point to a writable `int`.
+pub unsafe fn logical_ordinal(data: *mut std::ffi::c_void) -> CUresult {
+    let Some(ordinal) = (unsafe { data.cast::<CUdevice>().as_mut() }) else {
+        return CUDA_SUCCESS;
+    };
+    match to_logical(*ordinal) {
+        Ok(logical) => {
+            *ordinal = logical;
+            CUDA_SUCCESS
+        }
+        Err(e) => e,
+    }
+}
+
+/// Returns the number of devices to report, given the physical count.
+pub fn logical_count(physical_count: i32) -> i32 {
+    match &*MAP.read().unwrap() {
+        Some(map) => map.len() as i32,
+        None => physical_count,
+    }
+}
+
+pub fn remember_context(ctx: CUcontext, logical: CUdevice) {
+    if !ctx.is_null() {
+        CONTEXT_DEVICES
+            .write()
+            .unwrap()
+            .insert(ctx as usize, logical);
+    }
+}
+
+/// Returns the logical device for a context whose physical device is `physical`.
+pub fn context_device(ctx: CUcontext, physical: CUdevice) -> Result<CUdevice, CUresult> {
+    if let Some(&logical) = CONTEXT_DEVICES.read().unwrap().get(&(ctx as usize))
+        && to_physical(logical) == Ok(physical)
+    {
+        return Ok(logical);
+    }
+    to_logical(physical)
+}
+
+/// Makes the UUID of a replicated logical device distinct from the physical one.
+pub fn perturb_uuid(logical: CUdevice, uuid: &mut CUuuid) {
+    if let Some(map) = &*MAP.read().unwrap() {
+        let replica = map.replica(logical);
+        uuid.bytes[15] ^= replica as std::os::raw::c_char;
+    }
+}
+
+/// Installs hooks that present the devices described by the current [`DeviceMap`], with the
+/// overrides of the active [`crate::spoof::Profile`] applied.
+#[macro_export]
+macro_rules! install_device_hooks {
+    () => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuDeviceGetCount(count: *mut $crate::libc::c_int) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuDeviceGetCount)(count) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    unsafe { *count = $crate::devices::logical_count(*count) };
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuDeviceGet(
+                device: *mut $crate::ffi::CUdevice,
+                ordinal: $crate::libc::c_int
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(ordinal) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuDeviceGet)(device, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    unsafe { *device = ordinal };
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuDeviceGetByPCIBusId(
+                device: *mut $crate::ffi::CUdevice,
+                pci_bus_id: *const $crate::libc::c_char
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuDeviceGetByPCIBusId)(device, pci_bus_id) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    match $crate::devices::to_logical(unsafe { *device }) {
+                        Ok(logical) => unsafe { *device = logical },
+                        Err(e) => return e,
+                    }
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuDeviceGetUuid(
+                uuid: *mut $crate::ffi::CUuuid,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuDeviceGetUuid)(uuid, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    $crate::devices::perturb_uuid(dev, unsafe { &mut *uuid });
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuDeviceGetUuid_v2(
+                uuid: *mut $crate::ffi::CUuuid,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuDeviceGetUuid_v2)(uuid, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    $crate::devices::perturb_uuid(dev, unsafe { &mut *uuid });
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuDevicePrimaryCtxRetain(
+                pctx: *mut $crate::ffi::CUcontext,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuDevicePrimaryCtxRetain)(pctx, physical) };
+                unsafe { $crate::record::on_primary_ctx_retain(rc, pctx, dev) };
+                unsafe { $crate::checkpoint::on_primary_ctx_retain(rc, pctx, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    $crate::devices::remember_context(unsafe { *pctx }, dev);
+                }
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Context, pctx) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuCtxGetDevice(device: *mut $crate::ffi::CUdevice) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuCtxGetDevice)(device) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    let ctx = $crate::driver::current_context();
+                    match $crate::devices::context_device(ctx, unsafe { *device }) {
+                        Ok(logical) => unsafe { *device = logical },
+                        Err(e) => return e,
+                    }
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuDeviceGetAttribute(
+                pi: *mut $crate::libc::c_int,
+                attrib: $crate::libc::c_int,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuDeviceGetAttribute)(pi, attrib, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS
+                    && let Some(v) = $crate::spoof::attribute(dev, attrib)
+                {
+                    unsafe { *pi = v };
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuDeviceGetName(
+                name: *mut $crate::libc::c_char,
+                len: $crate::libc::c_int,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuDeviceGetName)(name, len, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    unsafe { $crate::spoof::write_name(dev, name, len.max(0) as usize) };
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuDeviceTotalMem_v2(
+                bytes: *mut usize,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuDeviceTotalMem_v2)(bytes, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS
+                    && let Some(total) = $crate::spoof::total_mem(dev)
+                {
+                    unsafe { *bytes = total };
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuDeviceComputeCapability(
+                major: *mut $crate::libc::c_int,
+                minor: *mut $crate::libc::c_int,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuDeviceComputeCapability)(major, minor, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS
+                    && let Some((ma, mi)) = $crate::spoof::compute_capability(dev)
+                {
+                    unsafe {
+                        *major = ma;
+                        *minor = mi;
+                    }
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuCtxCreate_v2(
+                pctx: *mut $crate::ffi::CUcontext,
+                flags: $crate::libc::c_uint,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuCtxCreate_v2)(pctx, flags, physical) };
+                unsafe { $crate::record::on_ctx_create(rc, pctx, flags, dev) };
+                unsafe { $crate::checkpoint::on_ctx_create(rc, pctx, flags, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    $crate::devices::remember_context(unsafe { *pctx }, dev);
+                }
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Context, pctx) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuCtxCreate_v3(
+                pctx: *mut $crate::ffi::CUcontext,
+                params: *mut $crate::libc::c_void,
+                num_params: $crate::libc::c_int,
+                flags: $crate::libc::c_uint,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuCtxCreate_v3)(pctx, params, num_params, flags, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    $crate::devices::remember_context(unsafe { *pctx }, dev);
+                }
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Context, pctx) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuCtxCreate_v4(
+                pctx: *mut $crate::ffi::CUcontext,
+                params: *mut $crate::libc::c_void,
+                flags: $crate::libc::c_uint,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let rc = unsafe { (*__real_cuCtxCreate_v4)(pctx, params, flags, physical) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    $crate::devices::remember_context(unsafe { *pctx }, dev);
+                }
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Context, pctx) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemPrefetchAsync(
+                dptr: $crate::ffi::CUdeviceptr,
+                count: usize,
+                dst_device: $crate::ffi::CUdevice,
+                stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical_or_host(dst_device) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                unsafe { (*__real_cuMemPrefetchAsync)(dptr, count, physical, stream) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemAdvise(
+                dptr: $crate::ffi::CUdeviceptr,
+                count: usize,
+                advice: $crate::libc::c_uint,
+                dev: $crate::ffi::CUdevice
+            ) -> $crate::ffi::CUresult {
+                let physical = match $crate::devices::to_physical_or_host(dev) {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                unsafe { (*__real_cuMemAdvise)(dptr, count, advice, physical) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemPrefetchAsync_v2(
+                dptr: $crate::ffi::CUdeviceptr,
+                count: usize,
+                location: $crate::ffi::CUmemLocation,
+                flags: $crate::libc::c_uint,
+                stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let location = match $crate::devices::physical_location(location) {
+                    Ok(l) => l,
+                    Err(e) => return e,
+                };
+                unsafe { (*__real_cuMemPrefetchAsync_v2)(dptr, count, location, flags, stream) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemAdvise_v2(
+                dptr: $crate::ffi::CUdeviceptr,
+                count: usize,
+                advice: $crate::libc::c_uint,
+                location: $crate::ffi::CUmemLocation
+            ) -> $crate::ffi::CUresult {
+                let location = match $crate::devices::physical_location(location) {
+                    Ok(l) => l,
+                    Err(e) => return e,
+                };
+                unsafe { (*__real_cuMemAdvise_v2)(dptr, count, advice, location) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemSetAccess(
+                dptr: $crate::ffi::CUdeviceptr,
+                size: usize,
+                desc: *const $crate::ffi::CUmemAccessDesc,
+                count: usize
+            ) -> $crate::ffi::CUresult {
+                let access = match unsafe { $crate::devices::physical_access(desc, count) } {
+                    Ok(a) => a,
+                    Err(e) => return e,
+                };
+                let desc = if desc.is_null() { desc } else { access.as_ptr() };
+                unsafe { (*__real_cuMemSetAccess)(dptr, size, desc, count) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemPoolSetAccess(
+                pool: $crate::ffi::CUmemoryPool,
+                desc: *const $crate::ffi::CUmemAccessDesc,
+                count: usize
+            ) -> $crate::ffi::CUresult {
+                let access = match unsafe { $crate::devices::physical_access(desc, count) } {
+                    Ok(a) => a,
+                    Err(e) => return e,
+                };
+                let desc = if desc.is_null() { desc } else { access.as_ptr() };
+                unsafe { (*__real_cuMemPoolSetAccess)(pool, desc, count) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemGetAccess(
+                flags: *mut u64,
+                location: *const $crate::ffi::CUmemLocation,
+                dptr: $crate::ffi::CUdeviceptr
+            ) -> $crate::ffi::CUresult {
+                let Some(&location) = (unsafe { location.as_ref() }) else {
+                    return unsafe { (*__real_cuMemGetAccess)(flags, location, dptr) };
+                };
+                let location = match $crate::devices::physical_location(location) {
+                    Ok(l) => l,
+                    Err(e) => return e,
+                };
+                unsafe { (*__real_cuMemGetAccess)(flags, &location, dptr) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemPoolGetAccess(
+                flags: *mut $crate::libc::c_uint,
+                pool: $crate::ffi::CUmemoryPool,
+                location: *mut $crate::ffi::CUmemLocation
+            ) -> $crate::ffi::CUresult {
+                let Some(&location) = (unsafe { location.as_ref() }) else {
+                    return unsafe { (*__real_cuMemPoolGetAccess)(flags, pool, location) };
+                };
+                let mut location = match $crate::devices::physical_location(location) {
+                    Ok(l) => l,
+                    Err(e) => return e,
+                };
+                unsafe { (*__real_cuMemPoolGetAccess)(flags, pool, &mut location) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemGetAllocationGranularity(
+                granularity: *mut usize,
+                prop: *const $crate::ffi::CUmemAllocationProp,
+                option: $crate::libc::c_uint
+            ) -> $crate::ffi::CUresult {
+                let Some(&prop) = (unsafe { prop.as_ref() }) else {
+                    return unsafe { (*__real_cuMemGetAllocationGranularity)(granularity, prop, option) };
+                };
+                let location = match $crate::devices::physical_location(prop.location) {
+                    Ok(l) => l,
+                    Err(e) => return e,
+                };
+                let prop = $crate::ffi::CUmemAllocationProp { location, ..prop };
+                unsafe { (*__real_cuMemGetAllocationGranularity)(granularity, &prop, option) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemGetAllocationPropertiesFromHandle(
+                prop: *mut $crate::ffi::CUmemAllocationProp,
+                handle: $crate::ffi::CUmemGenericAllocationHandle
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemGetAllocationPropertiesFromHandle)(prop, handle) };
+                if rc == $crate::ffi::CUDA_SUCCESS
+                    && let Some(prop) = unsafe { prop.as_mut() }
+                    && prop.location.type_ == $crate::ffi::CU_MEM_LOCATION_TYPE_DEVICE
+                {
+                    match $crate::devices::to_logical(prop.location.id) {
+                        Ok(logical) => prop.location.id = logical,
+                        Err(e) => return e,
+                    }
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemPoolCreate(
+                pool: *mut $crate::ffi::CUmemoryPool,
+                props: *const $crate::ffi::CUmemPoolProps
+            ) -> $crate::ffi::CUresult {
+                let Some(&props) = (unsafe { props.as_ref() }) else {
+                    return unsafe { (*__real_cuMemPoolCreate)(pool, props) };
+                };
+                let location = match $crate::devices::physical_location(props.location) {
+                    Ok(l) => l,
+                    Err(e) => return e,
+                };
+                let props = $crate::ffi::CUmemPoolProps { location, ..props };
+                unsafe { (*__real_cuMemPoolCreate)(pool, &props) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuPointerGetAttribute(
+                data: *mut $crate::libc::c_void,
+                attribute: $crate::libc::c_uint,
+                dptr: $crate::ffi::CUdeviceptr
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuPointerGetAttribute)(data, attribute, dptr) };
+                if rc == $crate::ffi::CUDA_SUCCESS
+                    && attribute == $crate::ffi::CU_POINTER_ATTRIBUTE_DEVICE_ORDINAL
+                {
+                    return unsafe { $crate::devices::logical_ordinal(data) };
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuPointerGetAttributes(
+                num_attributes: $crate::libc::c_uint,
+                attributes: *mut $crate::libc::c_uint,
+                data: *mut *mut $crate::libc::c_void,
+                dptr: $crate::ffi::CUdeviceptr
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuPointerGetAttributes)(num_attributes, attributes, data, dptr) };
+                if rc != $crate::ffi::CUDA_SUCCESS || attributes.is_null() || data.is_null() {
+                    return rc;
+                }
+                for i in 0..num_attributes as usize {
+                    if unsafe { *attributes.add(i) } == $crate::ffi::CU_POINTER_ATTRIBUTE_DEVICE_ORDINAL {
+                        let rc = unsafe { $crate::devices::logical_ordinal(*data.add(i)) };
+                        if rc != $crate::ffi::CUDA_SUCCESS {
+                            return rc;
+                        }
+                    }
+                }
+                rc
+            }
+        }
+
+        $crate::__device_passthrough! {
+            cuDevicePrimaryCtxRelease_v2(dev);
+            cuDevicePrimaryCtxReset_v2(dev);
+            cuDevicePrimaryCtxSetFlags_v2(dev, flags: $crate::libc::c_uint);
+            cuDevicePrimaryCtxGetState(dev, flags: *mut $crate::libc::c_uint, active: *mut $crate::libc::c_int);
+            cuDeviceGetPCIBusId(pci_bus_id: *mut $crate::libc::c_char, len: $crate::libc::c_int, dev);
+            cuDeviceGetLuid(luid: *mut $crate::libc::c_char, node_mask: *mut $crate::libc::c_uint, dev);
+            cuDeviceGetProperties(prop: *mut $crate::libc::c_void, dev);
+            cuDeviceGetTexture1DLinearMaxWidth(
+                max_width: *mut usize,
+                format: $crate::libc::c_uint,
+                num_channels: $crate::libc::c_uint,
+                dev
+            );
+            cuDeviceGetNvSciSyncAttributes(attrs: *mut $crate::libc::c_void, dev, flags: $crate::libc::c_int);
+            cuDeviceGetExecAffinitySupport(pi: *mut $crate::libc::c_int, kind: $crate::libc::c_uint, dev);
+            cuDeviceCanAccessPeer(can_access: *mut $crate::libc::c_int, dev, peer);
+            cuDeviceGetP2PAttribute(value: *mut $crate::libc::c_int, attrib: $crate::libc::c_uint, src, dst);
+            cuDeviceGetDefaultMemPool(pool: *mut $crate::ffi::CUmemoryPool, dev);
+            cuDeviceGetMemPool(pool: *mut $crate::ffi::CUmemoryPool, dev);
+            cuDeviceSetMemPool(dev, pool: $crate::ffi::CUmemoryPool);
+            cuDeviceGetGraphMemAttribute(dev, attr: $crate::libc::c_uint, value: *mut $crate::libc::c_void);
+            cuDeviceSetGraphMemAttribute(dev, attr: $crate::libc::c_uint, value: *mut $crate::libc::c_void);
+            cuDeviceGraphMemTrim(dev);
+            cuDeviceGetDevResource(dev, resource: *mut $crate::libc::c_void, kind: $crate::libc::c_uint);
+            cuDeviceRegisterAsyncNotification(
+                dev,
+                callback: *mut $crate::libc::c_void,
+                user_data: *mut $crate::libc::c_void,
+                handle: *mut *mut $crate::libc::c_void
+            );
+            cuDeviceUnregisterAsyncNotification(dev, handle: *mut $crate::libc::c_void);
+            cuGreenCtxCreate(
+                pctx: *mut *mut $crate::libc::c_void,
+                desc: *mut $crate::libc::c_void,
+                dev,
+                flags: $crate::libc::c_uint
+            );
+            cuMulticastAddDevice(handle: $crate::ffi::CUmemGenericAllocationHandle, dev);
+        }
+    };
+}
+
+/// Generates hooks for device APIs that only need their `CUdevice` arguments translated. Each
+/// device argument is written as a bare identifier, in its position among the typed arguments.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __device_passthrough {
+    ($( $fname:ident ( $($args:tt)* ); )*) => {
+        $( $crate::__device_passthrough!(@munch $fname [] [] [] $($args)*); )*
+    };
+    (@munch $fname:ident [$($params:tt)*] [$($call:tt)*] [$($devs:ident)*] $arg:ident : $ty:ty $(, $($rest:tt)*)?) => {
+        $crate::__device_passthrough!(@munch $fname
+            [$($params)* $arg: $ty,]
+            [$($call)* $arg,]
+            [$($devs)*]
+            $($($rest)*)?);
+    };
+    (@munch $fname:ident [$($params:tt)*] [$($call:tt)*] [$($devs:ident)*] $dev:ident $(, $($rest:tt)*)?) => {
+        $crate::__device_passthrough!(@munch $fname
+            [$($params)* $dev: $crate::ffi::CUdevice,]
+            [$($call)* $dev,]
+            [$($devs)* $dev]
+            $($($rest)*)?);
+    };
+    (@munch $fname:ident [$($params:tt)*] [$($call:tt)*] [$($devs:ident)*]) => {
+        $crate::paste::paste! {
+            $crate::cuda_hook! {
+                pub unsafe extern "C" fn $fname($($params)*) -> $crate::ffi::CUresult {
+                    $(
+                        let $devs = match $crate::devices::to_physical($devs) {
+                            Ok(p) => p,
+                            Err(e) => return e,
+                        };
+                    )*
+                    unsafe { (*[<__real_ $fname>])($($call)*) }
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_device_arguments_and_locations() {
+        set_map(Some(DeviceMap::parse("1, 0*2").unwrap()));
+        assert_eq!(to_physical(0), Ok(1));
+        assert_eq!(to_physical(2), Ok(0));
+        assert_eq!(to_physical(3), Err(CUDA_ERROR_INVALID_DEVICE));
+        assert_eq!(to_physical_or_host(-1), Ok(-1));
+        assert_eq!(to_logical(0), Ok(1));
+
+        let device = CUmemLocation {
+            type_: CU_MEM_LOCATION_TYPE_DEVICE,
+            id: 0,
+        };
+        assert_eq!(physical_location(device).unwrap().id, 1);
+        let host_numa = CUmemLocation { type_: 3, id: 5 };
+        assert_eq!(physical_location(host_numa).unwrap().id, 5);
+        let access = [CUmemAccessDesc {
+            location: CUmemLocation { id: 4, ..device },
+            flags: CU_MEM_ACCESS_FLAGS_PROT_READWRITE,
+        }];
+        assert_eq!(
+            unsafe { physical_access(access.as_ptr(), 1) }.unwrap_err(),
+            CUDA_ERROR_INVALID_DEVICE
+        );
+
+        let mut ordinal: CUdevice = 0;
+        let rc = unsafe { logical_ordinal(&mut ordinal as *mut CUdevice as *mut _) };
+        assert_eq!((rc, ordinal), (CUDA_SUCCESS, 1));
+        set_map(None);
+    }
+}
@@ -0,0 +1,238 @@
+//! Direct entry points into the real driver, used by the built-in hook sets for queries that
+//! must not go back through the interposer.
+
+use crate::dlsym_next;
+use crate::ffi::*;
+use once_cell::sync::Lazy;
+use std::ffi::{CStr, c_char, c_void};
+
+macro_rules! real_fn {
+    ($name:ident ( $($arg_ty:ty),* ) ) => {
+        #[allow(non_upper_case_globals)]
+        pub static $name: Lazy<Option<unsafe extern "C" fn($($arg_ty),*) -> CUresult>> =
+            Lazy::new(|| {
+                let sym = dlsym_next(concat!(stringify!($name), "\0").as_bytes());
+                if sym.is_null() {
+                    None
+                } else {
+                    Some(unsafe {
+                        std::mem::transmute::<
+                            *mut std::os::raw::c_void,
+                            unsafe extern "C" fn($($arg_ty),*) -> CUresult,
+                        >(sym)
+                    })
+                }
+            });
+    };
+}
+
+real_fn!(cuCtxGetCurrent(*mut CUcontext));
+real_fn!(cuCtxSetCurrent(CUcontext));
+real_fn!(cuCtxCreate_v2(*mut CUcontext, u32, CUdevice));
+real_fn!(cuCtxSynchronize());
+real_fn!(cuDevicePrimaryCtxRetain(*mut CUcontext, CUdevice));
+real_fn!(cuCtxGetDevice(*mut CUdevice));
+real_fn!(cuFuncGetName(*mut *const c_char, CUfunction));
+real_fn!(cuFuncGetParamInfo(CUfunction, usize, *mut usize, *mut usize));
+real_fn!(cuStreamSynchronize(CUstream));
+real_fn!(cuStreamIsCapturing(CUstream, *mut u32));
+real_fn!(cuStreamCreate(*mut CUstream, u32));
+real_fn!(cuStreamCreateWithPriority(*mut CUstream, u32, i32));
+real_fn!(cuModuleLoadData(*mut CUmodule, *const c_void));
+real_fn!(cuModuleGetFunction(*mut CUfunction, CUmodule, *const c_char));
+real_fn!(cuMemcpyHtoD_v2(CUdeviceptr, *const c_void, usize));
+real_fn!(cuMemcpyDtoH_v2(*mut c_void, CUdeviceptr, usize));
+real_fn!(cuMemGetAllocationGranularity(*mut usize, *const CUmemAllocationProp, u32));
+real_fn!(cuMemAddressReserve(*mut CUdeviceptr, usize, usize, CUdeviceptr, u64));
+real_fn!(cuMemAddressFree(CUdeviceptr, usize));
+real_fn!(cuMemCreate(*mut CUmemGenericAllocationHandle, usize, *const CUmemAllocationProp, u64));
+real_fn!(cuMemRelease(CUmemGenericAllocationHandle));
+real_fn!(cuMemMap(
+    CUdeviceptr,
+    usize,
+    usize,
+    CUmemGenericAllocationHandle,
+    u64
+));
+real_fn!(cuMemUnmap(CUdeviceptr, usize));
+real_fn!(cuMemSetAccess(CUdeviceptr, usize, *const CUmemAccessDesc, usize));
+real_fn!(cuGetErrorName(CUresult, *mut *const c_char));
+real_fn!(cuPointerGetAttribute(*mut c_void, u32, CUdeviceptr));
+real_fn!(cuGraphGetNodes(CUgraph, *mut CUgraphNode, *mut usize));
+real_fn!(cuGraphGetEdges(CUgraph, *mut CUgraphNode, *mut CUgraphNode, *mut usize));
+real_fn!(cuGraphNodeGetType(CUgraphNode, *mut u32));
+real_fn!(cuGraphKernelNodeGetParams_v2(CUgraphNode, *mut CUDA_KERNEL_NODE_PARAMS_v2));
+real_fn!(cuGraphMemcpyNodeGetParams(CUgraphNode, *mut CUDA_MEMCPY3D));
+real_fn!(cuGraphMemsetNodeGetParams(CUgraphNode, *mut CUDA_MEMSET_NODE_PARAMS));
+real_fn!(cuGraphChildGraphNodeGetGraph(CUgraphNode, *mut CUgraph));
+real_fn!(cuGraphEventRecordNodeGetEvent(CUgraphNode, *mut CUevent));
+real_fn!(cuGraphEventWaitNodeGetEvent(CUgraphNode, *mut CUevent));
+
+/// Returns the context current on the calling thread, or null if there is none.
+pub fn current_context() -> CUcontext {
+    let mut ctx: CUcontext = std::ptr::null_mut();
+    match *cuCtxGetCurrent {
+        Some(f) if unsafe { f(&mut ctx) } == CUDA_SUCCESS => ctx,
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Returns the device of the context current on the calling thread.
+pub fn current_device() -> Option<CUdevice> {
+    let mut dev: CUdevice = 0;
+    let f = (*cuCtxGetDevice)?;
+    (unsafe { f(&mut dev) } == CUDA_SUCCESS).then_some(dev)
+}
+
+/// Returns the name of `func` as the driver has it, i.e. mangled. Needs CUDA 12.3 or newer.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn function_name(func: CUfunction) -> Option<String> {
+    let mut name: *const c_char = std::ptr::null();
+    let f = (*cuFuncGetName)?;
+    if unsafe { f(&mut name, func) } != CUDA_SUCCESS || name.is_null() {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// Returns the offset and size of every parameter of `func`. Needs CUDA 12.4 or newer.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn function_params(func: CUfunction) -> Option<Vec<(usize, usize)>> {
+    let f = (*cuFuncGetParamInfo)?;
+    let mut params = Vec::new();
+    loop {
+        let (mut offset, mut size) = (0, 0);
+        if unsafe { f(func, params.len(), &mut offset, &mut size) } != CUDA_SUCCESS {
+            // The driver fails past the last parameter. A kernel without parameters looks the
+            // same as one the driver cannot describe; both fall back to module metadata.
+            return Some(params).filter(|p| !p.is_empty());
+        }
+        params.push((offset, size));
+    }
+}
+
+/// Waits for the work on `stream`, without going through the interposer's hooks.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn stream_synchronize(stream: CUstream) -> CUresult {
+    match *cuStreamSynchronize {
+        Some(f) => unsafe { f(stream) },
+        None => CUDA_ERROR_NOT_FOUND,
+    }
+}
+
+/// Returns whether `stream` is being captured into a graph, or cannot be waited for because
+/// another stream is. Synchronizing such a stream would invalidate the capture.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn stream_is_capturing(stream: CUstream) -> bool {
+    let Some(f) = *cuStreamIsCapturing else {
+        return false;
+    };
+    let mut status = CU_STREAM_CAPTURE_STATUS_NONE;
+    // Querying the legacy stream fails while another stream is captured in global mode.
+    let rc = unsafe { f(stream, &mut status) };
+    rc != CUDA_SUCCESS || status != CU_STREAM_CAPTURE_STATUS_NONE
+}
+
+/// Returns the name of the error code `rc`, e.g. `CUDA_ERROR_ILLEGAL_ADDRESS`.
+pub fn error_name(rc: CUresult) -> Option<String> {
+    let mut name: *const c_char = std::ptr::null();
+    let f = (*cuGetErrorName)?;
+    if unsafe { f(rc, &mut name) } != CUDA_SUCCESS || name.is_null() {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// Returns whether the host memory at `ptr` is page-locked, i.e. allocated or registered with
+/// the driver. Pageable memory is unknown to the driver, which rejects the query.
+pub fn is_pinned(ptr: *const c_void) -> bool {
+    const CU_POINTER_ATTRIBUTE_MEMORY_TYPE: u32 = 2;
+    let Some(f) = *cuPointerGetAttribute else {
+        return false;
+    };
+    let mut memory_type = 0u32;
+    let data = &mut memory_type as *mut u32 as *mut c_void;
+    unsafe { f(data, CU_POINTER_ATTRIBUTE_MEMORY_TYPE, ptr as CUdeviceptr) == CUDA_SUCCESS }
+}
+
+/// Returns the nodes of `graph`.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn graph_nodes(graph: CUgraph) -> Option<Vec<CUgraphNode>> {
+    let f = (*cuGraphGetNodes)?;
+    let mut count = 0;
+    if unsafe { f(graph, std::ptr::null_mut(), &mut count) } != CUDA_SUCCESS {
+        return None;
+    }
+    let mut nodes = vec![std::ptr::null_mut(); count];
+    if unsafe { f(graph, nodes.as_mut_ptr(), &mut count) } != CUDA_SUCCESS {
+        return None;
+    }
+    nodes.truncate(count);
+    Some(nodes)
+}
+
+/// Returns the dependency edges of `graph`, as (from, to) pairs.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn graph_edges(graph: CUgraph) -> Option<Vec<(CUgraphNode, CUgraphNode)>> {
+    let f = (*cuGraphGetEdges)?;
+    let mut count = 0;
+    let null = std::ptr::null_mut();
+    if unsafe { f(graph, null, null, &mut count) } != CUDA_SUCCESS {
+        return None;
+    }
+    let mut from = vec![std::ptr::null_mut(); count];
+    let mut to = vec![std::ptr::null_mut(); count];
+    if unsafe { f(graph, from.as_mut_ptr(), to.as_mut_ptr(), &mut count) } != CUDA_SUCCESS {
+        return None;
+    }
+    Some(from.into_iter().zip(to).take(count).collect())
+}
+
+/// Calls a driver query that fills in one value about `node`.
+fn node_query<T>(
+    f: &Lazy<Option<unsafe extern "C" fn(CUgraphNode, *mut T) -> CUresult>>,
+    node: CUgraphNode,
+) -> Option<T> {
+    let f = (**f)?;
+    let mut value = std::mem::MaybeUninit::<T>::zeroed();
+    (unsafe { f(node, value.as_mut_ptr()) } == CUDA_SUCCESS).then(|| unsafe { value.assume_init() })
+}
+
+/// Returns the `CUgraphNodeType` of `node`.
+pub fn graph_node_type(node: CUgraphNode) -> Option<u32> {
+    node_query(&cuGraphNodeGetType, node)
+}
+
+pub fn kernel_node_params(node: CUgraphNode) -> Option<CUDA_KERNEL_NODE_PARAMS_v2> {
+    node_query(&cuGraphKernelNodeGetParams_v2, node)
+}
+
+pub fn memcpy_node_params(node: CUgraphNode) -> Option<CUDA_MEMCPY3D> {
+    node_query(&cuGraphMemcpyNodeGetParams, node)
+}
+
+pub fn memset_node_params(node: CUgraphNode) -> Option<CUDA_MEMSET_NODE_PARAMS> {
+    node_query(&cuGraphMemsetNodeGetParams, node)
+}
+
+/// Returns the graph embedded in a child graph node.
+pub fn child_graph(node: CUgraphNode) -> Option<CUgraph> {
+    node_query(&cuGraphChildGraphNodeGetGraph, node)
+}
+
+/// Returns the event of an event record or event wait node.
+pub fn node_event(node: CUgraphNode, record: bool) -> Option<CUevent> {
+    if record {
+        node_query(&cuGraphEventRecordNodeGetEvent, node)
+    } else {
+        node_query(&cuGraphEventWaitNodeGetEvent, node)
+    }
+}
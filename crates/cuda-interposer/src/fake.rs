@@ -0,0 +1,64 @@
+//! The `libcuda.so` of `cuda-fake-driver`, which cargo builds next to the test binary, for
+//! tests that need a driver.
+
+use crate::ffi::*;
+use crate::replay::Driver;
+use cuda::kernels::{CUfakeKernel, CUfakeLaunch};
+use once_cell::sync::Lazy;
+use std::ffi::{CString, c_char, c_void};
+use std::sync::{Mutex, MutexGuard};
+
+pub const PTX: &[u8] = b".version 8.0\n.target sm_80\n.address_size 64\n\n\
+    .visible .entry scale(.param .u64 data, .param .u32 n)\n{\n\tret;\n}\n\0";
+
+pub static PATH: Lazy<String> = Lazy::new(|| {
+    let exe = std::env::current_exe().unwrap();
+    let path = exe.parent().unwrap().join("libcuda.so");
+    path.to_string_lossy().into_owned()
+});
+
+static HANDLE: Lazy<usize> = Lazy::new(|| {
+    let path = CString::new(PATH.as_str()).unwrap();
+    let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    assert!(!handle.is_null(), "cannot load {}", *PATH);
+    handle as usize
+});
+
+/// Serializes the tests that share the fake driver and the interposer's global state.
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn symbol(name: &str) -> *mut c_void {
+    let name = CString::new(name).unwrap();
+    unsafe { libc::dlsym(*HANDLE as *mut c_void, name.as_ptr()) }
+}
+
+/// Doubles the `n` floats at `data`, for `scale(float* data, int n)`.
+unsafe extern "C" fn scale(launch: *const CUfakeLaunch, _user_data: *mut c_void) -> CUresult {
+    let params = unsafe { (*launch).kernelParams };
+    let data = unsafe { *(*params as *const *mut f32) };
+    let n = unsafe { *(*params.add(1) as *const u32) };
+    let data = unsafe { std::slice::from_raw_parts_mut(data, n as usize) };
+    data.iter_mut().for_each(|x| *x *= 2.0);
+    CUDA_SUCCESS
+}
+
+/// Holds the other tests that share the fake driver and the interposer's global state off until
+/// dropped, for tests that change that state without a driver.
+pub fn lock() -> MutexGuard<'static, ()> {
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Resolves the real entry points of the interposer to the fake driver, registers `scale` with
+/// it, and opens it. Holds the other tests that use it off until dropped.
+pub fn driver() -> (Driver, MutexGuard<'static, ()>) {
+    type RegisterKernel =
+        unsafe extern "C" fn(*const c_char, Option<CUfakeKernel>, *mut c_void) -> CUresult;
+    let guard = lock();
+    crate::set_resolver(Some(symbol));
+    let register = symbol("cufakeRegisterKernel");
+    assert!(!register.is_null());
+    let register: RegisterKernel = unsafe { std::mem::transmute(register) };
+    let rc = unsafe { register(c"scale".as_ptr(), Some(scale), std::ptr::null_mut()) };
+    assert_eq!(rc, CUDA_SUCCESS);
+    (Driver::open(&PATH).unwrap(), guard)
+}
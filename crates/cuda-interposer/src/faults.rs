@@ -0,0 +1,626 @@
+//! Fault injection.
+//!
+//! A [`Plan`] makes chosen API calls fail without reaching the driver, to exercise error
+//! handling that real hardware failures rarely reach. Each [`Rule`] names the symbols it applies
+//! to with a glob (see [`crate::pattern`]), the error code to return, and a [`Trigger`]: every
+//! call, only the nth call, each call with some probability, or every call once the process has
+//! run for a while. Probabilities are drawn from a generator seeded by the plan, so a run fails
+//! the same calls every time for the same sequence of calls.
+//!
+//! Plans are read from `CUDA_HOOK_FAULTS`, as `;`-separated rules of the form
+//! `symbol:error[:trigger]`:
+//!
+//! ```text
+//! CUDA_HOOK_FAULTS='cuMemAlloc_v2:CUDA_ERROR_OUT_OF_MEMORY:nth=3;
+//!                   cuModuleLoad*:CUDA_ERROR_INVALID_IMAGE:p=0.01'
+//! ```
+//!
+//! or from a TOML file named by `CUDA_HOOK_FAULTS_FILE`:
+//!
+//! ```toml
+//! seed = 42
+//!
+//! [[rules]]
+//! symbol = "cuStreamSynchronize"
+//! error = "CUDA_ERROR_LAUNCH_FAILED"
+//! after = "10s"
+//! ```
+//!
+//! `CUDA_HOOK_FAULTS_SEED` overrides the seed, which is 0 by default. Errors are given by name
+//! (`CUDA_ERROR_*` for driver symbols, `cudaError*` for runtime ones) or by number.
+//!
+//! Rules apply to every [`cuda_hook!`] and to the passthroughs generated by
+//! `cuda-interposer-build`, for symbols whose return type is a [`Status`]: `CUresult`,
+//! `cudaError_t` or `u32`. While a plan is armed, `cuGetProcAddress` hands out the passthroughs
+//! rather than the real driver's entry points, so faults reach callers that look symbols up
+//! through it too. Entry points looked up before a plan is armed with [`set_plan`] stay the
+//! driver's.
+
+use crate::config::{ConfigError, parse_duration};
+use crate::pattern::glob_match;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::warn;
+
+pub use cuda_interposer_tables::status::{DRIVER_ERRORS, RUNTIME_ERRORS, Status};
+
+const FAULTS_ENV: &str = "CUDA_HOOK_FAULTS";
+const FILE_ENV: &str = "CUDA_HOOK_FAULTS_FILE";
+const SEED_ENV: &str = "CUDA_HOOK_FAULTS_SEED";
+
+static ENABLED: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(ARMED.read().unwrap().is_some()));
+
+static ARMED: Lazy<RwLock<Option<Armed>>> = Lazy::new(|| {
+    let plan = match (std::env::var_os(FILE_ENV), std::env::var(FAULTS_ENV)) {
+        (Some(path), _) => Plan::load(&path)
+            .map_err(|e| warn!("Ignoring fault plan {:?}: {}", path, e))
+            .ok(),
+        (None, Ok(spec)) => Plan::parse(&spec)
+            .map_err(|e| warn!("Ignoring fault plan: {}", e))
+            .ok(),
+        (None, Err(_)) => None,
+    };
+    let plan = plan.map(|mut plan| {
+        match std::env::var(SEED_ENV).map(|s| s.trim().parse()) {
+            Ok(Ok(seed)) => plan.seed = seed,
+            Ok(Err(_)) => warn!("Ignoring {}: not a number", SEED_ENV),
+            Err(_) => {}
+        }
+        plan
+    });
+    RwLock::new(plan.map(Armed::new))
+});
+
+/// Returns the error code for a rule's `error`, a name from [`DRIVER_ERRORS`] or
+/// [`RUNTIME_ERRORS`] or a number from either.
+///
+/// Generated passthroughs return the `cuda-interposer-sys` enums, so only codes listed there
+/// are accepted.
+pub fn error_code(error: &str) -> Option<u32> {
+    let error = error.trim();
+    let mut known = DRIVER_ERRORS.iter().chain(RUNTIME_ERRORS);
+    match error.parse::<u32>() {
+        Ok(code) => known.any(|(_, c)| *c == code).then_some(code),
+        Err(_) => known.find(|(name, _)| *name == error).map(|(_, c)| *c),
+    }
+}
+
+/// When a rule fails a matching call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// Every call.
+    Always,
+    /// Only the nth call, counting from 1.
+    Nth(u64),
+    /// Each call with this probability.
+    Probability(f64),
+    /// Every call once the interposer has run this long (see [`crate::elapsed`]), counting from
+    /// when it was loaded or, failing that, when the plan was armed.
+    After(Duration),
+}
+
+/// Fails the calls to `symbol` selected by `trigger` with `error`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "RuleRepr")]
+pub struct Rule {
+    /// A glob over symbol names.
+    pub symbol: String,
+    pub error: u32,
+    pub trigger: Trigger,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleRepr {
+    symbol: String,
+    error: ErrorRepr,
+    nth: Option<u64>,
+    probability: Option<f64>,
+    after: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ErrorRepr {
+    Code(u32),
+    Name(String),
+}
+
+impl TryFrom<RuleRepr> for Rule {
+    type Error = String;
+
+    fn try_from(repr: RuleRepr) -> Result<Self, Self::Error> {
+        let error = match repr.error {
+            ErrorRepr::Code(code) => error_code(&code.to_string()),
+            ErrorRepr::Name(name) => error_code(&name),
+        }
+        .ok_or("unknown error code")?;
+        let trigger = match (repr.nth, repr.probability, repr.after) {
+            (None, None, None) => Trigger::Always,
+            (Some(0), None, None) => return Err("nth counts from 1".into()),
+            (Some(n), None, None) => Trigger::Nth(n),
+            (None, Some(p), None) if (0.0..=1.0).contains(&p) => Trigger::Probability(p),
+            (None, Some(_), None) => return Err("probability must be between 0 and 1".into()),
+            (None, None, Some(after)) => Trigger::After(
+                parse_duration(&after).ok_or_else(|| format!("bad duration '{after}'"))?,
+            ),
+            _ => return Err("at most one of nth, probability and after may be set".into()),
+        };
+        Ok(Rule {
+            symbol: repr.symbol,
+            error,
+            trigger,
+        })
+    }
+}
+
+impl Rule {
+    /// Parses a `symbol:error[:trigger]` rule, where the trigger is `nth=N`, `p=P` or
+    /// `after=DURATION`.
+    pub fn parse(spec: &str) -> Result<Self, ConfigError> {
+        let err = |reason| ConfigError::new(FAULTS_ENV, spec, reason);
+        let mut parts = spec.split(':').map(str::trim);
+        let symbol = parts
+            .next()
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| err("missing symbol"))?;
+        let error = parts.next().ok_or_else(|| err("missing error"))?;
+        let error = error_code(error).ok_or_else(|| err("unknown error code"))?;
+        let trigger = match parts.next().map(|t| t.split_once('=')) {
+            None => Trigger::Always,
+            Some(Some(("nth", n))) => match n.trim().parse() {
+                Ok(0) | Err(_) => return Err(err("nth must be a positive number")),
+                Ok(n) => Trigger::Nth(n),
+            },
+            Some(Some(("p", p))) => match p.trim().parse() {
+                Ok(p) if (0.0..=1.0).contains(&p) => Trigger::Probability(p),
+                _ => return Err(err("p must be a probability between 0 and 1")),
+            },
+            Some(Some(("after", d))) => {
+                Trigger::After(parse_duration(d).ok_or_else(|| err("bad duration"))?)
+            }
+            Some(_) => return Err(err("trigger must be nth=N, p=P or after=DURATION")),
+        };
+        if parts.next().is_some() {
+            return Err(err("too many fields"));
+        }
+        Ok(Rule {
+            symbol: symbol.to_string(),
+            error,
+            trigger,
+        })
+    }
+}
+
+/// A set of rules, tried in order for every call.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+pub enum PlanError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::Io(e) => write!(f, "{e}"),
+            PlanError::Toml(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+impl Plan {
+    /// Parses a `CUDA_HOOK_FAULTS` specification.
+    pub fn parse(spec: &str) -> Result<Self, ConfigError> {
+        let rules = spec
+            .split(';')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(Rule::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Plan { seed: 0, rules })
+    }
+
+    pub fn from_toml(src: &str) -> Result<Self, PlanError> {
+        toml::from_str(src).map_err(PlanError::Toml)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PlanError> {
+        let src = std::fs::read_to_string(path).map_err(PlanError::Io)?;
+        Self::from_toml(&src)
+    }
+}
+
+#[derive(Debug)]
+struct ArmedRule {
+    rule: Rule,
+    calls: AtomicU64,
+    injected: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Armed {
+    seed: u64,
+    rules: Vec<ArmedRule>,
+}
+
+impl Armed {
+    fn new(plan: Plan) -> Self {
+        // Start the clock `Trigger::After` counts from, in case no call has been seen yet
+        crate::elapsed();
+        let rules = plan
+            .rules
+            .into_iter()
+            .map(|rule| ArmedRule {
+                rule,
+                calls: AtomicU64::new(0),
+                injected: AtomicU64::new(0),
+            })
+            .collect();
+        Self {
+            seed: plan.seed,
+            rules,
+        }
+    }
+}
+
+/// Returns a number in `[0, 1)` from SplitMix64, as a stateless hash of the seed, rule and call
+/// number. Drawing per call keeps each rule's sequence independent of how calls to other
+/// symbols interleave.
+pub(crate) fn draw(seed: u64, rule: usize, call: u64) -> f64 {
+    let mut z = seed ^ (rule as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ call;
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Replaces the active plan, resetting call counts. `None` disables injection.
+pub fn set_plan(plan: Option<Plan>) {
+    let armed = plan.map(Armed::new);
+    ENABLED.store(armed.is_some(), Ordering::Relaxed);
+    *ARMED.write().unwrap() = armed;
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns how many calls each rule of the active plan has failed, in rule order.
+pub fn injected() -> Vec<u64> {
+    ARMED
+        .read()
+        .unwrap()
+        .as_ref()
+        .map_or_else(Vec::new, |armed| {
+            armed
+                .rules
+                .iter()
+                .map(|r| r.injected.load(Ordering::Relaxed))
+                .collect()
+        })
+}
+
+/// Returns the error a call to `symbol` should fail with, if any, counting the call against
+/// every matching rule.
+pub fn check(symbol: &str) -> Option<u32> {
+    if !enabled() {
+        return None;
+    }
+    let armed = ARMED.read().unwrap();
+    let armed = armed.as_ref()?;
+    let mut fault = None;
+    for (i, r) in armed.rules.iter().enumerate() {
+        if !glob_match(&r.rule.symbol, symbol) {
+            continue;
+        }
+        let call = r.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        let hit = match r.rule.trigger {
+            Trigger::Always => true,
+            Trigger::Nth(n) => call == n,
+            Trigger::Probability(p) => draw(armed.seed, i, call) < p,
+            Trigger::After(after) => crate::elapsed() >= after,
+        };
+        if hit && fault.is_none() {
+            r.injected.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Injecting {} into call {} to {} (rule {})",
+                r.rule.error,
+                call,
+                symbol,
+                i + 1
+            );
+            fault = Some(r.rule.error);
+        }
+    }
+    fault
+}
+
+/// Returns the status an intercepted call to `symbol` should return instead of calling
+/// through, if a rule fails it. Hooks call it through [`inject_fault!`], which skips calls that
+/// do not return a [`Status`].
+pub fn inject<T: Status>(symbol: &str) -> Option<T> {
+    if !enabled() {
+        return None;
+    }
+    let rc = check(symbol)?;
+    // Each status type only defines the codes of its own API, and `u32` tells us nothing.
+    let runtime = symbol.starts_with("cuda") || symbol.starts_with("__cuda");
+    let codes = if runtime {
+        RUNTIME_ERRORS
+    } else {
+        DRIVER_ERRORS
+    };
+    if !codes.iter().any(|(_, c)| *c == rc) {
+        warn!(
+            "Not injecting {} into {}: not one of its error codes",
+            rc, symbol
+        );
+        return None;
+    }
+    T::from_code(rc)
+}
+
+/// A hook's return type, for [`inject_fault!`]: `Ret::<T>(PhantomData).inject(symbol)` resolves
+/// to [`InjectStatus`] when `T` is a [`Status`] and to [`InjectNothing`], which never fails the
+/// call, for anything else.
+#[doc(hidden)]
+pub struct Ret<T>(pub PhantomData<T>);
+
+#[doc(hidden)]
+pub trait InjectStatus<T> {
+    fn inject(self, symbol: &str) -> Option<T>;
+}
+
+impl<T: Status> InjectStatus<T> for Ret<T> {
+    fn inject(self, symbol: &str) -> Option<T> {
+        inject(symbol)
+    }
+}
+
+#[doc(hidden)]
+pub trait InjectNothing<T> {
+    fn inject(self, symbol: &str) -> Option<T>;
+}
+
+impl<T> InjectNothing<T> for &Ret<T> {
+    fn inject(self, _symbol: &str) -> Option<T> {
+        None
+    }
+}
+
+/// Returns the fault to return from a hook for `symbol` with return type `ret`, if any. Used by
+/// [`cuda_hook!`] and [`generate_proxy!`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! inject_fault {
+    ($ret:ty, $symbol:ident) => {{
+        #[allow(unused_imports)]
+        use $crate::faults::{InjectNothing as _, InjectStatus as _};
+        $crate::faults::Ret::<$ret>(::std::marker::PhantomData).inject(stringify!($symbol))
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Serializes the tests that arm a plan, which is process-wide.
+    fn lock() -> MutexGuard<'static, ()> {
+        static LOCK: Mutex<()> = Mutex::new(());
+        LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn hits(symbol: &str, calls: u64) -> Vec<u64> {
+        (1..=calls).filter(|_| check(symbol).is_some()).collect()
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule = Rule::parse(" cuMemAlloc_v2 : CUDA_ERROR_OUT_OF_MEMORY : nth=3 ").unwrap();
+        assert_eq!(
+            rule,
+            Rule {
+                symbol: "cuMemAlloc_v2".into(),
+                error: 2,
+                trigger: Trigger::Nth(3),
+            }
+        );
+        assert_eq!(Rule::parse("cu*:999").unwrap().trigger, Trigger::Always);
+        assert_eq!(
+            Rule::parse("cuda*:cudaErrorMemoryAllocation:p=0.25").unwrap(),
+            Rule {
+                symbol: "cuda*".into(),
+                error: 2,
+                trigger: Trigger::Probability(0.25),
+            }
+        );
+        assert_eq!(
+            Rule::parse("cuCtxSynchronize:CUDA_ERROR_LAUNCH_FAILED:after=250ms")
+                .unwrap()
+                .trigger,
+            Trigger::After(Duration::from_millis(250))
+        );
+
+        for (spec, reason) in [
+            (":CUDA_ERROR_UNKNOWN", "missing symbol"),
+            ("cuInit", "missing error"),
+            ("cuInit:CUDA_ERROR_NOT_A_THING", "unknown error code"),
+            ("cuInit:12345", "unknown error code"),
+            ("cuInit:999:nth=0", "nth must be a positive number"),
+            (
+                "cuInit:999:p=1.5",
+                "p must be a probability between 0 and 1",
+            ),
+            ("cuInit:999:after=soon", "bad duration"),
+            (
+                "cuInit:999:every=2",
+                "trigger must be nth=N, p=P or after=DURATION",
+            ),
+            ("cuInit:999:nth=1:p=0.5", "too many fields"),
+        ] {
+            assert_eq!(Rule::parse(spec).unwrap_err().reason, reason, "{spec}");
+        }
+    }
+
+    #[test]
+    fn parses_plans() {
+        // The example from the module documentation
+        let plan = Plan::parse(
+            "cuMemAlloc_v2:CUDA_ERROR_OUT_OF_MEMORY:nth=3;
+             cuModuleLoad*:CUDA_ERROR_INVALID_IMAGE:p=0.01",
+        )
+        .unwrap();
+        assert_eq!(plan.seed, 0);
+        let symbols: Vec<_> = plan.rules.iter().map(|r| r.symbol.as_str()).collect();
+        assert_eq!(symbols, ["cuMemAlloc_v2", "cuModuleLoad*"]);
+        assert_eq!(Plan::parse(" ; ").unwrap(), Plan::default());
+        assert!(Plan::parse("cuInit:999;cuInit").is_err());
+    }
+
+    #[test]
+    fn parses_plan_files() {
+        let plan = Plan::from_toml(
+            r#"
+            seed = 42
+
+            [[rules]]
+            symbol = "cuStreamSynchronize"
+            error = "CUDA_ERROR_LAUNCH_FAILED"
+            after = "10s"
+
+            [[rules]]
+            symbol = "cuMemAlloc*"
+            error = 2
+            nth = 4
+
+            [[rules]]
+            symbol = "cuLaunchKernel"
+            error = "CUDA_ERROR_UNKNOWN"
+            probability = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(plan.seed, 42);
+        let triggers: Vec<_> = plan.rules.iter().map(|r| r.trigger).collect();
+        assert_eq!(
+            triggers,
+            [
+                Trigger::After(Duration::from_secs(10)),
+                Trigger::Nth(4),
+                Trigger::Probability(0.5),
+            ]
+        );
+        assert_eq!(plan.rules[0].error, 719);
+        assert_eq!(Plan::from_toml("").unwrap(), Plan::default());
+
+        for rule in [
+            r#"symbol = "cuInit"
+               error = "CUDA_ERROR_NOT_A_THING""#,
+            r#"symbol = "cuInit"
+               error = 999
+               nth = 0"#,
+            r#"symbol = "cuInit"
+               error = 999
+               probability = 2.0"#,
+            r#"symbol = "cuInit"
+               error = 999
+               nth = 1
+               after = "1s""#,
+            r#"symbol = "cuInit"
+               error = 999
+               count = 1"#,
+        ] {
+            let src = format!("[[rules]]\n{rule}");
+            assert!(Plan::from_toml(&src).is_err(), "{src}");
+        }
+    }
+
+    #[test]
+    fn fails_only_the_nth_call() {
+        let _lock = lock();
+        set_plan(Some(Plan::parse("cuTestNth*:999:nth=3").unwrap()));
+        assert_eq!(hits("cuTestNthA", 5), [3]);
+        // Calls to every symbol the glob matches count against the rule
+        assert_eq!(hits("cuTestNthB", 5), Vec::<u64>::new());
+        assert_eq!(injected(), [1]);
+        set_plan(None);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let _lock = lock();
+        set_plan(Some(
+            Plan::parse("cuTestOrder:CUDA_ERROR_OUT_OF_MEMORY:nth=2;cuTestOrd*:999").unwrap(),
+        ));
+        let errors: Vec<_> = (0..3).map(|_| check("cuTestOrder")).collect();
+        assert_eq!(errors, [Some(999), Some(2), Some(999)]);
+        assert_eq!(injected(), [1, 2]);
+        set_plan(None);
+    }
+
+    #[test]
+    fn fails_after_the_plan_is_armed() {
+        let _lock = lock();
+        set_plan(Some(Plan::parse("cuTestAfter:999:after=0s").unwrap()));
+        assert_eq!(check("cuTestAfter"), Some(999));
+        set_plan(Some(Plan::parse("cuTestAfter:999:after=1h").unwrap()));
+        assert_eq!(check("cuTestAfter"), None);
+        set_plan(None);
+    }
+
+    #[test]
+    fn seeded_probabilities_are_deterministic() {
+        let _lock = lock();
+        let run = |seed| {
+            let mut plan = Plan::parse("cuTestRandom:999:p=0.5").unwrap();
+            plan.seed = seed;
+            set_plan(Some(plan));
+            let hits = hits("cuTestRandom", 200);
+            set_plan(None);
+            hits
+        };
+        let first = run(7);
+        assert_eq!(run(7), first);
+        assert_ne!(run(8), first);
+        assert!((60..140).contains(&first.len()), "{}", first.len());
+        // Rules draw independently of each other
+        assert_ne!(draw(7, 0, 1), draw(7, 1, 1));
+    }
+
+    #[test]
+    fn only_fails_status_returning_calls() {
+        let _lock = lock();
+        set_plan(Some(
+            Plan::parse("cuTest*:CUDA_ERROR_OUT_OF_MEMORY").unwrap(),
+        ));
+        assert_eq!(crate::inject_fault!(u32, cuTestStatus), Some(2));
+        assert_eq!(
+            crate::inject_fault!(*mut std::ffi::c_void, cuTestPointer),
+            None
+        );
+        assert_eq!(crate::inject_fault!((), cuTestVoid), None);
+        // Only the status-returning call counted against the rule
+        assert_eq!(injected(), vec![1]);
+        set_plan(None);
+        assert_eq!(crate::inject_fault!(u32, cuTestStatus), None);
+    }
+}
@@ -0,0 +1,460 @@
+//! CUDA Graph introspection.
+//!
+//! Work submitted as a graph never reaches the launch or memcpy hooks: it is captured with
+//! `cuStreamBeginCapture` or built node by node, then replayed by `cuGraphLaunch`.
+//! [`install_graph_hooks!`] walks each graph as it is instantiated, with `cuGraphGetNodes`,
+//! `cuGraphGetEdges`, `cuGraphNodeGetType` and the per-type parameter queries, into a
+//! [`GraphDump`] of its topology and node parameters. Kernel nodes are named like launches (see
+//! [`crate::modules`]), with their arguments decoded when [`crate::args`] decoding is enabled,
+//! and child graphs are dumped in place.
+//!
+//! The legacy five-argument `cuGraphInstantiate`/`cuGraphInstantiate_v2` are hooked alongside
+//! `cuGraphInstantiateWithFlags` and `cuGraphInstantiateWithParams`, and per-thread default
+//! stream launches (`cuGraphLaunch_ptsz`) alongside `cuGraphLaunch`.
+//!
+//! Every `cuGraphLaunch` is attributed to the dump of the graph it was instantiated from. Both
+//! are passed as [`GraphEvent`]s to the callbacks registered with [`subscribe`], written as JSON
+//! lines to `CUDA_HOOK_GRAPH_LOG` (a file path or `stderr`) when it is set, and launches are
+//! added to the [`crate::streams`] graph when it is tracked.
+
+use crate::args::{self, KernelArg};
+use crate::driver;
+use crate::ffi::*;
+use crate::modules::{self, KernelName};
+use crate::streams;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::warn;
+
+const LOG_ENV: &str = "CUDA_HOOK_GRAPH_LOG";
+
+type Subscriber = Box<dyn Fn(&GraphEvent) + Send + Sync>;
+
+static SUBSCRIBERS: RwLock<Vec<Subscriber>> = RwLock::new(Vec::new());
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The dump of the graph each live `CUgraphExec` was instantiated from.
+static EXECS: Lazy<RwLock<HashMap<usize, Arc<GraphDump>>>> = Lazy::new(Default::default);
+
+static LOG: Lazy<Option<Mutex<Box<dyn Write + Send>>>> = Lazy::new(|| {
+    let dest = std::env::var_os(LOG_ENV)?;
+    let sink: Box<dyn Write + Send> = if dest == "stderr" {
+        Box::new(std::io::stderr())
+    } else {
+        match std::fs::File::create(&dest) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                warn!("Failed to open graph log {:?}: {}", dest, e);
+                return None;
+            }
+        }
+    };
+    Some(Mutex::new(sink))
+});
+
+/// The topology and node parameters of a graph.
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphDump {
+    /// A sequence number identifying the dump in the log.
+    pub id: u64,
+    /// The `CUgraph`, as an address.
+    pub graph: usize,
+    pub nodes: Vec<GraphNode>,
+    /// Dependencies as (from, to) indices into `nodes`.
+    pub edges: Vec<(usize, usize)>,
+}
+
+impl GraphDump {
+    /// The number of kernel nodes, child graphs included.
+    pub fn kernel_count(&self) -> usize {
+        self.nodes
+            .iter()
+            .map(|n| match &n.params {
+                NodeParams::Kernel { .. } => 1,
+                NodeParams::Graph { graph } => graph.kernel_count(),
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphNode {
+    pub index: usize,
+    /// The `CUgraphNode`, as an address.
+    pub handle: usize,
+    #[serde(flatten)]
+    pub params: NodeParams,
+}
+
+/// A node's type and, for the types that have them, its parameters.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeParams {
+    Kernel {
+        kernel: Option<Arc<KernelName>>,
+        /// The `CUfunction`, or the `CUkernel` for library kernels, as an address.
+        function: usize,
+        grid: [u32; 3],
+        block: [u32; 3],
+        shared_mem: u32,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        args: Vec<KernelArg>,
+    },
+    Memcpy {
+        /// `CUmemorytype` of each side: 1 host, 2 device, 3 array, 4 unified.
+        src_memory_type: u32,
+        dst_memory_type: u32,
+        /// The host or device address of each side.
+        src: u64,
+        dst: u64,
+        width: usize,
+        height: usize,
+        depth: usize,
+    },
+    Memset {
+        dst: CUdeviceptr,
+        value: u32,
+        element_size: u32,
+        width: usize,
+        height: usize,
+        pitch: usize,
+    },
+    Host,
+    Graph {
+        graph: Box<GraphDump>,
+    },
+    Empty,
+    WaitEvent {
+        event: usize,
+    },
+    EventRecord {
+        event: usize,
+    },
+    /// A node type without parameters recorded here, or one whose parameters the driver would
+    /// not return.
+    Other {
+        node_type: Option<u32>,
+    },
+}
+
+/// An instantiation or a launch of a graph.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GraphEvent {
+    Instantiate {
+        /// Microseconds since the interposer was loaded.
+        at_us: u64,
+        exec: usize,
+        graph: Arc<GraphDump>,
+    },
+    Launch {
+        at_us: u64,
+        exec: usize,
+        /// The id of the [`GraphDump`] the executable graph was instantiated from, if its
+        /// instantiation was seen.
+        graph_id: Option<u64>,
+        stream: usize,
+        result: CUresult,
+    },
+}
+
+/// Registers a callback that is passed every graph instantiation and launch.
+pub fn subscribe(f: impl Fn(&GraphEvent) + Send + Sync + 'static) {
+    SUBSCRIBERS.write().unwrap().push(Box::new(f));
+}
+
+fn enabled() -> bool {
+    LOG.is_some() || streams::enabled() || !SUBSCRIBERS.read().unwrap().is_empty()
+}
+
+fn record(event: GraphEvent) {
+    for f in SUBSCRIBERS.read().unwrap().iter() {
+        f(&event);
+    }
+    if let Some(log) = LOG.as_ref() {
+        let mut line = match serde_json::to_vec(&event) {
+            Ok(line) => line,
+            Err(e) => return warn!("Failed to serialize graph event: {}", e),
+        };
+        line.push(b'\n');
+        let _ = log.lock().unwrap().write_all(&line);
+    }
+}
+
+fn kernel_node(node: CUgraphNode) -> Option<NodeParams> {
+    let p = driver::kernel_node_params(node)?;
+    let function = if p.func.is_null() { p.kern } else { p.func };
+    let kernel = modules::kernel_name(function);
+    let args = if args::enabled() {
+        let layout = args::layout(function, kernel.as_deref());
+        unsafe {
+            args::decode_launch(
+                layout.as_deref(),
+                kernel.as_deref(),
+                p.kernelParams,
+                p.extra,
+            )
+        }
+    } else {
+        Vec::new()
+    };
+    Some(NodeParams::Kernel {
+        kernel,
+        function: function as usize,
+        grid: [p.gridDimX, p.gridDimY, p.gridDimZ],
+        block: [p.blockDimX, p.blockDimY, p.blockDimZ],
+        shared_mem: p.sharedMemBytes,
+        args,
+    })
+}
+
+fn node_params(node: CUgraphNode) -> NodeParams {
+    let node_type = driver::graph_node_type(node);
+    let params = match node_type {
+        Some(CU_GRAPH_NODE_TYPE_KERNEL) => kernel_node(node),
+        Some(CU_GRAPH_NODE_TYPE_MEMCPY) => {
+            driver::memcpy_node_params(node).map(|p| NodeParams::Memcpy {
+                src_memory_type: p.srcMemoryType,
+                dst_memory_type: p.dstMemoryType,
+                src: if p.srcHost.is_null() {
+                    p.srcDevice
+                } else {
+                    p.srcHost as u64
+                },
+                dst: if p.dstHost.is_null() {
+                    p.dstDevice
+                } else {
+                    p.dstHost as u64
+                },
+                width: p.WidthInBytes,
+                height: p.Height,
+                depth: p.Depth,
+            })
+        }
+        Some(CU_GRAPH_NODE_TYPE_MEMSET) => {
+            driver::memset_node_params(node).map(|p| NodeParams::Memset {
+                dst: p.dst,
+                value: p.value,
+                element_size: p.elementSize,
+                width: p.width,
+                height: p.height,
+                pitch: p.pitch,
+            })
+        }
+        Some(CU_GRAPH_NODE_TYPE_HOST) => Some(NodeParams::Host),
+        Some(CU_GRAPH_NODE_TYPE_GRAPH) => driver::child_graph(node).map(|g| NodeParams::Graph {
+            graph: Box::new(dump(g)),
+        }),
+        Some(CU_GRAPH_NODE_TYPE_EMPTY) => Some(NodeParams::Empty),
+        Some(CU_GRAPH_NODE_TYPE_WAIT_EVENT) => {
+            driver::node_event(node, false).map(|e| NodeParams::WaitEvent { event: e as usize })
+        }
+        Some(CU_GRAPH_NODE_TYPE_EVENT_RECORD) => {
+            driver::node_event(node, true).map(|e| NodeParams::EventRecord { event: e as usize })
+        }
+        _ => None,
+    };
+    params.unwrap_or(NodeParams::Other { node_type })
+}
+
+/// Walks `graph` into a [`GraphDump`]. A graph the driver cannot enumerate yields an empty dump.
+pub fn dump(graph: CUgraph) -> GraphDump {
+    let handles = driver::graph_nodes(graph).unwrap_or_default();
+    let index: HashMap<usize, usize> = handles
+        .iter()
+        .enumerate()
+        .map(|(i, &h)| (h as usize, i))
+        .collect();
+    let nodes = handles
+        .iter()
+        .enumerate()
+        .map(|(i, &node)| GraphNode {
+            index: i,
+            handle: node as usize,
+            params: node_params(node),
+        })
+        .collect();
+    let edges = driver::graph_edges(graph)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(from, to)| Some((*index.get(&(from as usize))?, *index.get(&(to as usize))?)))
+        .collect();
+    GraphDump {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        graph: graph as usize,
+        nodes,
+        edges,
+    }
+}
+
+/// Returns the dump of the graph `exec` was instantiated from.
+pub fn exec_graph(exec: CUgraphExec) -> Option<Arc<GraphDump>> {
+    EXECS.read().unwrap().get(&(exec as usize)).cloned()
+}
+
+/// Dumps the graph behind a successful instantiation. Used by [`install_graph_hooks!`].
+///
+/// # Safety
+/// `exec` must be the output pointer passed to the call.
+pub unsafe fn after_instantiate(rc: CUresult, exec: *const CUgraphExec, graph: CUgraph) {
+    if rc != CUDA_SUCCESS || exec.is_null() || !enabled() {
+        return;
+    }
+    let exec = unsafe { *exec } as usize;
+    let dump = Arc::new(dump(graph));
+    EXECS.write().unwrap().insert(exec, dump.clone());
+    record(GraphEvent::Instantiate {
+        at_us: crate::elapsed().as_micros() as u64,
+        exec,
+        graph: dump,
+    });
+}
+
+/// Attributes a `cuGraphLaunch` to its graph. Used by [`install_graph_hooks!`].
+pub fn after_launch(rc: CUresult, exec: CUgraphExec, stream: CUstream) {
+    if !enabled() {
+        return;
+    }
+    let graph = exec_graph(exec);
+    if rc == CUDA_SUCCESS {
+        streams::record_graph_launch(stream, graph.as_deref());
+    }
+    record(GraphEvent::Launch {
+        at_us: crate::elapsed().as_micros() as u64,
+        exec: exec as usize,
+        graph_id: graph.map(|g| g.id),
+        stream: stream as usize,
+        result: rc,
+    });
+}
+
+/// Forgets a destroyed executable graph. Used by [`install_graph_hooks!`].
+pub fn after_exec_destroy(rc: CUresult, exec: CUgraphExec) {
+    if rc == CUDA_SUCCESS {
+        EXECS.write().unwrap().remove(&(exec as usize));
+    }
+}
+
+/// Installs hooks that dump every graph as it is instantiated and attribute graph launches to
+/// it.
+///
+/// The hooked symbols are listed in [`crate::hook_sets::HOOK_SETS`], so
+/// `cuda-interposer-build` leaves them out of the generated passthroughs.
+#[macro_export]
+macro_rules! install_graph_hooks {
+    () => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphInstantiate(
+                ph_graph_exec: *mut $crate::ffi::CUgraphExec,
+                h_graph: $crate::ffi::CUgraph,
+                ph_error_node: *mut $crate::ffi::CUgraphNode,
+                log_buffer: *mut $crate::libc::c_char,
+                buffer_size: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuGraphInstantiate)(
+                        ph_graph_exec,
+                        h_graph,
+                        ph_error_node,
+                        log_buffer,
+                        buffer_size,
+                    )
+                };
+                unsafe { $crate::graphs::after_instantiate(rc, ph_graph_exec, h_graph) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphInstantiate_v2(
+                ph_graph_exec: *mut $crate::ffi::CUgraphExec,
+                h_graph: $crate::ffi::CUgraph,
+                ph_error_node: *mut $crate::ffi::CUgraphNode,
+                log_buffer: *mut $crate::libc::c_char,
+                buffer_size: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuGraphInstantiate_v2)(
+                        ph_graph_exec,
+                        h_graph,
+                        ph_error_node,
+                        log_buffer,
+                        buffer_size,
+                    )
+                };
+                unsafe { $crate::graphs::after_instantiate(rc, ph_graph_exec, h_graph) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphInstantiateWithFlags(
+                ph_graph_exec: *mut $crate::ffi::CUgraphExec,
+                h_graph: $crate::ffi::CUgraph,
+                flags: u64
+            ) -> $crate::ffi::CUresult {
+                let rc =
+                    unsafe { (*__real_cuGraphInstantiateWithFlags)(ph_graph_exec, h_graph, flags) };
+                unsafe { $crate::graphs::after_instantiate(rc, ph_graph_exec, h_graph) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphInstantiateWithParams(
+                ph_graph_exec: *mut $crate::ffi::CUgraphExec,
+                h_graph: $crate::ffi::CUgraph,
+                instantiate_params: *mut $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuGraphInstantiateWithParams)(
+                        ph_graph_exec,
+                        h_graph,
+                        instantiate_params,
+                    )
+                };
+                unsafe { $crate::graphs::after_instantiate(rc, ph_graph_exec, h_graph) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphLaunch(
+                h_graph_exec: $crate::ffi::CUgraphExec,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuGraphLaunch)(h_graph_exec, h_stream) };
+                $crate::graphs::after_launch(rc, h_graph_exec, h_stream);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphLaunch_ptsz(
+                h_graph_exec: $crate::ffi::CUgraphExec,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuGraphLaunch_ptsz)(h_graph_exec, h_stream) };
+                let stream = $crate::streams::per_thread(h_stream);
+                $crate::graphs::after_launch(rc, h_graph_exec, stream);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphExecDestroy(
+                h_graph_exec: $crate::ffi::CUgraphExec
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuGraphExecDestroy)(h_graph_exec) };
+                $crate::graphs::after_exec_destroy(rc, h_graph_exec);
+                rc
+            }
+        }
+    };
+}
@@ -0,0 +1,980 @@
+//! Handle virtualization.
+//!
+//! Replay, remoting and checkpointing need the handles an application holds to stay valid when
+//! the driver objects behind them are recreated. With virtualization enabled the application
+//! is handed stable virtual handles in place of the driver's `CUcontext`, `CUstream`, `CUevent`,
+//! `CUmodule`, `CUfunction` and `CUdeviceptr` values, and every intercepted call translates
+//! them back before reaching the driver.
+//!
+//! Virtual values carry a tag in their top bits that no host or device address has, so inputs
+//! are translated by value alone: [`cuda_hook!`] and the passthroughs generated by
+//! `cuda-interposer-build` replace every tagged pointer or 64-bit argument with the real handle
+//! before the hook body or the driver sees it. Hooks therefore only ever deal in real handles.
+//! Outputs are translated where handles are created: the built-in hook sets [`export`] the
+//! handles their create calls return and [`release`] those their destroy calls end, and the
+//! generated passthroughs do the same for out-parameters of handle type and for the destroy
+//! calls listed by [`released_by`].
+//!
+//! Device pointers are virtualized per allocation: each allocation made through
+//! [`install_memory_hooks!`] is given a virtual range of the same size, so pointer arithmetic
+//! carries over, at the same offset modulo 2 MiB, so alignment checks do too. Managed
+//! allocations stay real, since the host dereferences them. The device pointers held in kernel
+//! arguments are translated by [`install_launch_hooks!`], which fails launches of kernels whose
+//! parameter layout is unknown rather than pass virtual pointers to them. Those held in other
+//! structures, the `CUDA_MEMCPY2D`/`CUDA_MEMCPY3D` of 2D and 3D copies, the `CUDA_LAUNCH_PARAMS`
+//! of multi-device launches and the parameters of kernel, memcpy and memset graph nodes, are
+//! translated by [`install_handle_hooks!`], along with their per-thread default stream variants
+//! (`_ptds`, `_ptsz`). Node parameters read back from a graph hold real values.
+//!
+//! Two kinds of values escape this. Device pointers the application stores in device memory,
+//! such as an array of pointers copied with `cuMemcpyHtoD`, are never translated, so kernels
+//! reading them see virtual addresses. And since inputs are translated by value, a 64-bit value
+//! argument whose top 16 bits happen to be the tag, like the `value` of `cuStreamWriteValue64`,
+//! is translated as if it were a virtual device address. Leave `deviceptr` out of the kinds for
+//! applications that do either.
+//!
+//! Virtualization is enabled with `CUDA_HOOK_VIRTUALIZE`, set to `1` for every kind of handle or
+//! to a comma-separated list of kinds (`context`, `stream`, `event`, `module`, `function`,
+//! `deviceptr`), or with [`set_kinds`]. While it is enabled, `cuGetProcAddress` hands out the
+//! generated passthroughs as well as the hooks, so that calls the runtime makes are translated.
+
+use crate::args;
+use crate::ffi::*;
+use crate::modules;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::c_void;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use tracing::warn;
+
+pub use cuda_interposer_tables::handles::{HandleKind, released_by};
+
+const VIRTUALIZE_ENV: &str = "CUDA_HOOK_VIRTUALIZE";
+
+/// The tag in the top 16 bits of every virtual value.
+const TAG: u64 = 0x5ead << 48;
+const TAG_MASK: u64 = 0xffff << 48;
+/// Set on virtual opaque handles, which hold their kind in bits 40..47; clear on virtual device
+/// addresses.
+const OPAQUE: u64 = 1 << 47;
+/// Virtual device ranges start at the same offset as the real ones modulo this.
+const RANGE_ALIGN: u64 = 2 << 20;
+
+static KINDS: Lazy<AtomicU8> = Lazy::new(|| {
+    let kinds = match std::env::var(VIRTUALIZE_ENV) {
+        Ok(v) if v == "1" || v == "all" => HandleKind::ALL.to_vec(),
+        Ok(v) => v
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| {
+                let kind = HandleKind::parse(s);
+                if kind.is_none() {
+                    warn!("Ignoring unknown handle kind in {}: {}", VIRTUALIZE_ENV, s);
+                }
+                kind
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    AtomicU8::new(mask(&kinds))
+});
+
+/// Whether any virtual value was handed out; until then inputs need no lookup.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+static TABLE: Lazy<RwLock<Table>> = Lazy::new(Default::default);
+
+fn bit(kind: HandleKind) -> u8 {
+    1 << kind as u8
+}
+
+fn mask(kinds: &[HandleKind]) -> u8 {
+    kinds.iter().fold(0, |mask, kind| mask | bit(*kind))
+}
+
+/// Virtualizes the handles of `kinds` from now on. Handles already handed out stay valid.
+pub fn set_kinds(kinds: &[HandleKind]) {
+    KINDS.store(mask(kinds), Ordering::Relaxed);
+}
+
+/// Returns whether handles of `kind` are virtualized.
+pub fn virtualizes(kind: HandleKind) -> bool {
+    KINDS.load(Ordering::Relaxed) & bit(kind) != 0
+}
+
+pub fn enabled() -> bool {
+    KINDS.load(Ordering::Relaxed) != 0
+}
+
+/// Returns whether `bits` is a virtual handle or device address.
+pub fn is_virtual(bits: u64) -> bool {
+    bits & TAG_MASK == TAG
+}
+
+/// A value that may hold a handle: a pointer, or a `CUdeviceptr`.
+pub trait Handle: Copy {
+    fn to_bits(self) -> u64;
+    fn from_bits(bits: u64) -> Self;
+}
+
+impl<T> Handle for *mut T {
+    fn to_bits(self) -> u64 {
+        self as u64
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        bits as Self
+    }
+}
+
+impl<T> Handle for *const T {
+    fn to_bits(self) -> u64 {
+        self as u64
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        bits as Self
+    }
+}
+
+impl Handle for u64 {
+    fn to_bits(self) -> u64 {
+        self
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        bits
+    }
+}
+
+#[derive(Default)]
+struct Table {
+    /// Virtual to real opaque handles.
+    real: HashMap<u64, u64>,
+    /// Real to virtual opaque handles.
+    virtual_: HashMap<(HandleKind, u64), u64>,
+    next: u64,
+    /// Virtual base to real base and size.
+    ranges: BTreeMap<u64, (u64, usize)>,
+    /// Real base to virtual base and size.
+    real_ranges: BTreeMap<u64, (u64, usize)>,
+    next_range: u64,
+}
+
+/// Finds the range of `map` containing `addr`, or ending at it, and maps `addr` to the same
+/// offset in the range it is paired with.
+fn in_range(map: &BTreeMap<u64, (u64, usize)>, addr: u64) -> Option<u64> {
+    let (&base, &(other, size)) = map.range(..=addr).next_back()?;
+    let offset = addr - base;
+    (offset <= size as u64).then_some(other + offset)
+}
+
+/// Returns the real handle or device address behind `h`, or `h` if it is not virtual.
+pub fn to_real<H: Handle>(h: H) -> H {
+    let bits = h.to_bits();
+    if !ACTIVE.load(Ordering::Relaxed) || !is_virtual(bits) {
+        return h;
+    }
+    let table = TABLE.read().unwrap();
+    let real = if bits & OPAQUE != 0 {
+        table.real.get(&bits).copied()
+    } else {
+        in_range(&table.ranges, bits)
+    };
+    match real {
+        Some(real) => H::from_bits(real),
+        None => {
+            warn!("Passing unknown virtual handle {:#x} to the driver", bits);
+            h
+        }
+    }
+}
+
+/// Returns the virtual handle for the real handle `real` of `kind`, creating one the first
+/// time. Device addresses outside the allocations seen by [`register_allocation`] and handles of
+/// kinds not virtualized are returned as they are.
+pub fn to_virtual<H: Handle>(kind: HandleKind, real: H) -> H {
+    let bits = real.to_bits();
+    if bits == 0 || is_virtual(bits) || !virtualizes(kind) {
+        return real;
+    }
+    if kind == HandleKind::DevicePtr {
+        let table = TABLE.read().unwrap();
+        return H::from_bits(in_range(&table.real_ranges, bits).unwrap_or(bits));
+    }
+    if let Some(&v) = TABLE.read().unwrap().virtual_.get(&(kind, bits)) {
+        return H::from_bits(v);
+    }
+    let mut table = TABLE.write().unwrap();
+    let table = &mut *table;
+    let v = *table.virtual_.entry((kind, bits)).or_insert_with(|| {
+        table.next += 1;
+        TAG | OPAQUE | (kind as u64) << 40 | table.next
+    });
+    table.real.insert(v, bits);
+    ACTIVE.store(true, Ordering::Relaxed);
+    H::from_bits(v)
+}
+
+/// Gives the allocation of `size` bytes at `real` a virtual range, and returns its base.
+/// Returns `real` if device pointers are not virtualized.
+pub fn register_allocation(real: CUdeviceptr, size: usize) -> CUdeviceptr {
+    if real == 0 || is_virtual(real) || !virtualizes(HandleKind::DevicePtr) {
+        return real;
+    }
+    let mut table = TABLE.write().unwrap();
+    if let Some(&(v, _)) = table.real_ranges.get(&real) {
+        return v;
+    }
+    let base = table.next_range.next_multiple_of(RANGE_ALIGN) + real % RANGE_ALIGN;
+    // Leave a gap, so that the end of a range is not the start of the next.
+    table.next_range = base + size as u64 + 1;
+    let v = TAG | base;
+    table.ranges.insert(v, (real, size));
+    table.real_ranges.insert(real, (v, size));
+    ACTIVE.store(true, Ordering::Relaxed);
+    v
+}
+
+/// Returns the virtual handle or device address the application was given for `real`, without
+/// creating one, or `real` if it has none.
+pub fn virtual_of<H: Handle>(kind: HandleKind, real: H) -> H {
+    let bits = real.to_bits();
+    if !ACTIVE.load(Ordering::Relaxed) || bits == 0 {
+        return real;
+    }
+    let table = TABLE.read().unwrap();
+    let v = match kind {
+        HandleKind::DevicePtr => in_range(&table.real_ranges, bits),
+        _ => table.virtual_.get(&(kind, bits)).copied(),
+    };
+    H::from_bits(v.unwrap_or(bits))
+}
+
+/// Points the virtual handle `virtual_` of `kind` at the real handle `real`, as when the driver
+/// object behind it was recreated. For device addresses `virtual_` is the base of a range of
+/// `size` bytes; `size` is ignored otherwise. Virtual handles handed out later do not collide
+/// with `virtual_`.
+pub fn rebind(kind: HandleKind, virtual_: u64, real: u64, size: usize) {
+    if !is_virtual(virtual_) {
+        return;
+    }
+    let mut table = TABLE.write().unwrap();
+    if kind == HandleKind::DevicePtr {
+        if let Some((old, _)) = table.ranges.insert(virtual_, (real, size)) {
+            table.real_ranges.remove(&old);
+        }
+        table.real_ranges.insert(real, (virtual_, size));
+        let end = (virtual_ & !TAG_MASK) + size as u64 + 1;
+        table.next_range = table.next_range.max(end);
+    } else {
+        if let Some(old) = table.real.insert(virtual_, real) {
+            table.virtual_.remove(&(kind, old));
+        }
+        table.virtual_.insert((kind, real), virtual_);
+        table.next = table.next.max(virtual_ & ((1 << 40) - 1));
+    }
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Forgets the virtual handle of the real handle `real` of `kind`, or the virtual range of the
+/// allocation at `real`.
+pub fn forget<H: Handle>(kind: HandleKind, real: H) {
+    let bits = real.to_bits();
+    if !ACTIVE.load(Ordering::Relaxed) || bits == 0 {
+        return;
+    }
+    let mut table = TABLE.write().unwrap();
+    if kind == HandleKind::DevicePtr {
+        if let Some((v, _)) = table.real_ranges.remove(&bits) {
+            table.ranges.remove(&v);
+        }
+    } else if let Some(v) = table.virtual_.remove(&(kind, bits)) {
+        table.real.remove(&v);
+    }
+}
+
+/// Returns how many virtual handles of `kind` are live.
+pub fn live(kind: HandleKind) -> usize {
+    let table = TABLE.read().unwrap();
+    match kind {
+        HandleKind::DevicePtr => table.ranges.len(),
+        _ => table.virtual_.keys().filter(|(k, _)| *k == kind).count(),
+    }
+}
+
+/// Returns whether a call returning `rc` succeeded: a status of 0, or any value that is not a
+/// 32-bit status.
+pub fn succeeded<R: Copy>(rc: &R) -> bool {
+    std::mem::size_of::<R>() != std::mem::size_of::<u32>()
+        || unsafe { std::mem::transmute_copy::<R, u32>(rc) } == 0
+}
+
+/// Replaces the real handle a successful call wrote to `out` with its virtual handle. Used by
+/// the built-in hook sets and [`generate_proxy!`].
+///
+/// # Safety
+/// `out` must be null or valid for reads and writes.
+pub unsafe fn export<R: Copy, H: Handle>(rc: &R, kind: HandleKind, out: *mut H) {
+    if succeeded(rc) && !out.is_null() && virtualizes(kind) {
+        unsafe { *out = to_virtual(kind, *out) };
+    }
+}
+
+/// Forgets the virtual handle of `real` once a call destroying it succeeded. Used by the
+/// built-in hook sets and [`generate_proxy!`].
+pub fn release<R: Copy, H: Handle>(rc: &R, kind: HandleKind, real: H) {
+    if succeeded(rc) {
+        forget(kind, real);
+    }
+}
+
+/// A hook argument, translated by [`cuda_hook!`] and [`generate_proxy!`] whatever its type:
+/// `Arg(x).real()` resolves to [`TranslateHandle`] for pointers and `CUdeviceptr`s and to
+/// [`TranslateValue`], which returns the argument as it is, for anything else.
+#[doc(hidden)]
+pub struct Arg<T>(pub T);
+
+#[doc(hidden)]
+pub trait TranslateHandle {
+    type Real;
+    fn real(self) -> Self::Real;
+}
+
+impl<H: Handle> TranslateHandle for Arg<H> {
+    type Real = H;
+    fn real(self) -> H {
+        to_real(self.0)
+    }
+}
+
+#[doc(hidden)]
+pub trait TranslateValue {
+    type Real;
+    fn real(self) -> Self::Real;
+}
+
+impl<T: Copy> TranslateValue for &Arg<T> {
+    type Real = T;
+    fn real(self) -> T {
+        self.0
+    }
+}
+
+/// The arguments of a kernel launch with the virtual device pointers they hold replaced by
+/// real ones. Arguments are scanned in 8-byte words, so pointers inside structures passed by
+/// value are translated too.
+pub struct RealParams {
+    kernel_params: *mut *mut c_void,
+    extra: *mut *mut c_void,
+    _args: Vec<Vec<u8>>,
+    params: Vec<*mut c_void>,
+    _buffer: Vec<u8>,
+    _size: Box<usize>,
+    extra_array: Vec<*mut c_void>,
+}
+
+/// Replaces the tagged 8-byte words of `bytes`, returning whether any were.
+fn translate_words(bytes: &mut [u8]) -> bool {
+    let mut changed = false;
+    for word in bytes.chunks_exact_mut(8) {
+        let value = u64::from_ne_bytes(word.try_into().unwrap());
+        if is_virtual(value) {
+            word.copy_from_slice(&to_real(value).to_ne_bytes());
+            changed = true;
+        }
+    }
+    changed
+}
+
+impl RealParams {
+    /// Translates the arguments of a launch of `func`. Arguments passed through
+    /// `kernel_params` need the kernel's parameter layout (see [`crate::args`]); without one the
+    /// launch cannot be translated, and this fails with `CUDA_ERROR_NOT_SUPPORTED`.
+    ///
+    /// # Safety
+    /// `kernel_params` and `extra` must be as passed to the launch of `func`.
+    pub unsafe fn new(
+        func: CUfunction,
+        kernel_params: *mut *mut c_void,
+        extra: *mut *mut c_void,
+    ) -> Result<Self, CUresult> {
+        let mut real = Self {
+            kernel_params,
+            extra,
+            _args: Vec::new(),
+            params: Vec::new(),
+            _buffer: Vec::new(),
+            _size: Box::new(0),
+            extra_array: Vec::new(),
+        };
+        if !ACTIVE.load(Ordering::Relaxed) || !virtualizes(HandleKind::DevicePtr) {
+            return Ok(real);
+        }
+        if !kernel_params.is_null() {
+            let kernel = modules::kernel_name(func);
+            let Some(layout) = args::layout(func, kernel.as_deref()) else {
+                warn!(
+                    "Failing the launch of {}: its parameter layout is unknown, so the device \
+                     pointers in its arguments cannot be translated. Use CUDA 12.4 or newer, or \
+                     install the capture hooks.",
+                    kernel
+                        .as_ref()
+                        .map_or("an unnamed kernel", |k| k.demangled.as_str())
+                );
+                return Err(CUDA_ERROR_NOT_SUPPORTED);
+            };
+            let mut changed = false;
+            let mut args: Vec<Vec<u8>> = layout
+                .params
+                .iter()
+                .enumerate()
+                .map(|(i, param)| {
+                    let ptr = unsafe { *kernel_params.add(i) } as *const u8;
+                    if ptr.is_null() {
+                        return Vec::new();
+                    }
+                    let size = param.size as usize;
+                    let mut bytes = unsafe { std::slice::from_raw_parts(ptr, size) }.to_vec();
+                    changed |= translate_words(&mut bytes);
+                    bytes
+                })
+                .collect();
+            if changed {
+                real.params = args
+                    .iter_mut()
+                    .zip(0..)
+                    .map(|(bytes, i)| match bytes.is_empty() {
+                        true => unsafe { *kernel_params.add(i) },
+                        false => bytes.as_mut_ptr() as *mut c_void,
+                    })
+                    .collect();
+                real.kernel_params = real.params.as_mut_ptr();
+                real._args = args;
+            }
+        } else if let Some(buffer) = unsafe { args::extra_buffer(extra) } {
+            let mut buffer = buffer.to_vec();
+            if translate_words(&mut buffer) {
+                real._size = Box::new(buffer.len());
+                real.extra_array = vec![
+                    args::CU_LAUNCH_PARAM_BUFFER_POINTER as *mut c_void,
+                    buffer.as_mut_ptr() as *mut c_void,
+                    args::CU_LAUNCH_PARAM_BUFFER_SIZE as *mut c_void,
+                    &*real._size as *const usize as *mut c_void,
+                    args::CU_LAUNCH_PARAM_END as *mut c_void,
+                ];
+                real.extra = real.extra_array.as_mut_ptr();
+                real._buffer = buffer;
+            }
+        }
+        Ok(real)
+    }
+
+    /// Returns the `kernel_params` and `extra` to launch with.
+    pub fn pointers(&self) -> (*mut *mut c_void, *mut *mut c_void) {
+        (self.kernel_params, self.extra)
+    }
+}
+
+/// Returns a copy of a `cuLaunchKernelEx` configuration with its stream translated, if it is
+/// virtual.
+///
+/// # Safety
+/// `config` must be null or valid for reads.
+pub unsafe fn real_config(config: *const CUlaunchConfig) -> Option<CUlaunchConfig> {
+    if config.is_null() || !ACTIVE.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut real = unsafe { *config };
+    if !is_virtual(real.hStream as u64) {
+        return None;
+    }
+    real.hStream = to_real(real.hStream);
+    Some(real)
+}
+
+/// A structure passed by pointer that holds handles or device pointers.
+pub trait RealFields: Copy {
+    /// Translates the handles and device pointers `self` holds, returning the translated kernel
+    /// arguments it now points to, if it has any.
+    ///
+    /// # Safety
+    /// The pointers in `self` must be valid as the driver requires them to be.
+    unsafe fn to_real(&mut self) -> Result<Option<RealParams>, CUresult>;
+
+    /// Returns whether `self` holds anything to translate. Structures that do not are passed to
+    /// the driver as they are, so it can write its outputs to them.
+    fn holds_handles(&self) -> bool {
+        true
+    }
+}
+
+impl RealFields for CUDA_MEMCPY2D {
+    unsafe fn to_real(&mut self) -> Result<Option<RealParams>, CUresult> {
+        self.srcHost = to_real(self.srcHost);
+        self.srcDevice = to_real(self.srcDevice);
+        self.dstHost = to_real(self.dstHost);
+        self.dstDevice = to_real(self.dstDevice);
+        Ok(None)
+    }
+}
+
+impl RealFields for CUDA_MEMCPY3D {
+    unsafe fn to_real(&mut self) -> Result<Option<RealParams>, CUresult> {
+        self.srcHost = to_real(self.srcHost);
+        self.srcDevice = to_real(self.srcDevice);
+        self.dstHost = to_real(self.dstHost);
+        self.dstDevice = to_real(self.dstDevice);
+        Ok(None)
+    }
+}
+
+impl RealFields for CUDA_MEMSET_NODE_PARAMS {
+    unsafe fn to_real(&mut self) -> Result<Option<RealParams>, CUresult> {
+        self.dst = to_real(self.dst);
+        Ok(None)
+    }
+}
+
+impl RealFields for CUDA_KERNEL_NODE_PARAMS_v2 {
+    unsafe fn to_real(&mut self) -> Result<Option<RealParams>, CUresult> {
+        self.func = to_real(self.func);
+        self.ctx = to_real(self.ctx);
+        // The driver launches `kern` when `func` is null, and describes its parameters alike.
+        let func = if self.func.is_null() {
+            self.kern as CUfunction
+        } else {
+            self.func
+        };
+        let params = unsafe { RealParams::new(func, self.kernelParams, self.extra) }?;
+        (self.kernelParams, self.extra) = params.pointers();
+        Ok(Some(params))
+    }
+}
+
+impl RealFields for CUDA_LAUNCH_PARAMS {
+    unsafe fn to_real(&mut self) -> Result<Option<RealParams>, CUresult> {
+        self.function = to_real(self.function);
+        self.hStream = to_real(self.hStream);
+        let params =
+            unsafe { RealParams::new(self.function, self.kernelParams, std::ptr::null_mut()) }?;
+        self.kernelParams = params.pointers().0;
+        Ok(Some(params))
+    }
+}
+
+impl RealFields for CUgraphNodeParams {
+    fn holds_handles(&self) -> bool {
+        matches!(
+            self.type_,
+            CU_GRAPH_NODE_TYPE_KERNEL | CU_GRAPH_NODE_TYPE_MEMCPY | CU_GRAPH_NODE_TYPE_MEMSET
+        )
+    }
+
+    unsafe fn to_real(&mut self) -> Result<Option<RealParams>, CUresult> {
+        let union = self.params.as_mut_ptr() as *mut u8;
+        unsafe {
+            match self.type_ {
+                CU_GRAPH_NODE_TYPE_KERNEL => {
+                    (*(union as *mut CUDA_KERNEL_NODE_PARAMS_v2)).to_real()
+                }
+                CU_GRAPH_NODE_TYPE_MEMCPY => {
+                    let ctx = union.add(8) as *mut CUcontext;
+                    *ctx = to_real(*ctx);
+                    (*(union.add(16) as *mut CUDA_MEMCPY3D)).to_real()
+                }
+                CU_GRAPH_NODE_TYPE_MEMSET => {
+                    let ctx = union.add(40) as *mut CUcontext;
+                    *ctx = to_real(*ctx);
+                    (*(union as *mut CUDA_MEMSET_NODE_PARAMS)).to_real()
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+}
+
+/// Structures passed by pointer, copied with their handles and device pointers translated.
+/// Owns the translated kernel arguments they point to.
+pub struct RealStructs<T> {
+    original: *const T,
+    copies: Vec<T>,
+    _params: Vec<RealParams>,
+}
+
+impl<T: RealFields> RealStructs<T> {
+    /// Translates the `count` structures at `ptr`. Nothing is copied while no virtual handle has
+    /// been handed out.
+    ///
+    /// # Safety
+    /// `ptr` must be null or point to `count` valid structures.
+    pub unsafe fn new(ptr: *const T, count: usize) -> Result<Self, CUresult> {
+        let mut real = Self {
+            original: ptr,
+            copies: Vec::new(),
+            _params: Vec::new(),
+        };
+        if ptr.is_null() || !ACTIVE.load(Ordering::Relaxed) {
+            return Ok(real);
+        }
+        let structs = unsafe { std::slice::from_raw_parts(ptr, count) };
+        if !structs.iter().any(T::holds_handles) {
+            return Ok(real);
+        }
+        real.copies = structs.to_vec();
+        for copy in &mut real.copies {
+            real._params.extend(unsafe { copy.to_real() }?);
+        }
+        Ok(real)
+    }
+
+    /// Returns the structures to pass to the driver.
+    pub fn as_ptr(&self) -> *const T {
+        if self.copies.is_empty() {
+            self.original
+        } else {
+            self.copies.as_ptr()
+        }
+    }
+
+    /// Returns the structures to pass to the driver, for APIs that take them as `*mut`.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        if self.copies.is_empty() {
+            self.original as *mut T
+        } else {
+            self.copies.as_mut_ptr()
+        }
+    }
+}
+
+/// Translates the structure at a hook argument, returning from the hook if it cannot be. Used by
+/// [`install_handle_hooks!`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! real_structs {
+    ($ptr:expr, $count:expr) => {
+        match unsafe { $crate::handles::RealStructs::new($ptr as *const _, $count as usize) } {
+            Ok(real) => real,
+            Err(rc) => return rc,
+        }
+    };
+}
+
+/// Translates a hook argument of any type. Used by [`cuda_hook!`] and [`generate_proxy!`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! real_arg {
+    ($arg:ident) => {{
+        #[allow(unused_imports)]
+        use $crate::handles::{TranslateHandle as _, TranslateValue as _};
+        $crate::handles::Arg($arg).real()
+    }};
+}
+
+/// Installs hooks that translate the handles and device pointers held in structures passed by
+/// pointer: 2D and 3D copies, including their `_ptds`/`_ptsz` variants, multi-device cooperative
+/// launches and the parameters of kernel, memcpy and memset graph nodes. Only needed with
+/// virtualization enabled.
+///
+/// The hooked symbols are listed in [`crate::hook_sets::HOOK_SETS`], so
+/// `cuda-interposer-build` leaves them out of the generated passthroughs.
+#[macro_export]
+macro_rules! install_handle_hooks {
+    () => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy2D_v2(
+                p_copy: *const $crate::ffi::CUDA_MEMCPY2D
+            ) -> $crate::ffi::CUresult {
+                let p_copy = $crate::real_structs!(p_copy, 1);
+                unsafe { (*__real_cuMemcpy2D_v2)(p_copy.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy2D_v2_ptds(
+                p_copy: *const $crate::ffi::CUDA_MEMCPY2D
+            ) -> $crate::ffi::CUresult {
+                let p_copy = $crate::real_structs!(p_copy, 1);
+                unsafe { (*__real_cuMemcpy2D_v2_ptds)(p_copy.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy2DUnaligned_v2(
+                p_copy: *const $crate::ffi::CUDA_MEMCPY2D
+            ) -> $crate::ffi::CUresult {
+                let p_copy = $crate::real_structs!(p_copy, 1);
+                unsafe { (*__real_cuMemcpy2DUnaligned_v2)(p_copy.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy2DUnaligned_v2_ptds(
+                p_copy: *const $crate::ffi::CUDA_MEMCPY2D
+            ) -> $crate::ffi::CUresult {
+                let p_copy = $crate::real_structs!(p_copy, 1);
+                unsafe { (*__real_cuMemcpy2DUnaligned_v2_ptds)(p_copy.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy2DAsync_v2(
+                p_copy: *const $crate::ffi::CUDA_MEMCPY2D,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let p_copy = $crate::real_structs!(p_copy, 1);
+                unsafe { (*__real_cuMemcpy2DAsync_v2)(p_copy.as_ptr(), h_stream) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy2DAsync_v2_ptsz(
+                p_copy: *const $crate::ffi::CUDA_MEMCPY2D,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let p_copy = $crate::real_structs!(p_copy, 1);
+                unsafe { (*__real_cuMemcpy2DAsync_v2_ptsz)(p_copy.as_ptr(), h_stream) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy3D_v2(
+                p_copy: *const $crate::ffi::CUDA_MEMCPY3D
+            ) -> $crate::ffi::CUresult {
+                let p_copy = $crate::real_structs!(p_copy, 1);
+                unsafe { (*__real_cuMemcpy3D_v2)(p_copy.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy3D_v2_ptds(
+                p_copy: *const $crate::ffi::CUDA_MEMCPY3D
+            ) -> $crate::ffi::CUresult {
+                let p_copy = $crate::real_structs!(p_copy, 1);
+                unsafe { (*__real_cuMemcpy3D_v2_ptds)(p_copy.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy3DAsync_v2(
+                p_copy: *const $crate::ffi::CUDA_MEMCPY3D,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let p_copy = $crate::real_structs!(p_copy, 1);
+                unsafe { (*__real_cuMemcpy3DAsync_v2)(p_copy.as_ptr(), h_stream) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy3DAsync_v2_ptsz(
+                p_copy: *const $crate::ffi::CUDA_MEMCPY3D,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let p_copy = $crate::real_structs!(p_copy, 1);
+                unsafe { (*__real_cuMemcpy3DAsync_v2_ptsz)(p_copy.as_ptr(), h_stream) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuLaunchCooperativeKernelMultiDevice(
+                launch_params_list: *mut $crate::ffi::CUDA_LAUNCH_PARAMS,
+                num_devices: u32,
+                flags: u32
+            ) -> $crate::ffi::CUresult {
+                let mut launch_params_list = $crate::real_structs!(launch_params_list, num_devices);
+                unsafe { (*__real_cuLaunchCooperativeKernelMultiDevice)(launch_params_list.as_mut_ptr(), num_devices, flags) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphAddKernelNode_v2(
+                ph_graph_node: *mut $crate::ffi::CUgraphNode,
+                h_graph: $crate::ffi::CUgraph,
+                dependencies: *const $crate::ffi::CUgraphNode,
+                num_dependencies: usize,
+                node_params: *const $crate::ffi::CUDA_KERNEL_NODE_PARAMS_v2
+            ) -> $crate::ffi::CUresult {
+                let node_params = $crate::real_structs!(node_params, 1);
+                unsafe { (*__real_cuGraphAddKernelNode_v2)(ph_graph_node, h_graph, dependencies, num_dependencies, node_params.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphKernelNodeSetParams_v2(
+                h_node: $crate::ffi::CUgraphNode,
+                node_params: *const $crate::ffi::CUDA_KERNEL_NODE_PARAMS_v2
+            ) -> $crate::ffi::CUresult {
+                let node_params = $crate::real_structs!(node_params, 1);
+                unsafe { (*__real_cuGraphKernelNodeSetParams_v2)(h_node, node_params.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphExecKernelNodeSetParams_v2(
+                h_graph_exec: $crate::ffi::CUgraphExec,
+                h_node: $crate::ffi::CUgraphNode,
+                node_params: *const $crate::ffi::CUDA_KERNEL_NODE_PARAMS_v2
+            ) -> $crate::ffi::CUresult {
+                let node_params = $crate::real_structs!(node_params, 1);
+                unsafe { (*__real_cuGraphExecKernelNodeSetParams_v2)(h_graph_exec, h_node, node_params.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphAddMemcpyNode(
+                ph_graph_node: *mut $crate::ffi::CUgraphNode,
+                h_graph: $crate::ffi::CUgraph,
+                dependencies: *const $crate::ffi::CUgraphNode,
+                num_dependencies: usize,
+                copy_params: *const $crate::ffi::CUDA_MEMCPY3D,
+                ctx: $crate::ffi::CUcontext
+            ) -> $crate::ffi::CUresult {
+                let copy_params = $crate::real_structs!(copy_params, 1);
+                unsafe { (*__real_cuGraphAddMemcpyNode)(ph_graph_node, h_graph, dependencies, num_dependencies, copy_params.as_ptr(), ctx) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphMemcpyNodeSetParams(
+                h_node: $crate::ffi::CUgraphNode,
+                node_params: *const $crate::ffi::CUDA_MEMCPY3D
+            ) -> $crate::ffi::CUresult {
+                let node_params = $crate::real_structs!(node_params, 1);
+                unsafe { (*__real_cuGraphMemcpyNodeSetParams)(h_node, node_params.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphExecMemcpyNodeSetParams(
+                h_graph_exec: $crate::ffi::CUgraphExec,
+                h_node: $crate::ffi::CUgraphNode,
+                copy_params: *const $crate::ffi::CUDA_MEMCPY3D,
+                ctx: $crate::ffi::CUcontext
+            ) -> $crate::ffi::CUresult {
+                let copy_params = $crate::real_structs!(copy_params, 1);
+                unsafe { (*__real_cuGraphExecMemcpyNodeSetParams)(h_graph_exec, h_node, copy_params.as_ptr(), ctx) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphAddMemsetNode(
+                ph_graph_node: *mut $crate::ffi::CUgraphNode,
+                h_graph: $crate::ffi::CUgraph,
+                dependencies: *const $crate::ffi::CUgraphNode,
+                num_dependencies: usize,
+                memset_params: *const $crate::ffi::CUDA_MEMSET_NODE_PARAMS,
+                ctx: $crate::ffi::CUcontext
+            ) -> $crate::ffi::CUresult {
+                let memset_params = $crate::real_structs!(memset_params, 1);
+                unsafe { (*__real_cuGraphAddMemsetNode)(ph_graph_node, h_graph, dependencies, num_dependencies, memset_params.as_ptr(), ctx) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphMemsetNodeSetParams(
+                h_node: $crate::ffi::CUgraphNode,
+                node_params: *const $crate::ffi::CUDA_MEMSET_NODE_PARAMS
+            ) -> $crate::ffi::CUresult {
+                let node_params = $crate::real_structs!(node_params, 1);
+                unsafe { (*__real_cuGraphMemsetNodeSetParams)(h_node, node_params.as_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphExecMemsetNodeSetParams(
+                h_graph_exec: $crate::ffi::CUgraphExec,
+                h_node: $crate::ffi::CUgraphNode,
+                memset_params: *const $crate::ffi::CUDA_MEMSET_NODE_PARAMS,
+                ctx: $crate::ffi::CUcontext
+            ) -> $crate::ffi::CUresult {
+                let memset_params = $crate::real_structs!(memset_params, 1);
+                unsafe { (*__real_cuGraphExecMemsetNodeSetParams)(h_graph_exec, h_node, memset_params.as_ptr(), ctx) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphAddNode(
+                ph_graph_node: *mut $crate::ffi::CUgraphNode,
+                h_graph: $crate::ffi::CUgraph,
+                dependencies: *const $crate::ffi::CUgraphNode,
+                num_dependencies: usize,
+                node_params: *mut $crate::ffi::CUgraphNodeParams
+            ) -> $crate::ffi::CUresult {
+                let mut node_params = $crate::real_structs!(node_params, 1);
+                unsafe { (*__real_cuGraphAddNode)(ph_graph_node, h_graph, dependencies, num_dependencies, node_params.as_mut_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphAddNode_v2(
+                ph_graph_node: *mut $crate::ffi::CUgraphNode,
+                h_graph: $crate::ffi::CUgraph,
+                dependencies: *const $crate::ffi::CUgraphNode,
+                dependency_data: *const $crate::libc::c_void,
+                num_dependencies: usize,
+                node_params: *mut $crate::ffi::CUgraphNodeParams
+            ) -> $crate::ffi::CUresult {
+                let mut node_params = $crate::real_structs!(node_params, 1);
+                unsafe { (*__real_cuGraphAddNode_v2)(ph_graph_node, h_graph, dependencies, dependency_data, num_dependencies, node_params.as_mut_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphNodeSetParams(
+                h_node: $crate::ffi::CUgraphNode,
+                node_params: *mut $crate::ffi::CUgraphNodeParams
+            ) -> $crate::ffi::CUresult {
+                let mut node_params = $crate::real_structs!(node_params, 1);
+                unsafe { (*__real_cuGraphNodeSetParams)(h_node, node_params.as_mut_ptr()) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuGraphExecNodeSetParams(
+                h_graph_exec: $crate::ffi::CUgraphExec,
+                h_node: $crate::ffi::CUgraphNode,
+                node_params: *mut $crate::ffi::CUgraphNodeParams
+            ) -> $crate::ffi::CUresult {
+                let mut node_params = $crate::real_structs!(node_params, 1);
+                unsafe { (*__real_cuGraphExecNodeSetParams)(h_graph_exec, h_node, node_params.as_mut_ptr()) }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake;
+
+    #[test]
+    fn translates_pointers_held_in_structures() {
+        let _guard = fake::lock();
+        set_kinds(&[HandleKind::DevicePtr]);
+        let real = 0x7f00_0020_1000;
+        let virtual_ = register_allocation(real, 1 << 20);
+        assert!(is_virtual(virtual_));
+
+        let mut copy: CUDA_MEMCPY2D = unsafe { std::mem::zeroed() };
+        copy.srcDevice = virtual_ + 64;
+        copy.dstHost = 0x1234 as *mut c_void;
+        let structs = unsafe { RealStructs::new(&copy, 1) }.unwrap();
+        let translated = unsafe { *structs.as_ptr() };
+        assert_eq!(translated.srcDevice, real + 64);
+        assert_eq!(translated.dstHost, copy.dstHost);
+
+        let mut node: CUgraphNodeParams = unsafe { std::mem::zeroed() };
+        node.type_ = CU_GRAPH_NODE_TYPE_MEMSET;
+        node.params[0] = virtual_;
+        let structs = unsafe { RealStructs::new(&node, 1) }.unwrap();
+        assert_eq!(unsafe { (*structs.as_ptr()).params[0] }, real);
+
+        // Nodes without handles reach the driver as they are, so it can write to them
+        node.type_ = CU_GRAPH_NODE_TYPE_HOST;
+        let structs = unsafe { RealStructs::new(&node, 1) }.unwrap();
+        assert_eq!(structs.as_ptr(), &node as *const _);
+        set_kinds(&[]);
+    }
+}
@@ -0,0 +1,312 @@
+//! Latency injection.
+//!
+//! Delays chosen API calls before they reach the driver, to emulate a slow `cuInit`, slow module
+//! JIT or long synchronizations, and so exercise timeout and watchdog logic without a contended
+//! GPU. Each [`Rule`] names the symbols it applies to with a glob (see [`crate::pattern`]) and a
+//! [`Delay`], fixed or drawn from a distribution; the first matching rule applies.
+//!
+//! Rules are read from `CUDA_HOOK_LATENCY`, as `;`-separated `symbol=delay` pairs:
+//!
+//! ```text
+//! CUDA_HOOK_LATENCY='cuInit=2s;
+//!                    cuModuleLoad*=uniform(100ms,500ms);
+//!                    cuStreamSynchronize=normal(50ms,10ms)'
+//! ```
+//!
+//! A delay is a duration (`250ms`), `uniform(min,max)`, `normal(mean,stddev)` or `exp(mean)`.
+//! Draws are seeded by `CUDA_HOOK_LATENCY_SEED` (0 by default), so the same sequence of calls
+//! sleeps the same amounts in every run.
+//!
+//! Like [`crate::faults`], rules apply to every [`cuda_hook!`] and to the passthroughs generated
+//! by `cuda-interposer-build`.
+
+use crate::config::{ConfigError, parse_duration};
+use crate::faults;
+use crate::pattern::glob_match;
+use once_cell::sync::Lazy;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::warn;
+
+const LATENCY_ENV: &str = "CUDA_HOOK_LATENCY";
+const SEED_ENV: &str = "CUDA_HOOK_LATENCY_SEED";
+
+static ENABLED: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(ARMED.read().unwrap().is_some()));
+
+static ARMED: Lazy<RwLock<Option<Armed>>> = Lazy::new(|| {
+    let rules = match std::env::var(LATENCY_ENV) {
+        Ok(spec) => parse_rules(&spec)
+            .map_err(|e| warn!("Ignoring latency rules: {}", e))
+            .ok(),
+        Err(_) => None,
+    };
+    let seed = match std::env::var(SEED_ENV).map(|s| s.trim().parse()) {
+        Ok(Ok(seed)) => seed,
+        Ok(Err(_)) => {
+            warn!("Ignoring {}: not a number", SEED_ENV);
+            0
+        }
+        Err(_) => 0,
+    };
+    RwLock::new(rules.map(|rules| Armed::new(rules, seed)))
+});
+
+/// How long to delay a call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delay {
+    Fixed(Duration),
+    Uniform(Duration, Duration),
+    /// Normally distributed around a mean, with a standard deviation. Negative draws are 0.
+    Normal(Duration, Duration),
+    /// Exponentially distributed with a mean, as for independent arrivals.
+    Exponential(Duration),
+}
+
+impl Delay {
+    /// Parses `250ms`, `uniform(min,max)`, `normal(mean,stddev)` or `exp(mean)`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let Some((dist, params)) = s.strip_suffix(')').and_then(|s| s.split_once('(')) else {
+            return parse_duration(s).map(Delay::Fixed);
+        };
+        let params: Vec<_> = params
+            .split(',')
+            .map(parse_duration)
+            .collect::<Option<_>>()?;
+        match (dist.trim(), params.as_slice()) {
+            ("uniform", &[min, max]) if min <= max => Some(Delay::Uniform(min, max)),
+            ("normal", &[mean, stddev]) => Some(Delay::Normal(mean, stddev)),
+            ("exp", &[mean]) => Some(Delay::Exponential(mean)),
+            _ => None,
+        }
+    }
+
+    /// Returns a delay for two independent draws in `[0, 1)`.
+    fn sample(self, u1: f64, u2: f64) -> Duration {
+        match self {
+            Delay::Fixed(d) => d,
+            Delay::Uniform(min, max) => min + (max - min).mul_f64(u1),
+            Delay::Normal(mean, stddev) => {
+                // Box-Muller.
+                let z = (-2.0 * (1.0 - u1).ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                let secs = mean.as_secs_f64() + z * stddev.as_secs_f64();
+                Duration::from_secs_f64(secs.max(0.0))
+            }
+            Delay::Exponential(mean) => mean.mul_f64(-(1.0 - u1).ln()),
+        }
+    }
+}
+
+/// Delays the calls to `symbol`, a glob over symbol names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub symbol: String,
+    pub delay: Delay,
+}
+
+/// Parses a `CUDA_HOOK_LATENCY` specification.
+pub fn parse_rules(spec: &str) -> Result<Vec<Rule>, ConfigError> {
+    spec.split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|item| {
+            let err = |reason| ConfigError::new(LATENCY_ENV, item, reason);
+            let (symbol, delay) = item
+                .split_once('=')
+                .ok_or_else(|| err("expected symbol=delay"))?;
+            Ok(Rule {
+                symbol: symbol.trim().to_string(),
+                delay: Delay::parse(delay).ok_or_else(|| err("bad delay"))?,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Armed {
+    seed: u64,
+    rules: Vec<(Rule, AtomicU64)>,
+}
+
+impl Armed {
+    fn new(rules: Vec<Rule>, seed: u64) -> Self {
+        Self {
+            seed,
+            rules: rules.into_iter().map(|r| (r, AtomicU64::new(0))).collect(),
+        }
+    }
+}
+
+/// Replaces the active rules, drawing delays from `seed`. `None` disables injection.
+pub fn set_rules(rules: Option<Vec<Rule>>, seed: u64) {
+    let armed = rules.map(|rules| Armed::new(rules, seed));
+    ENABLED.store(armed.is_some(), Ordering::Relaxed);
+    *ARMED.write().unwrap() = armed;
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns how long a call to `symbol` should be delayed, if a rule matches it.
+pub fn delay_for(symbol: &str) -> Option<Duration> {
+    if !enabled() {
+        return None;
+    }
+    let armed = ARMED.read().unwrap();
+    let armed = armed.as_ref()?;
+    let (i, (rule, calls)) = armed
+        .rules
+        .iter()
+        .enumerate()
+        .find(|(_, (rule, _))| glob_match(&rule.symbol, symbol))?;
+    let call = calls.fetch_add(1, Ordering::Relaxed) + 1;
+    // Two draws per call; odd and even call numbers keep them apart.
+    let u1 = faults::draw(armed.seed, i, 2 * call);
+    let u2 = faults::draw(armed.seed, i, 2 * call + 1);
+    Some(rule.delay.sample(u1, u2))
+}
+
+/// Sleeps for the delay of a call to `symbol`, if any. Used by [`cuda_hook!`] and
+/// [`generate_proxy!`].
+pub fn delay(symbol: &str) {
+    if let Some(d) = delay_for(symbol) {
+        std::thread::sleep(d);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Serializes the tests that arm rules, which are process-wide.
+    fn lock() -> MutexGuard<'static, ()> {
+        static LOCK: Mutex<()> = Mutex::new(());
+        LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn parses_delays() {
+        assert_eq!(Delay::parse(" 250ms "), Some(Delay::Fixed(ms(250))));
+        assert_eq!(
+            Delay::parse("2"),
+            Some(Delay::Fixed(Duration::from_secs(2)))
+        );
+        assert_eq!(
+            Delay::parse("uniform(100ms, 500ms)"),
+            Some(Delay::Uniform(ms(100), ms(500)))
+        );
+        assert_eq!(
+            Delay::parse("uniform(1s,1s)"),
+            Some(Delay::Uniform(
+                Duration::from_secs(1),
+                Duration::from_secs(1)
+            ))
+        );
+        assert_eq!(
+            Delay::parse("normal(50ms,10ms)"),
+            Some(Delay::Normal(ms(50), ms(10)))
+        );
+        assert_eq!(Delay::parse("exp(1ms)"), Some(Delay::Exponential(ms(1))));
+
+        for bad in [
+            "uniform(500ms,100ms)",
+            "uniform(100ms)",
+            "normal(50ms)",
+            "exp(1ms,2ms)",
+            "poisson(1ms)",
+            "exp(soon)",
+            "uniform(1ms,2ms",
+            "",
+        ] {
+            assert_eq!(Delay::parse(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn parses_rules() {
+        // The example from the module documentation
+        let rules = parse_rules(
+            "cuInit=2s;
+             cuModuleLoad*=uniform(100ms,500ms);
+             cuStreamSynchronize=normal(50ms,10ms)",
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            [
+                Rule {
+                    symbol: "cuInit".into(),
+                    delay: Delay::Fixed(Duration::from_secs(2)),
+                },
+                Rule {
+                    symbol: "cuModuleLoad*".into(),
+                    delay: Delay::Uniform(ms(100), ms(500)),
+                },
+                Rule {
+                    symbol: "cuStreamSynchronize".into(),
+                    delay: Delay::Normal(ms(50), ms(10)),
+                },
+            ]
+        );
+        assert_eq!(parse_rules(" ; ").unwrap(), []);
+        assert_eq!(
+            parse_rules("cuInit=1s;cuCtxSynchronize")
+                .unwrap_err()
+                .reason,
+            "expected symbol=delay"
+        );
+        assert_eq!(
+            parse_rules("cuInit=uniform(2s,1s)").unwrap_err().reason,
+            "bad delay"
+        );
+    }
+
+    #[test]
+    fn applies_the_first_matching_rule() {
+        let _lock = lock();
+        set_rules(
+            Some(parse_rules("cuTestMemcpy=3ms;cuTestMem*=2ms;cuTest*=1ms").unwrap()),
+            0,
+        );
+        assert_eq!(delay_for("cuTestMemcpy"), Some(ms(3)));
+        assert_eq!(delay_for("cuTestMemset"), Some(ms(2)));
+        assert_eq!(delay_for("cuTestInit"), Some(ms(1)));
+        assert_eq!(delay_for("cuInit"), None);
+        set_rules(None, 0);
+        assert_eq!(delay_for("cuTestMemcpy"), None);
+    }
+
+    #[test]
+    fn draws_the_same_delays_for_the_same_seed() {
+        let _lock = lock();
+        let run = |seed| {
+            let rules = parse_rules("cuTestUniform=uniform(0ms,100ms);cuTestExp=exp(10ms)");
+            set_rules(Some(rules.unwrap()), seed);
+            let delays: Vec<_> = (0..50)
+                .flat_map(|_| [delay_for("cuTestUniform"), delay_for("cuTestExp")])
+                .map(Option::unwrap)
+                .collect();
+            set_rules(None, 0);
+            delays
+        };
+        let first = run(3);
+        assert_eq!(run(3), first);
+        assert_ne!(run(4), first);
+        let uniform = first.iter().step_by(2);
+        assert!(uniform.clone().all(|d| *d < ms(100)));
+        assert!(
+            uniform
+                .clone()
+                .collect::<std::collections::HashSet<_>>()
+                .len()
+                > 40
+        );
+    }
+}
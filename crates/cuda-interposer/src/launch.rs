@@ -0,0 +1,821 @@
+//! Kernel launch recording.
+//!
+//! [`install_launch_hooks!`] interposes `cuLaunchKernel`, `cuLaunchKernelEx`,
+//! `cuLaunchCooperativeKernel` and the runtime's `cudaLaunchKernel`, and turns every launch into
+//! a [`LaunchEvent`] naming the kernel (see [`crate::modules`]) and recording its geometry,
+//! stream and launch attributes. Events are passed to the callbacks registered with
+//! [`subscribe`], and written as JSON lines to `CUDA_HOOK_LAUNCH_LOG` (a file path or `stderr`)
+//! when it is set.
+//!
+//! The per-thread default stream launches (`cuLaunchKernel_ptsz`, `cuLaunchKernelEx_ptsz`,
+//! `cuLaunchCooperativeKernel_ptsz` and `cudaLaunchKernel_ptsz`) are hooked alongside, and
+//! recorded as the same APIs on `CU_STREAM_PER_THREAD` when they are passed the `NULL` stream.
+//!
+//! Launches made through the runtime reach the driver as well; they are recorded once, as the
+//! runtime call. Kernel arguments are decoded onto the events when [`crate::args`] decoding is
+//! enabled, and launches are added to the [`crate::streams`] graph when it is tracked. In the
+//! [`crate::blocking`] debug mode, launches are synchronized and checked for failures.
+
+use crate::args::{self, KernelArg};
+use crate::blocking;
+use crate::driver;
+use crate::ffi::*;
+use crate::lint;
+use crate::modules::{self, KernelName};
+use crate::record;
+use crate::streams;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::io::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::warn;
+
+const LOG_ENV: &str = "CUDA_HOOK_LAUNCH_LOG";
+
+type Subscriber = Box<dyn Fn(&LaunchEvent) + Send + Sync>;
+
+static SUBSCRIBERS: RwLock<Vec<Subscriber>> = RwLock::new(Vec::new());
+
+static LOG: Lazy<Option<Mutex<Box<dyn Write + Send>>>> = Lazy::new(|| {
+    let dest = std::env::var_os(LOG_ENV)?;
+    let sink: Box<dyn Write + Send> = if dest == "stderr" {
+        Box::new(std::io::stderr())
+    } else {
+        match std::fs::File::create(&dest) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                warn!("Failed to open launch log {:?}: {}", dest, e);
+                return None;
+            }
+        }
+    };
+    Some(Mutex::new(sink))
+});
+
+thread_local! {
+    static IN_RUNTIME_LAUNCH: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The API a launch was made through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LaunchApi {
+    #[serde(rename = "cuLaunchKernel")]
+    LaunchKernel,
+    #[serde(rename = "cuLaunchKernelEx")]
+    LaunchKernelEx,
+    #[serde(rename = "cuLaunchCooperativeKernel")]
+    LaunchCooperativeKernel,
+    #[serde(rename = "cudaLaunchKernel")]
+    RuntimeLaunchKernel,
+}
+
+/// A `CUlaunchAttribute` passed to `cuLaunchKernelEx`.
+#[derive(Debug, Clone, Serialize)]
+pub struct LaunchAttribute {
+    pub id: u32,
+    pub name: Option<&'static str>,
+    /// The first words of the attribute value. Scalar attributes are in `value[0]`, cluster
+    /// dimensions in `value[0..3]`.
+    pub value: [u32; 4],
+}
+
+impl LaunchAttribute {
+    fn new(attr: &CUlaunchAttribute) -> Self {
+        let mut value = [0; 4];
+        value.copy_from_slice(&attr.value[..4]);
+        Self {
+            id: attr.id,
+            name: attribute_name(attr.id),
+            value,
+        }
+    }
+}
+
+/// Returns the `CUlaunchAttributeID` name of `id`, without the `CU_LAUNCH_ATTRIBUTE_` prefix.
+pub fn attribute_name(id: u32) -> Option<&'static str> {
+    Some(match id {
+        1 => "ACCESS_POLICY_WINDOW",
+        2 => "COOPERATIVE",
+        3 => "SYNCHRONIZATION_POLICY",
+        4 => "CLUSTER_DIMENSION",
+        5 => "CLUSTER_SCHEDULING_POLICY_PREFERENCE",
+        6 => "PROGRAMMATIC_STREAM_SERIALIZATION",
+        7 => "PROGRAMMATIC_EVENT",
+        8 => "PRIORITY",
+        9 => "MEM_SYNC_DOMAIN_MAP",
+        10 => "MEM_SYNC_DOMAIN",
+        12 => "LAUNCH_COMPLETION_EVENT",
+        13 => "DEVICE_UPDATABLE_KERNEL_NODE",
+        _ => return None,
+    })
+}
+
+/// A recorded kernel launch.
+#[derive(Debug, Clone, Serialize)]
+pub struct LaunchEvent {
+    /// Microseconds since the interposer was loaded.
+    pub at_us: u64,
+    pub api: LaunchApi,
+    pub kernel: Option<Arc<KernelName>>,
+    /// The `CUfunction`, or the host stub for runtime launches, as an address.
+    pub function: usize,
+    pub grid: [u32; 3],
+    pub block: [u32; 3],
+    pub shared_mem: usize,
+    pub stream: usize,
+    pub context: usize,
+    pub device: Option<CUdevice>,
+    pub attributes: Vec<LaunchAttribute>,
+    /// The decoded arguments, when [`crate::args`] decoding is enabled.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<KernelArg>,
+    pub result: CUresult,
+}
+
+/// Registers a callback that is passed every recorded launch.
+pub fn subscribe(f: impl Fn(&LaunchEvent) + Send + Sync + 'static) {
+    SUBSCRIBERS.write().unwrap().push(Box::new(f));
+}
+
+fn enabled() -> bool {
+    LOG.is_some()
+        || streams::enabled()
+        || lint::enabled()
+        || blocking::enabled()
+        || !SUBSCRIBERS.read().unwrap().is_empty()
+}
+
+fn record(event: LaunchEvent) {
+    streams::record_launch(&event);
+    lint::on_launch(&event);
+    blocking::after_launch(&event);
+    for f in SUBSCRIBERS.read().unwrap().iter() {
+        f(&event);
+    }
+    if let Some(log) = LOG.as_ref() {
+        let mut line = match serde_json::to_vec(&event) {
+            Ok(line) => line,
+            Err(e) => return warn!("Failed to serialize launch event: {}", e),
+        };
+        line.push(b'\n');
+        let _ = log.lock().unwrap().write_all(&line);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn event(
+    api: LaunchApi,
+    kernel: Option<Arc<KernelName>>,
+    function: usize,
+    grid: [u32; 3],
+    block: [u32; 3],
+    shared_mem: usize,
+    stream: CUstream,
+    attributes: Vec<LaunchAttribute>,
+    args: Vec<KernelArg>,
+    result: CUresult,
+) -> LaunchEvent {
+    LaunchEvent {
+        at_us: crate::elapsed().as_micros() as u64,
+        api,
+        kernel,
+        function,
+        grid,
+        block,
+        shared_mem,
+        stream: stream as usize,
+        context: driver::current_context() as usize,
+        device: driver::current_device(),
+        attributes,
+        args,
+        result,
+    }
+}
+
+/// Returns whether launch arguments are decoded, as they are for [`crate::args`] and for the
+/// reports of [`crate::blocking`].
+fn decode_args() -> bool {
+    args::enabled() || blocking::enabled()
+}
+
+/// Decodes the arguments of a driver launch of `func`, if decoding is enabled.
+///
+/// # Safety
+/// `kernel_params` and `extra` must be as passed to `cuLaunchKernel` for `func`.
+unsafe fn driver_args(
+    func: CUfunction,
+    kernel: Option<&KernelName>,
+    kernel_params: *mut *mut c_void,
+    extra: *mut *mut c_void,
+) -> Vec<KernelArg> {
+    if !decode_args() {
+        return Vec::new();
+    }
+    let layout = args::layout(func, kernel);
+    unsafe { args::decode_launch(layout.as_deref(), kernel, kernel_params, extra) }
+}
+
+/// Records a `cuLaunchKernel` or `cuLaunchCooperativeKernel` call. Used by
+/// [`install_launch_hooks!`].
+///
+/// # Safety
+/// `kernel_params` and `extra` must be as passed to the launch.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn after_launch(
+    api: LaunchApi,
+    func: CUfunction,
+    grid: [u32; 3],
+    block: [u32; 3],
+    shared_mem: u32,
+    stream: CUstream,
+    kernel_params: *mut *mut c_void,
+    extra: *mut *mut c_void,
+    rc: CUresult,
+) {
+    // Runtime launches are recorded as the driver launches they become, which replay can issue.
+    unsafe {
+        record::on_launch(
+            rc,
+            func,
+            grid,
+            block,
+            shared_mem,
+            stream,
+            kernel_params,
+            extra,
+        )
+    };
+    if IN_RUNTIME_LAUNCH.get() || !enabled() {
+        return;
+    }
+    let kernel = modules::kernel_name(func);
+    let args = unsafe { driver_args(func, kernel.as_deref(), kernel_params, extra) };
+    record(event(
+        api,
+        kernel,
+        func as usize,
+        grid,
+        block,
+        shared_mem as usize,
+        stream,
+        Vec::new(),
+        args,
+        rc,
+    ));
+}
+
+/// Records a `cuLaunchKernelEx` call. Used by [`install_launch_hooks!`].
+///
+/// # Safety
+/// `config` must be null or point to a valid `CUlaunchConfig`, and `kernel_params` and `extra`
+/// must be as passed to the launch.
+pub unsafe fn after_launch_ex(
+    config: *const CUlaunchConfig,
+    func: CUfunction,
+    kernel_params: *mut *mut c_void,
+    extra: *mut *mut c_void,
+    rc: CUresult,
+) {
+    let Some(config) = (unsafe { config.as_ref() }) else {
+        return;
+    };
+    // Recorded without its launch attributes.
+    unsafe {
+        record::on_launch(
+            rc,
+            func,
+            [config.gridDimX, config.gridDimY, config.gridDimZ],
+            [config.blockDimX, config.blockDimY, config.blockDimZ],
+            config.sharedMemBytes,
+            config.hStream,
+            kernel_params,
+            extra,
+        )
+    };
+    if IN_RUNTIME_LAUNCH.get() || !enabled() {
+        return;
+    }
+    let attributes = if config.attrs.is_null() {
+        Vec::new()
+    } else {
+        let attrs = unsafe { std::slice::from_raw_parts(config.attrs, config.numAttrs as usize) };
+        attrs.iter().map(LaunchAttribute::new).collect()
+    };
+    let kernel = modules::kernel_name(func);
+    let args = unsafe { driver_args(func, kernel.as_deref(), kernel_params, extra) };
+    record(event(
+        LaunchApi::LaunchKernelEx,
+        kernel,
+        func as usize,
+        [config.gridDimX, config.gridDimY, config.gridDimZ],
+        [config.blockDimX, config.blockDimY, config.blockDimZ],
+        config.sharedMemBytes as usize,
+        config.hStream,
+        attributes,
+        args,
+        rc,
+    ));
+}
+
+/// Returns `config` with its stream as [`streams::per_thread`] sees it, for recording a
+/// `cuLaunchKernelEx_ptsz`. Used by [`install_launch_hooks!`].
+///
+/// # Safety
+/// `config` must be null or point to a valid `CUlaunchConfig`.
+pub unsafe fn per_thread_config(config: *const CUlaunchConfig) -> Option<CUlaunchConfig> {
+    let config = unsafe { config.as_ref() }?;
+    Some(CUlaunchConfig {
+        hStream: streams::per_thread(config.hStream),
+        ..*config
+    })
+}
+
+/// Runs a runtime launch with the driver launch hooks muted, then records it. Used by
+/// [`install_launch_hooks!`].
+///
+/// # Safety
+/// `kernel_params` must be as passed to `cudaLaunchKernel` for `func`.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn runtime_launch(
+    func: *const c_void,
+    grid: dim3,
+    block: dim3,
+    shared_mem: usize,
+    stream: CUstream,
+    kernel_params: *mut *mut c_void,
+    launch: impl FnOnce() -> u32,
+) -> u32 {
+    let outer = IN_RUNTIME_LAUNCH.replace(true);
+    let rc = launch();
+    IN_RUNTIME_LAUNCH.set(outer);
+    if !outer && enabled() {
+        let kernel = modules::host_kernel_name(func);
+        let args = if decode_args() {
+            let layout = kernel
+                .as_ref()
+                .and_then(|k| modules::param_layout(&k.mangled));
+            unsafe {
+                args::decode_launch(
+                    layout.as_deref(),
+                    kernel.as_deref(),
+                    kernel_params,
+                    std::ptr::null_mut(),
+                )
+            }
+        } else {
+            Vec::new()
+        };
+        record(event(
+            LaunchApi::RuntimeLaunchKernel,
+            kernel,
+            func as usize,
+            [grid.x, grid.y, grid.z],
+            [block.x, block.y, block.z],
+            shared_mem,
+            stream,
+            Vec::new(),
+            args,
+            rc,
+        ));
+    }
+    rc
+}
+
+/// Installs hooks that record every kernel launch as a [`LaunchEvent`]. Install
+/// [`install_module_hooks!`] too so that kernels are named on drivers without `cuFuncGetName`.
+///
+/// The hooked symbols are listed in [`crate::hook_sets::HOOK_SETS`], so
+/// `cuda-interposer-build` leaves them out of the generated passthroughs.
+#[macro_export]
+macro_rules! install_launch_hooks {
+    () => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuLaunchKernel(
+                f: $crate::ffi::CUfunction,
+                grid_dim_x: $crate::libc::c_uint,
+                grid_dim_y: $crate::libc::c_uint,
+                grid_dim_z: $crate::libc::c_uint,
+                block_dim_x: $crate::libc::c_uint,
+                block_dim_y: $crate::libc::c_uint,
+                block_dim_z: $crate::libc::c_uint,
+                shared_mem_bytes: $crate::libc::c_uint,
+                h_stream: $crate::ffi::CUstream,
+                kernel_params: *mut *mut $crate::libc::c_void,
+                extra: *mut *mut $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let params = match unsafe { $crate::handles::RealParams::new(f, kernel_params, extra) } {
+                    Ok(params) => params,
+                    Err(rc) => return rc,
+                };
+                let (kernel_params, extra) = params.pointers();
+                let rc = unsafe {
+                    (*__real_cuLaunchKernel)(
+                        f, grid_dim_x, grid_dim_y, grid_dim_z, block_dim_x, block_dim_y,
+                        block_dim_z, shared_mem_bytes, h_stream, kernel_params, extra,
+                    )
+                };
+                unsafe {
+                    $crate::launch::after_launch(
+                        $crate::launch::LaunchApi::LaunchKernel,
+                        f,
+                        [grid_dim_x, grid_dim_y, grid_dim_z],
+                        [block_dim_x, block_dim_y, block_dim_z],
+                        shared_mem_bytes,
+                        h_stream,
+                        kernel_params,
+                        extra,
+                        rc,
+                    )
+                };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuLaunchKernel_ptsz(
+                f: $crate::ffi::CUfunction,
+                grid_dim_x: $crate::libc::c_uint,
+                grid_dim_y: $crate::libc::c_uint,
+                grid_dim_z: $crate::libc::c_uint,
+                block_dim_x: $crate::libc::c_uint,
+                block_dim_y: $crate::libc::c_uint,
+                block_dim_z: $crate::libc::c_uint,
+                shared_mem_bytes: $crate::libc::c_uint,
+                h_stream: $crate::ffi::CUstream,
+                kernel_params: *mut *mut $crate::libc::c_void,
+                extra: *mut *mut $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let params = match unsafe { $crate::handles::RealParams::new(f, kernel_params, extra) } {
+                    Ok(params) => params,
+                    Err(rc) => return rc,
+                };
+                let (kernel_params, extra) = params.pointers();
+                let rc = unsafe {
+                    (*__real_cuLaunchKernel_ptsz)(
+                        f, grid_dim_x, grid_dim_y, grid_dim_z, block_dim_x, block_dim_y,
+                        block_dim_z, shared_mem_bytes, h_stream, kernel_params, extra,
+                    )
+                };
+                unsafe {
+                    $crate::launch::after_launch(
+                        $crate::launch::LaunchApi::LaunchKernel,
+                        f,
+                        [grid_dim_x, grid_dim_y, grid_dim_z],
+                        [block_dim_x, block_dim_y, block_dim_z],
+                        shared_mem_bytes,
+                        $crate::streams::per_thread(h_stream),
+                        kernel_params,
+                        extra,
+                        rc,
+                    )
+                };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuLaunchCooperativeKernel(
+                f: $crate::ffi::CUfunction,
+                grid_dim_x: $crate::libc::c_uint,
+                grid_dim_y: $crate::libc::c_uint,
+                grid_dim_z: $crate::libc::c_uint,
+                block_dim_x: $crate::libc::c_uint,
+                block_dim_y: $crate::libc::c_uint,
+                block_dim_z: $crate::libc::c_uint,
+                shared_mem_bytes: $crate::libc::c_uint,
+                h_stream: $crate::ffi::CUstream,
+                kernel_params: *mut *mut $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let params = match unsafe {
+                    $crate::handles::RealParams::new(f, kernel_params, std::ptr::null_mut())
+                } {
+                    Ok(params) => params,
+                    Err(rc) => return rc,
+                };
+                let (kernel_params, _) = params.pointers();
+                let rc = unsafe {
+                    (*__real_cuLaunchCooperativeKernel)(
+                        f, grid_dim_x, grid_dim_y, grid_dim_z, block_dim_x, block_dim_y,
+                        block_dim_z, shared_mem_bytes, h_stream, kernel_params,
+                    )
+                };
+                unsafe {
+                    $crate::launch::after_launch(
+                        $crate::launch::LaunchApi::LaunchCooperativeKernel,
+                        f,
+                        [grid_dim_x, grid_dim_y, grid_dim_z],
+                        [block_dim_x, block_dim_y, block_dim_z],
+                        shared_mem_bytes,
+                        h_stream,
+                        kernel_params,
+                        std::ptr::null_mut(),
+                        rc,
+                    )
+                };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuLaunchCooperativeKernel_ptsz(
+                f: $crate::ffi::CUfunction,
+                grid_dim_x: $crate::libc::c_uint,
+                grid_dim_y: $crate::libc::c_uint,
+                grid_dim_z: $crate::libc::c_uint,
+                block_dim_x: $crate::libc::c_uint,
+                block_dim_y: $crate::libc::c_uint,
+                block_dim_z: $crate::libc::c_uint,
+                shared_mem_bytes: $crate::libc::c_uint,
+                h_stream: $crate::ffi::CUstream,
+                kernel_params: *mut *mut $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let params = match unsafe {
+                    $crate::handles::RealParams::new(f, kernel_params, std::ptr::null_mut())
+                } {
+                    Ok(params) => params,
+                    Err(rc) => return rc,
+                };
+                let (kernel_params, _) = params.pointers();
+                let rc = unsafe {
+                    (*__real_cuLaunchCooperativeKernel_ptsz)(
+                        f, grid_dim_x, grid_dim_y, grid_dim_z, block_dim_x, block_dim_y,
+                        block_dim_z, shared_mem_bytes, h_stream, kernel_params,
+                    )
+                };
+                unsafe {
+                    $crate::launch::after_launch(
+                        $crate::launch::LaunchApi::LaunchCooperativeKernel,
+                        f,
+                        [grid_dim_x, grid_dim_y, grid_dim_z],
+                        [block_dim_x, block_dim_y, block_dim_z],
+                        shared_mem_bytes,
+                        $crate::streams::per_thread(h_stream),
+                        kernel_params,
+                        std::ptr::null_mut(),
+                        rc,
+                    )
+                };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuLaunchKernelEx(
+                config: *const $crate::ffi::CUlaunchConfig,
+                f: $crate::ffi::CUfunction,
+                kernel_params: *mut *mut $crate::libc::c_void,
+                extra: *mut *mut $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let real_config = unsafe { $crate::handles::real_config(config) };
+                let config = real_config.as_ref().map_or(config, |c| c as *const _);
+                let params = match unsafe { $crate::handles::RealParams::new(f, kernel_params, extra) } {
+                    Ok(params) => params,
+                    Err(rc) => return rc,
+                };
+                let (kernel_params, extra) = params.pointers();
+                let rc = unsafe { (*__real_cuLaunchKernelEx)(config, f, kernel_params, extra) };
+                unsafe { $crate::launch::after_launch_ex(config, f, kernel_params, extra, rc) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuLaunchKernelEx_ptsz(
+                config: *const $crate::ffi::CUlaunchConfig,
+                f: $crate::ffi::CUfunction,
+                kernel_params: *mut *mut $crate::libc::c_void,
+                extra: *mut *mut $crate::libc::c_void
+            ) -> $crate::ffi::CUresult {
+                let real_config = unsafe { $crate::handles::real_config(config) };
+                let config = real_config.as_ref().map_or(config, |c| c as *const _);
+                let params = match unsafe { $crate::handles::RealParams::new(f, kernel_params, extra) } {
+                    Ok(params) => params,
+                    Err(rc) => return rc,
+                };
+                let (kernel_params, extra) = params.pointers();
+                let rc = unsafe { (*__real_cuLaunchKernelEx_ptsz)(config, f, kernel_params, extra) };
+                let recorded = unsafe { $crate::launch::per_thread_config(config) };
+                let config = recorded.as_ref().map_or(config, |c| c as *const _);
+                unsafe { $crate::launch::after_launch_ex(config, f, kernel_params, extra, rc) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cudaLaunchKernel(
+                func: *const $crate::libc::c_void,
+                grid_dim: $crate::ffi::dim3,
+                block_dim: $crate::ffi::dim3,
+                args: *mut *mut $crate::libc::c_void,
+                shared_mem: usize,
+                stream: $crate::ffi::CUstream
+            ) -> $crate::libc::c_uint {
+                unsafe {
+                    $crate::launch::runtime_launch(func, grid_dim, block_dim, shared_mem, stream, args, || {
+                        (*__real_cudaLaunchKernel)(func, grid_dim, block_dim, args, shared_mem, stream)
+                    })
+                }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cudaLaunchKernel_ptsz(
+                func: *const $crate::libc::c_void,
+                grid_dim: $crate::ffi::dim3,
+                block_dim: $crate::ffi::dim3,
+                args: *mut *mut $crate::libc::c_void,
+                shared_mem: usize,
+                stream: $crate::ffi::CUstream
+            ) -> $crate::libc::c_uint {
+                let recorded = $crate::streams::per_thread(stream);
+                unsafe {
+                    $crate::launch::runtime_launch(func, grid_dim, block_dim, shared_mem, recorded, args, || {
+                        (*__real_cudaLaunchKernel_ptsz)(func, grid_dim, block_dim, args, shared_mem, stream)
+                    })
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{self, PTX};
+    use crate::replay::Driver;
+    use std::ptr::null_mut;
+    use std::sync::Once;
+
+    static EVENTS: Mutex<Vec<LaunchEvent>> = Mutex::new(Vec::new());
+
+    /// Starts collecting the recorded launches, once for all tests.
+    fn listen() {
+        static SUBSCRIBE: Once = Once::new();
+        SUBSCRIBE.call_once(|| subscribe(|event| EVENTS.lock().unwrap().push(event.clone())));
+    }
+
+    /// Returns the launches of `function` collected since the last call.
+    fn take_events(function: usize) -> Vec<LaunchEvent> {
+        let mut events = EVENTS.lock().unwrap();
+        let (taken, kept) = events.drain(..).partition(|e| e.function == function);
+        *events = kept;
+        taken
+    }
+
+    /// Creates a context and returns it with the `scale` kernel of [`PTX`], listening for its
+    /// launches.
+    fn scale_kernel(d: &Driver) -> (CUcontext, CUfunction) {
+        listen();
+        assert_eq!(unsafe { d.cuInit.unwrap()(0) }, CUDA_SUCCESS);
+        let mut ctx = null_mut();
+        assert_eq!(
+            unsafe { d.cuCtxCreate_v2.unwrap()(&mut ctx, 0, 0) },
+            CUDA_SUCCESS
+        );
+        let mut module = null_mut();
+        let image = PTX.as_ptr() as *const c_void;
+        assert_eq!(
+            unsafe { d.cuModuleLoadData.unwrap()(&mut module, image) },
+            CUDA_SUCCESS
+        );
+        let mut function = null_mut();
+        let rc =
+            unsafe { d.cuModuleGetFunction.unwrap()(&mut function, module, c"scale".as_ptr()) };
+        assert_eq!(rc, CUDA_SUCCESS);
+        (ctx, function)
+    }
+
+    #[test]
+    fn names_launch_attributes() {
+        let mut attr: CUlaunchAttribute = unsafe { std::mem::zeroed() };
+        attr.id = 4;
+        attr.value[..4].copy_from_slice(&[2, 1, 1, 7]);
+        attr.value[4] = 9;
+        let cluster = LaunchAttribute::new(&attr);
+        assert_eq!(cluster.name, Some("CLUSTER_DIMENSION"));
+        assert_eq!(cluster.value, [2, 1, 1, 7]);
+
+        attr.id = 11;
+        let unknown = LaunchAttribute::new(&attr);
+        assert_eq!((unknown.id, unknown.name), (11, None));
+    }
+
+    #[test]
+    fn records_a_launch_as_an_event() {
+        let (d, _guard) = fake::driver();
+        let (ctx, function) = scale_kernel(&d);
+
+        let mut data = 0u64;
+        let mut n = 0u32;
+        let mut params = [
+            &mut data as *mut u64 as *mut c_void,
+            &mut n as *mut u32 as *mut c_void,
+        ];
+        unsafe {
+            after_launch(
+                LaunchApi::LaunchCooperativeKernel,
+                function,
+                [2, 1, 1],
+                [32, 4, 1],
+                128,
+                CU_STREAM_PER_THREAD,
+                params.as_mut_ptr(),
+                null_mut(),
+                CUDA_SUCCESS,
+            )
+        };
+
+        let events = take_events(function as usize);
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.api, LaunchApi::LaunchCooperativeKernel);
+        let kernel = event.kernel.as_ref().map(|k| k.mangled.as_str());
+        assert_eq!(kernel, Some("scale"));
+        assert_eq!((event.grid, event.block), ([2, 1, 1], [32, 4, 1]));
+        assert_eq!(event.shared_mem, 128);
+        assert_eq!(event.stream, CU_STREAM_PER_THREAD as usize);
+        assert_eq!(event.context, ctx as usize);
+        assert_eq!(event.device, Some(0));
+        assert!(event.attributes.is_empty());
+        assert_eq!(event.result, CUDA_SUCCESS);
+
+        assert_eq!(unsafe { d.cuCtxDestroy_v2.unwrap()(ctx) }, CUDA_SUCCESS);
+    }
+
+    #[test]
+    fn records_per_thread_launch_configs() {
+        let (d, _guard) = fake::driver();
+        let (ctx, function) = scale_kernel(&d);
+
+        let mut attr: CUlaunchAttribute = unsafe { std::mem::zeroed() };
+        attr.id = 8;
+        attr.value[0] = 1;
+        let config = CUlaunchConfig {
+            gridDimX: 4,
+            gridDimY: 1,
+            gridDimZ: 1,
+            blockDimX: 64,
+            blockDimY: 1,
+            blockDimZ: 1,
+            sharedMemBytes: 0,
+            hStream: null_mut(),
+            attrs: &mut attr,
+            numAttrs: 1,
+        };
+        let recorded = unsafe { per_thread_config(&config) }.unwrap();
+        unsafe { after_launch_ex(&recorded, function, null_mut(), null_mut(), CUDA_SUCCESS) };
+
+        let events = take_events(function as usize);
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.api, LaunchApi::LaunchKernelEx);
+        assert_eq!((event.grid, event.block), ([4, 1, 1], [64, 1, 1]));
+        assert_eq!(event.stream, CU_STREAM_PER_THREAD as usize);
+        assert_eq!(event.attributes.len(), 1);
+        assert_eq!(event.attributes[0].name, Some("PRIORITY"));
+        assert_eq!(event.attributes[0].value[0], 1);
+        assert!(unsafe { per_thread_config(std::ptr::null()) }.is_none());
+
+        assert_eq!(unsafe { d.cuCtxDestroy_v2.unwrap()(ctx) }, CUDA_SUCCESS);
+    }
+
+    #[test]
+    fn records_runtime_launches_once() {
+        static HOST_STUB: u8 = 0;
+        let (d, _guard) = fake::driver();
+        let (ctx, function) = scale_kernel(&d);
+
+        let stub = &HOST_STUB as *const u8 as *const c_void;
+        let grid = dim3 { x: 8, y: 1, z: 1 };
+        let block = dim3 { x: 256, y: 1, z: 1 };
+        let rc = unsafe {
+            runtime_launch(stub, grid, block, 0, null_mut(), null_mut(), || {
+                // The driver launch the runtime makes underneath
+                after_launch(
+                    LaunchApi::LaunchKernel,
+                    function,
+                    [8, 1, 1],
+                    [256, 1, 1],
+                    0,
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                    CUDA_SUCCESS,
+                );
+                0
+            })
+        };
+        assert_eq!(rc, 0);
+
+        assert!(take_events(function as usize).is_empty());
+        let events = take_events(stub as usize);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].api, LaunchApi::RuntimeLaunchKernel);
+        assert_eq!((events[0].grid, events[0].block), ([8, 1, 1], [256, 1, 1]));
+        assert!(!IN_RUNTIME_LAUNCH.get());
+
+        assert_eq!(unsafe { d.cuCtxDestroy_v2.unwrap()(ctx) }, CUDA_SUCCESS);
+    }
+}
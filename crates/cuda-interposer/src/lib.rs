@@ -1,10 +1,48 @@
-use std::{env, ffi::CString, os::raw::c_void, sync::OnceLock};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{CStr, CString},
+    os::raw::c_void,
+    sync::{OnceLock, RwLock},
+    time::{Duration, Instant},
+};
 use tracing::{debug, warn};
 
+pub mod args;
+pub mod blocking;
+pub mod calls;
+pub mod capture;
+pub mod checkpoint;
+pub mod cudart;
+pub mod devices;
+pub mod driver;
+#[cfg(test)]
+mod fake;
+pub mod faults;
+pub mod graphs;
+pub mod handles;
+pub mod latency;
+pub mod launch;
+pub mod lint;
+pub mod memory;
+pub mod modules;
+pub mod pattern;
+pub mod quota;
+pub mod record;
+pub mod registration;
+pub mod remote;
+pub mod replay;
+pub mod spoof;
+pub mod streams;
+
+pub use cuda_interposer_common::{config, cubin, elf, fatbin, ffi};
+pub use cuda_interposer_tables::hook_sets;
+
 // Re-exports for macros
 pub use libc;
 pub use once_cell;
 pub use paste;
+pub use serde;
 pub use tracing;
 
 // ─── Library Loading ─────────────────────────────────────────────────────────
@@ -30,6 +68,11 @@ fn get_libcuda() -> *mut c_void {
             paths.insert(0, path);
         }
 
+        // An explicit driver, such as the host-memory one of `cuda-fake-driver`, comes first
+        if let Some(driver) = env::var_os("CUDA_HOOK_DRIVER") {
+            paths.insert(0, driver.to_string_lossy().into_owned());
+        }
+
         for path in paths.iter() {
             let s = CString::new(path.clone()).unwrap();
             let flags = libc::RTLD_NOW | libc::RTLD_LOCAL | libc::RTLD_NODELETE;
@@ -74,11 +117,47 @@ fn get_libcudart() -> *mut c_void {
     handle_wrapper.0
 }
 
+/// Looks up an entry point by name in place of the real libraries, returning null for symbols
+/// it does not know.
+pub type Resolver = fn(&str) -> *mut c_void;
+
+static RESOLVER: RwLock<Option<Resolver>> = RwLock::new(None);
+
+/// The hooked symbols by the address of their real entry point, built by the first
+/// [`hook_for_address`] and cleared by [`set_resolver`].
+static ADDRESSES: RwLock<Option<HashMap<usize, &'static str>>> = RwLock::new(None);
+
+/// Resolves the real entry points of hooks and passthroughs with `resolver` instead of libcuda
+/// and libcudart, or with the real libraries again when it is `None`. This is how hooks run
+/// against a mock driver in unit tests (see the `cuda-interposer-test` crate).
+///
+/// Each `__real_*` entry point is resolved once, on first use, so the resolver must be set
+/// before the hooks under test are first called. The addresses [`hook_for_address`] matches
+/// against are looked up again after each change.
+pub fn set_resolver(resolver: Option<Resolver>) {
+    *RESOLVER.write().unwrap() = resolver;
+    *ADDRESSES.write().unwrap() = None;
+}
+
+fn resolver() -> Option<Resolver> {
+    *RESOLVER.read().unwrap()
+}
+
 pub fn dlsym_next(symbol: &[u8]) -> *mut c_void {
     let sym_str = std::str::from_utf8(symbol).unwrap_or("");
+    let is_runtime = sym_str.starts_with("cuda") || sym_str.starts_with("__cuda");
+
+    if let Some(resolve) = resolver() {
+        return resolve(sym_str.trim_end_matches('\0'));
+    }
+
+    // While remoting, driver calls go to the server instead of libcuda
+    if remote::enabled() && !is_runtime {
+        return remote::resolve(sym_str.trim_end_matches('\0'));
+    }
 
     // Route to the correct library based on prefix
-    let handle = if sym_str.starts_with("cuda") || sym_str.starts_with("__cuda") {
+    let handle = if is_runtime {
         get_libcudart()
     } else {
         get_libcuda()
@@ -91,6 +170,70 @@ pub fn dlsym_next(symbol: &[u8]) -> *mut c_void {
     ptr
 }
 
+/// Returns the name of the hooked symbol whose real driver entry point is `real`.
+///
+/// Applications ask `cuGetProcAddress` for unversioned names such as `cuMemAlloc`, and the
+/// driver picks a versioned entry point (`cuMemAlloc_v2`) based on the requested CUDA version.
+/// Matching the address it returned against the real address of every hooked symbol finds the
+/// right hook whichever name was asked for.
+pub fn hook_for_address(real: *mut c_void, hooks: &'static [&'static str]) -> Option<&'static str> {
+    if real.is_null() {
+        return None;
+    }
+    if let Some(addresses) = &*ADDRESSES.read().unwrap() {
+        return addresses.get(&(real as usize)).copied();
+    }
+    let mut addresses = ADDRESSES.write().unwrap();
+    let addresses = addresses.get_or_insert_with(|| {
+        hooks
+            .iter()
+            .filter(|name| name.starts_with("cu") && !name.starts_with("cuda"))
+            .filter_map(|name| {
+                let ptr = if let Some(resolve) = resolver() {
+                    resolve(name)
+                } else if remote::enabled() {
+                    remote::stub(name)?
+                } else {
+                    let c_name = CString::new(*name).ok()?;
+                    unsafe { libc::dlsym(get_libcuda(), c_name.as_ptr()) }
+                };
+                (!ptr.is_null()).then_some((ptr as usize, *name))
+            })
+            .collect()
+    });
+    addresses.get(&(real as usize)).copied()
+}
+
+/// Returns the interposer's passthrough for the real driver function at `real`, so that
+/// `cuGetProcAddress` hands it out while handles are virtualized or a fault plan is armed, both
+/// of which need every call to go through the interposer. Used by [`install_hooks!`].
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn proc_address(real: *mut c_void) -> Option<*mut c_void> {
+    if !(handles::enabled() || faults::enabled()) || real.is_null() {
+        return None;
+    }
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    if unsafe { libc::dladdr(real, &mut info) } == 0
+        || info.dli_sname.is_null()
+        || info.dli_saddr != real
+    {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(info.dli_sname) };
+    let ours = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+    (!ours.is_null() && ours != real).then_some(ours)
+}
+
+// ─── Process Clock ───────────────────────────────────────────────────────────
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Time since the interposer was loaded, or first observed a call if it was loaded without
+/// [`install_hooks!`]. Used to timestamp recorded events.
+pub fn elapsed() -> Duration {
+    EPOCH.get_or_init(Instant::now).elapsed()
+}
+
 // ─── Macros ──────────────────────────────────────────────────────────────────
 
 /// Installs the `cuGetProcAddress` hooks required for the interposer to function.
@@ -129,7 +272,16 @@ macro_rules! install_hooks {
                 let real_fn = *__real_cuGetProcAddress_v2;
                 let ret = unsafe { real_fn(symbol, pfn, cuda_version, flags, symbol_status) };
 
-                if let Some(our_ptr) = get_local_hook(&sym_name) {
+                // B. Prefer the hook for the versioned symbol the driver resolved to
+                const HOOK_NAMES: &[&str] = include!(concat!(env!("OUT_DIR"), "/hook_names.rs"));
+                $crate::cudart::check(HOOK_NAMES);
+                let real_ptr = if ret == 0 && !pfn.is_null() { unsafe { *pfn } } else { std::ptr::null_mut() };
+                let hook = $crate::hook_for_address(real_ptr, HOOK_NAMES)
+                    .and_then(get_local_hook)
+                    .or_else(|| get_local_hook(&sym_name))
+                    .or_else(|| $crate::proc_address(real_ptr));
+
+                if let Some(our_ptr) = hook {
                     $crate::tracing::debug!("Hooking symbol via cuGetProcAddress_v2: {}", sym_name);
                     unsafe { *pfn = our_ptr };
                     return 0; // CUDA_SUCCESS
@@ -139,6 +291,19 @@ macro_rules! install_hooks {
             }
         }
 
+        /// Starts the `elapsed` clock and warns about runtime hooks a statically linked runtime
+        /// bypasses as soon as the interposer is loaded.
+        #[used]
+        #[unsafe(link_section = ".init_array")]
+        static __CUDA_HOOK_CHECK_RUNTIME: extern "C" fn() = {
+            extern "C" fn check_runtime() {
+                $crate::elapsed();
+                const HOOK_NAMES: &[&str] = include!(concat!(env!("OUT_DIR"), "/hook_names.rs"));
+                $crate::cudart::check_on_load(HOOK_NAMES);
+            }
+            check_runtime
+        };
+
         $crate::cuda_hook! {
             pub unsafe extern "C" fn cuGetProcAddress(
                 symbol: *const $crate::libc::c_char,
@@ -175,7 +340,12 @@ macro_rules! cuda_hook {
 
             #[unsafe(no_mangle)]
             pub unsafe extern "C" fn $fname( $($arg : $arg_ty),* ) -> $ret {
-                 $body
+                $crate::latency::delay(stringify!($fname));
+                if let Some(rc) = $crate::inject_fault!($ret, $fname) {
+                    return rc;
+                }
+                $( let $arg = $crate::real_arg!($arg); )*
+                $body
             }
         }
     };
@@ -183,19 +353,54 @@ macro_rules! cuda_hook {
 
 #[macro_export]
 macro_rules! generate_proxy {
+    // Internal: Translate the handles a successful call returned or destroyed
+    (@handle out $arg:ident $kind:ident $rc:ident) => {
+        unsafe { $crate::handles::export(&$rc, $crate::handles::HandleKind::$kind, $arg) }
+    };
+    (@handle release $arg:ident $kind:ident $rc:ident) => {
+        $crate::handles::release(&$rc, $crate::handles::HandleKind::$kind, $arg)
+    };
+
+    // Internal: Call the real function with translated handles
+    (
+        @call
+        $symbol:ident,
+        $real:expr,
+        args: [ $( ($arg:ident : $arg_ty:ty) ),* ],
+        handles: [ $( ($dir:ident $harg:ident $kind:ident) ),* ],
+        ret: $ret:ty
+    ) => {{
+        $crate::latency::delay(stringify!($symbol));
+        if let Some(rc) = $crate::inject_fault!($ret, $symbol) {
+            return rc;
+        }
+        $( let $arg = $crate::real_arg!($arg); )*
+        let f = *$real;
+        let rc = f( $( $arg ),* );
+        $( $crate::generate_proxy!(@handle $dir $harg $kind rc); )*
+        rc
+    }};
+
     // Internal: Generate specific alias function
     (
         @generate_alias
         alias: $alias:ident,
         target_fn: $fname:ident,
         args: ( [ $( ($arg:ident : $arg_ty:ty) ),* ] ),
+        handles: $handles:tt,
         ret: $ret:ty
     ) => {
         $crate::paste::paste! {
             #[unsafe(no_mangle)]
             pub unsafe extern "C" fn $alias( $( $arg : $arg_ty ),* ) -> $ret {
-                let f = *[<__REAL_ $fname:upper>];
-                f( $( $arg ),* )
+                $crate::generate_proxy!(
+                    @call
+                    $alias,
+                    [<__REAL_ $fname:upper>],
+                    args: [ $( ($arg : $arg_ty) ),* ],
+                    handles: $handles,
+                    ret: $ret
+                )
             }
         }
     };
@@ -206,6 +411,7 @@ macro_rules! generate_proxy {
         target_fn: $fname:ident,
         ret: $ret:ty,
         args_tt: $args_tt:tt,
+        handles: $handles:tt,
         aliases: [ $($alias:ident),* ]
     ) => {
         $(
@@ -214,6 +420,7 @@ macro_rules! generate_proxy {
                 alias: $alias,
                 target_fn: $fname,
                 args: $args_tt,
+                handles: $handles,
                 ret: $ret
             );
         )*
@@ -223,7 +430,8 @@ macro_rules! generate_proxy {
     (
         @generate_main
         fn $fname:ident ( [ $( ($arg:ident : $arg_ty:ty) ),* ] ) -> $ret:ty;
-        target_symbol: $real_sym:ident
+        target_symbol: $real_sym:ident;
+        handles: $handles:tt
     ) => {
         $crate::paste::paste! {
             static [<__REAL_ $fname:upper>]: $crate::once_cell::sync::Lazy<
@@ -240,22 +448,32 @@ macro_rules! generate_proxy {
 
             #[unsafe(no_mangle)]
             pub unsafe extern "C" fn $fname( $( $arg : $arg_ty ),* ) -> $ret {
-                let f = *[<__REAL_ $fname:upper>];
-                f( $( $arg ),* )
+                $crate::generate_proxy!(
+                    @call
+                    $fname,
+                    [<__REAL_ $fname:upper>],
+                    args: [ $( ($arg : $arg_ty) ),* ],
+                    handles: $handles,
+                    ret: $ret
+                )
             }
         }
     };
 
-    // Entry Point
+    // Entry Point. `handles` lists the out-parameters that return handles and the argument a
+    // destroy call releases, as `(out arg Kind)` and `(release arg Kind)`; see
+    // [`crate::handles`].
     (
         fn $fname:ident $args_tt:tt -> $ret:ty;
-        name: $real_sym:ident
+        name: $real_sym:ident,
+        handles: $handles:tt
         $(, aliases: $($alias:ident),* )?
     ) => {
         $crate::generate_proxy!(
             @generate_main
             fn $fname $args_tt -> $ret;
-            target_symbol: $real_sym
+            target_symbol: $real_sym;
+            handles: $handles
         );
         $(
             $crate::generate_proxy!(
@@ -263,8 +481,65 @@ macro_rules! generate_proxy {
                 target_fn: $fname,
                 ret: $ret,
                 args_tt: $args_tt,
+                handles: $handles,
                 aliases: [ $($alias),* ]
             );
         )?
     };
+    (
+        fn $fname:ident $args_tt:tt -> $ret:ty;
+        name: $real_sym:ident
+        $(, aliases: $($alias:ident),* )?
+    ) => {
+        $crate::generate_proxy!(
+            fn $fname $args_tt -> $ret;
+            name: $real_sym,
+            handles: []
+            $(, aliases: $($alias),* )?
+        );
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first(name: &str) -> *mut c_void {
+        match name {
+            "cuTestA" => 0x1000 as *mut c_void,
+            _ => std::ptr::null_mut(),
+        }
+    }
+
+    fn second(name: &str) -> *mut c_void {
+        match name {
+            "cuTestA" => 0x2000 as *mut c_void,
+            "cuTestB" => 0x1000 as *mut c_void,
+            _ => std::ptr::null_mut(),
+        }
+    }
+
+    #[test]
+    fn matches_addresses_from_the_current_resolver() {
+        let _lock = fake::lock();
+        let hooks = &["cuTestA", "cuTestB", "cudaTestA"];
+        set_resolver(Some(first));
+        assert_eq!(
+            hook_for_address(0x1000 as *mut c_void, hooks),
+            Some("cuTestA")
+        );
+        assert_eq!(hook_for_address(0x2000 as *mut c_void, hooks), None);
+        assert_eq!(hook_for_address(std::ptr::null_mut(), hooks), None);
+
+        set_resolver(Some(second));
+        assert_eq!(
+            hook_for_address(0x1000 as *mut c_void, hooks),
+            Some("cuTestB")
+        );
+        assert_eq!(
+            hook_for_address(0x2000 as *mut c_void, hooks),
+            Some("cuTestA")
+        );
+        set_resolver(None);
+    }
 }
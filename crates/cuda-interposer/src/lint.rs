@@ -0,0 +1,451 @@
+//! Performance anti-pattern detection.
+//!
+//! The built-in hook sets pass what they intercept to this module, which looks for the mistakes
+//! usually found by reading profiler traces:
+//!
+//! - memcpys from or to pageable host memory, which the driver stages through a pinned buffer
+//!   and which block the host even when issued as async copies,
+//! - small memcpys, dominated by their fixed overhead,
+//! - device memory allocated and freed over and over with the same size, as in a loop,
+//! - kernels launched, or async copies queued, on the legacy default stream, which serializes
+//!   with every blocking stream,
+//! - `cuCtxSynchronize` called often relative to the work launched, as once per iteration.
+//!
+//! Each [`Finding`] counts the occurrences of one [`Rule`] for one subject (an API, an allocation
+//! size, a kernel) and keeps the host backtrace of its first occurrence. [`report`] ranks them
+//! by a severity weight times their count.
+//!
+//! Detection is enabled by setting `CUDA_HOOK_LINT` to `stderr` or a file path, where the report
+//! is written at exit, or with [`set_enabled`]. Memcpys and synchronization are seen through
+//! [`install_stream_hooks!`], allocations through [`install_memory_hooks!`] and launches through
+//! [`install_launch_hooks!`].
+
+use crate::driver;
+use crate::ffi::*;
+use crate::launch::LaunchEvent;
+use crate::memory::AllocKind;
+use once_cell::sync::Lazy;
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use tracing::warn;
+
+const LINT_ENV: &str = "CUDA_HOOK_LINT";
+
+/// Copies up to this size count as small.
+pub const SMALL_MEMCPY_BYTES: usize = 4096;
+
+static ENABLED: Lazy<AtomicBool> =
+    Lazy::new(|| AtomicBool::new(std::env::var_os(LINT_ENV).is_some()));
+
+static STATE: Lazy<Mutex<State>> = Lazy::new(Default::default);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// An anti-pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Rule {
+    PageableMemcpy,
+    SmallMemcpy,
+    AllocChurn,
+    LegacyStreamLaunch,
+    FrequentCtxSynchronize,
+}
+
+impl Rule {
+    /// How costly one occurrence is, relative to the other rules.
+    pub fn weight(self) -> usize {
+        match self {
+            Rule::PageableMemcpy => 5,
+            Rule::FrequentCtxSynchronize => 4,
+            Rule::AllocChurn => 3,
+            Rule::LegacyStreamLaunch => 2,
+            Rule::SmallMemcpy => 1,
+        }
+    }
+
+    /// How many occurrences it takes before the rule is reported.
+    pub fn threshold(self) -> usize {
+        match self {
+            Rule::PageableMemcpy | Rule::LegacyStreamLaunch => 1,
+            Rule::AllocChurn | Rule::FrequentCtxSynchronize => 10,
+            Rule::SmallMemcpy => 100,
+        }
+    }
+
+    pub fn advice(self) -> &'static str {
+        match self {
+            Rule::PageableMemcpy => {
+                "copies through pageable host memory; allocate the buffer with cuMemHostAlloc or \
+                 register it with cuMemHostRegister"
+            }
+            Rule::SmallMemcpy => "many small copies; batch them into fewer, larger ones",
+            Rule::AllocChurn => {
+                "the same size is allocated and freed repeatedly; reuse the buffer or use \
+                 stream-ordered allocation (cuMemAllocAsync)"
+            }
+            Rule::LegacyStreamLaunch => {
+                "work on the legacy default stream serializes with every blocking stream; use a \
+                 created stream or per-thread default streams"
+            }
+            Rule::FrequentCtxSynchronize => {
+                "the whole context is synchronized often; wait on the stream or an event instead"
+            }
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rule::PageableMemcpy => "pageable-memcpy",
+            Rule::SmallMemcpy => "small-memcpy",
+            Rule::AllocChurn => "alloc-churn",
+            Rule::LegacyStreamLaunch => "legacy-stream-launch",
+            Rule::FrequentCtxSynchronize => "frequent-ctx-synchronize",
+        })
+    }
+}
+
+/// The occurrences of one rule for one subject.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub rule: Rule,
+    pub subject: String,
+    pub count: usize,
+    /// The host backtrace of the first occurrence.
+    pub backtrace: String,
+}
+
+impl Finding {
+    pub fn score(&self) -> usize {
+        self.rule.weight() * self.count
+    }
+}
+
+/// The findings over the threshold of their rule, highest score first.
+#[derive(Debug, Clone, Default)]
+pub struct LintReport {
+    pub findings: Vec<Finding>,
+}
+
+impl fmt::Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "CUDA lint: {} findings", self.findings.len())?;
+        for (i, finding) in self.findings.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>3}. [{}] {} x {} (score {})",
+                i + 1,
+                finding.rule,
+                finding.count,
+                finding.subject,
+                finding.score()
+            )?;
+            writeln!(f, "     {}", finding.rule.advice())?;
+            writeln!(f, "     first seen at:")?;
+            for line in finding.backtrace.lines() {
+                writeln!(f, "       {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Occurrences {
+    count: usize,
+    backtrace: Backtrace,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    findings: HashMap<(Rule, String), Occurrences>,
+    /// Frees not yet followed by an allocation of the same size and kind.
+    freed: HashMap<(usize, AllocKind), usize>,
+    launches: usize,
+    ctx_syncs: usize,
+}
+
+impl State {
+    fn hit(&mut self, rule: Rule, subject: String) {
+        self.findings
+            .entry((rule, subject))
+            .and_modify(|o| o.count += 1)
+            .or_insert_with(|| Occurrences {
+                count: 1,
+                backtrace: Backtrace::force_capture(),
+            });
+    }
+
+    fn alloc(&mut self, size: usize, kind: AllocKind) {
+        if matches!(kind, AllocKind::Async | AllocKind::Pool) {
+            // Stream-ordered allocation is the fix for churn, not an instance of it.
+            return;
+        }
+        if let Some(freed) = self.freed.get_mut(&(size, kind))
+            && *freed > 0
+        {
+            *freed -= 1;
+            self.hit(
+                Rule::AllocChurn,
+                format!("{} of {} bytes", kind.api(), size),
+            );
+        }
+    }
+
+    fn free(&mut self, size: usize, kind: AllocKind) {
+        *self.freed.entry((size, kind)).or_default() += 1;
+    }
+
+    fn ctx_synchronize(&mut self) {
+        // Synchronizing before any launch, as at startup, says nothing about the loop.
+        if self.launches == 0 {
+            return;
+        }
+        self.ctx_syncs += 1;
+        // More than one context synchronization per ten launches looks like one per iteration.
+        if self.ctx_syncs * 10 > self.launches {
+            self.hit(Rule::FrequentCtxSynchronize, "cuCtxSynchronize".to_string());
+        }
+    }
+
+    fn report(&self) -> LintReport {
+        let mut findings: Vec<_> = self
+            .findings
+            .iter()
+            .filter(|((rule, _), o)| o.count >= rule.threshold())
+            .map(|((rule, subject), o)| Finding {
+                rule: *rule,
+                subject: subject.clone(),
+                count: o.count,
+                backtrace: o.backtrace.to_string(),
+            })
+            .collect();
+        findings.sort_by(|a, b| {
+            b.score()
+                .cmp(&a.score())
+                .then(a.rule.cmp(&b.rule))
+                .then(a.subject.cmp(&b.subject))
+        });
+        LintReport { findings }
+    }
+}
+
+fn with_state(f: impl FnOnce(&mut State)) {
+    if !enabled() {
+        return;
+    }
+    install_exit_report();
+    f(&mut STATE.lock().unwrap());
+}
+
+/// Checks a successful memcpy. Called by [`crate::streams`].
+pub fn on_memcpy(
+    api: &'static str,
+    stream: CUstream,
+    host: *const c_void,
+    bytes: usize,
+    blocking: bool,
+) {
+    if !enabled() {
+        return;
+    }
+    let pageable = !host.is_null() && !driver::is_pinned(host);
+    let legacy = stream.is_null() || stream == CU_STREAM_LEGACY;
+    with_state(|s| {
+        if pageable {
+            s.hit(Rule::PageableMemcpy, api.to_string());
+        }
+        if bytes <= SMALL_MEMCPY_BYTES {
+            s.hit(Rule::SmallMemcpy, api.to_string());
+        }
+        if legacy && !blocking && api.contains("Async") {
+            s.hit(Rule::LegacyStreamLaunch, api.to_string());
+        }
+    });
+}
+
+/// Counts a successful allocation. Called by [`crate::memory`].
+pub fn on_alloc(size: usize, kind: AllocKind) {
+    with_state(|s| s.alloc(size, kind));
+}
+
+/// Counts a successful free. Called by [`crate::memory`].
+pub fn on_free(size: usize, kind: AllocKind) {
+    with_state(|s| s.free(size, kind));
+}
+
+/// Checks a kernel launch. Called by [`crate::launch`].
+pub fn on_launch(event: &LaunchEvent) {
+    if event.result != CUDA_SUCCESS {
+        return;
+    }
+    let legacy = event.stream == 0 || event.stream == CU_STREAM_LEGACY as usize;
+    with_state(|s| {
+        s.launches += 1;
+        if legacy {
+            let name = event
+                .kernel
+                .as_ref()
+                .map_or("<unknown kernel>", |k| k.demangled.as_str());
+            s.hit(Rule::LegacyStreamLaunch, name.to_string());
+        }
+    });
+}
+
+/// Counts a successful `cuCtxSynchronize`. Called by [`crate::streams`].
+pub fn on_ctx_synchronize() {
+    with_state(State::ctx_synchronize);
+}
+
+/// Returns the findings so far, ranked.
+pub fn report() -> LintReport {
+    STATE.lock().unwrap().report()
+}
+
+fn install_exit_report() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        if std::env::var_os(LINT_ENV).is_some() {
+            unsafe { libc::atexit(write_exit_report) };
+        }
+    });
+}
+
+extern "C" fn write_exit_report() {
+    let Some(dest) = std::env::var_os(LINT_ENV) else {
+        return;
+    };
+    let report = report().to_string();
+    if dest == "stderr" {
+        let _ = std::io::stderr().write_all(report.as_bytes());
+    } else if let Err(e) = std::fs::write(&dest, report) {
+        warn!("Failed to write lint report to {:?}: {}", dest, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(report: &LintReport) -> Vec<(Rule, &str, usize)> {
+        report
+            .findings
+            .iter()
+            .map(|f| (f.rule, f.subject.as_str(), f.count))
+            .collect()
+    }
+
+    #[test]
+    fn reports_rules_over_their_threshold() {
+        let mut s = State::default();
+        for _ in 0..99 {
+            s.hit(Rule::SmallMemcpy, "cuMemcpyHtoD_v2".into());
+        }
+        for _ in 0..9 {
+            s.hit(Rule::AllocChurn, "cuMemAlloc_v2 of 256 bytes".into());
+        }
+        assert!(s.report().findings.is_empty());
+
+        s.hit(Rule::SmallMemcpy, "cuMemcpyHtoD_v2".into());
+        s.hit(Rule::PageableMemcpy, "cuMemcpyDtoH_v2".into());
+        assert_eq!(
+            counts(&s.report()),
+            [
+                (Rule::SmallMemcpy, "cuMemcpyHtoD_v2", 100),
+                (Rule::PageableMemcpy, "cuMemcpyDtoH_v2", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn ranks_findings_by_score() {
+        let mut s = State::default();
+        for (rule, subject, count) in [
+            (Rule::LegacyStreamLaunch, "scale", 3),
+            (Rule::PageableMemcpy, "cuMemcpyHtoD_v2", 2),
+            (Rule::LegacyStreamLaunch, "fill", 5),
+            (Rule::PageableMemcpy, "cuMemcpyDtoH_v2", 2),
+            (Rule::FrequentCtxSynchronize, "cuCtxSynchronize", 10),
+        ] {
+            for _ in 0..count {
+                s.hit(rule, subject.into());
+            }
+        }
+        let report = s.report();
+        // Equal scores rank by rule, then subject
+        assert_eq!(
+            counts(&report),
+            [
+                (Rule::FrequentCtxSynchronize, "cuCtxSynchronize", 10),
+                (Rule::PageableMemcpy, "cuMemcpyDtoH_v2", 2),
+                (Rule::PageableMemcpy, "cuMemcpyHtoD_v2", 2),
+                (Rule::LegacyStreamLaunch, "fill", 5),
+                (Rule::LegacyStreamLaunch, "scale", 3),
+            ]
+        );
+        let scores: Vec<_> = report.findings.iter().map(Finding::score).collect();
+        assert_eq!(scores, [40, 10, 10, 10, 6]);
+    }
+
+    #[test]
+    fn pairs_frees_with_allocations_of_the_same_size_and_kind() {
+        let mut s = State::default();
+        s.alloc(256, AllocKind::Device);
+        s.free(256, AllocKind::Device);
+        s.alloc(512, AllocKind::Device);
+        s.alloc(256, AllocKind::Managed);
+        assert!(s.findings.is_empty());
+
+        // Each free pairs with one allocation
+        s.alloc(256, AllocKind::Device);
+        s.alloc(256, AllocKind::Device);
+        assert_eq!(
+            s.findings[&(Rule::AllocChurn, "cuMemAlloc_v2 of 256 bytes".to_string())].count,
+            1
+        );
+
+        for _ in 0..3 {
+            s.free(64, AllocKind::Async);
+            s.alloc(64, AllocKind::Async);
+        }
+        assert_eq!(s.findings.len(), 1);
+
+        for _ in 0..9 {
+            s.free(256, AllocKind::Device);
+            s.alloc(256, AllocKind::Device);
+        }
+        assert_eq!(
+            counts(&s.report()),
+            [(Rule::AllocChurn, "cuMemAlloc_v2 of 256 bytes", 10)]
+        );
+    }
+
+    #[test]
+    fn ignores_ctx_synchronizes_before_the_first_launch() {
+        let mut s = State::default();
+        for _ in 0..20 {
+            s.ctx_synchronize();
+        }
+        assert!(s.findings.is_empty());
+
+        s.launches = 20;
+        s.ctx_synchronize();
+        s.ctx_synchronize();
+        assert!(s.findings.is_empty());
+        // One synchronization per ten launches is fine, more is not
+        s.ctx_synchronize();
+        assert_eq!(s.findings.len(), 1);
+    }
+}
@@ -0,0 +1,760 @@
+//! Device memory allocation tracking.
+//!
+//! [`install_memory_hooks!`] interposes the driver allocation and free APIs and records every
+//! live allocation in [`ALLOCATIONS`], along with per-context peak usage and a timeline of
+//! allocation events. A report of leaks, peaks and the timeline can be produced on demand with
+//! [`AllocationTable::report`], or written at exit by setting `CUDA_HOOK_ALLOC_REPORT` to a file
+//! path (or `stderr`). Host backtraces are captured when `CUDA_HOOK_ALLOC_BACKTRACE=1`.
+//!
+//! Physical memory created through the VMM API (`cuMemCreate`) is tracked by handle, so that it
+//! counts towards usage and [`crate::quota`] budgets.
+
+use crate::args::PointerStatus;
+use crate::driver;
+use crate::ffi::*;
+use crate::handles::{self, HandleKind};
+use crate::lint;
+use crate::record;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::backtrace::Backtrace;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
+use tracing::warn;
+
+const TIMELINE_CAPACITY: usize = 1 << 20;
+
+static CAPTURE_BACKTRACES: Lazy<bool> =
+    Lazy::new(|| std::env::var("CUDA_HOOK_ALLOC_BACKTRACE").is_ok_and(|v| v == "1"));
+
+/// The process-wide allocation table fed by [`install_memory_hooks!`].
+pub static ALLOCATIONS: AllocationTable = AllocationTable::new();
+
+/// The API family an allocation was made through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AllocKind {
+    Device,
+    Pitched,
+    Async,
+    Pool,
+    Managed,
+    Physical,
+}
+
+impl AllocKind {
+    pub fn api(self) -> &'static str {
+        match self {
+            AllocKind::Device => "cuMemAlloc_v2",
+            AllocKind::Pitched => "cuMemAllocPitch_v2",
+            AllocKind::Async => "cuMemAllocAsync",
+            AllocKind::Pool => "cuMemAllocFromPoolAsync",
+            AllocKind::Managed => "cuMemAllocManaged",
+            AllocKind::Physical => "cuMemCreate",
+        }
+    }
+}
+
+/// A live device allocation. For [`AllocKind::Physical`] allocations `ptr` is the handle
+/// returned by `cuMemCreate`.
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    pub ptr: CUdeviceptr,
+    pub size: usize,
+    pub kind: AllocKind,
+    /// The context current when the allocation was made, as an address.
+    pub context: usize,
+    pub device: Option<CUdevice>,
+    /// Time since the interposer was loaded.
+    pub at: Duration,
+    pub backtrace: Option<Arc<Backtrace>>,
+}
+
+impl Allocation {
+    fn new(ptr: u64, size: usize, kind: AllocKind, device: Option<CUdevice>) -> Self {
+        Self {
+            ptr,
+            size,
+            kind,
+            context: driver::current_context() as usize,
+            device,
+            at: crate::elapsed(),
+            backtrace: CAPTURE_BACKTRACES.then(|| Arc::new(Backtrace::force_capture())),
+        }
+    }
+
+    pub fn contains(&self, addr: CUdeviceptr) -> bool {
+        addr >= self.ptr && addr - self.ptr < self.size as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimelineOp {
+    Alloc,
+    Free,
+}
+
+/// One allocation or free, with the bytes in use on its context afterwards.
+#[derive(Debug, Clone, Copy)]
+pub struct TimelineEvent {
+    pub at: Duration,
+    pub op: TimelineOp,
+    pub ptr: CUdeviceptr,
+    pub size: usize,
+    pub context: usize,
+    pub device: Option<CUdevice>,
+    pub in_use: usize,
+}
+
+/// Byte counters for one context and device, or for the whole process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    pub current: usize,
+    pub peak: usize,
+    pub allocations: u64,
+}
+
+impl Usage {
+    fn add(&mut self, size: usize) {
+        self.current += size;
+        self.peak = self.peak.max(self.current);
+        self.allocations += 1;
+    }
+
+    fn sub(&mut self, size: usize) {
+        self.current = self.current.saturating_sub(size);
+    }
+}
+
+pub type UsageKey = (usize, Option<CUdevice>);
+
+struct State {
+    live: BTreeMap<CUdeviceptr, Allocation>,
+    physical: BTreeMap<u64, Allocation>,
+    usage: BTreeMap<UsageKey, Usage>,
+    total: Usage,
+    timeline: VecDeque<TimelineEvent>,
+    /// The address ranges freed by the frees in the timeline, as disjoint pieces keyed by start
+    /// and holding their end and the most recent free covering them, so lookups need not scan
+    /// the timeline.
+    freed: BTreeMap<CUdeviceptr, (CUdeviceptr, TimelineEvent)>,
+    span: Option<(CUdeviceptr, CUdeviceptr)>,
+}
+
+impl State {
+    fn push_timeline(&mut self, event: TimelineEvent) {
+        if self.timeline.len() == TIMELINE_CAPACITY
+            && let Some(old) = self.timeline.pop_front()
+            && old.op == TimelineOp::Free
+        {
+            let end = old.ptr.saturating_add(old.size as u64);
+            let pieces: Vec<_> = self
+                .freed
+                .range(old.ptr..end)
+                .filter(|(_, (_, e))| e.ptr == old.ptr && e.at == old.at)
+                .map(|(&start, _)| start)
+                .collect();
+            for start in pieces {
+                self.freed.remove(&start);
+            }
+        }
+        self.timeline.push_back(event);
+    }
+
+    /// Indexes the range a device allocation free released, over the parts of older frees it
+    /// overlaps.
+    fn index_free(&mut self, event: TimelineEvent) {
+        let (start, end) = (event.ptr, event.ptr.saturating_add(event.size as u64));
+        // Pieces are disjoint, so those overlapping are the last ones starting before `end`
+        let overlapping: Vec<_> = self
+            .freed
+            .range(..end)
+            .rev()
+            .take_while(|(_, (piece_end, _))| *piece_end > start)
+            .map(|(&piece_start, &(piece_end, e))| (piece_start, piece_end, e))
+            .collect();
+        for (piece_start, piece_end, e) in overlapping {
+            self.freed.remove(&piece_start);
+            if piece_start < start {
+                self.freed.insert(piece_start, (start, e));
+            }
+            if piece_end > end {
+                self.freed.insert(end, (piece_end, e));
+            }
+        }
+        self.freed.insert(start, (end, event));
+    }
+}
+
+/// Live allocations keyed by device pointer.
+pub struct AllocationTable {
+    state: Mutex<State>,
+}
+
+impl Default for AllocationTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AllocationTable {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                live: BTreeMap::new(),
+                physical: BTreeMap::new(),
+                usage: BTreeMap::new(),
+                total: Usage {
+                    current: 0,
+                    peak: 0,
+                    allocations: 0,
+                },
+                timeline: VecDeque::new(),
+                freed: BTreeMap::new(),
+                span: None,
+            }),
+        }
+    }
+
+    /// Records a successful allocation made on the calling thread's current context.
+    pub fn record_alloc(&self, ptr: CUdeviceptr, size: usize, kind: AllocKind) {
+        let device = driver::current_device();
+        self.track(Allocation::new(ptr, size, kind, device));
+    }
+
+    /// Records a successful free, returning the allocation it released.
+    pub fn record_free(&self, ptr: CUdeviceptr) -> Option<Allocation> {
+        let alloc = self.untrack(ptr, false);
+        if alloc.is_none() {
+            warn!("Free of untracked device pointer {:#x}", ptr);
+        }
+        alloc
+    }
+
+    /// Records physical memory created through `cuMemCreate`. These are keyed by their
+    /// allocation handle and count towards usage, but have no device address of their own.
+    pub fn record_create(&self, handle: u64, size: usize, device: Option<CUdevice>) {
+        self.track(Allocation::new(handle, size, AllocKind::Physical, device));
+    }
+
+    /// Records a `cuMemRelease` of a handle returned by `cuMemCreate`.
+    pub fn record_release(&self, handle: u64) -> Option<Allocation> {
+        self.untrack(handle, true)
+    }
+
+    fn track(&self, alloc: Allocation) {
+        install_exit_report();
+        let mut state = self.state.lock().unwrap();
+        let usage = state
+            .usage
+            .entry((alloc.context, alloc.device))
+            .or_default();
+        usage.add(alloc.size);
+        let in_use = usage.current;
+        state.total.add(alloc.size);
+        state.push_timeline(TimelineEvent {
+            at: alloc.at,
+            op: TimelineOp::Alloc,
+            ptr: alloc.ptr,
+            size: alloc.size,
+            context: alloc.context,
+            device: alloc.device,
+            in_use,
+        });
+        let table = if alloc.kind == AllocKind::Physical {
+            &mut state.physical
+        } else {
+            let end = alloc.ptr + alloc.size as u64;
+            state.span = Some(match state.span {
+                Some((lo, hi)) => (lo.min(alloc.ptr), hi.max(end)),
+                None => (alloc.ptr, end),
+            });
+            &mut state.live
+        };
+        if let Some(prev) = table.insert(alloc.ptr, alloc) {
+            warn!(
+                "Allocation at {:#x} was never freed before being reused",
+                prev.ptr
+            );
+        }
+    }
+
+    fn untrack(&self, key: u64, physical: bool) -> Option<Allocation> {
+        let mut state = self.state.lock().unwrap();
+        let table = if physical {
+            &mut state.physical
+        } else {
+            &mut state.live
+        };
+        let alloc = table.remove(&key)?;
+        let usage = state
+            .usage
+            .entry((alloc.context, alloc.device))
+            .or_default();
+        usage.sub(alloc.size);
+        let in_use = usage.current;
+        state.total.sub(alloc.size);
+        let event = TimelineEvent {
+            at: crate::elapsed(),
+            op: TimelineOp::Free,
+            ptr: key,
+            size: alloc.size,
+            context: alloc.context,
+            device: alloc.device,
+            in_use,
+        };
+        state.push_timeline(event);
+        if !physical {
+            state.index_free(event);
+        }
+        Some(alloc)
+    }
+
+    /// Bytes currently allocated on `device`, across all contexts.
+    pub fn device_usage(&self, device: CUdevice) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .usage
+            .iter()
+            .filter(|((_, dev), _)| *dev == Some(device))
+            .map(|(_, usage)| usage.current)
+            .sum()
+    }
+
+    /// Returns the live allocation containing `addr`, if any.
+    pub fn find(&self, addr: CUdeviceptr) -> Option<Allocation> {
+        let state = self.state.lock().unwrap();
+        state
+            .live
+            .range(..=addr)
+            .next_back()
+            .map(|(_, a)| a)
+            .filter(|a| a.contains(addr))
+            .cloned()
+    }
+
+    /// Returns what `addr` points into: the live allocation containing it, or else the most
+    /// recent free of an allocation that contained it, if that is still in the timeline. `None`
+    /// if `addr` is outside the span of every allocation made so far.
+    pub fn pointer_status(&self, addr: CUdeviceptr) -> Option<PointerStatus> {
+        let state = self.state.lock().unwrap();
+        let (lo, hi) = state.span?;
+        if addr < lo || addr >= hi {
+            return None;
+        }
+        let live = state.live.range(..=addr).next_back().map(|(_, a)| a);
+        if let Some(alloc) = live.filter(|a| a.contains(addr)) {
+            return Some(PointerStatus::Live {
+                base: alloc.ptr,
+                offset: addr - alloc.ptr,
+            });
+        }
+        let freed = state
+            .freed
+            .range(..=addr)
+            .next_back()
+            .map(|(_, piece)| piece);
+        Some(match freed.filter(|(end, _)| addr < *end) {
+            Some((_, free)) => PointerStatus::Freed {
+                base: free.ptr,
+                size: free.size,
+            },
+            None => PointerStatus::Unknown,
+        })
+    }
+
+    /// The address range spanned by every device allocation made so far, live or not.
+    pub fn span(&self) -> Option<(CUdeviceptr, CUdeviceptr)> {
+        self.state.lock().unwrap().span
+    }
+
+    pub fn live(&self) -> Vec<Allocation> {
+        self.state.lock().unwrap().live.values().cloned().collect()
+    }
+
+    /// Snapshots leaks, usage counters and the timeline.
+    pub fn report(&self) -> AllocationReport {
+        let state = self.state.lock().unwrap();
+        AllocationReport {
+            leaks: state
+                .live
+                .values()
+                .chain(state.physical.values())
+                .cloned()
+                .collect(),
+            usage: state.usage.iter().map(|(k, v)| (*k, *v)).collect(),
+            total: state.total,
+            timeline: state.timeline.iter().copied().collect(),
+        }
+    }
+}
+
+/// A snapshot of the allocation table.
+#[derive(Debug, Clone)]
+pub struct AllocationReport {
+    /// Allocations still live when the report was taken.
+    pub leaks: Vec<Allocation>,
+    /// Usage per (context, device).
+    pub usage: Vec<(UsageKey, Usage)>,
+    pub total: Usage,
+    /// The most recent allocation events, oldest first.
+    pub timeline: Vec<TimelineEvent>,
+}
+
+fn fmt_device(device: Option<CUdevice>) -> String {
+    device.map_or_else(|| "?".to_string(), |d| d.to_string())
+}
+
+impl fmt::Display for AllocationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "=== CUDA allocation report ===")?;
+        writeln!(
+            f,
+            "peak usage: {} bytes over {} allocations",
+            self.total.peak, self.total.allocations
+        )?;
+        for ((ctx, dev), usage) in &self.usage {
+            writeln!(
+                f,
+                "  context {:#x} device {}: peak {} bytes, in use {} bytes, {} allocations",
+                ctx,
+                fmt_device(*dev),
+                usage.peak,
+                usage.current,
+                usage.allocations
+            )?;
+        }
+
+        let leaked: usize = self.leaks.iter().map(|a| a.size).sum();
+        writeln!(
+            f,
+            "{} leaked allocations, {} bytes:",
+            self.leaks.len(),
+            leaked
+        )?;
+        for a in &self.leaks {
+            writeln!(
+                f,
+                "  {:#x}: {} bytes via {} (context {:#x} device {}) at {:.3?}",
+                a.ptr,
+                a.size,
+                a.kind.api(),
+                a.context,
+                fmt_device(a.device),
+                a.at
+            )?;
+            if let Some(bt) = &a.backtrace {
+                for line in bt.to_string().lines() {
+                    writeln!(f, "      {line}")?;
+                }
+            }
+        }
+
+        writeln!(f, "timeline ({} events):", self.timeline.len())?;
+        for e in &self.timeline {
+            let op = match e.op {
+                TimelineOp::Alloc => "alloc",
+                TimelineOp::Free => "free ",
+            };
+            writeln!(
+                f,
+                "  {:>12.3?} {} {:#x} {} bytes (context {:#x} device {}, {} bytes in use)",
+                e.at,
+                op,
+                e.ptr,
+                e.size,
+                e.context,
+                fmt_device(e.device),
+                e.in_use
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn install_exit_report() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        if std::env::var_os("CUDA_HOOK_ALLOC_REPORT").is_some() {
+            unsafe { libc::atexit(write_exit_report) };
+        }
+    });
+}
+
+extern "C" fn write_exit_report() {
+    let Some(dest) = std::env::var_os("CUDA_HOOK_ALLOC_REPORT") else {
+        return;
+    };
+    let report = ALLOCATIONS.report().to_string();
+    if dest == "stderr" {
+        let _ = std::io::stderr().write_all(report.as_bytes());
+    } else if let Err(e) = std::fs::write(&dest, report) {
+        warn!("Failed to write allocation report to {:?}: {}", dest, e);
+    }
+}
+
+/// Records the result of an allocation call. Used by [`install_memory_hooks!`].
+///
+/// Hands the application a virtual address when device pointers are virtualized (see
+/// [`crate::handles`]).
+///
+/// # Safety
+/// `dptr` must be the output pointer passed to the allocation call.
+pub unsafe fn after_alloc(rc: CUresult, dptr: *mut CUdeviceptr, size: usize, kind: AllocKind) {
+    unsafe { record::on_alloc(rc, dptr, size, kind) };
+    if rc == CUDA_SUCCESS && !dptr.is_null() {
+        ALLOCATIONS.record_alloc(unsafe { *dptr }, size, kind);
+        lint::on_alloc(size, kind);
+        if kind != AllocKind::Managed {
+            unsafe { *dptr = handles::register_allocation(*dptr, size) };
+        }
+    }
+}
+
+/// Records the result of a free call. Used by [`install_memory_hooks!`].
+pub fn after_free(rc: CUresult, ptr: CUdeviceptr) {
+    record::on_free(rc, ptr);
+    handles::release(&rc, HandleKind::DevicePtr, ptr);
+    if rc == CUDA_SUCCESS
+        && ptr != 0
+        && let Some(alloc) = ALLOCATIONS.record_free(ptr)
+    {
+        lint::on_free(alloc.size, alloc.kind);
+    }
+}
+
+/// Installs hooks on the driver allocation APIs that feed [`ALLOCATIONS`] and enforce the
+/// budgets in [`crate::quota`].
+///
+/// The hooked symbols are listed in [`crate::hook_sets::HOOK_SETS`], so
+/// `cuda-interposer-build` leaves them out of the generated passthroughs.
+#[macro_export]
+macro_rules! install_memory_hooks {
+    () => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemAlloc_v2(
+                dptr: *mut $crate::ffi::CUdeviceptr,
+                bytesize: usize
+            ) -> $crate::ffi::CUresult {
+                let Some(_reservation) = $crate::quota::reserve(None, bytesize) else {
+                    return $crate::ffi::CUDA_ERROR_OUT_OF_MEMORY;
+                };
+                let rc = unsafe { (*__real_cuMemAlloc_v2)(dptr, bytesize) };
+                unsafe { $crate::memory::after_alloc(rc, dptr, bytesize, $crate::memory::AllocKind::Device) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemAllocPitch_v2(
+                dptr: *mut $crate::ffi::CUdeviceptr,
+                pitch: *mut usize,
+                width_in_bytes: usize,
+                height: usize,
+                element_size_bytes: $crate::libc::c_uint
+            ) -> $crate::ffi::CUresult {
+                let Some(mut reservation) =
+                    $crate::quota::reserve(None, width_in_bytes.saturating_mul(height))
+                else {
+                    return $crate::ffi::CUDA_ERROR_OUT_OF_MEMORY;
+                };
+                let rc = unsafe {
+                    (*__real_cuMemAllocPitch_v2)(dptr, pitch, width_in_bytes, height, element_size_bytes)
+                };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    // Rows are padded to the pitch, which is what the allocation takes
+                    let size = unsafe { *pitch }.saturating_mul(height);
+                    if !reservation.grow(size) {
+                        unsafe { (*__real_cuMemFree_v2)(*dptr) };
+                        return $crate::ffi::CUDA_ERROR_OUT_OF_MEMORY;
+                    }
+                    unsafe { $crate::memory::after_alloc(rc, dptr, size, $crate::memory::AllocKind::Pitched) };
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemAllocAsync(
+                dptr: *mut $crate::ffi::CUdeviceptr,
+                bytesize: usize,
+                stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let Some(_reservation) = $crate::quota::reserve(None, bytesize) else {
+                    return $crate::ffi::CUDA_ERROR_OUT_OF_MEMORY;
+                };
+                let rc = unsafe { (*__real_cuMemAllocAsync)(dptr, bytesize, stream) };
+                unsafe { $crate::memory::after_alloc(rc, dptr, bytesize, $crate::memory::AllocKind::Async) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemAllocFromPoolAsync(
+                dptr: *mut $crate::ffi::CUdeviceptr,
+                bytesize: usize,
+                pool: $crate::ffi::CUmemoryPool,
+                stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let Some(_reservation) = $crate::quota::reserve(None, bytesize) else {
+                    return $crate::ffi::CUDA_ERROR_OUT_OF_MEMORY;
+                };
+                let rc = unsafe { (*__real_cuMemAllocFromPoolAsync)(dptr, bytesize, pool, stream) };
+                unsafe { $crate::memory::after_alloc(rc, dptr, bytesize, $crate::memory::AllocKind::Pool) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemAllocManaged(
+                dptr: *mut $crate::ffi::CUdeviceptr,
+                bytesize: usize,
+                flags: $crate::libc::c_uint
+            ) -> $crate::ffi::CUresult {
+                let Some(_reservation) = $crate::quota::reserve(None, bytesize) else {
+                    return $crate::ffi::CUDA_ERROR_OUT_OF_MEMORY;
+                };
+                let rc = unsafe { (*__real_cuMemAllocManaged)(dptr, bytesize, flags) };
+                unsafe { $crate::memory::after_alloc(rc, dptr, bytesize, $crate::memory::AllocKind::Managed) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemFree_v2(dptr: $crate::ffi::CUdeviceptr) -> $crate::ffi::CUresult {
+                let rc = match $crate::checkpoint::free_restored(dptr, None) {
+                    Some(rc) => rc,
+                    None => unsafe { (*__real_cuMemFree_v2)(dptr) },
+                };
+                $crate::memory::after_free(rc, dptr);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemFreeAsync(
+                dptr: $crate::ffi::CUdeviceptr,
+                stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = match $crate::checkpoint::free_restored(dptr, Some(stream)) {
+                    Some(rc) => rc,
+                    None => unsafe { (*__real_cuMemFreeAsync)(dptr, stream) },
+                };
+                $crate::memory::after_free(rc, dptr);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemCreate(
+                handle: *mut $crate::ffi::CUmemGenericAllocationHandle,
+                size: usize,
+                prop: *const $crate::ffi::CUmemAllocationProp,
+                flags: u64
+            ) -> $crate::ffi::CUresult {
+                // Physical device, as the other allocations are charged to
+                let mut physical = unsafe { prop.as_ref() }.copied();
+                if let Some(prop) = &mut physical {
+                    match $crate::devices::physical_location(prop.location) {
+                        Ok(location) => prop.location = location,
+                        Err(e) => return e,
+                    }
+                }
+                let prop = physical.as_ref().map_or(prop, |p| p as *const _);
+                let device = unsafe { $crate::quota::allocation_device(prop) };
+                let Some(_reservation) = $crate::quota::reserve(device, size) else {
+                    return $crate::ffi::CUDA_ERROR_OUT_OF_MEMORY;
+                };
+                let rc = unsafe { (*__real_cuMemCreate)(handle, size, prop, flags) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    $crate::memory::ALLOCATIONS.record_create(unsafe { *handle }, size, device);
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemRelease(
+                handle: $crate::ffi::CUmemGenericAllocationHandle
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemRelease)(handle) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    $crate::memory::ALLOCATIONS.record_release(handle);
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemGetInfo_v2(free: *mut usize, total: *mut usize) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemGetInfo_v2)(free, total) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    unsafe { $crate::quota::virtualize_mem_info(&mut *free, &mut *total) };
+                }
+                rc
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alloc(table: &AllocationTable, ptr: CUdeviceptr, size: usize) {
+        table.track(Allocation {
+            ptr,
+            size,
+            kind: AllocKind::Device,
+            context: 0,
+            device: None,
+            at: crate::elapsed(),
+            backtrace: None,
+        });
+    }
+
+    #[test]
+    fn classifies_pointers_into_live_and_freed_allocations() {
+        let table = AllocationTable::new();
+        assert_eq!(table.pointer_status(0x1000), None);
+        alloc(&table, 0x1000, 0x100);
+        alloc(&table, 0x2000, 0x1000);
+        table.record_free(0x2000);
+        // A smaller allocation reusing the start of the freed range, freed in turn
+        alloc(&table, 0x2000, 0x10);
+
+        assert_eq!(
+            table.pointer_status(0x1010),
+            Some(PointerStatus::Live {
+                base: 0x1000,
+                offset: 0x10
+            })
+        );
+        assert_eq!(table.pointer_status(0x1800), Some(PointerStatus::Unknown));
+        assert_eq!(
+            table.pointer_status(0x2008),
+            Some(PointerStatus::Live {
+                base: 0x2000,
+                offset: 8
+            })
+        );
+        table.record_free(0x2000);
+        assert_eq!(
+            table.pointer_status(0x2800),
+            Some(PointerStatus::Freed {
+                base: 0x2000,
+                size: 0x1000
+            })
+        );
+        assert_eq!(
+            table.pointer_status(0x2008),
+            Some(PointerStatus::Freed {
+                base: 0x2000,
+                size: 0x10
+            })
+        );
+        assert_eq!(table.pointer_status(0x3000), None);
+    }
+}
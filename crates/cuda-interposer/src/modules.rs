@@ -0,0 +1,156 @@
+//! Module and kernel metadata.
+//!
+//! [`install_module_hooks!`] remembers the name of every function looked up through
+//! `cuModuleGetFunction` or `cuLibraryGetKernel`, so that other hook sets can name the kernel
+//! behind a `CUfunction`. [`kernel_name`] falls back to `cuFuncGetName` for functions obtained
+//! some other way. Runtime host stubs are named by [`crate::registration`] as the application
+//! registers its kernels, or else from the dynamic symbol table.
+//!
+//! Images passed to [`register_image`] (by [`install_capture_hooks!`] when argument decoding is
+//! enabled) are parsed for the parameter layouts of their kernels, see [`param_layout`].
+
+use crate::cubin::{Cubin, ParamLayout};
+use crate::driver;
+use crate::fatbin::{EntryKind, Fatbin};
+use crate::ffi::*;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ffi::{CStr, c_char, c_void};
+use std::sync::{Arc, RwLock};
+use tracing::debug;
+
+pub use cuda_interposer_common::kernel::{KernelName, demangle};
+
+static NAMES: Lazy<RwLock<HashMap<usize, Arc<KernelName>>>> = Lazy::new(Default::default);
+static LAYOUTS: Lazy<RwLock<HashMap<String, Arc<ParamLayout>>>> = Lazy::new(Default::default);
+
+/// Records that `func`, a `CUfunction` or `CUkernel`, is the kernel called `name`.
+///
+/// # Safety
+/// `name` must be null or a valid C string.
+pub unsafe fn remember_function(func: *mut c_void, name: *const c_char) {
+    if func.is_null() || name.is_null() {
+        return;
+    }
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    NAMES
+        .write()
+        .unwrap()
+        .insert(func as usize, Arc::new(KernelName::new(name)));
+}
+
+/// Forgets the name recorded for the function or host stub at `key`.
+pub fn forget_function(key: usize) {
+    NAMES.write().unwrap().remove(&key);
+}
+
+/// Returns the name of the kernel behind `func`, if it is known or the driver can tell.
+pub fn kernel_name(func: CUfunction) -> Option<Arc<KernelName>> {
+    if func.is_null() {
+        return None;
+    }
+    lookup(func as usize, || driver::function_name(func))
+}
+
+/// Returns the name of the kernel whose runtime host stub is at `func`, as passed to
+/// `cudaLaunchKernel`. The stub carries the kernel's mangled name when the application exports
+/// its symbols.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn host_kernel_name(func: *const c_void) -> Option<Arc<KernelName>> {
+    if func.is_null() {
+        return None;
+    }
+    lookup(func as usize, || {
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        if unsafe { libc::dladdr(func, &mut info) } == 0 || info.dli_sname.is_null() {
+            return None;
+        }
+        std::ptr::eq(info.dli_saddr, func).then(|| {
+            unsafe { CStr::from_ptr(info.dli_sname) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    })
+}
+
+fn lookup(key: usize, resolve: impl FnOnce() -> Option<String>) -> Option<Arc<KernelName>> {
+    if let Some(name) = NAMES.read().unwrap().get(&key) {
+        return Some(name.clone());
+    }
+    let name = Arc::new(KernelName::new(resolve()?));
+    NAMES.write().unwrap().insert(key, name.clone());
+    Some(name)
+}
+
+/// Records the parameter layouts of the kernels in a cubin or fatbin image. PTX images carry no
+/// layouts and are ignored.
+pub fn register_image(image: &[u8]) {
+    if let Ok(fatbin) = Fatbin::parse(image) {
+        for entry in fatbin.entries().flatten() {
+            if entry.kind == EntryKind::Cubin
+                && let Ok(data) = entry.data()
+            {
+                register_cubin(&data);
+            }
+        }
+    } else {
+        register_cubin(image);
+    }
+}
+
+fn register_cubin(image: &[u8]) {
+    let kernels = match Cubin::parse(image).and_then(|c| c.kernels()) {
+        Ok(kernels) => kernels,
+        Err(e) => return debug!("No kernel metadata in module image: {}", e),
+    };
+    let mut layouts = LAYOUTS.write().unwrap();
+    for (name, info) in kernels {
+        layouts.entry(name).or_insert_with(|| Arc::new(info.params));
+    }
+}
+
+/// Returns the parameter layout of the kernel with mangled name `name`, if an image defining it
+/// has been registered.
+pub fn param_layout(name: &str) -> Option<Arc<ParamLayout>> {
+    LAYOUTS.read().unwrap().get(name).cloned()
+}
+
+/// Installs hooks that record kernel names as functions are looked up in modules and libraries.
+///
+/// The hooked symbols are listed in [`crate::hook_sets::HOOK_SETS`], so
+/// `cuda-interposer-build` leaves them out of the generated passthroughs.
+#[macro_export]
+macro_rules! install_module_hooks {
+    () => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuModuleGetFunction(
+                hfunc: *mut $crate::ffi::CUfunction,
+                hmod: $crate::ffi::CUmodule,
+                name: *const $crate::libc::c_char
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuModuleGetFunction)(hfunc, hmod, name) };
+                unsafe { $crate::record::on_get_function(rc, hfunc, hmod, name) };
+                unsafe { $crate::checkpoint::on_get_function(rc, hfunc, hmod, name) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    unsafe { $crate::modules::remember_function(*hfunc, name) };
+                }
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Function, hfunc) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuLibraryGetKernel(
+                p_kernel: *mut $crate::ffi::CUkernel,
+                library: $crate::ffi::CUlibrary,
+                name: *const $crate::libc::c_char
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuLibraryGetKernel)(p_kernel, library, name) };
+                if rc == $crate::ffi::CUDA_SUCCESS {
+                    unsafe { $crate::modules::remember_function(*p_kernel, name) };
+                }
+                rc
+            }
+        }
+    };
+}
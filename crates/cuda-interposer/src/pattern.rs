@@ -0,0 +1,118 @@
+//! Glob patterns for selecting symbols and kernels in configuration.
+//!
+//! `*` matches any run of characters and `?` any single character; everything else matches
+//! itself. A [`PatternSet`] is a comma-separated list of patterns that matches when any of them
+//! does, as in `CUDA_HOOK_BLOCKING=gemm*,*reduce*`.
+
+/// Returns whether `text` matches the glob `pattern` in full.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t) = (pattern.as_bytes(), text.as_bytes());
+    let (mut pi, mut ti) = (0, 0);
+    // Where to resume after the last `*`: the pattern index after it, and the text index it
+    // currently stands in for.
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        match p.get(pi) {
+            Some(b'*') => {
+                star = Some((pi + 1, ti));
+                pi += 1;
+            }
+            Some(&c) if c == b'?' || c == t[ti] => {
+                pi += 1;
+                ti += 1;
+            }
+            _ => match star {
+                Some((resume, matched)) => {
+                    pi = resume;
+                    ti = matched + 1;
+                    star = Some((resume, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
+/// A list of glob patterns.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PatternSet {
+    patterns: Vec<String>,
+}
+
+impl PatternSet {
+    /// Parses a comma-separated list of patterns. Empty entries are ignored.
+    pub fn parse(s: &str) -> Self {
+        Self {
+            patterns: s
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        self.patterns.iter().any(|p| glob_match(p, text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        for (pattern, text) in [
+            ("cuInit", "cuInit"),
+            ("cu*", "cu"),
+            ("cu*", "cuMemcpy"),
+            ("*", ""),
+            ("*", "anything"),
+            ("**", "x"),
+            ("cuMem?py", "cuMemcpy"),
+            ("?u*", "cuInit"),
+            // The first `*` must give back characters to the second
+            ("*reduce*", "block_reduce_sum"),
+            ("*a*b", "aaxab"),
+            ("a*b?c", "axxbbyc"),
+            ("*_v2", "cuMemcpy_v2_v2"),
+            ("", ""),
+        ] {
+            assert!(glob_match(pattern, text), "{pattern} {text}");
+        }
+        for (pattern, text) in [
+            ("cuInit", "cuInitX"),
+            ("cuInit", "cuIni"),
+            ("cu?", "cu"),
+            ("?", ""),
+            ("*a*b", "aaxabc"),
+            ("a*b?c", "axxbc"),
+            ("*_v2", "cuMemcpy_v3"),
+            ("", "cuInit"),
+        ] {
+            assert!(!glob_match(pattern, text), "{pattern} {text}");
+        }
+    }
+
+    #[test]
+    fn parses_pattern_sets() {
+        let set = PatternSet::parse(" gemm* , *reduce*,,");
+        assert!(!set.is_empty());
+        assert!(set.matches("gemm_nt"));
+        assert!(set.matches("block_reduce"));
+        assert!(!set.matches("scale"));
+
+        for empty in ["", " ", ",", " , "] {
+            let set = PatternSet::parse(empty);
+            assert!(set.is_empty());
+            assert_eq!(set, PatternSet::default());
+            assert!(!set.matches(""));
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Per-process device memory budgets.
+//!
+//! When a budget is set for a device, the hooks installed by [`install_memory_hooks!`] fail any
+//! allocation that would take the process over it with `CUDA_ERROR_OUT_OF_MEMORY`. Each
+//! allocation [`reserve`]s its bytes before it reaches the driver, so concurrent allocations
+//! cannot overrun the budget together. `cuMemGetInfo_v2` reports the budget as the device's
+//! total memory so that frameworks size their caches to it. Usage is what
+//! [`crate::memory::ALLOCATIONS`] has tracked on the device.
+//!
+//! Budgets are read from `CUDA_HOOK_MEM_QUOTA`, either one size applied to every device (`8G`)
+//! or per-device sizes (`0=8G,1=512M`), optionally mixed (`4G,1=8G`). They can be changed at
+//! runtime with [`set_limit`].
+
+use crate::config::{ConfigError, parse_size};
+use crate::driver;
+use crate::ffi::*;
+use crate::memory::ALLOCATIONS;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, RwLock};
+use tracing::warn;
+
+const QUOTA_ENV: &str = "CUDA_HOOK_MEM_QUOTA";
+
+static LIMITS: Lazy<RwLock<Limits>> = Lazy::new(|| {
+    let limits = match std::env::var(QUOTA_ENV) {
+        Ok(spec) => Limits::parse(&spec).unwrap_or_else(|e| {
+            warn!("Ignoring device memory quota: {}", e);
+            Limits::default()
+        }),
+        Err(_) => Limits::default(),
+    };
+    RwLock::new(limits)
+});
+
+/// Byte budgets, per device with an optional default for the rest.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub default: Option<usize>,
+    pub devices: BTreeMap<CUdevice, usize>,
+}
+
+impl Limits {
+    /// Parses a `CUDA_HOOK_MEM_QUOTA` specification.
+    pub fn parse(spec: &str) -> Result<Self, ConfigError> {
+        let mut limits = Limits::default();
+        for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let err = |reason| ConfigError::new(QUOTA_ENV, item, reason);
+            match item.split_once('=') {
+                Some((dev, size)) => {
+                    let dev = dev.trim().parse().map_err(|_| err("bad device ordinal"))?;
+                    let size = parse_size(size).ok_or_else(|| err("bad size"))?;
+                    limits.devices.insert(dev, size);
+                }
+                None => limits.default = Some(parse_size(item).ok_or_else(|| err("bad size"))?),
+            }
+        }
+        Ok(limits)
+    }
+
+    pub fn get(&self, device: CUdevice) -> Option<usize> {
+        self.devices.get(&device).copied().or(self.default)
+    }
+}
+
+/// Sets the budget for `device`, or the default budget when `device` is `None`. A `None` limit
+/// removes the budget.
+pub fn set_limit(device: Option<CUdevice>, limit: Option<usize>) {
+    let mut limits = LIMITS.write().unwrap();
+    match (device, limit) {
+        (Some(dev), Some(limit)) => {
+            limits.devices.insert(dev, limit);
+        }
+        (Some(dev), None) => {
+            limits.devices.remove(&dev);
+        }
+        (None, limit) => limits.default = limit,
+    }
+}
+
+/// Returns the budget for `device`, if one is set.
+pub fn limit(device: CUdevice) -> Option<usize> {
+    LIMITS.read().unwrap().get(device)
+}
+
+/// Bytes held by allocations in flight, which are not tracked yet. Counting them lets concurrent
+/// allocations check the budget without all passing before any of them is tracked.
+static RESERVED: Lazy<Mutex<HashMap<CUdevice, usize>>> = Lazy::new(Default::default);
+
+/// Bytes held against the budget of a device while an allocation is made. Dropped once the
+/// allocation is tracked, or has failed.
+#[derive(Debug)]
+pub struct Reservation {
+    device: Option<CUdevice>,
+    size: usize,
+}
+
+/// Reserves `size` bytes in the budget of `device`, or of the current context's device when
+/// `device` is `None`. Returns `None` if they do not fit.
+pub fn reserve(device: Option<CUdevice>, size: usize) -> Option<Reservation> {
+    let unlimited = Reservation {
+        device: None,
+        size: 0,
+    };
+    let Some(device) = device.or_else(driver::current_device) else {
+        return Some(unlimited);
+    };
+    let Some(limit) = limit(device) else {
+        return Some(unlimited);
+    };
+    let mut reserved = RESERVED.lock().unwrap();
+    let held = reserved.entry(device).or_default();
+    let used = ALLOCATIONS.device_usage(device).saturating_add(*held);
+    if used.saturating_add(size) > limit {
+        warn!(
+            "Denying {} byte allocation on device {}: {} of {} bytes in use",
+            size, device, used, limit
+        );
+        return None;
+    }
+    *held += size;
+    Some(Reservation {
+        device: Some(device),
+        size,
+    })
+}
+
+impl Reservation {
+    /// Grows the reservation to `size` bytes, for allocations the driver made larger than asked,
+    /// such as pitched ones. Returns whether they fit in the budget.
+    pub fn grow(&mut self, size: usize) -> bool {
+        let Some(device) = self.device else {
+            return true;
+        };
+        if size <= self.size {
+            return true;
+        }
+        match reserve(Some(device), size - self.size) {
+            Some(extra) => {
+                self.size += extra.size;
+                std::mem::forget(extra);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(device) = self.device {
+            let mut reserved = RESERVED.lock().unwrap();
+            if let Some(held) = reserved.get_mut(&device) {
+                *held = held.saturating_sub(self.size);
+            }
+        }
+    }
+}
+
+/// Rewrites the free and total figures reported by `cuMemGetInfo_v2` for the current context's
+/// device to reflect its budget.
+pub fn virtualize_mem_info(free: &mut usize, total: &mut usize) {
+    let Some(device) = driver::current_device() else {
+        return;
+    };
+    if let Some(limit) = limit(device) {
+        let used = ALLOCATIONS.device_usage(device);
+        *free = (*free).min(limit.saturating_sub(used));
+        *total = (*total).min(limit);
+    }
+}
+
+/// Returns the device a `cuMemCreate` allocation is placed on.
+///
+/// # Safety
+/// `prop` must be null or point to a valid `CUmemAllocationProp`.
+pub unsafe fn allocation_device(prop: *const CUmemAllocationProp) -> Option<CUdevice> {
+    let prop = unsafe { prop.as_ref() }?;
+    (prop.location.type_ == CU_MEM_LOCATION_TYPE_DEVICE).then_some(prop.location.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_default_and_per_device_budgets() {
+        let limits = Limits::parse("4G, 1=512M").unwrap();
+        assert_eq!(limits.get(0), Some(4 << 30));
+        assert_eq!(limits.get(1), Some(512 << 20));
+        assert!(Limits::parse("x=1G").is_err());
+    }
+
+    #[test]
+    fn reservations_hold_bytes_until_dropped() {
+        // A device no other test budgets
+        let device = 7;
+        set_limit(Some(device), Some(8 << 30));
+        let first = reserve(Some(device), 6 << 30).unwrap();
+        assert!(reserve(Some(device), 6 << 30).is_none());
+        drop(first);
+        let mut second = reserve(Some(device), 6 << 30).unwrap();
+        assert!(second.grow(7 << 30));
+        assert!(!second.grow(9 << 30));
+        drop(second);
+        assert!(reserve(Some(device), 8 << 30).is_some());
+        set_limit(Some(device), None);
+    }
+}
@@ -0,0 +1,783 @@
+//! Driver call recording.
+//!
+//! When recording, the built-in hook sets append every call they intercept from a core subset of
+//! the driver API to a trace: context and module management, allocation, memcpys and memsets,
+//! streams, events and kernel launches. Each [`Entry`] holds the call's arguments and outputs,
+//! handles as their raw values, and its result. Memory the driver reads or writes on the host is
+//! stored alongside as content-addressed [`Blob`]s: the source of host-to-device copies, the
+//! result of synchronous device-to-host copies, and loaded module images. Kernel arguments are
+//! stored by value, using the parameter layouts of [`crate::args`].
+//!
+//! A trace is a directory with `trace.jsonl`, one [`Entry`] per line, and the blobs under
+//! `blobs/`, named by their SHA-256. [`crate::replay`] re-issues it against a driver.
+//!
+//! Recording is enabled by setting `CUDA_HOOK_RECORD_DIR` to the trace directory, or with
+//! [`start`]. Calls are seen through [`install_memory_hooks!`], [`install_stream_hooks!`],
+//! [`install_launch_hooks!`], [`install_capture_hooks!`] (module loads),
+//! [`install_module_hooks!`] (function lookups) and [`install_device_hooks!`] (context
+//! creation); [`install_record_hooks!`] covers the rest. Calls are recorded as they return, so
+//! calls made concurrently on several threads are ordered by completion.
+
+use crate::args;
+use crate::capture;
+use crate::ffi::*;
+use crate::memory::AllocKind;
+use crate::modules;
+use crate::streams::MemcpyKind;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ffi::{CStr, c_char, c_void};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::warn;
+
+const RECORD_ENV: &str = "CUDA_HOOK_RECORD_DIR";
+
+static ENABLED: Lazy<AtomicBool> =
+    Lazy::new(|| AtomicBool::new(RECORDER.lock().unwrap().is_some()));
+
+static RECORDER: Lazy<Mutex<Option<Recorder>>> = Lazy::new(|| {
+    let recorder = std::env::var_os(RECORD_ENV).and_then(|dir| {
+        Recorder::create(PathBuf::from(&dir))
+            .map_err(|e| warn!("Not recording to {:?}: {}", dir, e))
+            .ok()
+    });
+    Mutex::new(recorder)
+});
+
+/// Host memory stored in a trace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Blob {
+    pub sha256: String,
+    pub size: usize,
+}
+
+/// A recorded call. Handles and device pointers are the values the application saw.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "call", rename_all = "snake_case")]
+pub enum Call {
+    Init {
+        flags: u32,
+    },
+    CtxCreate {
+        flags: u32,
+        device: CUdevice,
+        ctx: u64,
+    },
+    DevicePrimaryCtxRetain {
+        device: CUdevice,
+        ctx: u64,
+    },
+    CtxDestroy {
+        ctx: u64,
+    },
+    CtxSetCurrent {
+        ctx: u64,
+    },
+    CtxSynchronize,
+    ModuleLoad {
+        api: String,
+        image: Blob,
+        module: u64,
+    },
+    ModuleUnload {
+        module: u64,
+    },
+    ModuleGetFunction {
+        module: u64,
+        name: String,
+        function: u64,
+    },
+    MemAlloc {
+        api: String,
+        bytes: usize,
+        dptr: u64,
+    },
+    MemFree {
+        dptr: u64,
+    },
+    /// A copy. Host addresses are kept for reference; `data` holds what was copied from the
+    /// host, or what a synchronous copy wrote to it.
+    Memcpy {
+        api: String,
+        kind: MemcpyKind,
+        dst: u64,
+        src: u64,
+        bytes: usize,
+        stream: Option<u64>,
+        data: Option<Blob>,
+    },
+    Memset {
+        dst: u64,
+        value: u32,
+        /// 1 or 4 bytes.
+        element_size: usize,
+        count: usize,
+        stream: Option<u64>,
+    },
+    StreamCreate {
+        flags: u32,
+        priority: Option<i32>,
+        stream: u64,
+    },
+    StreamDestroy {
+        stream: u64,
+    },
+    StreamSynchronize {
+        stream: u64,
+    },
+    EventCreate {
+        flags: u32,
+        event: u64,
+    },
+    EventDestroy {
+        event: u64,
+    },
+    EventRecord {
+        event: u64,
+        stream: u64,
+    },
+    StreamWaitEvent {
+        stream: u64,
+        event: u64,
+        flags: u32,
+    },
+    EventSynchronize {
+        event: u64,
+    },
+    /// A kernel launch, with the bytes of each argument. `args` is `None` when the kernel's
+    /// parameter layout is unknown.
+    LaunchKernel {
+        function: u64,
+        grid: [u32; 3],
+        block: [u32; 3],
+        shared_mem: u32,
+        stream: u64,
+        args: Option<Vec<Vec<u8>>>,
+    },
+}
+
+impl Call {
+    /// Returns the driver API the call was made through.
+    pub fn name(&self) -> &str {
+        match self {
+            Call::Init { .. } => "cuInit",
+            Call::CtxCreate { .. } => "cuCtxCreate_v2",
+            Call::DevicePrimaryCtxRetain { .. } => "cuDevicePrimaryCtxRetain",
+            Call::CtxDestroy { .. } => "cuCtxDestroy_v2",
+            Call::CtxSetCurrent { .. } => "cuCtxSetCurrent",
+            Call::CtxSynchronize => "cuCtxSynchronize",
+            Call::ModuleLoad { api, .. }
+            | Call::MemAlloc { api, .. }
+            | Call::Memcpy { api, .. } => api,
+            Call::ModuleUnload { .. } => "cuModuleUnload",
+            Call::ModuleGetFunction { .. } => "cuModuleGetFunction",
+            Call::MemFree { .. } => "cuMemFree_v2",
+            Call::Memset {
+                element_size,
+                stream,
+                ..
+            } => match (element_size, stream) {
+                (1, None) => "cuMemsetD8_v2",
+                (1, Some(_)) => "cuMemsetD8Async",
+                (_, None) => "cuMemsetD32_v2",
+                (_, Some(_)) => "cuMemsetD32Async",
+            },
+            Call::StreamCreate { priority: None, .. } => "cuStreamCreate",
+            Call::StreamCreate { .. } => "cuStreamCreateWithPriority",
+            Call::StreamDestroy { .. } => "cuStreamDestroy_v2",
+            Call::StreamSynchronize { .. } => "cuStreamSynchronize",
+            Call::EventCreate { .. } => "cuEventCreate",
+            Call::EventDestroy { .. } => "cuEventDestroy_v2",
+            Call::EventRecord { .. } => "cuEventRecord",
+            Call::StreamWaitEvent { .. } => "cuStreamWaitEvent",
+            Call::EventSynchronize { .. } => "cuEventSynchronize",
+            Call::LaunchKernel { .. } => "cuLaunchKernel",
+        }
+    }
+}
+
+/// One line of a trace.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub seq: u64,
+    /// Microseconds since the interposer was loaded.
+    pub at_us: u64,
+    pub tid: i32,
+    #[serde(flatten)]
+    pub call: Call,
+    pub result: CUresult,
+}
+
+/// A trace read back from its directory.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub dir: PathBuf,
+    pub entries: Vec<Entry>,
+}
+
+impl Trace {
+    pub fn load(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        let file = File::open(dir.join("trace.jsonl"))?;
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str(&line)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            entries.push(entry);
+        }
+        Ok(Self { dir, entries })
+    }
+
+    /// Reads the contents of `blob`.
+    pub fn blob(&self, blob: &Blob) -> std::io::Result<Vec<u8>> {
+        std::fs::read(blob_path(&self.dir, &blob.sha256))
+    }
+}
+
+fn blob_path(dir: &Path, sha256: &str) -> PathBuf {
+    dir.join("blobs").join(sha256)
+}
+
+struct Recorder {
+    dir: PathBuf,
+    file: File,
+    seq: u64,
+}
+
+impl Recorder {
+    fn create(dir: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir.join("blobs"))?;
+        let file = File::create(dir.join("trace.jsonl"))?;
+        Ok(Self { dir, file, seq: 0 })
+    }
+}
+
+/// Starts recording to a fresh trace in `dir`, replacing any trace being recorded.
+pub fn start(dir: impl Into<PathBuf>) -> std::io::Result<()> {
+    let recorder = Recorder::create(dir.into())?;
+    *RECORDER.lock().unwrap() = Some(recorder);
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Stops recording.
+pub fn stop() {
+    ENABLED.store(false, Ordering::Relaxed);
+    *RECORDER.lock().unwrap() = None;
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Stores `bytes` in the trace being recorded.
+fn blob(bytes: &[u8]) -> Option<Blob> {
+    let dir = RECORDER.lock().unwrap().as_ref()?.dir.clone();
+    let sha256: String = Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let path = blob_path(&dir, &sha256);
+    if let Err(e) = capture::write_image(&dir.join("blobs"), &path, bytes) {
+        warn!("Failed to store recorded memory to {:?}: {}", path, e);
+        return None;
+    }
+    Some(Blob {
+        sha256,
+        size: bytes.len(),
+    })
+}
+
+fn record(call: Call, result: CUresult) {
+    let mut recorder = RECORDER.lock().unwrap();
+    let Some(r) = recorder.as_mut() else {
+        return;
+    };
+    let entry = Entry {
+        seq: r.seq,
+        at_us: crate::elapsed().as_micros() as u64,
+        tid: unsafe { libc::gettid() },
+        call,
+        result,
+    };
+    r.seq += 1;
+    let mut line = match serde_json::to_vec(&entry) {
+        Ok(line) => line,
+        Err(e) => return warn!("Failed to serialize recorded call: {}", e),
+    };
+    line.push(b'\n');
+    if let Err(e) = r.file.write_all(&line) {
+        warn!("Stopping recording to {:?}: {}", r.dir, e);
+        *recorder = None;
+        ENABLED.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Reads an output handle, which the driver only writes on success.
+///
+/// # Safety
+/// `out` must be null or valid for reads.
+unsafe fn output<T: Copy + Into<u64>>(rc: CUresult, out: *const T) -> u64 {
+    if rc == CUDA_SUCCESS && !out.is_null() {
+        unsafe { *out }.into()
+    } else {
+        0
+    }
+}
+
+/// Reads an output pointer handle, which the driver only writes on success.
+///
+/// # Safety
+/// `out` must be null or valid for reads.
+unsafe fn output_handle(rc: CUresult, out: *const *mut c_void) -> u64 {
+    if rc == CUDA_SUCCESS && !out.is_null() {
+        unsafe { *out as u64 }
+    } else {
+        0
+    }
+}
+
+pub fn on_init(rc: CUresult, flags: u32) {
+    if enabled() {
+        record(Call::Init { flags }, rc);
+    }
+}
+
+/// # Safety
+/// `pctx` must be the output pointer passed to `cuCtxCreate`.
+pub unsafe fn on_ctx_create(rc: CUresult, pctx: *const CUcontext, flags: u32, device: CUdevice) {
+    if enabled() {
+        let ctx = unsafe { output_handle(rc, pctx) };
+        record(Call::CtxCreate { flags, device, ctx }, rc);
+    }
+}
+
+/// # Safety
+/// `pctx` must be the output pointer passed to `cuDevicePrimaryCtxRetain`.
+pub unsafe fn on_primary_ctx_retain(rc: CUresult, pctx: *const CUcontext, device: CUdevice) {
+    if enabled() {
+        let ctx = unsafe { output_handle(rc, pctx) };
+        record(Call::DevicePrimaryCtxRetain { device, ctx }, rc);
+    }
+}
+
+pub fn on_ctx_destroy(rc: CUresult, ctx: CUcontext) {
+    if enabled() {
+        record(Call::CtxDestroy { ctx: ctx as u64 }, rc);
+    }
+}
+
+pub fn on_ctx_set_current(rc: CUresult, ctx: CUcontext) {
+    if enabled() {
+        record(Call::CtxSetCurrent { ctx: ctx as u64 }, rc);
+    }
+}
+
+pub fn on_ctx_synchronize(rc: CUresult) {
+    if enabled() {
+        record(Call::CtxSynchronize, rc);
+    }
+}
+
+/// Records a module loaded from an image in memory.
+///
+/// # Safety
+/// `module` must be the output pointer passed to the load, and `image` the image.
+pub unsafe fn on_module_load(
+    rc: CUresult,
+    api: &'static str,
+    module: *const CUmodule,
+    image: *const c_void,
+) {
+    if !enabled() {
+        return;
+    }
+    let Some(image) = (unsafe { capture::image_bytes(image) }).and_then(blob) else {
+        return;
+    };
+    let module = unsafe { output_handle(rc, module) };
+    record(
+        Call::ModuleLoad {
+            api: api.to_string(),
+            image,
+            module,
+        },
+        rc,
+    );
+}
+
+/// Records a module loaded from a file with `cuModuleLoad`.
+///
+/// # Safety
+/// `module` must be the output pointer passed to the load, and `fname` null or a C string.
+pub unsafe fn on_module_load_file(rc: CUresult, module: *const CUmodule, fname: *const c_char) {
+    if !enabled() || fname.is_null() {
+        return;
+    }
+    let fname = unsafe { CStr::from_ptr(fname) }.to_string_lossy();
+    let image = match std::fs::read(&*fname) {
+        Ok(bytes) => blob(&bytes),
+        Err(e) => return warn!("Failed to read module {:?} for recording: {}", fname, e),
+    };
+    let Some(image) = image else {
+        return;
+    };
+    let module = unsafe { output_handle(rc, module) };
+    record(
+        Call::ModuleLoad {
+            api: "cuModuleLoad".to_string(),
+            image,
+            module,
+        },
+        rc,
+    );
+}
+
+pub fn on_module_unload(rc: CUresult, module: CUmodule) {
+    if enabled() {
+        record(
+            Call::ModuleUnload {
+                module: module as u64,
+            },
+            rc,
+        );
+    }
+}
+
+/// # Safety
+/// `hfunc` must be the output pointer passed to `cuModuleGetFunction`, and `name` a C string.
+pub unsafe fn on_get_function(
+    rc: CUresult,
+    hfunc: *const CUfunction,
+    module: CUmodule,
+    name: *const c_char,
+) {
+    if !enabled() || name.is_null() {
+        return;
+    }
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .into_owned();
+    let function = unsafe { output_handle(rc, hfunc) };
+    record(
+        Call::ModuleGetFunction {
+            module: module as u64,
+            name,
+            function,
+        },
+        rc,
+    );
+}
+
+/// Records an allocation. Called by [`crate::memory`].
+///
+/// # Safety
+/// `dptr` must be the output pointer passed to the allocation call.
+pub unsafe fn on_alloc(rc: CUresult, dptr: *const CUdeviceptr, bytes: usize, kind: AllocKind) {
+    if enabled() {
+        let dptr = unsafe { output(rc, dptr) };
+        let api = kind.api().to_string();
+        record(Call::MemAlloc { api, bytes, dptr }, rc);
+    }
+}
+
+/// Records a free. Called by [`crate::memory`].
+pub fn on_free(rc: CUresult, dptr: CUdeviceptr) {
+    if enabled() {
+        record(Call::MemFree { dptr }, rc);
+    }
+}
+
+/// Records a copy, with the host memory it reads, or the host memory it wrote if it is
+/// synchronous. Host addresses are passed as integers.
+///
+/// # Safety
+/// The host side of the copy must be valid for `bytes` bytes.
+pub unsafe fn on_memcpy(
+    rc: CUresult,
+    api: &'static str,
+    kind: MemcpyKind,
+    dst: u64,
+    src: u64,
+    bytes: usize,
+    stream: Option<CUstream>,
+) {
+    if !enabled() {
+        return;
+    }
+    let host = match kind {
+        MemcpyKind::HtoD => Some(src),
+        MemcpyKind::DtoH if rc == CUDA_SUCCESS && stream.is_none() => Some(dst),
+        _ => None,
+    };
+    let data = host
+        .filter(|&ptr| ptr != 0)
+        .and_then(|ptr| blob(unsafe { std::slice::from_raw_parts(ptr as *const u8, bytes) }));
+    record(
+        Call::Memcpy {
+            api: api.to_string(),
+            kind,
+            dst,
+            src,
+            bytes,
+            stream: stream.map(|s| s as u64),
+            data,
+        },
+        rc,
+    );
+}
+
+pub fn on_memset(
+    rc: CUresult,
+    dst: CUdeviceptr,
+    value: u32,
+    element_size: usize,
+    count: usize,
+    stream: Option<CUstream>,
+) {
+    if enabled() {
+        record(
+            Call::Memset {
+                dst,
+                value,
+                element_size,
+                count,
+                stream: stream.map(|s| s as u64),
+            },
+            rc,
+        );
+    }
+}
+
+/// # Safety
+/// `ph_stream` must be the output pointer passed to the stream creation.
+pub unsafe fn on_stream_create(
+    rc: CUresult,
+    ph_stream: *const CUstream,
+    flags: u32,
+    priority: Option<i32>,
+) {
+    if enabled() {
+        let stream = unsafe { output_handle(rc, ph_stream) };
+        record(
+            Call::StreamCreate {
+                flags,
+                priority,
+                stream,
+            },
+            rc,
+        );
+    }
+}
+
+pub fn on_stream_destroy(rc: CUresult, stream: CUstream) {
+    if enabled() {
+        record(
+            Call::StreamDestroy {
+                stream: stream as u64,
+            },
+            rc,
+        );
+    }
+}
+
+pub fn on_stream_synchronize(rc: CUresult, stream: CUstream) {
+    if enabled() {
+        record(
+            Call::StreamSynchronize {
+                stream: stream as u64,
+            },
+            rc,
+        );
+    }
+}
+
+/// # Safety
+/// `ph_event` must be the output pointer passed to `cuEventCreate`.
+pub unsafe fn on_event_create(rc: CUresult, ph_event: *const CUevent, flags: u32) {
+    if enabled() {
+        let event = unsafe { output_handle(rc, ph_event) };
+        record(Call::EventCreate { flags, event }, rc);
+    }
+}
+
+pub fn on_event_destroy(rc: CUresult, event: CUevent) {
+    if enabled() {
+        record(
+            Call::EventDestroy {
+                event: event as u64,
+            },
+            rc,
+        );
+    }
+}
+
+pub fn on_event_record(rc: CUresult, event: CUevent, stream: CUstream) {
+    if enabled() {
+        record(
+            Call::EventRecord {
+                event: event as u64,
+                stream: stream as u64,
+            },
+            rc,
+        );
+    }
+}
+
+pub fn on_stream_wait_event(rc: CUresult, stream: CUstream, event: CUevent, flags: u32) {
+    if enabled() {
+        record(
+            Call::StreamWaitEvent {
+                stream: stream as u64,
+                event: event as u64,
+                flags,
+            },
+            rc,
+        );
+    }
+}
+
+pub fn on_event_synchronize(rc: CUresult, event: CUevent) {
+    if enabled() {
+        record(
+            Call::EventSynchronize {
+                event: event as u64,
+            },
+            rc,
+        );
+    }
+}
+
+/// Records a kernel launch with its arguments. Called by [`crate::launch`].
+///
+/// # Safety
+/// `kernel_params` and `extra` must be as passed to the launch of `func`.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn on_launch(
+    rc: CUresult,
+    func: CUfunction,
+    grid: [u32; 3],
+    block: [u32; 3],
+    shared_mem: u32,
+    stream: CUstream,
+    kernel_params: *mut *mut c_void,
+    extra: *mut *mut c_void,
+) {
+    if !enabled() {
+        return;
+    }
+    let kernel = modules::kernel_name(func);
+    let args = args::layout(func, kernel.as_deref()).and_then(|layout| {
+        let params = unsafe { args::param_bytes(&layout, kernel_params, extra) };
+        // A launch is only replayable with every argument.
+        (params.len() == layout.len())
+            .then(|| params.into_iter().map(|(_, _, b)| b.to_vec()).collect())
+    });
+    if args.is_none() {
+        let name = kernel
+            .as_ref()
+            .map_or("<unknown kernel>", |k| k.demangled.as_str());
+        warn!(
+            "Recording launch of {} without its arguments: unknown layout",
+            name
+        );
+    }
+    record(
+        Call::LaunchKernel {
+            function: func as u64,
+            grid,
+            block,
+            shared_mem,
+            stream: stream as u64,
+            args,
+        },
+        rc,
+    );
+}
+
+/// Installs hooks that record the calls [`crate::record`] needs and no other hook set sees:
+/// `cuInit`, context destruction, `cuModuleUnload` and event creation and
+/// destruction. [`crate::checkpoint`] tracks contexts and module unloads, and restores, through
+/// them too.
+///
+/// The hooked symbols are listed in [`crate::hook_sets::HOOK_SETS`], so
+/// `cuda-interposer-build` leaves them out of the generated passthroughs.
+#[macro_export]
+macro_rules! install_record_hooks {
+    () => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuInit(flags: $crate::libc::c_uint) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuInit)(flags) };
+                $crate::record::on_init(rc, flags);
+                $crate::checkpoint::on_init(rc);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuCtxDestroy_v2(
+                ctx: $crate::ffi::CUcontext
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuCtxDestroy_v2)(ctx) };
+                $crate::record::on_ctx_destroy(rc, ctx);
+                $crate::checkpoint::on_ctx_destroy(rc, ctx);
+                $crate::handles::release(&rc, $crate::handles::HandleKind::Context, ctx);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuCtxSetCurrent(
+                ctx: $crate::ffi::CUcontext
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuCtxSetCurrent)(ctx) };
+                $crate::record::on_ctx_set_current(rc, ctx);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuModuleUnload(
+                hmod: $crate::ffi::CUmodule
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuModuleUnload)(hmod) };
+                $crate::record::on_module_unload(rc, hmod);
+                $crate::checkpoint::on_module_unload(rc, hmod);
+                $crate::handles::release(&rc, $crate::handles::HandleKind::Module, hmod);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuEventCreate(
+                ph_event: *mut $crate::ffi::CUevent,
+                flags: $crate::libc::c_uint
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuEventCreate)(ph_event, flags) };
+                unsafe { $crate::record::on_event_create(rc, ph_event, flags) };
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Event, ph_event) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuEventDestroy_v2(
+                h_event: $crate::ffi::CUevent
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuEventDestroy_v2)(h_event) };
+                $crate::record::on_event_destroy(rc, h_event);
+                $crate::handles::release(&rc, $crate::handles::HandleKind::Event, h_event);
+                rc
+            }
+        }
+    };
+}
@@ -0,0 +1,303 @@
+//! The runtime's registry of fatbins, kernels and variables.
+//!
+//! Code compiled with `nvcc` registers itself with the runtime at startup: each translation
+//! unit's fatbin through `__cudaRegisterFatBinary`, then every kernel's host stub through
+//! `__cudaRegisterFunction` and every `__device__` variable through `__cudaRegisterVar`.
+//! [`install_registration_hooks!`] records these calls, so that runtime launches, which name
+//! their kernel by host stub address, can be attributed to the device function and module. Kernel
+//! names are also handed to [`crate::modules`], where [`crate::launch`] finds them.
+//!
+//! Registered fatbins go through [`crate::capture`] like any other loaded image, to be captured
+//! and to provide parameter layouts when those are enabled.
+
+use crate::capture;
+use crate::modules;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ffi::{CStr, c_char, c_void};
+use std::sync::RwLock;
+
+static REGISTRY: Lazy<RwLock<Registry>> = Lazy::new(Default::default);
+
+/// A fatbin registered by `__cudaRegisterFatBinary`.
+#[derive(Debug, Clone, Default)]
+pub struct RegisteredModule {
+    /// The handle returned to the application, as an address.
+    pub handle: usize,
+    /// The fatbin wrapper that was registered, as an address.
+    pub fatbin: usize,
+    /// Host stubs of the kernels registered with this module.
+    pub functions: Vec<usize>,
+    /// Host shadows of the variables registered with this module.
+    pub vars: Vec<usize>,
+}
+
+/// A kernel registered by `__cudaRegisterFunction`.
+#[derive(Debug, Clone)]
+pub struct RegisteredFunction {
+    pub host_stub: usize,
+    pub module: usize,
+    /// The mangled device name.
+    pub device_name: String,
+    pub thread_limit: i32,
+}
+
+/// A variable registered by `__cudaRegisterVar`.
+#[derive(Debug, Clone)]
+pub struct RegisteredVar {
+    pub host_var: usize,
+    pub module: usize,
+    pub device_name: String,
+    pub size: usize,
+    pub constant: bool,
+    pub global: bool,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    modules: HashMap<usize, RegisteredModule>,
+    functions: HashMap<usize, RegisteredFunction>,
+    vars: HashMap<usize, RegisteredVar>,
+}
+
+unsafe fn c_str(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned()
+}
+
+/// Records a `__cudaRegisterFatBinary` call. Used by [`install_registration_hooks!`].
+///
+/// # Safety
+/// `fatbin` must be the fatbin wrapper passed to the call.
+pub unsafe fn register_fatbin(handle: *mut *mut c_void, fatbin: *const c_void) {
+    if let Some(captured) =
+        unsafe { capture::capture_image("__cudaRegisterFatBinary", fatbin, Vec::new()) }
+    {
+        captured.finish(crate::ffi::CUDA_SUCCESS);
+    }
+    let module = RegisteredModule {
+        handle: handle as usize,
+        fatbin: fatbin as usize,
+        ..Default::default()
+    };
+    REGISTRY
+        .write()
+        .unwrap()
+        .modules
+        .insert(handle as usize, module);
+}
+
+/// Records a `__cudaUnregisterFatBinary` call, forgetting the module's kernels and variables.
+/// Used by [`install_registration_hooks!`].
+pub fn unregister_fatbin(handle: *mut *mut c_void) {
+    let mut registry = REGISTRY.write().unwrap();
+    let Some(module) = registry.modules.remove(&(handle as usize)) else {
+        return;
+    };
+    for stub in &module.functions {
+        registry.functions.remove(stub);
+        modules::forget_function(*stub);
+    }
+    for var in &module.vars {
+        registry.vars.remove(var);
+    }
+}
+
+/// Records a `__cudaRegisterFunction` call. Used by [`install_registration_hooks!`].
+///
+/// # Safety
+/// `device_name` must be null or a valid C string.
+pub unsafe fn register_function(
+    handle: *mut *mut c_void,
+    host_stub: *const c_void,
+    device_name: *const c_char,
+    thread_limit: i32,
+) {
+    unsafe { modules::remember_function(host_stub as *mut c_void, device_name) };
+    let function = RegisteredFunction {
+        host_stub: host_stub as usize,
+        module: handle as usize,
+        device_name: unsafe { c_str(device_name) },
+        thread_limit,
+    };
+    let mut registry = REGISTRY.write().unwrap();
+    if let Some(module) = registry.modules.get_mut(&(handle as usize)) {
+        module.functions.push(host_stub as usize);
+    }
+    registry.functions.insert(host_stub as usize, function);
+}
+
+/// Records a `__cudaRegisterVar` call. Used by [`install_registration_hooks!`].
+///
+/// # Safety
+/// `device_name` must be null or a valid C string.
+pub unsafe fn register_var(
+    handle: *mut *mut c_void,
+    host_var: *const c_void,
+    device_name: *const c_char,
+    size: usize,
+    constant: bool,
+    global: bool,
+) {
+    let var = RegisteredVar {
+        host_var: host_var as usize,
+        module: handle as usize,
+        device_name: unsafe { c_str(device_name) },
+        size,
+        constant,
+        global,
+    };
+    let mut registry = REGISTRY.write().unwrap();
+    if let Some(module) = registry.modules.get_mut(&(handle as usize)) {
+        module.vars.push(host_var as usize);
+    }
+    registry.vars.insert(host_var as usize, var);
+}
+
+/// Returns the kernel registered with host stub `host_stub`.
+pub fn function(host_stub: *const c_void) -> Option<RegisteredFunction> {
+    let registry = REGISTRY.read().unwrap();
+    registry.functions.get(&(host_stub as usize)).cloned()
+}
+
+/// Returns the variable registered with host shadow `host_var`.
+pub fn var(host_var: *const c_void) -> Option<RegisteredVar> {
+    let registry = REGISTRY.read().unwrap();
+    registry.vars.get(&(host_var as usize)).cloned()
+}
+
+/// Returns the module registered with `handle`.
+pub fn module(handle: *mut *mut c_void) -> Option<RegisteredModule> {
+    let registry = REGISTRY.read().unwrap();
+    registry.modules.get(&(handle as usize)).cloned()
+}
+
+/// Returns every registered module.
+pub fn modules() -> Vec<RegisteredModule> {
+    REGISTRY.read().unwrap().modules.values().cloned().collect()
+}
+
+/// Installs hooks on the runtime's registration entry points that keep the registry of host
+/// stubs, device names and modules.
+///
+/// These are only reached when the application links `libcudart` dynamically.
+///
+/// The hooked symbols are listed in [`crate::hook_sets::HOOK_SETS`], so
+/// `cuda-interposer-build` leaves them out of the generated passthroughs.
+#[macro_export]
+macro_rules! install_registration_hooks {
+    () => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn __cudaRegisterFatBinary(
+                fat_cubin: *mut $crate::libc::c_void
+            ) -> *mut *mut $crate::libc::c_void {
+                let handle = unsafe { (*__real___cudaRegisterFatBinary)(fat_cubin) };
+                unsafe { $crate::registration::register_fatbin(handle, fat_cubin) };
+                handle
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn __cudaUnregisterFatBinary(
+                fat_cubin_handle: *mut *mut $crate::libc::c_void
+            ) -> () {
+                $crate::registration::unregister_fatbin(fat_cubin_handle);
+                unsafe { (*__real___cudaUnregisterFatBinary)(fat_cubin_handle) }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn __cudaRegisterFunction(
+                fat_cubin_handle: *mut *mut $crate::libc::c_void,
+                host_fun: *const $crate::libc::c_char,
+                device_fun: *mut $crate::libc::c_char,
+                device_name: *const $crate::libc::c_char,
+                thread_limit: $crate::libc::c_int,
+                tid: *mut $crate::ffi::dim3,
+                bid: *mut $crate::ffi::dim3,
+                b_dim: *mut $crate::ffi::dim3,
+                g_dim: *mut $crate::ffi::dim3,
+                w_size: *mut $crate::libc::c_int
+            ) -> () {
+                unsafe {
+                    (*__real___cudaRegisterFunction)(
+                        fat_cubin_handle, host_fun, device_fun, device_name, thread_limit, tid,
+                        bid, b_dim, g_dim, w_size,
+                    );
+                    $crate::registration::register_function(
+                        fat_cubin_handle,
+                        host_fun as *const $crate::libc::c_void,
+                        device_name,
+                        thread_limit,
+                    );
+                }
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn __cudaRegisterVar(
+                fat_cubin_handle: *mut *mut $crate::libc::c_void,
+                host_var: *mut $crate::libc::c_char,
+                device_address: *mut $crate::libc::c_char,
+                device_name: *const $crate::libc::c_char,
+                ext: $crate::libc::c_int,
+                size: usize,
+                constant: $crate::libc::c_int,
+                global: $crate::libc::c_int
+            ) -> () {
+                unsafe {
+                    (*__real___cudaRegisterVar)(
+                        fat_cubin_handle, host_var, device_address, device_name, ext, size,
+                        constant, global,
+                    );
+                    $crate::registration::register_var(
+                        fat_cubin_handle,
+                        host_var as *const $crate::libc::c_void,
+                        device_name,
+                        size,
+                        constant != 0,
+                        global != 0,
+                    );
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stand-ins for a kernel's host stub and a variable's host shadow
+    static STUB: u8 = 0;
+    static SHADOW: u8 = 0;
+
+    #[test]
+    fn forgets_a_module_when_it_is_unregistered() {
+        let mut fatbin_handle: *mut c_void = std::ptr::null_mut();
+        let handle = &mut fatbin_handle as *mut *mut c_void;
+        let stub = &STUB as *const u8 as *const c_void;
+        let shadow = &SHADOW as *const u8 as *const c_void;
+        unsafe {
+            register_fatbin(handle, std::ptr::null());
+            register_function(handle, stub, c"_Z5scalePfi".as_ptr(), -1);
+            register_var(handle, shadow, c"counter".as_ptr(), 4, false, true);
+        }
+
+        let registered = module(handle).unwrap();
+        assert_eq!(registered.functions, [stub as usize]);
+        assert_eq!(registered.vars, [shadow as usize]);
+        assert_eq!(function(stub).unwrap().device_name, "_Z5scalePfi");
+        assert_eq!(var(shadow).unwrap().size, 4);
+        let name = modules::host_kernel_name(stub).unwrap();
+        assert_eq!(name.mangled, "_Z5scalePfi");
+
+        unregister_fatbin(handle);
+        assert!(module(handle).is_none());
+        assert!(function(stub).is_none());
+        assert!(var(shadow).is_none());
+        assert!(modules::host_kernel_name(stub).is_none());
+    }
+}
@@ -0,0 +1,1131 @@
+//! API remoting.
+//!
+//! Runs a CUDA application on a machine without a GPU by forwarding its driver calls to a
+//! server process on one that has it. With `CUDA_HOOK_REMOTE` set, [`crate::dlsym_next`]
+//! resolves driver entry points to client stubs in place of `libcuda.so`: hooks and generated
+//! passthroughs still run on the client, and what they would have called in the driver is
+//! serialized, host buffers and module images included, and executed by the server, which
+//! sends back the result and any outputs. `cudaflow-server` is that server; [`serve`] is what
+//! it runs.
+//!
+//! `CUDA_HOOK_REMOTE` names the server as `unix:<path>` or `tcp:<host>:<port>`. Each client
+//! thread has its own connection, and the server runs each connection on its own thread, so
+//! that the context current on a client thread is current on its server thread.
+//!
+//! The forwarded calls cover initialization, device queries, contexts, modules loaded from
+//! memory, allocations, copies and memsets, streams, events and `cuLaunchKernel`. Kernel
+//! arguments are sent packed by the parameter layout of the kernel, which the client gets from
+//! `cuFuncGetParamInfo` on the server or from the images [`install_capture_hooks!`] registered.
+//! Asynchronous copies to or from host memory complete before the call returns. Other driver
+//! entry points return `CUDA_ERROR_NOT_SUPPORTED`, and handles are the server's, so calls that
+//! would dereference them on the client, as to host-mapped or managed memory, cannot work.
+//! Runtime API symbols are not forwarded; the runtime reaches the driver through
+//! `cuGetProcAddress`, which the client answers with its stubs.
+//!
+//! Messages are a little-endian `u32` length and a JSON header, then a `u64` length and the
+//! raw bytes of the buffer the call carries, if any. Headers are limited to 1 MiB and buffers
+//! to 1 GiB; the client splits larger copies, and the server drops connections that exceed them
+//! and fails device-to-host copies it could not send back with `CUDA_ERROR_INVALID_VALUE`.
+//!
+//! The server does not authenticate clients, and any client can read and write device memory
+//! and launch kernels with its privileges. Listen on a Unix socket or a loopback address.
+
+use crate::args;
+use crate::capture;
+use crate::config::ConfigError;
+use crate::ffi::*;
+use crate::modules;
+use crate::replay::Driver;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+
+const REMOTE_ENV: &str = "CUDA_HOOK_REMOTE";
+
+/// The largest JSON header a message may have.
+const MAX_HEADER: usize = 1 << 20;
+/// The largest buffer a message may carry, unless lowered with [`set_max_buffer`]. Larger
+/// copies are sent in pieces.
+const MAX_BUFFER: usize = 1 << 30;
+
+static BUFFER_LIMIT: AtomicUsize = AtomicUsize::new(MAX_BUFFER);
+
+static ENDPOINT: Lazy<Option<Endpoint>> = Lazy::new(|| {
+    let spec = std::env::var(REMOTE_ENV).ok()?;
+    let endpoint = Endpoint::parse(&spec);
+    if endpoint.is_none() {
+        let reason = "expected unix:<path> or tcp:<host>:<port>";
+        warn!(
+            "Not remoting: {}",
+            ConfigError::new(REMOTE_ENV, &spec, reason)
+        );
+    }
+    endpoint
+});
+
+thread_local! {
+    static CONNECTION: RefCell<Option<Box<dyn Transport>>> = const { RefCell::new(None) };
+}
+
+/// Where the server listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Unix(PathBuf),
+    /// A `host:port` address.
+    Tcp(String),
+}
+
+impl Endpoint {
+    /// Parses `unix:<path>` or `tcp:<host>:<port>`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().split_once(':')? {
+            ("unix", path) if !path.is_empty() => Some(Endpoint::Unix(path.into())),
+            ("tcp", addr) if addr.contains(':') => Some(Endpoint::Tcp(addr.to_string())),
+            _ => None,
+        }
+    }
+
+    pub fn connect(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(match self {
+            Endpoint::Unix(path) => Box::new(UnixStream::connect(path)?),
+            Endpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+            Endpoint::Tcp(addr) => write!(f, "tcp:{addr}"),
+        }
+    }
+}
+
+/// A connection between a client thread and the server.
+pub trait Transport: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Transport for T {}
+
+/// Returns the server calls are forwarded to, if remoting is enabled.
+pub fn endpoint() -> Option<&'static Endpoint> {
+    ENDPOINT.as_ref()
+}
+
+pub fn enabled() -> bool {
+    endpoint().is_some()
+}
+
+/// Lowers the largest buffer a message may carry, on both the client and the server of this
+/// process, so that tests can split copies without moving a gigabyte.
+pub fn set_max_buffer(bytes: usize) {
+    BUFFER_LIMIT.store(bytes.clamp(1, MAX_BUFFER), Ordering::Relaxed);
+}
+
+fn max_buffer() -> usize {
+    BUFFER_LIMIT.load(Ordering::Relaxed)
+}
+
+// ─── Protocol ────────────────────────────────────────────────────────────────
+
+/// A driver call, with its scalar and handle arguments as 64-bit words.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub symbol: String,
+    pub args: Vec<u64>,
+    /// The host buffer the call reads: the source of a copy, a module image, a function name or
+    /// packed kernel arguments.
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+/// The outcome of a [`Request`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Reply {
+    pub result: CUresult,
+    /// The values the call wrote through its out-parameters, in order.
+    pub outputs: Vec<u64>,
+    /// The host buffer the call wrote: the destination of a copy, or a string.
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl Request {
+    pub fn write_to(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_message(w, self, &self.data)
+    }
+
+    /// Reads the next request, or `None` once the client has hung up.
+    pub fn read_from(r: &mut dyn Read) -> io::Result<Option<Self>> {
+        Ok(read_message(r)?.map(|(request, data)| Self { data, ..request }))
+    }
+}
+
+impl Reply {
+    fn new(result: CUresult) -> Self {
+        Self {
+            result,
+            ..Default::default()
+        }
+    }
+
+    fn with_outputs(result: CUresult, outputs: &[u64]) -> Self {
+        Self {
+            result,
+            outputs: outputs.to_vec(),
+            data: Vec::new(),
+        }
+    }
+
+    fn with_data(result: CUresult, data: Vec<u8>) -> Self {
+        Self {
+            result,
+            outputs: Vec::new(),
+            data,
+        }
+    }
+
+    pub fn write_to(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_message(w, self, &self.data)
+    }
+
+    pub fn read_from(r: &mut dyn Read) -> io::Result<Self> {
+        let (reply, data) = read_message(r)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+        Ok(Self { data, ..reply })
+    }
+
+    /// Stores output `index` through `out` if the call succeeded, and returns its result.
+    ///
+    /// # Safety
+    /// `out` must be null or valid for writes.
+    unsafe fn store<T: Output>(&self, index: usize, out: *mut T) -> CUresult {
+        if self.result == CUDA_SUCCESS && !out.is_null() {
+            unsafe { *out = T::from_word(self.outputs.get(index).copied().unwrap_or(0)) };
+        }
+        self.result
+    }
+}
+
+fn write_message<T: Serialize>(w: &mut dyn Write, header: &T, data: &[u8]) -> io::Result<()> {
+    let header = serde_json::to_vec(header)?;
+    let mut message = Vec::with_capacity(12 + header.len() + data.len());
+    message.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    message.extend_from_slice(&header);
+    message.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    message.extend_from_slice(data);
+    w.write_all(&message)?;
+    w.flush()
+}
+
+fn read_message<T: DeserializeOwned>(r: &mut dyn Read) -> io::Result<Option<(T, Vec<u8>)>> {
+    let mut len = [0u8; 4];
+    match r.read_exact(&mut len) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_HEADER {
+        return Err(too_large("header", len as u64));
+    }
+    let mut header = vec![0u8; len];
+    r.read_exact(&mut header)?;
+    let mut len = [0u8; 8];
+    r.read_exact(&mut len)?;
+    if u64::from_le_bytes(len) > max_buffer() as u64 {
+        return Err(too_large("buffer", u64::from_le_bytes(len)));
+    }
+    let mut data = Vec::new();
+    r.take(u64::from_le_bytes(len)).read_to_end(&mut data)?;
+    if data.len() as u64 != u64::from_le_bytes(len) {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Some((serde_json::from_slice(&header)?, data)))
+}
+
+fn too_large(what: &str, len: u64) -> io::Error {
+    let message = format!("message {what} of {len} bytes is too large");
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A value a driver call writes through an out-parameter.
+trait Output {
+    fn from_word(word: u64) -> Self;
+}
+
+macro_rules! impl_output {
+    ($($ty:ty),*) => {
+        $(impl Output for $ty {
+            fn from_word(word: u64) -> Self {
+                word as $ty
+            }
+        })*
+    };
+}
+
+impl_output!(i32, u32, usize, u64);
+
+impl Output for *mut c_void {
+    fn from_word(word: u64) -> Self {
+        word as *mut c_void
+    }
+}
+
+impl Output for f32 {
+    fn from_word(word: u64) -> Self {
+        f32::from_bits(word as u32)
+    }
+}
+
+// ─── Client ──────────────────────────────────────────────────────────────────
+
+/// Sends a call to the server on the calling thread's connection and waits for its reply.
+/// Calls fail with `CUDA_ERROR_UNKNOWN` if the server cannot be reached.
+fn call(symbol: &str, args: &[u64], data: &[u8]) -> Reply {
+    let Some(endpoint) = endpoint() else {
+        return Reply::new(CUDA_ERROR_NOT_SUPPORTED);
+    };
+    let request = Request {
+        symbol: symbol.to_string(),
+        args: args.to_vec(),
+        data: data.to_vec(),
+    };
+    CONNECTION.with_borrow_mut(|connection| {
+        let result = (|| {
+            if connection.is_none() {
+                *connection = Some(endpoint.connect()?);
+                debug!("Connected to {}", endpoint);
+            }
+            let stream = connection.as_mut().unwrap();
+            request.write_to(stream)?;
+            Reply::read_from(stream)
+        })();
+        result.unwrap_or_else(|e| {
+            warn!("Cannot forward {} to {}: {}", symbol, endpoint, e);
+            *connection = None;
+            Reply::new(CUDA_ERROR_UNKNOWN)
+        })
+    })
+}
+
+/// Returns a NUL-terminated copy of `bytes` that lives as long as the process, as the driver's
+/// own strings do.
+fn intern(bytes: Vec<u8>) -> *const c_char {
+    static STRINGS: Lazy<Mutex<HashSet<&'static CStr>>> = Lazy::new(Default::default);
+    let Ok(s) = CString::new(bytes) else {
+        return std::ptr::null();
+    };
+    let mut strings = STRINGS.lock().unwrap();
+    if let Some(s) = strings.get(s.as_c_str()) {
+        return s.as_ptr();
+    }
+    let s: &'static CStr = Box::leak(s.into_boxed_c_str());
+    strings.insert(s);
+    s.as_ptr()
+}
+
+/// Stands in for every driver entry point that is not forwarded. It takes no arguments, which
+/// the C calling convention allows whatever the caller passes.
+extern "C" fn not_supported() -> CUresult {
+    CUDA_ERROR_NOT_SUPPORTED
+}
+
+/// Returns the entry point `symbol` resolves to while remoting: its stub, or one that returns
+/// `CUDA_ERROR_NOT_SUPPORTED`.
+pub fn resolve(symbol: &str) -> *mut c_void {
+    stub(symbol).unwrap_or_else(|| {
+        debug!("{} is not forwarded to the server", symbol);
+        not_supported as *mut c_void
+    })
+}
+
+macro_rules! stubs {
+    ($($name:ident),* $(,)?) => {
+        /// Returns the stub that forwards `symbol`, if it is one of the forwarded calls.
+        pub fn stub(symbol: &str) -> Option<*mut c_void> {
+            match symbol {
+                $(stringify!($name) => Some(stubs::$name as *mut c_void),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+stubs! {
+    cuGetProcAddress, cuGetProcAddress_v2, cuGetErrorName, cuGetErrorString, cuInit,
+    cuDriverGetVersion, cuDeviceGet, cuDeviceGetCount, cuDeviceGetName, cuDeviceGetAttribute,
+    cuDeviceTotalMem_v2, cuDevicePrimaryCtxRetain, cuDevicePrimaryCtxRelease_v2, cuCtxCreate_v2,
+    cuCtxDestroy_v2, cuCtxSetCurrent, cuCtxGetCurrent, cuCtxGetDevice, cuCtxSynchronize,
+    cuModuleLoadData, cuModuleLoadDataEx, cuModuleLoadFatBinary, cuModuleUnload,
+    cuModuleGetFunction, cuFuncGetName, cuFuncGetParamInfo, cuMemAlloc_v2, cuMemFree_v2,
+    cuMemGetInfo_v2, cuMemcpyHtoD_v2, cuMemcpyHtoDAsync_v2, cuMemcpyDtoH_v2,
+    cuMemcpyDtoHAsync_v2, cuMemcpyDtoD_v2, cuMemcpyDtoDAsync_v2, cuMemsetD8_v2, cuMemsetD8Async,
+    cuMemsetD32_v2, cuMemsetD32Async, cuStreamCreate, cuStreamCreateWithPriority,
+    cuStreamDestroy_v2, cuStreamSynchronize, cuStreamQuery, cuStreamWaitEvent, cuEventCreate,
+    cuEventDestroy_v2, cuEventRecord, cuEventSynchronize, cuEventQuery, cuEventElapsedTime,
+    cuLaunchKernel,
+}
+
+#[allow(non_snake_case, clippy::too_many_arguments)]
+mod stubs {
+    use super::*;
+
+    fn h<T>(handle: *mut T) -> u64 {
+        handle as u64
+    }
+
+    /// Resolves `symbol` to a stub, trying the versioned names of the forwarded calls since the
+    /// runtime asks for unversioned ones.
+    pub unsafe extern "C" fn cuGetProcAddress_v2(
+        symbol: *const c_char,
+        pfn: *mut *mut c_void,
+        _cuda_version: c_int,
+        _flags: u64,
+        symbol_status: *mut u32,
+    ) -> CUresult {
+        if symbol.is_null() || pfn.is_null() {
+            return CUDA_ERROR_INVALID_VALUE;
+        }
+        let name = unsafe { CStr::from_ptr(symbol) }.to_string_lossy();
+        let found = ["", "_v3", "_v2"]
+            .iter()
+            .find_map(|suffix| stub(&format!("{name}{suffix}")));
+        unsafe { *pfn = found.unwrap_or_else(|| resolve(&name)) };
+        if !symbol_status.is_null() {
+            // CU_GET_PROC_ADDRESS_SUCCESS
+            unsafe { *symbol_status = 0 };
+        }
+        CUDA_SUCCESS
+    }
+
+    pub unsafe extern "C" fn cuGetProcAddress(
+        symbol: *const c_char,
+        pfn: *mut *mut c_void,
+        cuda_version: c_int,
+        flags: u64,
+    ) -> CUresult {
+        unsafe { cuGetProcAddress_v2(symbol, pfn, cuda_version, flags, std::ptr::null_mut()) }
+    }
+
+    pub unsafe extern "C" fn cuGetErrorName(error: CUresult, s: *mut *const c_char) -> CUresult {
+        let reply = call("cuGetErrorName", &[error as u64], &[]);
+        if reply.result == CUDA_SUCCESS && !s.is_null() {
+            unsafe { *s = intern(reply.data) };
+        }
+        reply.result
+    }
+
+    pub unsafe extern "C" fn cuGetErrorString(error: CUresult, s: *mut *const c_char) -> CUresult {
+        let reply = call("cuGetErrorString", &[error as u64], &[]);
+        if reply.result == CUDA_SUCCESS && !s.is_null() {
+            unsafe { *s = intern(reply.data) };
+        }
+        reply.result
+    }
+
+    pub unsafe extern "C" fn cuInit(flags: u32) -> CUresult {
+        call("cuInit", &[flags as u64], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuDriverGetVersion(version: *mut i32) -> CUresult {
+        unsafe { call("cuDriverGetVersion", &[], &[]).store(0, version) }
+    }
+
+    pub unsafe extern "C" fn cuDeviceGet(device: *mut CUdevice, ordinal: c_int) -> CUresult {
+        unsafe { call("cuDeviceGet", &[ordinal as u64], &[]).store(0, device) }
+    }
+
+    pub unsafe extern "C" fn cuDeviceGetCount(count: *mut c_int) -> CUresult {
+        unsafe { call("cuDeviceGetCount", &[], &[]).store(0, count) }
+    }
+
+    pub unsafe extern "C" fn cuDeviceGetName(
+        name: *mut c_char,
+        len: c_int,
+        dev: CUdevice,
+    ) -> CUresult {
+        let reply = call("cuDeviceGetName", &[len as u64, dev as u64], &[]);
+        if reply.result == CUDA_SUCCESS && !name.is_null() {
+            let n = reply.data.len().min(len.max(0) as usize);
+            unsafe { std::ptr::copy_nonoverlapping(reply.data.as_ptr(), name as *mut u8, n) };
+        }
+        reply.result
+    }
+
+    pub unsafe extern "C" fn cuDeviceGetAttribute(
+        value: *mut c_int,
+        attrib: u32,
+        dev: CUdevice,
+    ) -> CUresult {
+        let reply = call("cuDeviceGetAttribute", &[attrib as u64, dev as u64], &[]);
+        unsafe { reply.store(0, value) }
+    }
+
+    pub unsafe extern "C" fn cuDeviceTotalMem_v2(bytes: *mut usize, dev: CUdevice) -> CUresult {
+        unsafe { call("cuDeviceTotalMem_v2", &[dev as u64], &[]).store(0, bytes) }
+    }
+
+    pub unsafe extern "C" fn cuDevicePrimaryCtxRetain(
+        ctx: *mut CUcontext,
+        dev: CUdevice,
+    ) -> CUresult {
+        unsafe { call("cuDevicePrimaryCtxRetain", &[dev as u64], &[]).store(0, ctx) }
+    }
+
+    pub unsafe extern "C" fn cuDevicePrimaryCtxRelease_v2(dev: CUdevice) -> CUresult {
+        call("cuDevicePrimaryCtxRelease_v2", &[dev as u64], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuCtxCreate_v2(
+        ctx: *mut CUcontext,
+        flags: u32,
+        dev: CUdevice,
+    ) -> CUresult {
+        let reply = call("cuCtxCreate_v2", &[flags as u64, dev as u64], &[]);
+        unsafe { reply.store(0, ctx) }
+    }
+
+    pub unsafe extern "C" fn cuCtxDestroy_v2(ctx: CUcontext) -> CUresult {
+        call("cuCtxDestroy_v2", &[h(ctx)], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuCtxSetCurrent(ctx: CUcontext) -> CUresult {
+        call("cuCtxSetCurrent", &[h(ctx)], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuCtxGetCurrent(ctx: *mut CUcontext) -> CUresult {
+        unsafe { call("cuCtxGetCurrent", &[], &[]).store(0, ctx) }
+    }
+
+    pub unsafe extern "C" fn cuCtxGetDevice(dev: *mut CUdevice) -> CUresult {
+        unsafe { call("cuCtxGetDevice", &[], &[]).store(0, dev) }
+    }
+
+    pub unsafe extern "C" fn cuCtxSynchronize() -> CUresult {
+        call("cuCtxSynchronize", &[], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuModuleLoadData(
+        module: *mut CUmodule,
+        image: *const c_void,
+    ) -> CUresult {
+        let Some(image) = (unsafe { capture::image_bytes(image) }) else {
+            return CUDA_ERROR_INVALID_VALUE;
+        };
+        unsafe { call("cuModuleLoadData", &[], image).store(0, module) }
+    }
+
+    /// The JIT options are not forwarded.
+    pub unsafe extern "C" fn cuModuleLoadDataEx(
+        module: *mut CUmodule,
+        image: *const c_void,
+        _num_options: u32,
+        _options: *mut c_void,
+        _option_values: *mut *mut c_void,
+    ) -> CUresult {
+        unsafe { cuModuleLoadData(module, image) }
+    }
+
+    pub unsafe extern "C" fn cuModuleLoadFatBinary(
+        module: *mut CUmodule,
+        fat_cubin: *const c_void,
+    ) -> CUresult {
+        unsafe { cuModuleLoadData(module, fat_cubin) }
+    }
+
+    pub unsafe extern "C" fn cuModuleUnload(module: CUmodule) -> CUresult {
+        call("cuModuleUnload", &[h(module)], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuModuleGetFunction(
+        func: *mut CUfunction,
+        module: CUmodule,
+        name: *const c_char,
+    ) -> CUresult {
+        if name.is_null() {
+            return CUDA_ERROR_INVALID_VALUE;
+        }
+        let name = unsafe { CStr::from_ptr(name) }.to_bytes();
+        unsafe { call("cuModuleGetFunction", &[h(module)], name).store(0, func) }
+    }
+
+    pub unsafe extern "C" fn cuFuncGetName(name: *mut *const c_char, func: CUfunction) -> CUresult {
+        let reply = call("cuFuncGetName", &[h(func)], &[]);
+        if reply.result == CUDA_SUCCESS && !name.is_null() {
+            unsafe { *name = intern(reply.data) };
+        }
+        reply.result
+    }
+
+    pub unsafe extern "C" fn cuFuncGetParamInfo(
+        func: CUfunction,
+        index: usize,
+        offset: *mut usize,
+        size: *mut usize,
+    ) -> CUresult {
+        let reply = call("cuFuncGetParamInfo", &[h(func), index as u64], &[]);
+        unsafe { reply.store(0, offset) };
+        unsafe { reply.store(1, size) }
+    }
+
+    pub unsafe extern "C" fn cuMemAlloc_v2(dptr: *mut CUdeviceptr, bytesize: usize) -> CUresult {
+        unsafe { call("cuMemAlloc_v2", &[bytesize as u64], &[]).store(0, dptr) }
+    }
+
+    pub unsafe extern "C" fn cuMemFree_v2(dptr: CUdeviceptr) -> CUresult {
+        call("cuMemFree_v2", &[dptr], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuMemGetInfo_v2(free: *mut usize, total: *mut usize) -> CUresult {
+        let reply = call("cuMemGetInfo_v2", &[], &[]);
+        unsafe { reply.store(0, free) };
+        unsafe { reply.store(1, total) }
+    }
+
+    unsafe fn host<'a>(src: *const c_void, bytes: usize) -> &'a [u8] {
+        if src.is_null() || bytes == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(src as *const u8, bytes) }
+    }
+
+    /// Runs a copy of `bytes` bytes as pieces of at most [`max_buffer`] bytes, calling `f` with
+    /// the offset and size of each until one fails.
+    fn chunked(bytes: usize, mut f: impl FnMut(usize, usize) -> CUresult) -> CUresult {
+        let mut offset = 0;
+        loop {
+            let n = (bytes - offset).min(max_buffer());
+            let rc = f(offset, n);
+            offset += n;
+            if rc != CUDA_SUCCESS || offset >= bytes {
+                return rc;
+            }
+        }
+    }
+
+    unsafe fn copy_out(reply: Reply, dst: *mut c_void, bytes: usize) -> CUresult {
+        if reply.result == CUDA_SUCCESS && !dst.is_null() {
+            let n = reply.data.len().min(bytes);
+            unsafe { std::ptr::copy_nonoverlapping(reply.data.as_ptr(), dst as *mut u8, n) };
+        }
+        reply.result
+    }
+
+    pub unsafe extern "C" fn cuMemcpyHtoD_v2(
+        dst: CUdeviceptr,
+        src: *const c_void,
+        bytes: usize,
+    ) -> CUresult {
+        let data = unsafe { host(src, bytes) };
+        chunked(data.len(), |offset, n| {
+            let args = [dst + offset as u64];
+            call("cuMemcpyHtoD_v2", &args, &data[offset..offset + n]).result
+        })
+    }
+
+    pub unsafe extern "C" fn cuMemcpyHtoDAsync_v2(
+        dst: CUdeviceptr,
+        src: *const c_void,
+        bytes: usize,
+        stream: CUstream,
+    ) -> CUresult {
+        let data = unsafe { host(src, bytes) };
+        chunked(data.len(), |offset, n| {
+            let args = [dst + offset as u64, h(stream)];
+            call("cuMemcpyHtoDAsync_v2", &args, &data[offset..offset + n]).result
+        })
+    }
+
+    pub unsafe extern "C" fn cuMemcpyDtoH_v2(
+        dst: *mut c_void,
+        src: CUdeviceptr,
+        bytes: usize,
+    ) -> CUresult {
+        chunked(bytes, |offset, n| {
+            let reply = call("cuMemcpyDtoH_v2", &[src + offset as u64, n as u64], &[]);
+            unsafe { copy_out(reply, dst.wrapping_byte_add(offset), n) }
+        })
+    }
+
+    pub unsafe extern "C" fn cuMemcpyDtoHAsync_v2(
+        dst: *mut c_void,
+        src: CUdeviceptr,
+        bytes: usize,
+        stream: CUstream,
+    ) -> CUresult {
+        chunked(bytes, |offset, n| {
+            let args = [src + offset as u64, n as u64, h(stream)];
+            let reply = call("cuMemcpyDtoHAsync_v2", &args, &[]);
+            unsafe { copy_out(reply, dst.wrapping_byte_add(offset), n) }
+        })
+    }
+
+    pub unsafe extern "C" fn cuMemcpyDtoD_v2(
+        dst: CUdeviceptr,
+        src: CUdeviceptr,
+        bytes: usize,
+    ) -> CUresult {
+        call("cuMemcpyDtoD_v2", &[dst, src, bytes as u64], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuMemcpyDtoDAsync_v2(
+        dst: CUdeviceptr,
+        src: CUdeviceptr,
+        bytes: usize,
+        stream: CUstream,
+    ) -> CUresult {
+        let args = [dst, src, bytes as u64, h(stream)];
+        call("cuMemcpyDtoDAsync_v2", &args, &[]).result
+    }
+
+    pub unsafe extern "C" fn cuMemsetD8_v2(dst: CUdeviceptr, value: u8, n: usize) -> CUresult {
+        call("cuMemsetD8_v2", &[dst, value as u64, n as u64], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuMemsetD8Async(
+        dst: CUdeviceptr,
+        value: u8,
+        n: usize,
+        stream: CUstream,
+    ) -> CUresult {
+        let args = [dst, value as u64, n as u64, h(stream)];
+        call("cuMemsetD8Async", &args, &[]).result
+    }
+
+    pub unsafe extern "C" fn cuMemsetD32_v2(dst: CUdeviceptr, value: u32, n: usize) -> CUresult {
+        call("cuMemsetD32_v2", &[dst, value as u64, n as u64], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuMemsetD32Async(
+        dst: CUdeviceptr,
+        value: u32,
+        n: usize,
+        stream: CUstream,
+    ) -> CUresult {
+        let args = [dst, value as u64, n as u64, h(stream)];
+        call("cuMemsetD32Async", &args, &[]).result
+    }
+
+    pub unsafe extern "C" fn cuStreamCreate(stream: *mut CUstream, flags: u32) -> CUresult {
+        unsafe { call("cuStreamCreate", &[flags as u64], &[]).store(0, stream) }
+    }
+
+    pub unsafe extern "C" fn cuStreamCreateWithPriority(
+        stream: *mut CUstream,
+        flags: u32,
+        priority: c_int,
+    ) -> CUresult {
+        let args = [flags as u64, priority as u64];
+        unsafe { call("cuStreamCreateWithPriority", &args, &[]).store(0, stream) }
+    }
+
+    pub unsafe extern "C" fn cuStreamDestroy_v2(stream: CUstream) -> CUresult {
+        call("cuStreamDestroy_v2", &[h(stream)], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuStreamSynchronize(stream: CUstream) -> CUresult {
+        call("cuStreamSynchronize", &[h(stream)], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuStreamQuery(stream: CUstream) -> CUresult {
+        call("cuStreamQuery", &[h(stream)], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuStreamWaitEvent(
+        stream: CUstream,
+        event: CUevent,
+        flags: u32,
+    ) -> CUresult {
+        let args = [h(stream), h(event), flags as u64];
+        call("cuStreamWaitEvent", &args, &[]).result
+    }
+
+    pub unsafe extern "C" fn cuEventCreate(event: *mut CUevent, flags: u32) -> CUresult {
+        unsafe { call("cuEventCreate", &[flags as u64], &[]).store(0, event) }
+    }
+
+    pub unsafe extern "C" fn cuEventDestroy_v2(event: CUevent) -> CUresult {
+        call("cuEventDestroy_v2", &[h(event)], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuEventRecord(event: CUevent, stream: CUstream) -> CUresult {
+        call("cuEventRecord", &[h(event), h(stream)], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuEventSynchronize(event: CUevent) -> CUresult {
+        call("cuEventSynchronize", &[h(event)], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuEventQuery(event: CUevent) -> CUresult {
+        call("cuEventQuery", &[h(event)], &[]).result
+    }
+
+    pub unsafe extern "C" fn cuEventElapsedTime(
+        ms: *mut f32,
+        start: CUevent,
+        end: CUevent,
+    ) -> CUresult {
+        let reply = call("cuEventElapsedTime", &[h(start), h(end)], &[]);
+        unsafe { reply.store(0, ms) }
+    }
+
+    /// Sends the arguments packed into a parameter buffer, as `extra` would pass them.
+    pub unsafe extern "C" fn cuLaunchKernel(
+        func: CUfunction,
+        grid_x: u32,
+        grid_y: u32,
+        grid_z: u32,
+        block_x: u32,
+        block_y: u32,
+        block_z: u32,
+        shared_mem_bytes: u32,
+        stream: CUstream,
+        kernel_params: *mut *mut c_void,
+        extra: *mut *mut c_void,
+    ) -> CUresult {
+        let kernel = modules::kernel_name(func);
+        let Some(layout) = args::layout(func, kernel.as_deref()) else {
+            warn!(
+                "Cannot forward a launch of {}: its parameter layout is unknown",
+                kernel.map_or_else(|| format!("{func:?}"), |k| k.demangled.clone())
+            );
+            return CUDA_ERROR_NOT_SUPPORTED;
+        };
+        let mut buffer = vec![0u8; layout.size as usize];
+        for (_, offset, bytes) in unsafe { args::param_bytes(&layout, kernel_params, extra) } {
+            if let Some(slot) = buffer.get_mut(offset..offset + bytes.len()) {
+                slot.copy_from_slice(bytes);
+            }
+        }
+        let args = [
+            h(func),
+            grid_x as u64,
+            grid_y as u64,
+            grid_z as u64,
+            block_x as u64,
+            block_y as u64,
+            block_z as u64,
+            shared_mem_bytes as u64,
+            h(stream),
+        ];
+        call("cuLaunchKernel", &args, &buffer).result
+    }
+}
+
+// ─── Server ──────────────────────────────────────────────────────────────────
+
+/// Accepts clients at `endpoint` and executes their calls against `driver`, each connection
+/// on its own thread. Returns only if the endpoint cannot be listened on or stops accepting.
+pub fn serve(endpoint: &Endpoint, driver: Driver) -> io::Result<()> {
+    let driver = Arc::new(driver);
+    let accept: Box<dyn Fn() -> io::Result<Box<dyn Transport>>> = match endpoint {
+        Endpoint::Unix(path) => {
+            // A socket left behind by an earlier server would make binding fail. Anything else
+            // at the path is not ours to remove.
+            match std::fs::symlink_metadata(path) {
+                Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+                Ok(_) => {
+                    let message = format!("{} exists and is not a socket", path.display());
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists, message));
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            let listener = UnixListener::bind(path)?;
+            Box::new(move || Ok(Box::new(listener.accept()?.0) as Box<dyn Transport>))
+        }
+        Endpoint::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            Box::new(move || {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Ok(Box::new(stream) as Box<dyn Transport>)
+            })
+        }
+    };
+    debug!("Serving driver calls at {}", endpoint);
+    loop {
+        let mut connection = accept()?;
+        let driver = Arc::clone(&driver);
+        std::thread::spawn(move || {
+            if let Err(e) = handle(&driver, &mut *connection) {
+                warn!("Dropping client: {}", e);
+            }
+        });
+    }
+}
+
+/// Executes the calls of one client until it hangs up.
+pub fn handle(driver: &Driver, connection: &mut dyn Transport) -> io::Result<()> {
+    while let Some(request) = Request::read_from(connection)? {
+        let reply = execute(driver, &request);
+        reply.write_to(connection)?;
+    }
+    Ok(())
+}
+
+macro_rules! call {
+    ($driver:expr, $name:ident($($arg:expr),*)) => {
+        match $driver.$name {
+            Some(f) => unsafe { f($($arg),*) },
+            None => CUDA_ERROR_NOT_SUPPORTED,
+        }
+    };
+}
+
+/// Executes `request` against `driver` on the calling thread.
+pub fn execute(d: &Driver, request: &Request) -> Reply {
+    let a = |i: usize| request.args.get(i).copied().unwrap_or(0);
+    let h = |i: usize| a(i) as *mut c_void;
+    let data = &request.data;
+    match request.symbol.as_str() {
+        "cuGetErrorName" | "cuGetErrorString" => {
+            let mut s = std::ptr::null();
+            let rc = if request.symbol == "cuGetErrorName" {
+                call!(d, cuGetErrorName(a(0) as CUresult, &mut s))
+            } else {
+                call!(d, cuGetErrorString(a(0) as CUresult, &mut s))
+            };
+            Reply::with_data(rc, c_bytes(s))
+        }
+        "cuInit" => Reply::new(call!(d, cuInit(a(0) as u32))),
+        "cuDriverGetVersion" => {
+            let mut version = 0;
+            let rc = call!(d, cuDriverGetVersion(&mut version));
+            Reply::with_outputs(rc, &[version as u64])
+        }
+        "cuDeviceGet" => {
+            let mut dev = 0;
+            let rc = call!(d, cuDeviceGet(&mut dev, a(0) as i32));
+            Reply::with_outputs(rc, &[dev as u64])
+        }
+        "cuDeviceGetCount" => {
+            let mut count = 0;
+            let rc = call!(d, cuDeviceGetCount(&mut count));
+            Reply::with_outputs(rc, &[count as u64])
+        }
+        "cuDeviceGetName" => {
+            let mut name = vec![0u8; a(0).min(4096) as usize];
+            let (ptr, len) = (name.as_mut_ptr() as *mut c_char, name.len() as i32);
+            let rc = call!(d, cuDeviceGetName(ptr, len, a(1) as CUdevice));
+            Reply::with_data(rc, name)
+        }
+        "cuDeviceGetAttribute" => {
+            let mut value = 0;
+            let rc = call!(
+                d,
+                cuDeviceGetAttribute(&mut value, a(0) as u32, a(1) as CUdevice)
+            );
+            Reply::with_outputs(rc, &[value as u64])
+        }
+        "cuDeviceTotalMem_v2" => {
+            let mut bytes = 0;
+            let rc = call!(d, cuDeviceTotalMem_v2(&mut bytes, a(0) as CUdevice));
+            Reply::with_outputs(rc, &[bytes as u64])
+        }
+        "cuDevicePrimaryCtxRetain" => {
+            let mut ctx = std::ptr::null_mut();
+            let rc = call!(d, cuDevicePrimaryCtxRetain(&mut ctx, a(0) as CUdevice));
+            Reply::with_outputs(rc, &[ctx as u64])
+        }
+        "cuDevicePrimaryCtxRelease_v2" => {
+            Reply::new(call!(d, cuDevicePrimaryCtxRelease_v2(a(0) as CUdevice)))
+        }
+        "cuCtxCreate_v2" => {
+            let mut ctx = std::ptr::null_mut();
+            let rc = call!(d, cuCtxCreate_v2(&mut ctx, a(0) as u32, a(1) as CUdevice));
+            Reply::with_outputs(rc, &[ctx as u64])
+        }
+        "cuCtxDestroy_v2" => Reply::new(call!(d, cuCtxDestroy_v2(h(0)))),
+        "cuCtxSetCurrent" => Reply::new(call!(d, cuCtxSetCurrent(h(0)))),
+        "cuCtxGetCurrent" => {
+            let mut ctx = std::ptr::null_mut();
+            let rc = call!(d, cuCtxGetCurrent(&mut ctx));
+            Reply::with_outputs(rc, &[ctx as u64])
+        }
+        "cuCtxGetDevice" => {
+            let mut dev = 0;
+            let rc = call!(d, cuCtxGetDevice(&mut dev));
+            Reply::with_outputs(rc, &[dev as u64])
+        }
+        "cuCtxSynchronize" => Reply::new(call!(d, cuCtxSynchronize())),
+        "cuModuleLoadData" => {
+            // PTX is sent without its terminating NUL; the other formats ignore trailing bytes.
+            let mut image = data.clone();
+            image.push(0);
+            let mut module = std::ptr::null_mut();
+            let rc = call!(
+                d,
+                cuModuleLoadData(&mut module, image.as_ptr() as *const c_void)
+            );
+            Reply::with_outputs(rc, &[module as u64])
+        }
+        "cuModuleUnload" => Reply::new(call!(d, cuModuleUnload(h(0)))),
+        "cuModuleGetFunction" => {
+            let Ok(name) = CString::new(data.clone()) else {
+                return Reply::new(CUDA_ERROR_INVALID_VALUE);
+            };
+            let mut func = std::ptr::null_mut();
+            let rc = call!(d, cuModuleGetFunction(&mut func, h(0), name.as_ptr()));
+            Reply::with_outputs(rc, &[func as u64])
+        }
+        "cuFuncGetName" => {
+            let mut name = std::ptr::null();
+            let rc = call!(d, cuFuncGetName(&mut name, h(0)));
+            Reply::with_data(rc, c_bytes(name))
+        }
+        "cuFuncGetParamInfo" => {
+            let (mut offset, mut size) = (0, 0);
+            let rc = call!(
+                d,
+                cuFuncGetParamInfo(h(0), a(1) as usize, &mut offset, &mut size)
+            );
+            Reply::with_outputs(rc, &[offset as u64, size as u64])
+        }
+        "cuMemAlloc_v2" => {
+            let mut dptr = 0;
+            let rc = call!(d, cuMemAlloc_v2(&mut dptr, a(0) as usize));
+            Reply::with_outputs(rc, &[dptr])
+        }
+        "cuMemFree_v2" => Reply::new(call!(d, cuMemFree_v2(a(0)))),
+        "cuMemGetInfo_v2" => {
+            let (mut free, mut total) = (0, 0);
+            let rc = call!(d, cuMemGetInfo_v2(&mut free, &mut total));
+            Reply::with_outputs(rc, &[free as u64, total as u64])
+        }
+        "cuMemcpyHtoD_v2" => {
+            let src = data.as_ptr() as *const c_void;
+            Reply::new(call!(d, cuMemcpyHtoD_v2(a(0), src, data.len())))
+        }
+        "cuMemcpyHtoDAsync_v2" => {
+            // The request buffer is only valid until the reply, so the copy has to finish.
+            let src = data.as_ptr() as *const c_void;
+            let rc = call!(d, cuMemcpyHtoDAsync_v2(a(0), src, data.len(), h(1)));
+            Reply::new(then_synchronize(d, rc, h(1)))
+        }
+        "cuMemcpyDtoH_v2" | "cuMemcpyDtoHAsync_v2" if a(1) > max_buffer() as u64 => {
+            Reply::new(CUDA_ERROR_INVALID_VALUE)
+        }
+        "cuMemcpyDtoH_v2" => {
+            let mut dst = vec![0u8; a(1) as usize];
+            let ptr = dst.as_mut_ptr() as *mut c_void;
+            let rc = call!(d, cuMemcpyDtoH_v2(ptr, a(0), dst.len()));
+            Reply::with_data(rc, dst)
+        }
+        "cuMemcpyDtoHAsync_v2" => {
+            let mut dst = vec![0u8; a(1) as usize];
+            let ptr = dst.as_mut_ptr() as *mut c_void;
+            let rc = call!(d, cuMemcpyDtoHAsync_v2(ptr, a(0), dst.len(), h(2)));
+            Reply::with_data(then_synchronize(d, rc, h(2)), dst)
+        }
+        "cuMemcpyDtoD_v2" => Reply::new(call!(d, cuMemcpyDtoD_v2(a(0), a(1), a(2) as usize))),
+        "cuMemcpyDtoDAsync_v2" => Reply::new(call!(
+            d,
+            cuMemcpyDtoDAsync_v2(a(0), a(1), a(2) as usize, h(3))
+        )),
+        "cuMemsetD8_v2" => Reply::new(call!(d, cuMemsetD8_v2(a(0), a(1) as u8, a(2) as usize))),
+        "cuMemsetD8Async" => Reply::new(call!(
+            d,
+            cuMemsetD8Async(a(0), a(1) as u8, a(2) as usize, h(3))
+        )),
+        "cuMemsetD32_v2" => Reply::new(call!(d, cuMemsetD32_v2(a(0), a(1) as u32, a(2) as usize))),
+        "cuMemsetD32Async" => Reply::new(call!(
+            d,
+            cuMemsetD32Async(a(0), a(1) as u32, a(2) as usize, h(3))
+        )),
+        "cuStreamCreate" => {
+            let mut stream = std::ptr::null_mut();
+            let rc = call!(d, cuStreamCreate(&mut stream, a(0) as u32));
+            Reply::with_outputs(rc, &[stream as u64])
+        }
+        "cuStreamCreateWithPriority" => {
+            let mut stream = std::ptr::null_mut();
+            let rc = call!(
+                d,
+                cuStreamCreateWithPriority(&mut stream, a(0) as u32, a(1) as i32)
+            );
+            Reply::with_outputs(rc, &[stream as u64])
+        }
+        "cuStreamDestroy_v2" => Reply::new(call!(d, cuStreamDestroy_v2(h(0)))),
+        "cuStreamSynchronize" => Reply::new(call!(d, cuStreamSynchronize(h(0)))),
+        "cuStreamQuery" => Reply::new(call!(d, cuStreamQuery(h(0)))),
+        "cuStreamWaitEvent" => Reply::new(call!(d, cuStreamWaitEvent(h(0), h(1), a(2) as u32))),
+        "cuEventCreate" => {
+            let mut event = std::ptr::null_mut();
+            let rc = call!(d, cuEventCreate(&mut event, a(0) as u32));
+            Reply::with_outputs(rc, &[event as u64])
+        }
+        "cuEventDestroy_v2" => Reply::new(call!(d, cuEventDestroy_v2(h(0)))),
+        "cuEventRecord" => Reply::new(call!(d, cuEventRecord(h(0), h(1)))),
+        "cuEventSynchronize" => Reply::new(call!(d, cuEventSynchronize(h(0)))),
+        "cuEventQuery" => Reply::new(call!(d, cuEventQuery(h(0)))),
+        "cuEventElapsedTime" => {
+            let mut ms = 0.0f32;
+            let rc = call!(d, cuEventElapsedTime(&mut ms, h(0), h(1)));
+            Reply::with_outputs(rc, &[ms.to_bits() as u64])
+        }
+        "cuLaunchKernel" => {
+            // The driver copies the parameter buffer before the launch returns.
+            let mut buffer = data.clone();
+            let mut size = buffer.len();
+            let mut extra = [
+                args::CU_LAUNCH_PARAM_BUFFER_POINTER as *mut c_void,
+                buffer.as_mut_ptr() as *mut c_void,
+                args::CU_LAUNCH_PARAM_BUFFER_SIZE as *mut c_void,
+                &mut size as *mut usize as *mut c_void,
+                args::CU_LAUNCH_PARAM_END as *mut c_void,
+            ];
+            let [grid_x, grid_y, grid_z, block_x, block_y, block_z, shared] =
+                [1, 2, 3, 4, 5, 6, 7].map(|i| a(i) as u32);
+            Reply::new(call!(
+                d,
+                cuLaunchKernel(
+                    h(0),
+                    grid_x,
+                    grid_y,
+                    grid_z,
+                    block_x,
+                    block_y,
+                    block_z,
+                    shared,
+                    h(8),
+                    std::ptr::null_mut(),
+                    extra.as_mut_ptr()
+                )
+            ))
+        }
+        symbol => {
+            debug!("Client asked for {}, which is not forwarded", symbol);
+            Reply::new(CUDA_ERROR_NOT_SUPPORTED)
+        }
+    }
+}
+
+fn then_synchronize(d: &Driver, rc: CUresult, stream: CUstream) -> CUresult {
+    if rc != CUDA_SUCCESS {
+        return rc;
+    }
+    call!(d, cuStreamSynchronize(stream))
+}
+
+fn c_bytes(s: *const c_char) -> Vec<u8> {
+    if s.is_null() {
+        return Vec::new();
+    }
+    unsafe { CStr::from_ptr(s) }.to_bytes().to_vec()
+}
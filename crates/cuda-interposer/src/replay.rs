@@ -0,0 +1,801 @@
+//! Trace replay.
+//!
+//! Re-issues a trace recorded by [`crate::record`] against a driver, in trace order on the
+//! calling thread. The handles and device pointers the driver returns during the replay differ
+//! from the recorded ones, so each call's arguments are remapped: contexts, modules, functions,
+//! streams and events through what the replayed creation call returned, and device pointers
+//! through the recorded allocations, so that pointers into the middle of an allocation map to
+//! the same offset. Kernel arguments of 8 bytes that fall within a recorded allocation are
+//! treated as device pointers and remapped the same way.
+//!
+//! Calls made before any context was created or set, as by the runtime, run in the primary
+//! context of device 0. Calls whose handles cannot be remapped are skipped, as are launches
+//! recorded without their arguments. Every call that was skipped, returned a different result,
+//! or, for synchronous device-to-host copies, copied different data is reported as a
+//! [`Divergence`].
+//!
+//! The driver is opened with `dlopen`, so a stand-in library exposing the same entry points can
+//! be replayed against; the interposer's hooks are not involved either way.
+
+use crate::ffi::*;
+use crate::record::{Blob, Call, Entry, Trace};
+use crate::streams::MemcpyKind;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::fmt;
+use std::path::Path;
+
+macro_rules! driver_api {
+    ($($name:ident($($arg_ty:ty),*);)*) => {
+        /// The entry points of a driver library opened directly, bypassing the interposer: what
+        /// traces are replayed against and what [`crate::remote::serve`] executes calls with.
+        #[allow(non_snake_case)]
+        pub struct Driver {
+            $(pub(crate) $name: Option<unsafe extern "C" fn($($arg_ty),*) -> CUresult>,)*
+        }
+
+        impl Driver {
+            /// Opens the driver library at `path`. Entry points it lacks fail the calls that
+            /// need them.
+            pub fn open(path: &str) -> Result<Self, ReplayError> {
+                let c_path = CString::new(path).map_err(|e| ReplayError::Open(e.to_string()))?;
+                // The library is never closed: the resources of a replay outlive the `Driver`.
+                let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+                if handle.is_null() {
+                    return Err(ReplayError::Open(dlerror()));
+                }
+                Ok(Self {
+                    $($name: {
+                        let name = concat!(stringify!($name), "\0");
+                        let sym = unsafe { libc::dlsym(handle, name.as_ptr() as *const c_char) };
+                        (!sym.is_null()).then(|| unsafe {
+                            std::mem::transmute::<
+                                *mut c_void,
+                                unsafe extern "C" fn($($arg_ty),*) -> CUresult,
+                            >(sym)
+                        })
+                    },)*
+                })
+            }
+        }
+    };
+}
+
+driver_api! {
+    cuGetErrorName(CUresult, *mut *const c_char);
+    cuGetErrorString(CUresult, *mut *const c_char);
+    cuInit(u32);
+    cuDriverGetVersion(*mut i32);
+    cuDeviceGet(*mut CUdevice, i32);
+    cuDeviceGetCount(*mut i32);
+    cuDeviceGetName(*mut c_char, i32, CUdevice);
+    cuDeviceGetAttribute(*mut i32, u32, CUdevice);
+    cuDeviceTotalMem_v2(*mut usize, CUdevice);
+    cuDevicePrimaryCtxRetain(*mut CUcontext, CUdevice);
+    cuDevicePrimaryCtxRelease_v2(CUdevice);
+    cuCtxCreate_v2(*mut CUcontext, u32, CUdevice);
+    cuCtxDestroy_v2(CUcontext);
+    cuCtxSetCurrent(CUcontext);
+    cuCtxGetCurrent(*mut CUcontext);
+    cuCtxGetDevice(*mut CUdevice);
+    cuCtxSynchronize();
+    cuModuleLoadData(*mut CUmodule, *const c_void);
+    cuModuleUnload(CUmodule);
+    cuModuleGetFunction(*mut CUfunction, CUmodule, *const c_char);
+    cuFuncGetName(*mut *const c_char, CUfunction);
+    cuFuncGetParamInfo(CUfunction, usize, *mut usize, *mut usize);
+    cuMemAlloc_v2(*mut CUdeviceptr, usize);
+    cuMemFree_v2(CUdeviceptr);
+    cuMemGetInfo_v2(*mut usize, *mut usize);
+    cuMemcpy(CUdeviceptr, CUdeviceptr, usize);
+    cuMemcpyAsync(CUdeviceptr, CUdeviceptr, usize, CUstream);
+    cuMemcpyHtoD_v2(CUdeviceptr, *const c_void, usize);
+    cuMemcpyHtoDAsync_v2(CUdeviceptr, *const c_void, usize, CUstream);
+    cuMemcpyDtoH_v2(*mut c_void, CUdeviceptr, usize);
+    cuMemcpyDtoHAsync_v2(*mut c_void, CUdeviceptr, usize, CUstream);
+    cuMemcpyDtoD_v2(CUdeviceptr, CUdeviceptr, usize);
+    cuMemcpyDtoDAsync_v2(CUdeviceptr, CUdeviceptr, usize, CUstream);
+    cuMemsetD8_v2(CUdeviceptr, u8, usize);
+    cuMemsetD8Async(CUdeviceptr, u8, usize, CUstream);
+    cuMemsetD32_v2(CUdeviceptr, u32, usize);
+    cuMemsetD32Async(CUdeviceptr, u32, usize, CUstream);
+    cuStreamCreate(*mut CUstream, u32);
+    cuStreamCreateWithPriority(*mut CUstream, u32, i32);
+    cuStreamDestroy_v2(CUstream);
+    cuStreamSynchronize(CUstream);
+    cuStreamQuery(CUstream);
+    cuStreamWaitEvent(CUstream, CUevent, u32);
+    cuEventCreate(*mut CUevent, u32);
+    cuEventDestroy_v2(CUevent);
+    cuEventRecord(CUevent, CUstream);
+    cuEventSynchronize(CUevent);
+    cuEventQuery(CUevent);
+    cuEventElapsedTime(*mut f32, CUevent, CUevent);
+    cuLaunchKernel(
+        CUfunction, u32, u32, u32, u32, u32, u32, u32, CUstream, *mut *mut c_void,
+        *mut *mut c_void
+    );
+}
+
+fn dlerror() -> String {
+    let err = unsafe { libc::dlerror() };
+    if err.is_null() {
+        return "unknown error".into();
+    }
+    unsafe { CStr::from_ptr(err) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    /// The driver library could not be loaded; `dlerror` names it.
+    Open(String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{e}"),
+            ReplayError::Open(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// How a replayed call departed from the recording.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DivergenceKind {
+    Result {
+        recorded: CUresult,
+        replayed: CUresult,
+    },
+    /// A synchronous device-to-host copy read different bytes, the first at `offset`.
+    Data {
+        offset: usize,
+    },
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub seq: u64,
+    pub api: String,
+    pub kind: DivergenceKind,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {}: ", self.seq, self.api)?;
+        match &self.kind {
+            DivergenceKind::Result { recorded, replayed } => {
+                write!(f, "returned {replayed}, recorded {recorded}")
+            }
+            DivergenceKind::Data { offset } => {
+                write!(f, "copied data differs from the recording at byte {offset}")
+            }
+            DivergenceKind::Skipped(reason) => write!(f, "skipped: {reason}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    /// Calls issued to the driver.
+    pub replayed: usize,
+    pub skipped: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+impl ReplayReport {
+    /// Returns whether every call was replayed with its recorded result and data.
+    pub fn is_faithful(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "replayed {} calls, skipped {}, {} divergences",
+            self.replayed,
+            self.skipped,
+            self.divergences.len()
+        )?;
+        for divergence in &self.divergences {
+            writeln!(f, "  {divergence}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Replays the trace in `dir` against the driver library at `driver`, e.g. `libcuda.so.1`.
+pub fn replay_dir(dir: impl AsRef<Path>, driver: &str) -> Result<ReplayReport, ReplayError> {
+    let trace = Trace::load(dir.as_ref()).map_err(ReplayError::Io)?;
+    let driver = Driver::open(driver)?;
+    Ok(replay(&trace, &driver))
+}
+
+/// Replays `trace` against `driver`.
+pub fn replay(trace: &Trace, driver: &Driver) -> ReplayReport {
+    let mut replayer = Replayer {
+        driver,
+        trace,
+        contexts: HashMap::new(),
+        modules: HashMap::new(),
+        functions: HashMap::new(),
+        streams: HashMap::new(),
+        events: HashMap::new(),
+        allocations: BTreeMap::new(),
+        primary: None,
+        current: None,
+        buffers: Vec::new(),
+        mismatch: None,
+        report: ReplayReport::default(),
+    };
+    for entry in &trace.entries {
+        replayer.replay(entry);
+    }
+    replayer.finish();
+    replayer.report
+}
+
+/// Calls a driver entry point, or skips the call if the driver lacks it.
+macro_rules! call {
+    ($driver:expr, $name:ident($($arg:expr),*)) => {
+        match $driver.$name {
+            Some(f) => unsafe { f($($arg),*) },
+            None => return Err(concat!("the driver lacks ", stringify!($name)).to_string()),
+        }
+    };
+}
+
+struct Replayer<'a> {
+    driver: &'a Driver,
+    trace: &'a Trace,
+    contexts: HashMap<u64, u64>,
+    modules: HashMap<u64, u64>,
+    functions: HashMap<u64, u64>,
+    streams: HashMap<u64, u64>,
+    events: HashMap<u64, u64>,
+    /// Recorded base address to replayed base address and size.
+    allocations: BTreeMap<u64, (CUdeviceptr, usize)>,
+    /// The primary context of device 0, once retained.
+    primary: Option<CUcontext>,
+    current: Option<CUcontext>,
+    /// Host memory of asynchronous copies, kept until the replay is synchronized.
+    buffers: Vec<Vec<u8>>,
+    /// Where the last synchronous device-to-host copy departed from the recording.
+    mismatch: Option<usize>,
+    report: ReplayReport,
+}
+
+/// Looks up the replayed value of a recorded handle.
+fn lookup(map: &HashMap<u64, u64>, what: &str, recorded: u64) -> Result<*mut c_void, String> {
+    map.get(&recorded)
+        .map(|&live| live as *mut c_void)
+        .ok_or_else(|| format!("unknown {what} {recorded:#x}"))
+}
+
+/// Maps a recorded handle to its replayed value, if both calls created one.
+fn bind(map: &mut HashMap<u64, u64>, rc: CUresult, recorded: u64, live: *mut c_void) {
+    if rc == CUDA_SUCCESS && recorded != 0 {
+        map.insert(recorded, live as u64);
+    }
+}
+
+impl Replayer<'_> {
+    fn replay(&mut self, entry: &Entry) {
+        let api = entry.call.name().to_string();
+        let kind = match self.issue(&entry.call, entry.result) {
+            Ok(rc) => {
+                self.report.replayed += 1;
+                if rc != entry.result {
+                    DivergenceKind::Result {
+                        recorded: entry.result,
+                        replayed: rc,
+                    }
+                } else if let Some(offset) = self.mismatch.take() {
+                    DivergenceKind::Data { offset }
+                } else {
+                    return;
+                }
+            }
+            Err(reason) => {
+                self.report.skipped += 1;
+                DivergenceKind::Skipped(reason)
+            }
+        };
+        self.diverge(entry.seq, api, kind);
+    }
+
+    fn diverge(&mut self, seq: u64, api: String, kind: DivergenceKind) {
+        self.report.divergences.push(Divergence { seq, api, kind });
+    }
+
+    /// Returns the primary context of device 0, retaining it on first use.
+    fn primary(&mut self) -> Result<CUcontext, String> {
+        if let Some(ctx) = self.primary {
+            return Ok(ctx);
+        }
+        let d = self.driver;
+        let rc = call!(d, cuInit(0));
+        if rc != CUDA_SUCCESS {
+            return Err(format!("cuInit failed with {rc}"));
+        }
+        let mut ctx = std::ptr::null_mut();
+        let rc = call!(d, cuDevicePrimaryCtxRetain(&mut ctx, 0));
+        if rc != CUDA_SUCCESS {
+            return Err(format!("cannot retain the primary context: {rc}"));
+        }
+        self.primary = Some(ctx);
+        Ok(ctx)
+    }
+
+    /// Makes the primary context current if no context is.
+    fn ensure_context(&mut self) -> Result<(), String> {
+        if self.current.is_some() {
+            return Ok(());
+        }
+        let ctx = self.primary()?;
+        let d = self.driver;
+        let rc = call!(d, cuCtxSetCurrent(ctx));
+        if rc != CUDA_SUCCESS {
+            return Err(format!("cannot set the primary context current: {rc}"));
+        }
+        self.current = Some(ctx);
+        Ok(())
+    }
+
+    fn context(&mut self, recorded: u64) -> Result<CUcontext, String> {
+        match self.contexts.get(&recorded) {
+            Some(&live) => Ok(live as CUcontext),
+            None => self.primary(),
+        }
+    }
+
+    fn stream(&self, recorded: u64) -> Result<CUstream, String> {
+        match recorded {
+            // The null stream and the legacy and per-thread default streams.
+            0..=2 => Ok(recorded as CUstream),
+            _ => lookup(&self.streams, "stream", recorded),
+        }
+    }
+
+    fn optional_stream(&self, recorded: Option<u64>) -> Result<Option<CUstream>, String> {
+        recorded.map(|s| self.stream(s)).transpose()
+    }
+
+    /// Maps a recorded device address to the same offset in the replayed allocation.
+    fn translate(&self, recorded: u64) -> Option<CUdeviceptr> {
+        let (&base, &(live, size)) = self.allocations.range(..=recorded).next_back()?;
+        let offset = recorded - base;
+        (offset < size as u64).then_some(live + offset)
+    }
+
+    fn device(&self, recorded: u64) -> Result<CUdeviceptr, String> {
+        self.translate(recorded)
+            .ok_or_else(|| format!("unknown device pointer {recorded:#x}"))
+    }
+
+    fn blob(&self, blob: Option<&Blob>, bytes: usize) -> Result<Vec<u8>, String> {
+        let blob = blob.ok_or("the host memory was not recorded")?;
+        let data = self
+            .trace
+            .blob(blob)
+            .map_err(|e| format!("cannot read blob {}: {e}", blob.sha256))?;
+        if data.len() < bytes {
+            return Err(format!("blob {} is truncated", blob.sha256));
+        }
+        Ok(data)
+    }
+
+    fn issue(&mut self, call: &Call, recorded_rc: CUresult) -> Result<CUresult, String> {
+        let d = self.driver;
+        let setup = matches!(
+            call,
+            Call::Init { .. }
+                | Call::CtxCreate { .. }
+                | Call::DevicePrimaryCtxRetain { .. }
+                | Call::CtxSetCurrent { .. }
+        );
+        if !setup {
+            self.ensure_context()?;
+        }
+        let rc = match call {
+            Call::Init { flags } => call!(d, cuInit(*flags)),
+            Call::CtxCreate { flags, device, ctx } => {
+                let mut live = std::ptr::null_mut();
+                let rc = call!(d, cuCtxCreate_v2(&mut live, *flags, *device));
+                bind(&mut self.contexts, rc, *ctx, live);
+                if rc == CUDA_SUCCESS {
+                    self.current = Some(live);
+                }
+                rc
+            }
+            Call::DevicePrimaryCtxRetain { device, ctx } => {
+                let mut live = std::ptr::null_mut();
+                let rc = call!(d, cuDevicePrimaryCtxRetain(&mut live, *device));
+                bind(&mut self.contexts, rc, *ctx, live);
+                rc
+            }
+            Call::CtxDestroy { ctx } => {
+                let live = lookup(&self.contexts, "context", *ctx)?;
+                let rc = call!(d, cuCtxDestroy_v2(live));
+                if rc == CUDA_SUCCESS {
+                    self.contexts.remove(ctx);
+                    if self.current == Some(live) {
+                        self.current = None;
+                    }
+                }
+                rc
+            }
+            Call::CtxSetCurrent { ctx } => {
+                let live = match ctx {
+                    0 => std::ptr::null_mut(),
+                    _ => self.context(*ctx)?,
+                };
+                let rc = call!(d, cuCtxSetCurrent(live));
+                if rc == CUDA_SUCCESS {
+                    self.current = (!live.is_null()).then_some(live);
+                }
+                rc
+            }
+            Call::CtxSynchronize => call!(d, cuCtxSynchronize()),
+            Call::ModuleLoad { image, module, .. } => {
+                let mut image = self.blob(Some(image), 0)?;
+                // PTX must be NUL-terminated; cubins and fat binaries ignore the extra byte.
+                image.push(0);
+                let mut live = std::ptr::null_mut();
+                let rc = call!(
+                    d,
+                    cuModuleLoadData(&mut live, image.as_ptr() as *const c_void)
+                );
+                bind(&mut self.modules, rc, *module, live);
+                rc
+            }
+            Call::ModuleUnload { module } => {
+                let live = lookup(&self.modules, "module", *module)?;
+                let rc = call!(d, cuModuleUnload(live));
+                if rc == CUDA_SUCCESS {
+                    self.modules.remove(module);
+                }
+                rc
+            }
+            Call::ModuleGetFunction {
+                module,
+                name,
+                function,
+            } => {
+                let live_module = lookup(&self.modules, "module", *module)?;
+                let name = CString::new(name.as_str()).map_err(|e| e.to_string())?;
+                let mut live = std::ptr::null_mut();
+                let rc = call!(
+                    d,
+                    cuModuleGetFunction(&mut live, live_module, name.as_ptr())
+                );
+                bind(&mut self.functions, rc, *function, live);
+                rc
+            }
+            Call::MemAlloc { api, bytes, dptr } => {
+                if api == "cuMemCreate" {
+                    return Err("physical allocations are not replayed".into());
+                }
+                let mut live = 0;
+                let rc = call!(d, cuMemAlloc_v2(&mut live, *bytes));
+                if rc == CUDA_SUCCESS && *dptr != 0 {
+                    self.allocations.insert(*dptr, (live, *bytes));
+                }
+                rc
+            }
+            Call::MemFree { dptr } => {
+                let &(live, _) = self
+                    .allocations
+                    .get(dptr)
+                    .ok_or_else(|| format!("unknown allocation {dptr:#x}"))?;
+                let rc = call!(d, cuMemFree_v2(live));
+                if rc == CUDA_SUCCESS {
+                    self.allocations.remove(dptr);
+                }
+                rc
+            }
+            Call::Memcpy {
+                kind,
+                dst,
+                src,
+                bytes,
+                stream,
+                data,
+                ..
+            } => {
+                let stream = self.optional_stream(*stream)?;
+                let bytes = *bytes;
+                match kind {
+                    MemcpyKind::HtoD => {
+                        let dst = self.device(*dst)?;
+                        let host = self.blob(data.as_ref(), bytes)?;
+                        let src = host.as_ptr() as *const c_void;
+                        let rc = match stream {
+                            None => call!(d, cuMemcpyHtoD_v2(dst, src, bytes)),
+                            Some(s) => call!(d, cuMemcpyHtoDAsync_v2(dst, src, bytes, s)),
+                        };
+                        if stream.is_some() {
+                            self.buffers.push(host);
+                        }
+                        rc
+                    }
+                    MemcpyKind::DtoH => {
+                        let src = self.device(*src)?;
+                        let mut host = vec![0u8; bytes];
+                        let dst = host.as_mut_ptr() as *mut c_void;
+                        match stream {
+                            None => {
+                                let rc = call!(d, cuMemcpyDtoH_v2(dst, src, bytes));
+                                if rc == CUDA_SUCCESS
+                                    && recorded_rc == CUDA_SUCCESS
+                                    && let Some(data) = data
+                                {
+                                    self.mismatch = self.first_difference(data, &host)?;
+                                }
+                                rc
+                            }
+                            Some(s) => {
+                                let rc = call!(d, cuMemcpyDtoHAsync_v2(dst, src, bytes, s));
+                                self.buffers.push(host);
+                                rc
+                            }
+                        }
+                    }
+                    MemcpyKind::DtoD | MemcpyKind::Default => {
+                        let (dst, src) = (self.device(*dst)?, self.device(*src)?);
+                        match (kind, stream) {
+                            (MemcpyKind::DtoD, None) => call!(d, cuMemcpyDtoD_v2(dst, src, bytes)),
+                            (MemcpyKind::DtoD, Some(s)) => {
+                                call!(d, cuMemcpyDtoDAsync_v2(dst, src, bytes, s))
+                            }
+                            (_, None) => call!(d, cuMemcpy(dst, src, bytes)),
+                            (_, Some(s)) => call!(d, cuMemcpyAsync(dst, src, bytes, s)),
+                        }
+                    }
+                }
+            }
+            Call::Memset {
+                dst,
+                value,
+                element_size,
+                count,
+                stream,
+            } => {
+                let dst = self.device(*dst)?;
+                let stream = self.optional_stream(*stream)?;
+                match (element_size, stream) {
+                    (1, None) => call!(d, cuMemsetD8_v2(dst, *value as u8, *count)),
+                    (1, Some(s)) => call!(d, cuMemsetD8Async(dst, *value as u8, *count, s)),
+                    (_, None) => call!(d, cuMemsetD32_v2(dst, *value, *count)),
+                    (_, Some(s)) => call!(d, cuMemsetD32Async(dst, *value, *count, s)),
+                }
+            }
+            Call::StreamCreate {
+                flags,
+                priority,
+                stream,
+            } => {
+                let mut live = std::ptr::null_mut();
+                let rc = match priority {
+                    None => call!(d, cuStreamCreate(&mut live, *flags)),
+                    Some(p) => call!(d, cuStreamCreateWithPriority(&mut live, *flags, *p)),
+                };
+                bind(&mut self.streams, rc, *stream, live);
+                rc
+            }
+            Call::StreamDestroy { stream } => {
+                let live = lookup(&self.streams, "stream", *stream)?;
+                let rc = call!(d, cuStreamDestroy_v2(live));
+                if rc == CUDA_SUCCESS {
+                    self.streams.remove(stream);
+                }
+                rc
+            }
+            Call::StreamSynchronize { stream } => {
+                let stream = self.stream(*stream)?;
+                call!(d, cuStreamSynchronize(stream))
+            }
+            Call::EventCreate { flags, event } => {
+                let mut live = std::ptr::null_mut();
+                let rc = call!(d, cuEventCreate(&mut live, *flags));
+                bind(&mut self.events, rc, *event, live);
+                rc
+            }
+            Call::EventDestroy { event } => {
+                let live = lookup(&self.events, "event", *event)?;
+                let rc = call!(d, cuEventDestroy_v2(live));
+                if rc == CUDA_SUCCESS {
+                    self.events.remove(event);
+                }
+                rc
+            }
+            Call::EventRecord { event, stream } => {
+                let event = lookup(&self.events, "event", *event)?;
+                let stream = self.stream(*stream)?;
+                call!(d, cuEventRecord(event, stream))
+            }
+            Call::StreamWaitEvent {
+                stream,
+                event,
+                flags,
+            } => {
+                let stream = self.stream(*stream)?;
+                let event = lookup(&self.events, "event", *event)?;
+                call!(d, cuStreamWaitEvent(stream, event, *flags))
+            }
+            Call::EventSynchronize { event } => {
+                let event = lookup(&self.events, "event", *event)?;
+                call!(d, cuEventSynchronize(event))
+            }
+            Call::LaunchKernel {
+                function,
+                grid,
+                block,
+                shared_mem,
+                stream,
+                args,
+            } => {
+                let args = args.as_ref().ok_or("the arguments were not recorded")?;
+                let function = lookup(&self.functions, "function", *function)?;
+                let stream = self.stream(*stream)?;
+                let mut args: Vec<Vec<u8>> = args.iter().map(|a| self.translate_arg(a)).collect();
+                let mut params: Vec<*mut c_void> = args
+                    .iter_mut()
+                    .map(|a| a.as_mut_ptr() as *mut c_void)
+                    .collect();
+                let [gx, gy, gz] = *grid;
+                let [bx, by, bz] = *block;
+                let null = std::ptr::null_mut();
+                call!(
+                    d,
+                    cuLaunchKernel(
+                        function,
+                        gx,
+                        gy,
+                        gz,
+                        bx,
+                        by,
+                        bz,
+                        *shared_mem,
+                        stream,
+                        params.as_mut_ptr(),
+                        null
+                    )
+                )
+            }
+        };
+        Ok(rc)
+    }
+
+    /// Remaps an argument that holds a recorded device address.
+    fn translate_arg(&self, arg: &[u8]) -> Vec<u8> {
+        let Ok(bytes) = <[u8; 8]>::try_from(arg) else {
+            return arg.to_vec();
+        };
+        match self.translate(u64::from_ne_bytes(bytes)) {
+            Some(live) => live.to_ne_bytes().to_vec(),
+            None => arg.to_vec(),
+        }
+    }
+
+    /// Returns where what a copy read first differs from the recording.
+    fn first_difference(&self, recorded: &Blob, host: &[u8]) -> Result<Option<usize>, String> {
+        let recorded = self.blob(Some(recorded), host.len())?;
+        Ok(host.iter().zip(&recorded).position(|(a, b)| a != b))
+    }
+
+    /// Waits for outstanding copies before releasing their host memory.
+    fn finish(&mut self) {
+        if !self.buffers.is_empty()
+            && self.current.is_some()
+            && let Some(f) = self.driver.cuCtxSynchronize
+        {
+            unsafe { f() };
+        }
+        self.buffers.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{self, PTX};
+    use crate::memory::AllocKind;
+    use crate::record;
+    use std::ptr::null_mut;
+
+    #[test]
+    fn replays_a_recording_against_the_fake_driver() {
+        let (d, _guard) = fake::driver();
+        let dir = std::env::temp_dir().join(format!("replay-test-{}", std::process::id()));
+        record::start(&dir).unwrap();
+
+        let rc = unsafe { d.cuInit.unwrap()(0) };
+        record::on_init(rc, 0);
+        let mut ctx = null_mut();
+        let rc = unsafe { d.cuCtxCreate_v2.unwrap()(&mut ctx, 0, 0) };
+        unsafe { record::on_ctx_create(rc, &ctx, 0, 0) };
+
+        let mut module = null_mut();
+        let image = PTX.as_ptr() as *const c_void;
+        let rc = unsafe { d.cuModuleLoadData.unwrap()(&mut module, image) };
+        unsafe { record::on_module_load(rc, "cuModuleLoadData", &module, image) };
+        let mut function = null_mut();
+        let rc =
+            unsafe { d.cuModuleGetFunction.unwrap()(&mut function, module, c"scale".as_ptr()) };
+        unsafe { record::on_get_function(rc, &function, module, c"scale".as_ptr()) };
+
+        let input = [1.0f32, 2.0, 3.0, 4.0];
+        let bytes = size_of_val(&input);
+        let mut dptr = 0;
+        let rc = unsafe { d.cuMemAlloc_v2.unwrap()(&mut dptr, bytes) };
+        unsafe { record::on_alloc(rc, &dptr, bytes, AllocKind::Device) };
+        let src = input.as_ptr() as *const c_void;
+        let rc = unsafe { d.cuMemcpyHtoD_v2.unwrap()(dptr, src, bytes) };
+        let api = "cuMemcpyHtoD_v2";
+        unsafe { record::on_memcpy(rc, api, MemcpyKind::HtoD, dptr, src as u64, bytes, None) };
+
+        let mut n = input.len() as u32;
+        let mut params = [
+            &mut dptr as *mut u64 as *mut c_void,
+            &mut n as *mut u32 as *mut c_void,
+        ];
+        let rc = unsafe {
+            d.cuLaunchKernel.unwrap()(
+                function,
+                1,
+                1,
+                1,
+                4,
+                1,
+                1,
+                0,
+                null_mut(),
+                params.as_mut_ptr(),
+                null_mut(),
+            )
+        };
+        let (grid, block) = ([1, 1, 1], [4, 1, 1]);
+        let kernel_params = params.as_mut_ptr();
+        unsafe {
+            record::on_launch(
+                rc,
+                function,
+                grid,
+                block,
+                0,
+                null_mut(),
+                kernel_params,
+                null_mut(),
+            )
+        };
+
+        let mut output = [0f32; 4];
+        let dst = output.as_mut_ptr() as *mut c_void;
+        let rc = unsafe { d.cuMemcpyDtoH_v2.unwrap()(dst, dptr, bytes) };
+        let api = "cuMemcpyDtoH_v2";
+        unsafe { record::on_memcpy(rc, api, MemcpyKind::DtoH, dst as u64, dptr, bytes, None) };
+        assert_eq!(output, [2.0, 4.0, 6.0, 8.0]);
+        record::stop();
+
+        let trace = Trace::load(&dir).unwrap();
+        assert_eq!(trace.entries.len(), 8);
+        let Call::LaunchKernel { args, .. } = &trace.entries[6].call else {
+            panic!("expected a launch, got {:?}", trace.entries[6].call);
+        };
+        assert_eq!(args.as_ref().map(Vec::len), Some(2));
+
+        let report = replay(&trace, &d);
+        assert!(report.is_faithful(), "{report}");
+        assert_eq!(report.replayed, 8);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
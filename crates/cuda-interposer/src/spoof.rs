@@ -0,0 +1,156 @@
+//! Device attribute and property spoofing.
+//!
+//! A [`Profile`] overrides what the driver reports about devices: the name, total memory,
+//! compute capability and any `cuDeviceGetAttribute` value. [`install_device_hooks!`] applies it
+//! to the driver queries and [`install_runtime_device_hooks!`] to `cudaGetDeviceProperties` and
+//! `cudaGetDeviceProperties_v2`. Profiles are TOML files in the format described in
+//! [`cuda_interposer_common::profile`], loaded from `CUDA_HOOK_DEVICE_PROFILE` or set with
+//! [`set_profile`]. Devices are the logical ones the application sees.
+
+use crate::ffi::*;
+use once_cell::sync::Lazy;
+use std::ffi::c_char;
+use std::sync::RwLock;
+use tracing::warn;
+
+pub use cuda_interposer_common::profile::{
+    ATTRIBUTE_NAMES, CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR,
+    CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR, DeviceProfile, Profile, ProfileError, Size,
+    attribute_id,
+};
+
+const PROFILE_ENV: &str = "CUDA_HOOK_DEVICE_PROFILE";
+
+static PROFILE: Lazy<RwLock<Option<Profile>>> = Lazy::new(|| {
+    let profile = std::env::var_os(PROFILE_ENV).and_then(|path| {
+        Profile::load(&path)
+            .map_err(|e| warn!("Ignoring device profile {:?}: {}", path, e))
+            .ok()
+    });
+    RwLock::new(profile)
+});
+
+/// Replaces the active profile. `None` reports devices as they are.
+pub fn set_profile(profile: Option<Profile>) {
+    *PROFILE.write().unwrap() = profile;
+}
+
+/// Runs `f` against the active profile, if there is one.
+pub fn with_profile<T>(f: impl FnOnce(&Profile) -> Option<T>) -> Option<T> {
+    PROFILE.read().unwrap().as_ref().and_then(f)
+}
+
+pub fn attribute(dev: CUdevice, attrib: i32) -> Option<i32> {
+    with_profile(|p| p.attribute(dev, attrib))
+}
+
+pub fn total_mem(dev: CUdevice) -> Option<usize> {
+    with_profile(|p| p.total_mem(dev))
+}
+
+pub fn compute_capability(dev: CUdevice) -> Option<(i32, i32)> {
+    with_profile(|p| p.compute_capability(dev))
+}
+
+/// Writes the spoofed name of `dev` into a C buffer of `len` bytes, truncating if needed.
+///
+/// # Safety
+/// `buf` must be valid for writes of `len` bytes.
+pub unsafe fn write_name(dev: CUdevice, buf: *mut c_char, len: usize) {
+    let Some(name) = with_profile(|p| p.name(dev)) else {
+        return;
+    };
+    if buf.is_null() || len == 0 {
+        return;
+    }
+    let n = name.len().min(len - 1);
+    unsafe {
+        std::ptr::copy_nonoverlapping(name.as_ptr() as *const c_char, buf, n);
+        *buf.add(n) = 0;
+    }
+}
+
+/// Installs `cudaGetDeviceProperties_v2` and legacy `cudaGetDeviceProperties` hooks applying
+/// the active [`Profile`]. Takes the `cudaDeviceProp` type from the runtime bindings, whose
+/// layout differs between CUDA releases:
+///
+/// ```ignore
+/// cuda_interposer::install_runtime_device_hooks!(cuda_interposer_sys::runtime_sys::cudaDeviceProp);
+/// ```
+///
+/// The legacy entry point fills the same structure unless a second type is given for it, for a
+/// runtime whose legacy `cudaDeviceProp` layout differs from the bindings'.
+#[macro_export]
+macro_rules! install_runtime_device_hooks {
+    ($prop:ty) => {
+        $crate::install_runtime_device_hooks!($prop, $prop);
+    };
+    ($prop:ty, $legacy:ty) => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cudaGetDeviceProperties(
+                prop: *mut $legacy,
+                device: $crate::libc::c_int
+            ) -> $crate::libc::c_uint {
+                let rc = unsafe { (*__real_cudaGetDeviceProperties)(prop, device) };
+                if rc == 0 {
+                    $crate::__spoof_device_prop!(unsafe { &mut *prop }, device);
+                }
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cudaGetDeviceProperties_v2(
+                prop: *mut $prop,
+                device: $crate::libc::c_int
+            ) -> $crate::libc::c_uint {
+                let rc = unsafe { (*__real_cudaGetDeviceProperties_v2)(prop, device) };
+                if rc == 0 {
+                    $crate::__spoof_device_prop!(unsafe { &mut *prop }, device);
+                }
+                rc
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __spoof_device_prop {
+    ($prop:expr, $dev:expr) => {{
+        let prop = $prop;
+        let dev = $dev;
+        unsafe {
+            $crate::spoof::write_name(dev, prop.name.as_mut_ptr(), prop.name.len());
+        }
+        if let Some(bytes) = $crate::spoof::total_mem(dev) {
+            prop.totalGlobalMem = bytes as _;
+        }
+        $crate::__spoof_device_prop!(@attrs prop, dev,
+            major = 75,
+            minor = 76,
+            multiProcessorCount = 16,
+            maxThreadsPerBlock = 1,
+            warpSize = 10,
+            sharedMemPerBlock = 8,
+            totalConstMem = 9,
+            regsPerBlock = 12,
+            l2CacheSize = 38,
+            maxThreadsPerMultiProcessor = 39,
+            sharedMemPerMultiprocessor = 81,
+            regsPerMultiprocessor = 82,
+            sharedMemPerBlockOptin = 97,
+            memoryBusWidth = 37,
+            pciBusID = 33,
+            pciDeviceID = 34,
+            pciDomainID = 50,
+        );
+    }};
+    (@attrs $prop:ident, $dev:ident, $($field:ident = $attr:expr),* $(,)?) => {
+        $(
+            if let Some(v) = $crate::spoof::attribute($dev, $attr) {
+                $prop.$field = v as _;
+            }
+        )*
+    };
+}
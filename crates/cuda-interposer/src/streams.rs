@@ -0,0 +1,1468 @@
+//! Stream and event dependency tracking.
+//!
+//! [`install_stream_hooks!`] follows the work submitted to each stream (kernel launches and graph
+//! launches, seen through [`crate::launch`] and [`crate::graphs`], memcpys and memsets) along
+//! with the calls that order it: event records and waits, and host synchronization. From these it
+//! builds a happens-before [`Graph`] of the GPU work, with an edge for:
+//!
+//! - program order within a stream,
+//! - a `cuStreamWaitEvent` on the work recorded in the event,
+//! - the implicit synchronization of the legacy default stream with every blocking stream,
+//! - host synchronization, which orders the work the host waited for before anything the
+//!   thread submits afterwards.
+//!
+//! The graph is exported as Graphviz DOT ([`Graph::to_dot`]) or JSON, and [`Graph::legacy_report`]
+//! finds work serialized by the legacy default stream, a common reason for streams that never
+//! overlap.
+//!
+//! Tracking is enabled by setting `CUDA_HOOK_STREAM_GRAPH` to the file the graph is written to
+//! at exit (DOT if it ends in `.dot`, JSON otherwise), or with [`set_enabled`].
+//!
+//! The per-thread default stream variants of these calls (`cuEventRecord_ptsz`,
+//! `cuStreamWaitEvent_ptsz`, `cuMemcpy_ptds`, `cuMemcpyHtoDAsync_v2_ptsz` and so on) are hooked
+//! alongside, and recorded as the same APIs on `CU_STREAM_PER_THREAD`.
+//!
+//! The graph keeps the first [`GRAPH_CAPACITY`] operations. Work seen after that is counted in
+//! [`Graph::dropped`] rather than recorded, and the exported graph says it was truncated.
+
+use crate::blocking;
+use crate::ffi::*;
+use crate::graphs::GraphDump;
+use crate::launch::LaunchEvent;
+use crate::lint;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::c_void;
+use std::fmt::{self, Write as _};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use tracing::warn;
+
+const GRAPH_ENV: &str = "CUDA_HOOK_STREAM_GRAPH";
+
+/// The most nodes the graph records.
+pub const GRAPH_CAPACITY: usize = 1 << 20;
+
+/// The stream key work on the legacy default stream (`NULL` or `CU_STREAM_LEGACY`) is filed
+/// under.
+pub const LEGACY_STREAM: usize = 0;
+
+static ENABLED: Lazy<AtomicBool> =
+    Lazy::new(|| AtomicBool::new(std::env::var_os(GRAPH_ENV).is_some()));
+
+static TRACKER: Lazy<Mutex<Tracker>> = Lazy::new(Default::default);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns the stream work passed `stream` runs on in the per-thread default stream variants of
+/// the driver API (`_ptsz`, `_ptds`), where `NULL` is `CU_STREAM_PER_THREAD`.
+pub fn per_thread(stream: CUstream) -> CUstream {
+    if stream.is_null() {
+        CU_STREAM_PER_THREAD
+    } else {
+        stream
+    }
+}
+
+/// The direction of a memcpy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemcpyKind {
+    HtoD,
+    DtoH,
+    DtoD,
+    /// `cuMemcpy`/`cuMemcpyAsync`, whose direction follows from unified addressing.
+    Default,
+}
+
+/// What a node of the graph stands for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Work {
+    Kernel {
+        name: Option<String>,
+    },
+    Memcpy {
+        kind: MemcpyKind,
+        bytes: usize,
+    },
+    Memset {
+        bytes: usize,
+    },
+    /// A `cuGraphLaunch` of the [`crate::graphs::GraphDump`] with id `graph`.
+    Graph {
+        graph: Option<u64>,
+        kernels: usize,
+    },
+    EventRecord {
+        event: usize,
+    },
+    StreamWaitEvent {
+        event: usize,
+    },
+    /// The host waiting for a stream.
+    StreamSynchronize,
+    /// The host waiting for an event.
+    EventSynchronize {
+        event: usize,
+    },
+    /// The host waiting for the whole context.
+    CtxSynchronize,
+}
+
+impl Work {
+    /// Whether this is work done by the host rather than queued on a stream.
+    pub fn is_host(&self) -> bool {
+        matches!(
+            self,
+            Work::StreamSynchronize | Work::EventSynchronize { .. } | Work::CtxSynchronize
+        )
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Work::Kernel { name } => name.clone().unwrap_or_else(|| "<unknown kernel>".into()),
+            Work::Memcpy { kind, bytes } => format!("memcpy {kind:?} {bytes} B"),
+            Work::Memset { bytes } => format!("memset {bytes} B"),
+            Work::Graph { graph, kernels } => match graph {
+                Some(id) => format!("graph #{id} ({kernels} kernels)"),
+                None => "graph".into(),
+            },
+            Work::EventRecord { event } => format!("record {event:#x}"),
+            Work::StreamWaitEvent { event } => format!("wait {event:#x}"),
+            Work::StreamSynchronize => "cuStreamSynchronize".into(),
+            Work::EventSynchronize { event } => format!("cuEventSynchronize {event:#x}"),
+            Work::CtxSynchronize => "cuCtxSynchronize".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    pub id: usize,
+    /// Microseconds since the interposer was loaded.
+    pub at_us: u64,
+    /// The stream the work was queued on, or waited for by the host.
+    pub stream: usize,
+    pub tid: i32,
+    #[serde(flatten)]
+    pub work: Work,
+}
+
+/// Why one node happens before another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+    /// Program order within a stream.
+    Stream,
+    /// A `cuStreamWaitEvent`, or a host wait, on a recorded event.
+    Event,
+    /// Implicit synchronization with the legacy default stream.
+    Legacy,
+    /// A host synchronization followed by more work from the same thread.
+    Host,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// The happens-before graph of the work seen so far.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    /// Operations left out because the graph already held [`GRAPH_CAPACITY`] nodes.
+    pub dropped: usize,
+}
+
+impl Graph {
+    /// Renders the graph in Graphviz DOT, with a cluster per stream.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph cuda {\n  rankdir=TB;\n  node [shape=box];\n");
+        if self.dropped > 0 {
+            let _ = writeln!(
+                out,
+                "  label=\"truncated: {} operations after the first {} were not recorded\";",
+                self.dropped,
+                self.nodes.len()
+            );
+        }
+        let mut streams: BTreeMap<usize, Vec<&Node>> = BTreeMap::new();
+        for node in &self.nodes {
+            if !node.work.is_host() {
+                streams.entry(node.stream).or_default().push(node);
+            }
+        }
+        for (stream, nodes) in &streams {
+            let label = if *stream == LEGACY_STREAM {
+                "legacy default stream".to_string()
+            } else {
+                format!("stream {stream:#x}")
+            };
+            let _ = writeln!(
+                out,
+                "  subgraph cluster_{stream:x} {{\n    label=\"{label}\";"
+            );
+            for node in nodes {
+                let _ = writeln!(
+                    out,
+                    "    n{} [label=\"{}\"];",
+                    node.id,
+                    escape(&node.work.label())
+                );
+            }
+            out.push_str("  }\n");
+        }
+        for node in self.nodes.iter().filter(|n| n.work.is_host()) {
+            let _ = writeln!(
+                out,
+                "  n{} [label=\"{}\", shape=ellipse];",
+                node.id,
+                escape(&node.work.label())
+            );
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Stream => "solid",
+                EdgeKind::Event => "bold",
+                EdgeKind::Legacy => "dashed, color=red",
+                EdgeKind::Host => "dotted",
+            };
+            let _ = writeln!(out, "  n{} -> n{} [style={}];", edge.from, edge.to, style);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Finds the work serialized by the legacy default stream.
+    pub fn legacy_report(&self) -> LegacyReport {
+        let mut report = LegacyReport::default();
+        let mut kernels: HashMap<String, usize> = HashMap::new();
+        for node in &self.nodes {
+            if node.stream != LEGACY_STREAM || node.work.is_host() {
+                continue;
+            }
+            report.legacy_work += 1;
+            if let Work::Kernel { name } = &node.work {
+                let name = name.clone().unwrap_or_else(|| "<unknown kernel>".into());
+                *kernels.entry(name).or_default() += 1;
+            }
+        }
+        report.implicit_syncs = self
+            .edges
+            .iter()
+            .filter(|e| e.kind == EdgeKind::Legacy)
+            .count();
+        report.kernels = kernels.into_iter().collect();
+        report
+            .kernels
+            .sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        report
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Work queued on the legacy default stream, and the cross-stream synchronization it caused.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LegacyReport {
+    /// Operations queued on the legacy default stream.
+    pub legacy_work: usize,
+    /// Edges between the legacy default stream and blocking streams, each of which kept
+    /// streams from overlapping.
+    pub implicit_syncs: usize,
+    /// Kernels launched on the legacy default stream, most frequent first.
+    pub kernels: Vec<(String, usize)>,
+}
+
+impl fmt::Display for LegacyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} operations on the legacy default stream caused {} implicit synchronizations \
+             with other streams",
+            self.legacy_work, self.implicit_syncs
+        )?;
+        for (name, count) in &self.kernels {
+            writeln!(f, "  {count:>6} x {name}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct StreamState {
+    last: Option<usize>,
+    blocking: bool,
+    /// Edges to add to the next work on the stream.
+    pending: Vec<(usize, EdgeKind)>,
+    /// The last legacy stream work this stream is already ordered after.
+    legacy_seen: Option<usize>,
+    /// The last work of this stream the legacy stream is already ordered after.
+    joined: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Tracker {
+    graph: Graph,
+    streams: HashMap<usize, StreamState>,
+    events: HashMap<usize, usize>,
+    /// The last host synchronization of each thread.
+    host: HashMap<i32, usize>,
+    legacy_last: Option<usize>,
+}
+
+fn stream_key(stream: CUstream) -> usize {
+    if stream.is_null() || stream == CU_STREAM_LEGACY {
+        LEGACY_STREAM
+    } else {
+        stream as usize
+    }
+}
+
+impl Tracker {
+    fn stream(&mut self, key: usize) -> &mut StreamState {
+        self.streams.entry(key).or_insert_with(|| StreamState {
+            blocking: true,
+            ..Default::default()
+        })
+    }
+
+    fn node(&mut self, stream: usize, work: Work) -> usize {
+        let id = self.graph.nodes.len();
+        self.graph.nodes.push(Node {
+            id,
+            at_us: crate::elapsed().as_micros() as u64,
+            stream,
+            tid: unsafe { libc::gettid() },
+            work,
+        });
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, kind: EdgeKind) {
+        self.graph.edges.push(Edge { from, to, kind });
+    }
+
+    /// Adds work queued on `stream`, ordered after what it depends on.
+    fn submit(&mut self, stream: usize, work: Work) -> usize {
+        let id = self.node(stream, work);
+        let tid = self.graph.nodes[id].tid;
+        if let Some(sync) = self.host.remove(&tid) {
+            self.edge(sync, id, EdgeKind::Host);
+        }
+        let legacy_last = self.legacy_last;
+        let state = self.stream(stream);
+        let mut edges: Vec<_> = state
+            .last
+            .map(|l| (l, EdgeKind::Stream))
+            .into_iter()
+            .collect();
+        edges.append(&mut state.pending);
+        if stream != LEGACY_STREAM
+            && state.blocking
+            && let Some(legacy) = legacy_last
+        {
+            if state.legacy_seen != Some(legacy) && Some(legacy) > state.last {
+                edges.push((legacy, EdgeKind::Legacy));
+            }
+            state.legacy_seen = Some(legacy);
+        }
+        state.last = Some(id);
+
+        if stream == LEGACY_STREAM {
+            for (key, other) in self.streams.iter_mut() {
+                if *key == LEGACY_STREAM || !other.blocking {
+                    continue;
+                }
+                if let Some(last) = other.last
+                    && other.joined != Some(last)
+                {
+                    edges.push((last, EdgeKind::Legacy));
+                }
+                other.joined = other.last;
+            }
+            self.legacy_last = Some(id);
+        }
+        for (from, kind) in edges {
+            self.edge(from, id, kind);
+        }
+        id
+    }
+
+    /// Adds a host wait on the given work, which orders the thread's next submission after it.
+    fn host_wait(&mut self, stream: usize, work: Work, on: Vec<(usize, EdgeKind)>) {
+        let id = self.node(stream, work);
+        for (from, kind) in on {
+            self.edge(from, id, kind);
+        }
+        let tid = self.graph.nodes[id].tid;
+        self.host.insert(tid, id);
+    }
+}
+
+fn with_tracker(f: impl FnOnce(&mut Tracker)) {
+    if !enabled() {
+        return;
+    }
+    install_exit_export();
+    let mut tracker = TRACKER.lock().unwrap();
+    if tracker.graph.nodes.len() >= GRAPH_CAPACITY {
+        if tracker.graph.dropped == 0 {
+            warn!(
+                "The stream graph reached {} operations; later work is not recorded",
+                GRAPH_CAPACITY
+            );
+        }
+        tracker.graph.dropped += 1;
+        return;
+    }
+    f(&mut tracker);
+}
+
+/// Returns the graph of the work seen so far.
+pub fn graph() -> Graph {
+    TRACKER.lock().unwrap().graph.clone()
+}
+
+/// Writes the graph to `path`, as DOT if it ends in `.dot` and JSON otherwise.
+pub fn write_graph(path: &std::path::Path) -> std::io::Result<()> {
+    let graph = graph();
+    let contents = if path.extension().is_some_and(|e| e == "dot") {
+        graph.to_dot()
+    } else {
+        serde_json::to_string_pretty(&graph).map_err(std::io::Error::other)?
+    };
+    std::fs::write(path, contents)
+}
+
+fn install_exit_export() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        if std::env::var_os(GRAPH_ENV).is_some() {
+            unsafe { libc::atexit(write_exit_graph) };
+        }
+    });
+}
+
+extern "C" fn write_exit_graph() {
+    let Some(dest) = std::env::var_os(GRAPH_ENV) else {
+        return;
+    };
+    if let Err(e) = write_graph(dest.as_ref()) {
+        warn!("Failed to write stream graph to {:?}: {}", dest, e);
+    }
+    let report = graph().legacy_report();
+    if report.implicit_syncs > 0 {
+        warn!("{}", report);
+    }
+}
+
+/// Records a stream created with `flags`. Used by [`install_stream_hooks!`].
+///
+/// # Safety
+/// `stream` must be the output pointer passed to the call.
+pub unsafe fn after_stream_create(rc: CUresult, stream: *const CUstream, flags: u32) {
+    if rc != CUDA_SUCCESS || stream.is_null() {
+        return;
+    }
+    let key = stream_key(unsafe { *stream });
+    let state = StreamState {
+        blocking: flags & CU_STREAM_NON_BLOCKING == 0,
+        ..Default::default()
+    };
+    with_tracker(|t| {
+        t.streams.insert(key, state);
+    });
+}
+
+/// Forgets a destroyed stream, whose handle may be reused. Used by [`install_stream_hooks!`].
+pub fn after_stream_destroy(rc: CUresult, stream: CUstream) {
+    if rc == CUDA_SUCCESS {
+        with_tracker(|t| {
+            t.streams.remove(&stream_key(stream));
+        });
+    }
+}
+
+/// Records a kernel launch. Called by [`crate::launch`] for every launch it records.
+pub fn record_launch(event: &LaunchEvent) {
+    if event.result != CUDA_SUCCESS {
+        return;
+    }
+    let name = event.kernel.as_ref().map(|k| k.demangled.clone());
+    with_tracker(|t| {
+        t.submit(stream_key(event.stream as CUstream), Work::Kernel { name });
+    });
+}
+
+/// Records a graph launch on `stream`. Called by [`crate::graphs`].
+pub fn record_graph_launch(stream: CUstream, graph: Option<&GraphDump>) {
+    let work = Work::Graph {
+        graph: graph.map(|g| g.id),
+        kernels: graph.map_or(0, |g| g.kernel_count()),
+    };
+    with_tracker(|t| {
+        t.submit(stream_key(stream), work);
+    });
+}
+
+/// Records a memcpy on `stream`, with `host` the host side of the copy if it has one.
+/// Synchronous copies are passed the legacy default stream and `blocking`, as they return once
+/// the copy is done. Used by [`install_stream_hooks!`].
+pub fn after_memcpy(
+    rc: CUresult,
+    api: &'static str,
+    stream: CUstream,
+    kind: MemcpyKind,
+    host: *const c_void,
+    bytes: usize,
+    blocking: bool,
+) {
+    blocking::after_memcpy(rc, api, stream, bytes);
+    if rc != CUDA_SUCCESS {
+        return;
+    }
+    lint::on_memcpy(api, stream, host, bytes, blocking);
+    with_tracker(|t| {
+        let key = stream_key(stream);
+        let id = t.submit(key, Work::Memcpy { kind, bytes });
+        if blocking {
+            t.host.insert(t.graph.nodes[id].tid, id);
+        }
+    });
+}
+
+/// Records a memset of `bytes` on `stream`. Used by [`install_stream_hooks!`].
+pub fn after_memset(rc: CUresult, stream: CUstream, bytes: usize) {
+    if rc == CUDA_SUCCESS {
+        with_tracker(|t| {
+            t.submit(stream_key(stream), Work::Memset { bytes });
+        });
+    }
+}
+
+/// Records `event` being recorded on `stream`. Used by [`install_stream_hooks!`].
+pub fn after_event_record(rc: CUresult, event: CUevent, stream: CUstream) {
+    if rc != CUDA_SUCCESS {
+        return;
+    }
+    with_tracker(|t| {
+        let id = t.submit(
+            stream_key(stream),
+            Work::EventRecord {
+                event: event as usize,
+            },
+        );
+        t.events.insert(event as usize, id);
+    });
+}
+
+/// Records `stream` waiting for `event`. Used by [`install_stream_hooks!`].
+pub fn after_stream_wait_event(rc: CUresult, stream: CUstream, event: CUevent) {
+    if rc != CUDA_SUCCESS {
+        return;
+    }
+    with_tracker(|t| {
+        let key = stream_key(stream);
+        if let Some(&recorded) = t.events.get(&(event as usize)) {
+            t.stream(key).pending.push((recorded, EdgeKind::Event));
+        }
+        t.submit(
+            key,
+            Work::StreamWaitEvent {
+                event: event as usize,
+            },
+        );
+    });
+}
+
+/// Records the host waiting for `stream`. Used by [`install_stream_hooks!`].
+pub fn after_stream_synchronize(rc: CUresult, stream: CUstream) {
+    if rc != CUDA_SUCCESS {
+        return;
+    }
+    with_tracker(|t| {
+        let key = stream_key(stream);
+        let on = t.stream(key).last.map(|l| (l, EdgeKind::Stream));
+        t.host_wait(key, Work::StreamSynchronize, on.into_iter().collect());
+    });
+}
+
+/// Records the host waiting for `event`. Used by [`install_stream_hooks!`].
+pub fn after_event_synchronize(rc: CUresult, event: CUevent) {
+    if rc != CUDA_SUCCESS {
+        return;
+    }
+    with_tracker(|t| {
+        let recorded = t.events.get(&(event as usize)).copied();
+        let stream = recorded.map_or(LEGACY_STREAM, |id| t.graph.nodes[id].stream);
+        let work = Work::EventSynchronize {
+            event: event as usize,
+        };
+        let on = recorded.map(|id| (id, EdgeKind::Event));
+        t.host_wait(stream, work, on.into_iter().collect());
+    });
+}
+
+/// Records the host waiting for every stream. Used by [`install_stream_hooks!`].
+pub fn after_ctx_synchronize(rc: CUresult) {
+    if rc != CUDA_SUCCESS {
+        return;
+    }
+    lint::on_ctx_synchronize();
+    with_tracker(|t| {
+        let on = t
+            .streams
+            .values()
+            .filter_map(|s| s.last)
+            .map(|l| (l, EdgeKind::Stream))
+            .collect();
+        t.host_wait(LEGACY_STREAM, Work::CtxSynchronize, on);
+    });
+}
+
+/// Installs hooks that track stream creation, event record and wait, host synchronization,
+/// memcpys and memsets into the happens-before [`Graph`]. Install [`install_launch_hooks!`] too
+/// so that kernel launches are part of it.
+///
+/// The hooked symbols are listed in [`crate::hook_sets::HOOK_SETS`], so
+/// `cuda-interposer-build` leaves them out of the generated passthroughs.
+#[macro_export]
+macro_rules! install_stream_hooks {
+    () => {
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuStreamCreate(
+                ph_stream: *mut $crate::ffi::CUstream,
+                flags: $crate::libc::c_uint
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuStreamCreate)(ph_stream, flags) };
+                unsafe { $crate::streams::after_stream_create(rc, ph_stream, flags) };
+                unsafe { $crate::record::on_stream_create(rc, ph_stream, flags, None) };
+                unsafe { $crate::checkpoint::on_stream_create(rc, ph_stream, flags, None) };
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Stream, ph_stream) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuStreamCreateWithPriority(
+                ph_stream: *mut $crate::ffi::CUstream,
+                flags: $crate::libc::c_uint,
+                priority: $crate::libc::c_int
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuStreamCreateWithPriority)(ph_stream, flags, priority) };
+                unsafe { $crate::streams::after_stream_create(rc, ph_stream, flags) };
+                unsafe { $crate::record::on_stream_create(rc, ph_stream, flags, Some(priority)) };
+                unsafe { $crate::checkpoint::on_stream_create(rc, ph_stream, flags, Some(priority)) };
+                unsafe { $crate::handles::export(&rc, $crate::handles::HandleKind::Stream, ph_stream) };
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuStreamDestroy_v2(
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuStreamDestroy_v2)(h_stream) };
+                $crate::streams::after_stream_destroy(rc, h_stream);
+                $crate::record::on_stream_destroy(rc, h_stream);
+                $crate::checkpoint::on_stream_destroy(rc, h_stream);
+                $crate::handles::release(&rc, $crate::handles::HandleKind::Stream, h_stream);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuEventRecord(
+                h_event: $crate::ffi::CUevent,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuEventRecord)(h_event, h_stream) };
+                $crate::streams::after_event_record(rc, h_event, h_stream);
+                $crate::record::on_event_record(rc, h_event, h_stream);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuEventRecord_ptsz(
+                h_event: $crate::ffi::CUevent,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuEventRecord_ptsz)(h_event, h_stream) };
+                let stream = $crate::streams::per_thread(h_stream);
+                $crate::streams::after_event_record(rc, h_event, stream);
+                $crate::record::on_event_record(rc, h_event, stream);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuEventRecordWithFlags(
+                h_event: $crate::ffi::CUevent,
+                h_stream: $crate::ffi::CUstream,
+                flags: $crate::libc::c_uint
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuEventRecordWithFlags)(h_event, h_stream, flags) };
+                $crate::streams::after_event_record(rc, h_event, h_stream);
+                $crate::record::on_event_record(rc, h_event, h_stream);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuEventRecordWithFlags_ptsz(
+                h_event: $crate::ffi::CUevent,
+                h_stream: $crate::ffi::CUstream,
+                flags: $crate::libc::c_uint
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuEventRecordWithFlags_ptsz)(h_event, h_stream, flags) };
+                let stream = $crate::streams::per_thread(h_stream);
+                $crate::streams::after_event_record(rc, h_event, stream);
+                $crate::record::on_event_record(rc, h_event, stream);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuStreamWaitEvent(
+                h_stream: $crate::ffi::CUstream,
+                h_event: $crate::ffi::CUevent,
+                flags: $crate::libc::c_uint
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuStreamWaitEvent)(h_stream, h_event, flags) };
+                $crate::streams::after_stream_wait_event(rc, h_stream, h_event);
+                $crate::record::on_stream_wait_event(rc, h_stream, h_event, flags);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuStreamWaitEvent_ptsz(
+                h_stream: $crate::ffi::CUstream,
+                h_event: $crate::ffi::CUevent,
+                flags: $crate::libc::c_uint
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuStreamWaitEvent_ptsz)(h_stream, h_event, flags) };
+                let stream = $crate::streams::per_thread(h_stream);
+                $crate::streams::after_stream_wait_event(rc, stream, h_event);
+                $crate::record::on_stream_wait_event(rc, stream, h_event, flags);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuStreamSynchronize(
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuStreamSynchronize)(h_stream) };
+                $crate::streams::after_stream_synchronize(rc, h_stream);
+                $crate::record::on_stream_synchronize(rc, h_stream);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuStreamSynchronize_ptsz(
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuStreamSynchronize_ptsz)(h_stream) };
+                let stream = $crate::streams::per_thread(h_stream);
+                $crate::streams::after_stream_synchronize(rc, stream);
+                $crate::record::on_stream_synchronize(rc, stream);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuEventSynchronize(
+                h_event: $crate::ffi::CUevent
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuEventSynchronize)(h_event) };
+                $crate::streams::after_event_synchronize(rc, h_event);
+                $crate::record::on_event_synchronize(rc, h_event);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuCtxSynchronize() -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuCtxSynchronize)() };
+                $crate::streams::after_ctx_synchronize(rc);
+                $crate::record::on_ctx_synchronize(rc);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy(
+                dst: $crate::ffi::CUdeviceptr,
+                src: $crate::ffi::CUdeviceptr,
+                byte_count: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemcpy)(dst, src, byte_count) };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpy",
+                        $crate::streams::MemcpyKind::Default,
+                        dst,
+                        src,
+                        byte_count,
+                        None,
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpy",
+                    std::ptr::null_mut(),
+                    $crate::streams::MemcpyKind::Default,
+                    std::ptr::null(),
+                    byte_count,
+                    true,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpy_ptds(
+                dst: $crate::ffi::CUdeviceptr,
+                src: $crate::ffi::CUdeviceptr,
+                byte_count: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemcpy_ptds)(dst, src, byte_count) };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpy",
+                        $crate::streams::MemcpyKind::Default,
+                        dst,
+                        src,
+                        byte_count,
+                        None,
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpy",
+                    $crate::ffi::CU_STREAM_PER_THREAD,
+                    $crate::streams::MemcpyKind::Default,
+                    std::ptr::null(),
+                    byte_count,
+                    true,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyAsync(
+                dst: $crate::ffi::CUdeviceptr,
+                src: $crate::ffi::CUdeviceptr,
+                byte_count: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemcpyAsync)(dst, src, byte_count, h_stream) };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyAsync",
+                        $crate::streams::MemcpyKind::Default,
+                        dst,
+                        src,
+                        byte_count,
+                        Some(h_stream),
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyAsync",
+                    h_stream,
+                    $crate::streams::MemcpyKind::Default,
+                    std::ptr::null(),
+                    byte_count,
+                    false,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyAsync_ptsz(
+                dst: $crate::ffi::CUdeviceptr,
+                src: $crate::ffi::CUdeviceptr,
+                byte_count: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemcpyAsync_ptsz)(dst, src, byte_count, h_stream) };
+                let stream = $crate::streams::per_thread(h_stream);
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyAsync",
+                        $crate::streams::MemcpyKind::Default,
+                        dst,
+                        src,
+                        byte_count,
+                        Some(stream),
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyAsync",
+                    stream,
+                    $crate::streams::MemcpyKind::Default,
+                    std::ptr::null(),
+                    byte_count,
+                    false,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyHtoD_v2(
+                dst_device: $crate::ffi::CUdeviceptr,
+                src_host: *const $crate::libc::c_void,
+                byte_count: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemcpyHtoD_v2)(dst_device, src_host, byte_count) };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyHtoD_v2",
+                        $crate::streams::MemcpyKind::HtoD,
+                        dst_device,
+                        src_host as u64,
+                        byte_count,
+                        None,
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyHtoD_v2",
+                    std::ptr::null_mut(),
+                    $crate::streams::MemcpyKind::HtoD,
+                    src_host as *const $crate::libc::c_void,
+                    byte_count,
+                    true,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyHtoD_v2_ptds(
+                dst_device: $crate::ffi::CUdeviceptr,
+                src_host: *const $crate::libc::c_void,
+                byte_count: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuMemcpyHtoD_v2_ptds)(dst_device, src_host, byte_count)
+                };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyHtoD_v2",
+                        $crate::streams::MemcpyKind::HtoD,
+                        dst_device,
+                        src_host as u64,
+                        byte_count,
+                        None,
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyHtoD_v2",
+                    $crate::ffi::CU_STREAM_PER_THREAD,
+                    $crate::streams::MemcpyKind::HtoD,
+                    src_host as *const $crate::libc::c_void,
+                    byte_count,
+                    true,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyDtoH_v2(
+                dst_host: *mut $crate::libc::c_void,
+                src_device: $crate::ffi::CUdeviceptr,
+                byte_count: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemcpyDtoH_v2)(dst_host, src_device, byte_count) };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyDtoH_v2",
+                        $crate::streams::MemcpyKind::DtoH,
+                        dst_host as u64,
+                        src_device,
+                        byte_count,
+                        None,
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyDtoH_v2",
+                    std::ptr::null_mut(),
+                    $crate::streams::MemcpyKind::DtoH,
+                    dst_host as *const $crate::libc::c_void,
+                    byte_count,
+                    true,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyDtoH_v2_ptds(
+                dst_host: *mut $crate::libc::c_void,
+                src_device: $crate::ffi::CUdeviceptr,
+                byte_count: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuMemcpyDtoH_v2_ptds)(dst_host, src_device, byte_count)
+                };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyDtoH_v2",
+                        $crate::streams::MemcpyKind::DtoH,
+                        dst_host as u64,
+                        src_device,
+                        byte_count,
+                        None,
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyDtoH_v2",
+                    $crate::ffi::CU_STREAM_PER_THREAD,
+                    $crate::streams::MemcpyKind::DtoH,
+                    dst_host as *const $crate::libc::c_void,
+                    byte_count,
+                    true,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyDtoD_v2(
+                dst_device: $crate::ffi::CUdeviceptr,
+                src_device: $crate::ffi::CUdeviceptr,
+                byte_count: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemcpyDtoD_v2)(dst_device, src_device, byte_count) };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyDtoD_v2",
+                        $crate::streams::MemcpyKind::DtoD,
+                        dst_device,
+                        src_device,
+                        byte_count,
+                        None,
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyDtoD_v2",
+                    std::ptr::null_mut(),
+                    $crate::streams::MemcpyKind::DtoD,
+                    std::ptr::null(),
+                    byte_count,
+                    false,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyDtoD_v2_ptds(
+                dst_device: $crate::ffi::CUdeviceptr,
+                src_device: $crate::ffi::CUdeviceptr,
+                byte_count: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuMemcpyDtoD_v2_ptds)(dst_device, src_device, byte_count)
+                };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyDtoD_v2",
+                        $crate::streams::MemcpyKind::DtoD,
+                        dst_device,
+                        src_device,
+                        byte_count,
+                        None,
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyDtoD_v2",
+                    $crate::ffi::CU_STREAM_PER_THREAD,
+                    $crate::streams::MemcpyKind::DtoD,
+                    std::ptr::null(),
+                    byte_count,
+                    false,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyHtoDAsync_v2(
+                dst_device: $crate::ffi::CUdeviceptr,
+                src_host: *const $crate::libc::c_void,
+                byte_count: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuMemcpyHtoDAsync_v2)(dst_device, src_host, byte_count, h_stream)
+                };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyHtoDAsync_v2",
+                        $crate::streams::MemcpyKind::HtoD,
+                        dst_device,
+                        src_host as u64,
+                        byte_count,
+                        Some(h_stream),
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyHtoDAsync_v2",
+                    h_stream,
+                    $crate::streams::MemcpyKind::HtoD,
+                    src_host as *const $crate::libc::c_void,
+                    byte_count,
+                    false,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyHtoDAsync_v2_ptsz(
+                dst_device: $crate::ffi::CUdeviceptr,
+                src_host: *const $crate::libc::c_void,
+                byte_count: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuMemcpyHtoDAsync_v2_ptsz)(dst_device, src_host, byte_count, h_stream)
+                };
+                let stream = $crate::streams::per_thread(h_stream);
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyHtoDAsync_v2",
+                        $crate::streams::MemcpyKind::HtoD,
+                        dst_device,
+                        src_host as u64,
+                        byte_count,
+                        Some(stream),
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyHtoDAsync_v2",
+                    stream,
+                    $crate::streams::MemcpyKind::HtoD,
+                    src_host as *const $crate::libc::c_void,
+                    byte_count,
+                    false,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyDtoHAsync_v2(
+                dst_host: *mut $crate::libc::c_void,
+                src_device: $crate::ffi::CUdeviceptr,
+                byte_count: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuMemcpyDtoHAsync_v2)(dst_host, src_device, byte_count, h_stream)
+                };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyDtoHAsync_v2",
+                        $crate::streams::MemcpyKind::DtoH,
+                        dst_host as u64,
+                        src_device,
+                        byte_count,
+                        Some(h_stream),
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyDtoHAsync_v2",
+                    h_stream,
+                    $crate::streams::MemcpyKind::DtoH,
+                    dst_host as *const $crate::libc::c_void,
+                    byte_count,
+                    false,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyDtoHAsync_v2_ptsz(
+                dst_host: *mut $crate::libc::c_void,
+                src_device: $crate::ffi::CUdeviceptr,
+                byte_count: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuMemcpyDtoHAsync_v2_ptsz)(dst_host, src_device, byte_count, h_stream)
+                };
+                let stream = $crate::streams::per_thread(h_stream);
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyDtoHAsync_v2",
+                        $crate::streams::MemcpyKind::DtoH,
+                        dst_host as u64,
+                        src_device,
+                        byte_count,
+                        Some(stream),
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyDtoHAsync_v2",
+                    stream,
+                    $crate::streams::MemcpyKind::DtoH,
+                    dst_host as *const $crate::libc::c_void,
+                    byte_count,
+                    false,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyDtoDAsync_v2(
+                dst_device: $crate::ffi::CUdeviceptr,
+                src_device: $crate::ffi::CUdeviceptr,
+                byte_count: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuMemcpyDtoDAsync_v2)(dst_device, src_device, byte_count, h_stream)
+                };
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyDtoDAsync_v2",
+                        $crate::streams::MemcpyKind::DtoD,
+                        dst_device,
+                        src_device,
+                        byte_count,
+                        Some(h_stream),
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyDtoDAsync_v2",
+                    h_stream,
+                    $crate::streams::MemcpyKind::DtoD,
+                    std::ptr::null(),
+                    byte_count,
+                    false,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemcpyDtoDAsync_v2_ptsz(
+                dst_device: $crate::ffi::CUdeviceptr,
+                src_device: $crate::ffi::CUdeviceptr,
+                byte_count: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe {
+                    (*__real_cuMemcpyDtoDAsync_v2_ptsz)(
+                        dst_device, src_device, byte_count, h_stream,
+                    )
+                };
+                let stream = $crate::streams::per_thread(h_stream);
+                unsafe {
+                    $crate::record::on_memcpy(
+                        rc,
+                        "cuMemcpyDtoDAsync_v2",
+                        $crate::streams::MemcpyKind::DtoD,
+                        dst_device,
+                        src_device,
+                        byte_count,
+                        Some(stream),
+                    )
+                };
+                $crate::streams::after_memcpy(
+                    rc,
+                    "cuMemcpyDtoDAsync_v2",
+                    stream,
+                    $crate::streams::MemcpyKind::DtoD,
+                    std::ptr::null(),
+                    byte_count,
+                    false,
+                );
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemsetD8_v2(
+                dst_device: $crate::ffi::CUdeviceptr,
+                uc: $crate::libc::c_uchar,
+                n: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemsetD8_v2)(dst_device, uc, n) };
+                $crate::streams::after_memset(rc, std::ptr::null_mut(), n);
+                $crate::record::on_memset(rc, dst_device, uc as u32, 1, n, None);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemsetD8_v2_ptds(
+                dst_device: $crate::ffi::CUdeviceptr,
+                uc: $crate::libc::c_uchar,
+                n: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemsetD8_v2_ptds)(dst_device, uc, n) };
+                $crate::streams::after_memset(rc, $crate::ffi::CU_STREAM_PER_THREAD, n);
+                $crate::record::on_memset(rc, dst_device, uc as u32, 1, n, None);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemsetD32_v2(
+                dst_device: $crate::ffi::CUdeviceptr,
+                ui: $crate::libc::c_uint,
+                n: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemsetD32_v2)(dst_device, ui, n) };
+                $crate::streams::after_memset(rc, std::ptr::null_mut(), n * 4);
+                $crate::record::on_memset(rc, dst_device, ui, 4, n, None);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemsetD32_v2_ptds(
+                dst_device: $crate::ffi::CUdeviceptr,
+                ui: $crate::libc::c_uint,
+                n: usize
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemsetD32_v2_ptds)(dst_device, ui, n) };
+                $crate::streams::after_memset(rc, $crate::ffi::CU_STREAM_PER_THREAD, n * 4);
+                $crate::record::on_memset(rc, dst_device, ui, 4, n, None);
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemsetD8Async(
+                dst_device: $crate::ffi::CUdeviceptr,
+                uc: $crate::libc::c_uchar,
+                n: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemsetD8Async)(dst_device, uc, n, h_stream) };
+                $crate::streams::after_memset(rc, h_stream, n);
+                $crate::record::on_memset(rc, dst_device, uc as u32, 1, n, Some(h_stream));
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemsetD8Async_ptsz(
+                dst_device: $crate::ffi::CUdeviceptr,
+                uc: $crate::libc::c_uchar,
+                n: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemsetD8Async_ptsz)(dst_device, uc, n, h_stream) };
+                let stream = $crate::streams::per_thread(h_stream);
+                $crate::streams::after_memset(rc, stream, n);
+                $crate::record::on_memset(rc, dst_device, uc as u32, 1, n, Some(stream));
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemsetD32Async(
+                dst_device: $crate::ffi::CUdeviceptr,
+                ui: $crate::libc::c_uint,
+                n: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemsetD32Async)(dst_device, ui, n, h_stream) };
+                $crate::streams::after_memset(rc, h_stream, n * 4);
+                $crate::record::on_memset(rc, dst_device, ui, 4, n, Some(h_stream));
+                rc
+            }
+        }
+
+        $crate::cuda_hook! {
+            pub unsafe extern "C" fn cuMemsetD32Async_ptsz(
+                dst_device: $crate::ffi::CUdeviceptr,
+                ui: $crate::libc::c_uint,
+                n: usize,
+                h_stream: $crate::ffi::CUstream
+            ) -> $crate::ffi::CUresult {
+                let rc = unsafe { (*__real_cuMemsetD32Async_ptsz)(dst_device, ui, n, h_stream) };
+                let stream = $crate::streams::per_thread(h_stream);
+                $crate::streams::after_memset(rc, stream, n * 4);
+                $crate::record::on_memset(rc, dst_device, ui, 4, n, Some(stream));
+                rc
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_reports_truncation() {
+        let graph = Graph {
+            dropped: 3,
+            ..Default::default()
+        };
+        assert!(
+            graph
+                .to_dot()
+                .contains("truncated: 3 operations after the first 0 were not recorded")
+        );
+        let json = serde_json::to_value(&graph).unwrap();
+        assert_eq!(json["dropped"], 3);
+    }
+}
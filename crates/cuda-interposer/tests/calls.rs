@@ -0,0 +1,142 @@
+//! Compiles the `CudaCall` that `cuda-interposer-build` generates for its bindgen fixture, and
+//! checks that it reads the structures a call points to.
+
+#![allow(
+    non_snake_case,
+    non_camel_case_types,
+    non_upper_case_globals,
+    dead_code
+)]
+
+use cuda_interposer::calls::{LaunchParams, MemcpySide};
+
+/// Stands in for the parameter table `cuda-interposer-sys` generates from `cuda.h`.
+mod cuda_interposer_sys {
+    pub mod driver_params {
+        pub struct ParamInfo {
+            pub name: &'static str,
+        }
+
+        pub fn params(name: &str) -> Option<&'static [ParamInfo]> {
+            (name == "cuInit").then_some(&[ParamInfo { name: "Flags" }][..])
+        }
+    }
+}
+
+mod bindings {
+    #![allow(clippy::upper_case_acronyms)]
+    include!("../../cuda-interposer-build/testdata/driver_internal_sys.rs");
+}
+
+mod calls {
+    use super::*;
+    use crate::bindings::*;
+    include!("../../cuda-interposer-build/testdata/calls_driver.rs");
+}
+
+use bindings::*;
+use calls::{CudaCall, args};
+
+#[test]
+fn reads_a_memcpy3d() {
+    let mut copy: CUDA_MEMCPY3D = unsafe { std::mem::zeroed() };
+    copy.srcMemoryType = 1;
+    copy.srcHost = 0x1000 as *const _;
+    copy.dstMemoryType = 2;
+    copy.dstDevice = 0x2000;
+    copy.dstPitch = 512;
+    copy.dstHeight = 4;
+    copy.WidthInBytes = 256;
+    copy.Height = 4;
+    copy.Depth = 2;
+
+    let call: CudaCall = unsafe { args::cuMemcpy3D_v2::new(&copy) }.into();
+    assert_eq!(call.name(), "cuMemcpy3D_v2");
+    let CudaCall::cuMemcpy3D_v2(args) = &call else {
+        panic!("{call:?}");
+    };
+    let copy = args.pCopy.as_ref().unwrap();
+    assert_eq!(copy.src.memory_type, 1);
+    assert_eq!(copy.src.host, 0x1000);
+    assert_eq!(
+        copy.dst,
+        MemcpySide {
+            memory_type: 2,
+            host: 0,
+            device: 0x2000,
+            array: 0,
+            x_in_bytes: 0,
+            y: 0,
+            z: 0,
+            lod: 0,
+            pitch: 512,
+            height: 4,
+        }
+    );
+    assert_eq!((copy.width_in_bytes, copy.height, copy.depth), (256, 4, 2));
+
+    let json = serde_json::to_string(&call).unwrap();
+    assert_eq!(serde_json::from_str::<CudaCall>(&json).unwrap(), call);
+
+    let call: CudaCall = unsafe { args::cuMemcpy3D_v2::new(std::ptr::null()) }.into();
+    assert_eq!(
+        call,
+        CudaCall::cuMemcpy3D_v2(args::cuMemcpy3D_v2 { pCopy: None })
+    );
+}
+
+#[test]
+fn reads_every_launch_params() {
+    let mut launches: [CUDA_LAUNCH_PARAMS; 2] = unsafe { std::mem::zeroed() };
+    for (i, launch) in launches.iter_mut().enumerate() {
+        launch.gridDimX = 8 << i;
+        launch.gridDimY = 1;
+        launch.gridDimZ = 1;
+        launch.blockDimX = 128;
+        launch.blockDimY = 1;
+        launch.blockDimZ = 1;
+        launch.sharedMemBytes = 1024;
+        launch.hStream = (0x10 * (i + 1)) as CUstream;
+    }
+
+    let call: CudaCall =
+        unsafe { args::cuLaunchCooperativeKernelMultiDevice::new(launches.as_mut_ptr(), 2, 0) }
+            .into();
+    assert_eq!(call.name(), "cuLaunchCooperativeKernelMultiDevice");
+    let CudaCall::cuLaunchCooperativeKernelMultiDevice(args) = &call else {
+        panic!("{call:?}");
+    };
+    assert_eq!(args.numDevices, 2);
+    assert_eq!(
+        args.launchParamsList,
+        [
+            LaunchParams {
+                function: 0,
+                grid: [8, 1, 1],
+                block: [128, 1, 1],
+                shared_mem: 1024,
+                stream: 0x10,
+                args: None,
+            },
+            LaunchParams {
+                function: 0,
+                grid: [16, 1, 1],
+                block: [128, 1, 1],
+                shared_mem: 1024,
+                stream: 0x20,
+                args: None,
+            },
+        ]
+    );
+
+    let json = serde_json::to_string(&call).unwrap();
+    assert_eq!(serde_json::from_str::<CudaCall>(&json).unwrap(), call);
+}
+
+#[test]
+fn looks_up_the_parameters_of_the_api() {
+    let call: CudaCall = unsafe { args::cuInit::new(0) }.into();
+    assert_eq!(call.params().len(), 1);
+    let call: CudaCall = unsafe { args::cuMemcpy3D_v2::new(std::ptr::null()) }.into();
+    assert!(call.params().is_empty());
+}
@@ -0,0 +1,57 @@
+//! Serves the driver calls of applications running under the interposer with
+//! `CUDA_HOOK_REMOTE` set, against the driver of this machine or a stand-in library.
+//!
+//! ```text
+//! cudaflow-server [--driver <libcuda.so>] <unix:<path> | tcp:<host>:<port>>
+//! ```
+
+use cuda_interposer::remote::{self, Endpoint};
+use cuda_interposer::replay::Driver;
+use std::process::ExitCode;
+
+const USAGE: &str =
+    "usage: cudaflow-server [--driver <libcuda.so>] <unix:<path> | tcp:<host>:<port>>";
+
+fn main() -> ExitCode {
+    let mut driver_path = "libcuda.so.1".to_string();
+    let mut endpoint = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--driver" => match args.next() {
+                Some(path) => driver_path = path,
+                None => return usage(),
+            },
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            spec => match Endpoint::parse(spec) {
+                Some(e) if endpoint.is_none() => endpoint = Some(e),
+                _ => return usage(),
+            },
+        }
+    }
+    let Some(endpoint) = endpoint else {
+        return usage();
+    };
+
+    let driver = match Driver::open(&driver_path) {
+        Ok(driver) => driver,
+        Err(e) => {
+            eprintln!("cudaflow-server: cannot load {driver_path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    eprintln!("cudaflow-server: serving {driver_path} at {endpoint}");
+    if let Err(e) = remote::serve(&endpoint, driver) {
+        eprintln!("cudaflow-server: {endpoint}: {e}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{USAGE}");
+    ExitCode::from(2)
+}
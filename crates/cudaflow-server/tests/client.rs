@@ -0,0 +1,126 @@
+//! Runs the client stubs of a remoting interposer against a server for the fake driver, in the
+//! same process.
+//!
+//! `CUDA_HOOK_REMOTE` is read once per process, so this is the only test here.
+
+mod common;
+
+use common::{PTX, fake_driver, register_scale};
+use cuda_interposer::ffi::*;
+use cuda_interposer::remote::{self, Endpoint};
+use cuda_interposer::replay::Driver;
+use std::ffi::{c_char, c_void};
+use std::ptr::null_mut;
+use std::time::{Duration, Instant};
+
+/// Resolves a driver entry point the way the interposer's hooks do, as a function of the given
+/// parameter types.
+macro_rules! entry {
+    ($name:ident($($arg:ty),*)) => {{
+        let ptr = cuda_interposer::dlsym_next(concat!(stringify!($name), "\0").as_bytes());
+        assert_eq!(Some(ptr), remote::stub(stringify!($name)), stringify!($name));
+        unsafe {
+            std::mem::transmute::<*mut c_void, unsafe extern "C" fn($($arg),*) -> CUresult>(ptr)
+        }
+    }};
+}
+
+#[test]
+fn forwards_calls_through_the_client_stubs() {
+    let socket = std::env::temp_dir().join(format!("cudaflow-client-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    // Nothing has read it yet: the first entry point resolved does
+    unsafe { std::env::set_var("CUDA_HOOK_REMOTE", format!("unix:{}", socket.display())) };
+    let endpoint = remote::endpoint().cloned();
+    assert_eq!(endpoint, Some(Endpoint::Unix(socket.clone())));
+
+    let path = fake_driver();
+    register_scale(&path);
+    let driver = Driver::open(&path).unwrap();
+    let endpoint = endpoint.unwrap();
+    std::thread::spawn(move || remote::serve(&endpoint, driver));
+    let start = Instant::now();
+    while !socket.exists() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "server did not start"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // Calls that are not forwarded fail without reaching the server
+    let unsupported = cuda_interposer::dlsym_next(b"cuGraphCreate\0");
+    assert!(remote::stub("cuGraphCreate").is_none());
+    let unsupported: unsafe extern "C" fn() -> CUresult =
+        unsafe { std::mem::transmute(unsupported) };
+    assert_eq!(unsafe { unsupported() }, CUDA_ERROR_NOT_SUPPORTED);
+
+    let cu_init = entry!(cuInit(u32));
+    let cu_ctx_create = entry!(cuCtxCreate_v2(*mut CUcontext, u32, CUdevice));
+    let cu_module_load_data = entry!(cuModuleLoadData(*mut CUmodule, *const c_void));
+    let cu_module_get_function =
+        entry!(cuModuleGetFunction(*mut CUfunction, CUmodule, *const c_char));
+    let cu_mem_alloc = entry!(cuMemAlloc_v2(*mut CUdeviceptr, usize));
+    let cu_memcpy_htod = entry!(cuMemcpyHtoD_v2(CUdeviceptr, *const c_void, usize));
+    let cu_memcpy_dtoh = entry!(cuMemcpyDtoH_v2(*mut c_void, CUdeviceptr, usize));
+    let cu_mem_free = entry!(cuMemFree_v2(CUdeviceptr));
+    #[rustfmt::skip]
+    let cu_launch_kernel = entry!(cuLaunchKernel(
+        CUfunction, u32, u32, u32, u32, u32, u32, u32, CUstream,
+        *mut *mut c_void, *mut *mut c_void
+    ));
+
+    assert_eq!(unsafe { cu_init(0) }, CUDA_SUCCESS);
+    let mut ctx = null_mut();
+    assert_eq!(unsafe { cu_ctx_create(&mut ctx, 0, 0) }, CUDA_SUCCESS);
+    let mut module = null_mut();
+    let image = PTX.as_ptr() as *const c_void;
+    assert_eq!(
+        unsafe { cu_module_load_data(&mut module, image) },
+        CUDA_SUCCESS
+    );
+    let mut function = null_mut();
+    let rc = unsafe { cu_module_get_function(&mut function, module, c"scale".as_ptr()) };
+    assert_eq!(rc, CUDA_SUCCESS);
+
+    // Copies larger than a message may carry are sent in pieces
+    remote::set_max_buffer(64);
+    let input: Vec<f32> = (0..100).map(|i| i as f32).collect();
+    let bytes = size_of_val(input.as_slice());
+    let mut dptr = 0;
+    assert_eq!(unsafe { cu_mem_alloc(&mut dptr, bytes) }, CUDA_SUCCESS);
+    let src = input.as_ptr() as *const c_void;
+    assert_eq!(unsafe { cu_memcpy_htod(dptr, src, bytes) }, CUDA_SUCCESS);
+
+    // The arguments are packed by the layout the server reports for `scale`
+    let mut n = input.len() as u32;
+    let mut params = [
+        &mut dptr as *mut u64 as *mut c_void,
+        &mut n as *mut u32 as *mut c_void,
+    ];
+    let rc = unsafe {
+        cu_launch_kernel(
+            function,
+            1,
+            1,
+            1,
+            128,
+            1,
+            1,
+            0,
+            null_mut(),
+            params.as_mut_ptr(),
+            null_mut(),
+        )
+    };
+    assert_eq!(rc, CUDA_SUCCESS);
+
+    let mut output = vec![0f32; input.len()];
+    let dst = output.as_mut_ptr() as *mut c_void;
+    assert_eq!(unsafe { cu_memcpy_dtoh(dst, dptr, bytes) }, CUDA_SUCCESS);
+    let expected: Vec<f32> = input.iter().map(|x| x * 2.0).collect();
+    assert_eq!(output, expected);
+
+    assert_eq!(unsafe { cu_mem_free(dptr) }, CUDA_SUCCESS);
+    let _ = std::fs::remove_file(&socket);
+}
@@ -0,0 +1,39 @@
+//! The fake driver, which cargo builds next to the test binaries as `libcuda.so`, and the
+//! kernel the tests launch on it.
+
+use cuda::kernels::{CUfakeKernel, CUfakeLaunch};
+use cuda_interposer::ffi::*;
+use std::ffi::{CString, c_char, c_void};
+
+pub const PTX: &[u8] = b".version 8.0\n.target sm_80\n.address_size 64\n\n\
+    .visible .entry scale(.param .u64 data, .param .u32 n)\n{\n\tret;\n}\n\0";
+
+pub fn fake_driver() -> String {
+    let exe = std::env::current_exe().unwrap();
+    let path = exe.parent().unwrap().join("libcuda.so");
+    path.to_string_lossy().into_owned()
+}
+
+/// Doubles the `n` floats at `data`, for `scale(float* data, int n)`.
+unsafe extern "C" fn scale(launch: *const CUfakeLaunch, _user_data: *mut c_void) -> CUresult {
+    let params = unsafe { (*launch).kernelParams };
+    let data = unsafe { *(*params as *const *mut f32) };
+    let n = unsafe { *(*params.add(1) as *const u32) };
+    let data = unsafe { std::slice::from_raw_parts_mut(data, n as usize) };
+    data.iter_mut().for_each(|x| *x *= 2.0);
+    CUDA_SUCCESS
+}
+
+/// Registers `scale` with the fake driver at `path`.
+pub fn register_scale(path: &str) {
+    type RegisterKernel =
+        unsafe extern "C" fn(*const c_char, Option<CUfakeKernel>, *mut c_void) -> CUresult;
+    let path = CString::new(path).unwrap();
+    let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    assert!(!handle.is_null());
+    let register = unsafe { libc::dlsym(handle, c"cufakeRegisterKernel".as_ptr()) };
+    assert!(!register.is_null());
+    let register: RegisterKernel = unsafe { std::mem::transmute(register) };
+    let rc = unsafe { register(c"scale".as_ptr(), Some(scale), std::ptr::null_mut()) };
+    assert_eq!(rc, CUDA_SUCCESS);
+}
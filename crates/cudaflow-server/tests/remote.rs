@@ -0,0 +1,95 @@
+//! Serves a client over a socket pair against the fake driver.
+
+mod common;
+
+use common::{PTX, fake_driver, register_scale};
+use cuda_interposer::ffi::*;
+use cuda_interposer::remote::{self, Endpoint, Reply, Request};
+use cuda_interposer::replay::Driver;
+use std::os::unix::net::UnixStream;
+
+struct Client(UnixStream);
+
+impl Client {
+    fn call(&mut self, symbol: &str, args: &[u64], data: &[u8]) -> Reply {
+        let request = Request {
+            symbol: symbol.to_string(),
+            args: args.to_vec(),
+            data: data.to_vec(),
+        };
+        request.write_to(&mut self.0).unwrap();
+        Reply::read_from(&mut self.0).unwrap()
+    }
+
+    /// Makes a call that writes one output, and returns it.
+    fn output(&mut self, symbol: &str, args: &[u64], data: &[u8]) -> u64 {
+        let reply = self.call(symbol, args, data);
+        assert_eq!(reply.result, CUDA_SUCCESS, "{symbol}");
+        reply.outputs[0]
+    }
+}
+
+#[test]
+fn round_trips_a_launch() {
+    let path = fake_driver();
+    register_scale(&path);
+    let driver = Driver::open(&path).unwrap();
+    let (client, mut server) = UnixStream::pair().unwrap();
+    let server = std::thread::spawn(move || remote::handle(&driver, &mut server));
+    let mut client = Client(client);
+
+    assert_eq!(client.call("cuInit", &[0], &[]).result, CUDA_SUCCESS);
+    client.output("cuCtxCreate_v2", &[0, 0], &[]);
+    let module = client.output("cuModuleLoadData", &[], PTX);
+    let function = client.output("cuModuleGetFunction", &[module], b"scale");
+    let reply = client.call("cuModuleGetFunction", &[module], b"missing");
+    assert_eq!(reply.result, CUDA_ERROR_NOT_FOUND);
+
+    let input: Vec<u8> = [1.0f32, 2.0, 3.0, 4.0]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let dptr = client.output("cuMemAlloc_v2", &[input.len() as u64], &[]);
+    let reply = client.call("cuMemcpyHtoD_v2", &[dptr], &input);
+    assert_eq!(reply.result, CUDA_SUCCESS);
+
+    // Arguments are sent packed as the kernel's parameter buffer
+    let mut params = dptr.to_le_bytes().to_vec();
+    params.extend_from_slice(&4u32.to_le_bytes());
+    let launch = [function, 1, 1, 1, 4, 1, 1, 0, 0];
+    assert_eq!(
+        client.call("cuLaunchKernel", &launch, &params).result,
+        CUDA_SUCCESS
+    );
+
+    let reply = client.call("cuMemcpyDtoH_v2", &[dptr, input.len() as u64], &[]);
+    assert_eq!(reply.result, CUDA_SUCCESS);
+    let output: Vec<f32> = reply
+        .data
+        .chunks(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+    assert_eq!(output, [2.0, 4.0, 6.0, 8.0]);
+
+    // A copy too large to send back is refused rather than allocated
+    let reply = client.call("cuMemcpyDtoH_v2", &[dptr, u64::MAX], &[]);
+    assert_eq!(reply.result, CUDA_ERROR_INVALID_VALUE);
+
+    assert_eq!(
+        client.call("cuMemFree_v2", &[dptr], &[]).result,
+        CUDA_SUCCESS
+    );
+    drop(client);
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn refuses_to_replace_a_file_with_its_socket() {
+    let path = std::env::temp_dir().join(format!("cudaflow-not-a-socket-{}", std::process::id()));
+    std::fs::write(&path, b"keep me").unwrap();
+    let driver = Driver::open(&fake_driver()).unwrap();
+    let err = remote::serve(&Endpoint::Unix(path.clone()), driver).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    assert_eq!(std::fs::read(&path).unwrap(), b"keep me");
+    std::fs::remove_file(&path).unwrap();
+}